@@ -0,0 +1,114 @@
+//! Maps the JIT-compiled code back to the wasm instructions that it's compiled from, with the
+//! address map that wasmtime keeps in the compiled image.
+//!
+//! The `.wasmtime.addrmap` section is a sorted table of the offsets in `.text` where the code
+//! of a wasm instruction starts, and the offsets of the instructions in the `.wasm` file:
+//!
+//! ```text
+//! count: u32 | code offsets: [u32; count] | wasm offsets: [u32; count]
+//! ```
+//!
+//! https://github.com/bytecodealliance/wasmtime/blob/v41.0.3/crates/environ/src/address_map.rs
+
+use std::ops::Range;
+
+use color_eyre::eyre::{self, eyre};
+
+use crate::{debugger_ctx::DebuggerCtx, wasm_abi};
+
+const ADDRMAP_SECTION: &str = ".wasmtime.addrmap";
+const TEXT_SECTION: &str = ".text";
+/// The wasm offset of the code that doesn't come from an instruction, e.g. the prologue
+const NO_WASM_OFFSET: u32 = u32::MAX;
+
+#[derive(Debug, Clone)]
+pub struct AddressMap {
+    /// Where `.text` is in the tracee
+    text: Range<u64>,
+    code_offsets: Vec<u32>,
+    wasm_offsets: Vec<u32>,
+}
+
+impl AddressMap {
+    /// Reads the address map of the compiled image that contains the code at `addr`.
+    pub fn read(ctx: &DebuggerCtx, addr: u64) -> eyre::Result<Self> {
+        let image = wasm_abi::locate_code_image(ctx.pid, addr, |addr| {
+            let bytes = ctx.read_memory(addr, size_of::<u64>())?;
+            Ok(u64::from_le_bytes(bytes.try_into().expect("a word")))
+        })?;
+
+        // the section headers of the ELF64 header, see elf(5)
+        let header = ctx.read_memory(image, 64)?;
+        let shoff = u64::from_le_bytes(header[0x28..0x30].try_into().expect("8 bytes"));
+        let shentsize = u16::from_le_bytes([header[0x3a], header[0x3b]]) as u64;
+        let shnum = u16::from_le_bytes([header[0x3c], header[0x3d]]) as u64;
+        let shstrndx = u16::from_le_bytes([header[0x3e], header[0x3f]]) as u64;
+        let sections = ctx.read_memory(image + shoff, (shentsize * shnum) as usize)?;
+        // the offset and the size of every section
+        let section = |index: u64| {
+            let header = &sections[(index * shentsize) as usize..][..shentsize as usize];
+            let name = u32::from_le_bytes(header[0..4].try_into().expect("4 bytes"));
+            let offset = u64::from_le_bytes(header[24..32].try_into().expect("8 bytes"));
+            let size = u64::from_le_bytes(header[32..40].try_into().expect("8 bytes"));
+            (name, offset, size)
+        };
+
+        let (_, names_offset, names_size) = section(shstrndx);
+        let names = ctx.read_memory(image + names_offset, names_size as usize)?;
+        let find = |wanted: &str| {
+            (0..shnum).map(section).find(|&(name, _, _)| {
+                names
+                    .get(name as usize..)
+                    .and_then(|name| name.split(|&b| b == 0).next())
+                    .is_some_and(|name| name == wanted.as_bytes())
+            })
+        };
+
+        let (_, text_offset, text_size) =
+            find(TEXT_SECTION).ok_or_else(|| eyre!("the compiled image has no `.text`"))?;
+        let (_, offset, size) = find(ADDRMAP_SECTION).ok_or_else(|| {
+            eyre!("the compiled image has no address map, it's disabled in the config of wasmtime")
+        })?;
+        let addrmap = ctx.read_memory(image + offset, size as usize)?;
+
+        Self::parse(
+            image + text_offset..image + text_offset + text_size,
+            &addrmap,
+        )
+    }
+
+    /// Parses the content of the address map section of the image whose `.text` is at `text`.
+    pub fn parse(text: Range<u64>, addrmap: &[u8]) -> eyre::Result<Self> {
+        let words: Vec<u32> = addrmap
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().expect("4 bytes")))
+            .collect();
+        let count = *words.first().unwrap_or(&0) as usize;
+        if words.len() < 1 + 2 * count {
+            return Err(eyre!("the address map is truncated"));
+        }
+
+        Ok(AddressMap {
+            text,
+            code_offsets: words[1..1 + count].to_vec(),
+            wasm_offsets: words[1 + count..1 + 2 * count].to_vec(),
+        })
+    }
+
+    /// Whether the code at `addr` is in the image of this map.
+    pub fn contains(&self, addr: u64) -> bool {
+        self.text.contains(&addr)
+    }
+
+    /// The offset in the `.wasm` file of the instruction that the code at `addr` is compiled
+    /// from.
+    pub fn wasm_offset(&self, addr: u64) -> Option<u32> {
+        if !self.contains(addr) {
+            return None;
+        }
+        let offset = (addr - self.text.start) as u32;
+        let i = self.code_offsets.partition_point(|&start| start <= offset);
+        let wasm_offset = self.wasm_offsets[i.checked_sub(1)?];
+        (wasm_offset != NO_WASM_OFFSET).then_some(wasm_offset)
+    }
+}
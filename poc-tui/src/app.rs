@@ -1,6 +1,19 @@
+use std::sync::Arc;
+
 use crate::{
-    debugger_ctx::DebuggerCtx,
+    breakpoint::{Breakpoint, Location},
+    console::{self, ConsoleCommand, ConsoleLine, ConsoleLineKind},
+    debugger::{DebuggerCommand, DebuggerHandle},
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    disassembly::Instruction,
     event::{AppEvent, Event, EventHandler},
+    expr::Expr,
+    function_mapping::{FunctionId, FunctionMapping},
+    fuzzy,
+    launch::{LaunchSpec, OutputLine},
+    notification::{Notification, NotificationLog, Severity},
+    registers::{self, Snapshot},
+    scroll_buffer::ScrollBuffer,
 };
 
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
@@ -11,6 +24,9 @@ use ratatui::{DefaultTerminal, widgets::ListState};
 pub enum Command {
     StartProcess,
     ParsePerfMap,
+    Continue,
+    Interrupt,
+    Kill,
 }
 
 impl Command {
@@ -18,6 +34,50 @@ impl Command {
         match self {
             Command::StartProcess => "Start process",
             Command::ParsePerfMap => "Parse perfmap output",
+            Command::Continue => "Continue [c]",
+            Command::Interrupt => "Interrupt [i]",
+            Command::Kill => "Kill [k]",
+        }
+    }
+}
+
+/// How many lines of the tracee's output are kept.
+const PROGRAM_OUTPUT_CAPACITY: usize = 10_000;
+
+/// The order of the functions in the function list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionSort {
+    Name,
+    Address,
+    Size,
+}
+
+impl FunctionSort {
+    pub fn title(self) -> &'static str {
+        match self {
+            FunctionSort::Name => "name",
+            FunctionSort::Address => "address",
+            FunctionSort::Size => "size",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            FunctionSort::Name => FunctionSort::Address,
+            FunctionSort::Address => FunctionSort::Size,
+            FunctionSort::Size => FunctionSort::Name,
+        }
+    }
+
+    fn sort(self, mapping: &FunctionMapping, ids: &mut [FunctionId]) {
+        let meta = |id: &FunctionId| mapping.function(*id).unwrap();
+        match self {
+            FunctionSort::Name => ids.sort_by(|a, b| {
+                (&meta(a).symbol.demangled, a).cmp(&(&meta(b).symbol.demangled, b))
+            }),
+            FunctionSort::Address => ids.sort_by_key(|id| (meta(id).addr, *id)),
+            // biggest first, since those are the interesting ones
+            FunctionSort::Size => ids.sort_by_key(|id| (std::cmp::Reverse(meta(id).size), *id)),
         }
     }
 }
@@ -26,6 +86,14 @@ impl Command {
 pub enum Mode {
     Normal,
     StartProcessPopup,
+    /// Typing into the fuzzy search filter of the function list.
+    FunctionSearch,
+    /// Typing a command into the `:` prompt.
+    Console,
+    /// Selecting a register in the registers pane.
+    Registers,
+    /// Typing the new value of the selected register.
+    RegisterEdit,
 }
 
 /// Application.
@@ -40,13 +108,58 @@ pub struct App {
     pub mapping_list_state: ListState,
     pub commands: Vec<Command>,
 
+    /// The functions that are shown in the function list, filtered and sorted.
+    /// `mapping_list_state` indexes into this.
+    pub function_view: Vec<FunctionId>,
+    pub function_sort: FunctionSort,
+    /// Fuzzy search filter of the function list
+    pub function_query: String,
+
     pub mode: Mode,
     // popup input
     pub attach_input: String,
 
-    pub debugger_ctx: DebuggerCtx,
+    /// The debugger thread, which owns the tracee
+    pub debugger: DebuggerHandle,
+    /// The functions of the module, once the debugger discovers them
+    pub function_mapping: Option<Arc<FunctionMapping>>,
+    pub tracee_pid: Option<Pid>,
+    pub tracee_state: TraceeState,
+    /// Where the tracee is stopped
+    pub pc: Option<u64>,
+    /// Why the tracee stopped the last time, kept after it's resumed
+    pub last_stop: Option<StopReason>,
+    pub notifications: NotificationLog,
+    /// What the tracee printed
+    pub program_output: ScrollBuffer<OutputLine>,
+
+    /// The commands and their results
+    pub console: ScrollBuffer<ConsoleLine>,
+    pub console_input: String,
+    /// The entered commands, the last one is the newest
+    pub console_history: Vec<String>,
+    /// The position in `console_history` while browsing it, `console_history.len()` otherwise
+    pub history_index: usize,
+    /// What `run` starts when it's not given a command line
+    pub last_launch: Option<LaunchSpec>,
+    pub breakpoints: Vec<Breakpoint>,
+    /// The registers at the current stop
+    pub registers: Option<Snapshot>,
+    /// The registers at the previous stop, to highlight the ones that changed
+    pub previous_registers: Option<Snapshot>,
+    /// The selected line of the registers pane: [`registers::PANE`], then the `xmm` registers
+    pub register_index: usize,
+    /// The new value of the selected register while it's edited
+    pub register_input: String,
+
+    /// The instructions of the selected function
+    pub disassembly: Vec<Instruction>,
+}
 
-    pub disas_str: String,
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl App {
@@ -58,19 +171,45 @@ impl App {
         let mut mapping_list_state = ListState::default();
         mapping_list_state.select(Some(0)); // default selection
 
+        let events = EventHandler::new();
+        let debugger = DebuggerHandle::spawn(DebuggerCtx::new(), events.sender());
+
         Self {
             running: true,
-            events: EventHandler::new(),
+            events,
             list_state,
             mapping_list_state,
-            commands: vec![Command::StartProcess, Command::ParsePerfMap],
+            commands: vec![
+                Command::StartProcess,
+                Command::ParsePerfMap,
+                Command::Continue,
+                Command::Interrupt,
+                Command::Kill,
+            ],
+            function_view: Vec::new(),
+            function_sort: FunctionSort::Name,
+            function_query: String::new(),
             mode: Mode::Normal,
             attach_input: "".into(),
-            debugger_ctx: DebuggerCtx {
-                pid: Pid::from_raw(0),
-                function_mapping: None,
-            },
-            disas_str: String::new(),
+            debugger,
+            function_mapping: None,
+            tracee_pid: None,
+            tracee_state: TraceeState::NotStarted,
+            pc: None,
+            last_stop: None,
+            notifications: NotificationLog::default(),
+            program_output: ScrollBuffer::with_capacity(PROGRAM_OUTPUT_CAPACITY),
+            console: ScrollBuffer::default(),
+            console_input: String::new(),
+            console_history: Vec::new(),
+            history_index: 0,
+            last_launch: None,
+            breakpoints: Vec::new(),
+            registers: None,
+            previous_registers: None,
+            register_index: 0,
+            register_input: String::new(),
+            disassembly: Vec::new(),
         }
     }
 
@@ -94,13 +233,95 @@ impl App {
                 }
                 _ => {}
             },
-            Event::App(app_event) => match app_event {
-                AppEvent::Quit => self.quit(),
-            },
+            Event::App(app_event) => self.handle_app_event(app_event),
         }
         Ok(())
     }
 
+    pub fn handle_app_event(&mut self, app_event: AppEvent) {
+        match app_event {
+            AppEvent::Quit => self.quit(),
+            AppEvent::TraceeStarted(pid) => {
+                self.notify(Severity::Info, format!("started the tracee with pid {pid}"));
+                self.tracee_pid = Some(pid);
+                self.tracee_state = TraceeState::Running;
+                self.pc = None;
+                self.last_stop = None;
+                self.program_output.clear();
+                self.function_mapping = None;
+                self.refresh_function_view();
+                self.disassembly.clear();
+                self.registers = None;
+                self.previous_registers = None;
+            }
+            AppEvent::TraceeRunning => {
+                self.tracee_state = TraceeState::Running;
+                self.pc = None;
+            }
+            AppEvent::TraceeStopped { reason, pc, .. } => {
+                self.notify(Severity::Info, format!("stopped: {reason}"));
+                self.console_print(
+                    ConsoleLineKind::Output,
+                    console::format_stop(&reason, pc, self.function_mapping.as_deref()),
+                );
+                self.last_stop = Some(reason.clone());
+                self.pc = pc;
+                self.tracee_state = TraceeState::Stopped(reason);
+                // the code might have changed while running
+                self.disassemble();
+            }
+            AppEvent::TraceeExited { pid, reason } => {
+                self.notify(Severity::Info, format!("{pid} exited: {reason}"));
+                self.tracee_state = TraceeState::Exited(reason);
+                self.pc = None;
+                self.registers = None;
+                self.previous_registers = None;
+            }
+            AppEvent::ModuleDiscovered(mapping) => {
+                let severity = if mapping.is_empty() {
+                    Severity::Warning
+                } else {
+                    Severity::Info
+                };
+                self.notify(
+                    severity,
+                    format!("discovered {} functions in the module", mapping.len()),
+                );
+                self.function_mapping = Some(mapping);
+                self.refresh_function_view();
+                self.disassemble();
+            }
+            AppEvent::Disassembly { id, instructions } => {
+                // the selection might have changed in the meantime
+                if self.selected_function() == Some(id) {
+                    self.disassembly = instructions;
+                }
+            }
+            AppEvent::Registers(snapshot) => {
+                // the same stop can be published more than once
+                if self.registers.as_ref() != Some(&snapshot) {
+                    self.previous_registers = self.registers.replace(snapshot);
+                }
+                // `rip` may have been written
+                self.pc = self.registers.as_ref().and_then(|regs| regs.get("rip"));
+            }
+            AppEvent::BreakpointsChanged(breakpoints) => self.breakpoints = breakpoints,
+            AppEvent::CommandOutput(lines) => {
+                for line in lines {
+                    self.console_print(ConsoleLineKind::Output, line);
+                }
+            }
+            AppEvent::ProgramOutput(line) => self.program_output.push(line),
+            AppEvent::Notify(notification) => {
+                // the failed commands are reported here, where the user looks at
+                if notification.severity == Severity::Error {
+                    self.console_print(ConsoleLineKind::Error, notification.message.clone());
+                }
+                self.notifications.push(notification);
+            }
+        }
+    }
+
     /// Handles the key events and updates the state of [`App`].
     pub fn handle_key_event(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
         // Global quit (optional)
@@ -118,7 +339,7 @@ impl App {
                     self.activate_selected();
                 }
                 KeyCode::Down => {
-                    if self.debugger_ctx.function_mapping.is_some() {
+                    if self.function_mapping.is_some() {
                         self.select_next_function();
                         self.disassemble();
                     } else {
@@ -126,13 +347,145 @@ impl App {
                     }
                 }
                 KeyCode::Up => {
-                    if self.debugger_ctx.function_mapping.is_some() {
+                    if self.function_mapping.is_some() {
                         self.select_prev_function();
                         self.disassemble();
                     } else {
                         self.select_prev_command();
                     }
                 }
+                KeyCode::Char('/') if self.function_mapping.is_some() => {
+                    self.mode = Mode::FunctionSearch;
+                }
+                KeyCode::Char(':') => {
+                    self.mode = Mode::Console;
+                    self.console_input.clear();
+                    self.history_index = self.console_history.len();
+                }
+                KeyCode::PageUp if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                    self.program_output.scroll_up(5)
+                }
+                KeyCode::PageDown if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                    self.program_output.scroll_down(5)
+                }
+                KeyCode::PageUp => self.notifications.scroll_up(5),
+                KeyCode::PageDown => self.notifications.scroll_down(5),
+                KeyCode::Char('c') => self.debugger.send(DebuggerCommand::Continue),
+                KeyCode::Char('i') => self.debugger.send(DebuggerCommand::Interrupt),
+                KeyCode::Char('k') => self.debugger.send(DebuggerCommand::Kill),
+                // run to the selected function
+                KeyCode::Char('g') => {
+                    if let Some(meta) = self
+                        .selected_function()
+                        .and_then(|id| self.function_mapping.as_ref()?.function(id))
+                    {
+                        let location = Location::Address(meta.addr);
+                        self.debugger.send(DebuggerCommand::Advance(location));
+                    }
+                }
+                KeyCode::Char('r') if self.registers.is_some() => self.mode = Mode::Registers,
+                KeyCode::Char('s') if self.function_mapping.is_some() => {
+                    self.function_sort = self.function_sort.next();
+                    self.refresh_function_view();
+                    self.disassemble();
+                }
+                _ => {}
+            },
+
+            Mode::FunctionSearch => match key_event.code {
+                // drop the filter
+                KeyCode::Esc => {
+                    self.mode = Mode::Normal;
+                    self.function_query.clear();
+                    self.refresh_function_view();
+                    self.disassemble();
+                }
+                // keep the filter
+                KeyCode::Enter => self.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    self.function_query.pop();
+                    self.refresh_function_view();
+                    self.disassemble();
+                }
+                KeyCode::Down => {
+                    self.select_next_function();
+                    self.disassemble();
+                }
+                KeyCode::Up => {
+                    self.select_prev_function();
+                    self.disassemble();
+                }
+                KeyCode::Char(c)
+                    if !key_event.modifiers.contains(KeyModifiers::CONTROL)
+                        && !key_event.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    self.function_query.push(c);
+                    self.refresh_function_view();
+                    self.disassemble();
+                }
+                _ => {}
+            },
+
+            Mode::Console => match key_event.code {
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Enter => self.submit_console_input(),
+                KeyCode::Backspace => {
+                    self.console_input.pop();
+                }
+                KeyCode::Tab => self.complete_console_input(),
+                KeyCode::Up => self.browse_history(-1),
+                KeyCode::Down => self.browse_history(1),
+                KeyCode::PageUp => self.console.scroll_up(5),
+                KeyCode::PageDown => self.console.scroll_down(5),
+                KeyCode::Char(c)
+                    if !key_event.modifiers.contains(KeyModifiers::CONTROL)
+                        && !key_event.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    self.console_input.push(c);
+                }
+                _ => {}
+            },
+
+            Mode::Registers => match key_event.code {
+                KeyCode::Esc | KeyCode::Char('r') => self.mode = Mode::Normal,
+                KeyCode::Up => self.register_index = self.register_index.saturating_sub(1),
+                KeyCode::Down => {
+                    let lines = self
+                        .registers
+                        .as_ref()
+                        .map_or(0, |regs| regs.general.len() + regs.xmm.len());
+                    self.register_index = (self.register_index + 1).min(lines.saturating_sub(1));
+                }
+                KeyCode::Enter => {
+                    let value = registers::PANE
+                        .get(self.register_index)
+                        .and_then(|name| self.registers.as_ref()?.get(name));
+                    match value {
+                        Some(value) => {
+                            self.register_input = format!("{value:#x}");
+                            self.mode = Mode::RegisterEdit;
+                        }
+                        None => self.notify(
+                            Severity::Warning,
+                            "only the general purpose registers can be written",
+                        ),
+                    }
+                }
+                _ => {}
+            },
+
+            Mode::RegisterEdit => match key_event.code {
+                KeyCode::Esc => self.mode = Mode::Registers,
+                KeyCode::Enter => self.write_register(),
+                KeyCode::Backspace => {
+                    self.register_input.pop();
+                }
+                KeyCode::Char(c)
+                    if !key_event.modifiers.contains(KeyModifiers::CONTROL)
+                        && !key_event.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    self.register_input.push(c);
+                }
                 _ => {}
             },
 
@@ -140,13 +493,12 @@ impl App {
                 KeyCode::Esc => self.close_attach_popup(),
                 KeyCode::Enter => self.confirm_attach(),
                 KeyCode::Backspace => self.input_backspace(),
-                KeyCode::Char(c) => {
-                    // ignore Ctrl/Alt combos
+                // ignore Ctrl/Alt combos
+                KeyCode::Char(c)
                     if !key_event.modifiers.contains(KeyModifiers::CONTROL)
-                        && !key_event.modifiers.contains(KeyModifiers::ALT)
-                    {
-                        self.input_push(c);
-                    }
+                        && !key_event.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    self.input_push(c);
                 }
                 _ => {}
             },
@@ -161,6 +513,11 @@ impl App {
     /// needs to be updated at a fixed frame rate. E.g. polling a server, updating an animation.
     pub fn tick(&self) {}
 
+    pub fn notify(&mut self, severity: Severity, message: impl Into<String>) {
+        self.notifications
+            .push(Notification::new(severity, message));
+    }
+
     /// Set running to false to quit the application.
     pub fn quit(&mut self) {
         self.running = false;
@@ -189,43 +546,27 @@ impl App {
     }
 
     pub fn select_next_function(&mut self) {
-        match &self.debugger_ctx.function_mapping {
-            Some(fm) => {
-                let len = fm.name_to_meta.len();
-                if len == 0 {
-                    self.mapping_list_state.select(None);
-                    return;
-                }
-
-                let i = self.mapping_list_state.selected().unwrap_or(0);
-                let next = if i + 1 >= len { 0 } else { i + 1 };
-                self.mapping_list_state.select(Some(next));
-            }
-            None => {
-                self.mapping_list_state.select(None);
-                return;
-            }
+        let len = self.function_view.len();
+        if len == 0 {
+            self.mapping_list_state.select(None);
+            return;
         }
+
+        let i = self.mapping_list_state.selected().unwrap_or(0);
+        let next = if i + 1 >= len { 0 } else { i + 1 };
+        self.mapping_list_state.select(Some(next));
     }
 
     pub fn select_prev_function(&mut self) {
-        match &self.debugger_ctx.function_mapping {
-            Some(fm) => {
-                let len = fm.name_to_meta.len();
-                if len == 0 {
-                    self.mapping_list_state.select(None);
-                    return;
-                }
-
-                let i = self.mapping_list_state.selected().unwrap_or(0);
-                let prev = if i == 0 { len - 1 } else { i - 1 };
-                self.mapping_list_state.select(Some(prev));
-            }
-            None => {
-                self.mapping_list_state.select(None);
-                return;
-            }
+        let len = self.function_view.len();
+        if len == 0 {
+            self.mapping_list_state.select(None);
+            return;
         }
+
+        let i = self.mapping_list_state.selected().unwrap_or(0);
+        let prev = if i == 0 { len - 1 } else { i - 1 };
+        self.mapping_list_state.select(Some(prev));
     }
 
     pub fn activate_selected(&mut self) {
@@ -237,10 +578,10 @@ impl App {
             Command::StartProcess => {
                 self.open_attach_popup();
             }
-            Command::ParsePerfMap => {
-                self.parse_perfmap_output();
-                self.disassemble();
-            }
+            Command::ParsePerfMap => self.parse_perfmap_output(),
+            Command::Continue => self.debugger.send(DebuggerCommand::Continue),
+            Command::Interrupt => self.debugger.send(DebuggerCommand::Interrupt),
+            Command::Kill => self.debugger.send(DebuggerCommand::Kill),
         }
     }
 
@@ -250,7 +591,51 @@ impl App {
     }
 
     pub fn parse_perfmap_output(&mut self) {
-        self.debugger_ctx.parse_perfmap("wasm_binary").unwrap();
+        self.debugger.send(DebuggerCommand::ParsePerfMap);
+    }
+
+    pub fn selected_function(&self) -> Option<FunctionId> {
+        self.function_view
+            .get(self.mapping_list_state.selected()?)
+            .copied()
+    }
+
+    /// Recomputes the function list from the mapping, the search query and the sort order,
+    /// keeping the selected function selected if it's still in the list.
+    pub fn refresh_function_view(&mut self) {
+        let Some(mapping) = &self.function_mapping else {
+            self.function_view.clear();
+            self.mapping_list_state.select(None);
+            return;
+        };
+
+        let selected = self.selected_function();
+
+        let mut scored: Vec<(i64, FunctionId)> = mapping
+            .into_iter()
+            .filter_map(|meta| {
+                let score = fuzzy::score(&self.function_query, &meta.symbol.demangled)?;
+                Some((score, meta.id))
+            })
+            .collect();
+
+        let mut ids: Vec<FunctionId> = scored.iter().map(|(_, id)| *id).collect();
+        self.function_sort.sort(mapping, &mut ids);
+
+        if !self.function_query.is_empty() {
+            // best match first, the sort order breaks the ties
+            let rank: std::collections::HashMap<FunctionId, usize> =
+                ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+            scored.sort_by_key(|(score, id)| (std::cmp::Reverse(*score), rank[id]));
+            ids = scored.into_iter().map(|(_, id)| id).collect();
+        }
+
+        let index = selected
+            .and_then(|selected| ids.iter().position(|id| *id == selected))
+            .or((!ids.is_empty()).then_some(0));
+
+        self.function_view = ids;
+        self.mapping_list_state.select(index);
     }
 
     pub fn open_attach_popup(&mut self) {
@@ -265,11 +650,31 @@ impl App {
             return;
         }
 
-        self.debugger_ctx.run_command(&s).unwrap();
+        match LaunchSpec::parse(&s) {
+            Ok(spec) => {
+                self.last_launch = Some(spec.clone());
+                self.debugger.send(DebuggerCommand::Launch(spec));
+            }
+            Err(e) => self.notify(Severity::Error, format!("{e:#}")),
+        }
 
         self.close_attach_popup();
     }
 
+    /// Writes the typed value, which is an expression like `$rax + 8`, into the selected
+    /// register.
+    fn write_register(&mut self) {
+        self.mode = Mode::Registers;
+        let input = std::mem::take(&mut self.register_input);
+        match Expr::parse(&input) {
+            Ok(value) => self.debugger.send(DebuggerCommand::SetRegister {
+                name: registers::PANE[self.register_index],
+                value,
+            }),
+            Err(e) => self.console_print(ConsoleLineKind::Error, format!("{e:#}")),
+        }
+    }
+
     pub fn close_attach_popup(&mut self) {
         self.mode = Mode::Normal;
     }
@@ -284,9 +689,138 @@ impl App {
     }
 
     pub fn disassemble(&mut self) {
-        self.disas_str = self
-            .debugger_ctx
-            .disassemble(self.mapping_list_state.selected().unwrap())
-            .unwrap();
+        let Some(id) = self.selected_function() else {
+            self.disassembly.clear();
+            return;
+        };
+
+        self.debugger.send(DebuggerCommand::Disassemble(id));
+    }
+}
+
+impl App {
+    pub fn console_print(&mut self, kind: ConsoleLineKind, text: impl Into<String>) {
+        for line in text.into().lines() {
+            self.console.push(ConsoleLine {
+                kind,
+                text: line.into(),
+            });
+        }
+    }
+
+    pub fn submit_console_input(&mut self) {
+        let line = std::mem::take(&mut self.console_input);
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+
+        self.console_print(ConsoleLineKind::Input, format!(": {line}"));
+        if self.console_history.last().map(String::as_str) != Some(line) {
+            self.console_history.push(line.into());
+        }
+        self.history_index = self.console_history.len();
+
+        match ConsoleCommand::parse(line) {
+            Ok(command) => self.execute(command),
+            Err(e) => self.console_print(ConsoleLineKind::Error, format!("{e:#}")),
+        }
+    }
+
+    pub fn execute(&mut self, command: ConsoleCommand) {
+        let debugger_command = match command {
+            ConsoleCommand::Run(spec) => {
+                let Some(spec) = spec.or_else(|| self.last_launch.clone()) else {
+                    self.console_print(
+                        ConsoleLineKind::Error,
+                        "nothing to run yet, use `run PROGRAM [ARGS ...]`",
+                    );
+                    return;
+                };
+                self.last_launch = Some(spec.clone());
+                DebuggerCommand::Launch(spec)
+            }
+            ConsoleCommand::Attach(pid) => DebuggerCommand::Attach(pid),
+            ConsoleCommand::Break {
+                location,
+                condition,
+                temporary,
+            } => DebuggerCommand::Break {
+                location,
+                condition,
+                temporary,
+            },
+            ConsoleCommand::Advance(location) => DebuggerCommand::Advance(location),
+            ConsoleCommand::Ignore { id, count } => DebuggerCommand::Ignore { id, count },
+            ConsoleCommand::Expire { id, stops } => DebuggerCommand::Expire { id, stops },
+            ConsoleCommand::Dprintf { location, format } => {
+                DebuggerCommand::Dprintf { location, format }
+            }
+            ConsoleCommand::RateLimit { id, per_second } => {
+                DebuggerCommand::RateLimit { id, per_second }
+            }
+            ConsoleCommand::Condition { id, condition } => {
+                DebuggerCommand::Condition { id, condition }
+            }
+            ConsoleCommand::OnHit { id, script } => DebuggerCommand::OnHit { id, script },
+            ConsoleCommand::Enable { id, enabled } => DebuggerCommand::Enable { id, enabled },
+            ConsoleCommand::Script(script) => DebuggerCommand::Script(script),
+            ConsoleCommand::Delete(id) => DebuggerCommand::Delete(id),
+            ConsoleCommand::Continue => DebuggerCommand::Continue,
+            ConsoleCommand::Interrupt => DebuggerCommand::Interrupt,
+            ConsoleCommand::StepInstruction => DebuggerCommand::StepInstruction,
+            ConsoleCommand::Finish => DebuggerCommand::Finish,
+            ConsoleCommand::InstructionTrace { calls, output } => {
+                DebuggerCommand::InstructionTrace { calls, output }
+            }
+            ConsoleCommand::Backtrace => DebuggerCommand::Backtrace,
+            ConsoleCommand::Examine { format, location } => {
+                DebuggerCommand::Examine { format, location }
+            }
+            ConsoleCommand::Print(expr) => DebuggerCommand::Print(expr),
+            ConsoleCommand::Kill => DebuggerCommand::Kill,
+            ConsoleCommand::InfoFunctions(regex) => {
+                let Some(mapping) = self.function_mapping.clone() else {
+                    self.console_print(ConsoleLineKind::Error, "the module is not loaded yet");
+                    return;
+                };
+                for line in console::format_functions(&mapping, regex.as_ref()) {
+                    self.console_print(ConsoleLineKind::Output, line);
+                }
+                return;
+            }
+            ConsoleCommand::InfoBreakpoints => {
+                for line in console::format_breakpoints(&self.breakpoints) {
+                    self.console_print(ConsoleLineKind::Output, line);
+                }
+                return;
+            }
+            ConsoleCommand::Help => {
+                self.console_print(ConsoleLineKind::Output, console::HELP);
+                return;
+            }
+        };
+
+        self.debugger.send(debugger_command);
+    }
+
+    /// Completes the word under the cursor, listing the candidates when it's ambiguous.
+    pub fn complete_console_input(&mut self) {
+        let completion = console::complete(&self.console_input, self.function_mapping.as_deref());
+        self.console_input = completion.line;
+        if !completion.candidates.is_empty() {
+            self.console_print(ConsoleLineKind::Output, completion.candidates.join("  "));
+        }
+    }
+
+    /// Moves `delta` entries in the history, going past the newest one clears the input.
+    pub fn browse_history(&mut self, delta: isize) {
+        let len = self.console_history.len();
+        self.history_index = self.history_index.saturating_add_signed(delta).min(len);
+        self.console_input = self
+            .console_history
+            .get(self.history_index)
+            .cloned()
+            .unwrap_or_default();
     }
 }
@@ -0,0 +1,289 @@
+//! What the subcommands that run the tracee without the user have in common, i.e. `record` and
+//! `coverage`: they start the tracee, then resume it until it exits, Ctrl-C or a deadline.
+//!
+//! The command line options that every subcommand takes are parsed here too.
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use color_eyre::eyre::{self, eyre};
+use nix::{
+    errno::Errno,
+    sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    unistd::Pid,
+};
+
+use crate::{
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    launch::LaunchSpec,
+};
+
+/// Set by `Ctrl-C` and by the alarm of `--duration`, which end the session.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_stop_signal(_: libc::c_int) {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// The options that pick the tracee, i.e. `--attach PID`, `--perfmap PATH` and
+/// `-- PROGRAM [ARGS ...]`, and `--duration SECS` for the subcommands that take it.
+#[derive(Debug)]
+pub struct TraceeArgs {
+    pub attach: Option<Pid>,
+    pub perfmap: Option<PathBuf>,
+    pub program: Option<LaunchSpec>,
+    pub duration: Option<u32>,
+    takes_duration: bool,
+    usage: &'static str,
+}
+
+impl TraceeArgs {
+    /// The options without `--duration`, `usage` is shown for the invalid ones.
+    pub fn new(usage: &'static str) -> Self {
+        TraceeArgs {
+            attach: None,
+            perfmap: None,
+            program: None,
+            duration: None,
+            takes_duration: false,
+            usage,
+        }
+    }
+
+    /// The options with `--duration`, for the subcommands that run until a deadline.
+    pub fn with_duration(usage: &'static str) -> Self {
+        TraceeArgs {
+            takes_duration: true,
+            ..Self::new(usage)
+        }
+    }
+
+    /// Parses `arg` and its value from `args` if it's one of the options. Returns whether it
+    /// was. `--` takes all the remaining arguments.
+    pub fn parse(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> eyre::Result<bool> {
+        let usage = self.usage;
+        match arg {
+            "--attach" => {
+                let pid = next_value(args, arg, usage)?;
+                let pid = pid
+                    .parse()
+                    .map_err(|_| eyre!("`{pid}` is not a pid\n\n{usage}"))?;
+                self.attach = Some(Pid::from_raw(pid));
+            }
+            "--perfmap" => self.perfmap = Some(next_value(args, arg, usage)?.into()),
+            "--duration" if self.takes_duration => {
+                let secs = next_value(args, arg, usage)?;
+                let secs = secs
+                    .parse()
+                    .map_err(|_| eyre!("`{secs}` is not a number of seconds\n\n{usage}"))?;
+                self.duration = Some(secs);
+            }
+            "--" => {
+                let program = args
+                    .next()
+                    .ok_or_else(|| eyre!("no program is given after `--`\n\n{usage}"))?;
+                self.program = Some(LaunchSpec {
+                    program,
+                    args: args.collect(),
+                    env: Vec::new(),
+                    cwd: None,
+                    capture_output: false,
+                });
+            }
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Starts the tracee in `ctx` with [`start`], which uses the given perf map.
+    pub fn start(self, ctx: &mut DebuggerCtx) -> eyre::Result<String> {
+        ctx.perfmap_path = self.perfmap;
+        start(ctx, self.attach, self.program, self.usage)
+    }
+}
+
+/// The value of `option`, the next argument. `usage` is shown when there is none.
+pub fn next_value(
+    args: &mut impl Iterator<Item = String>,
+    option: &str,
+    usage: &str,
+) -> eyre::Result<String> {
+    args.next()
+        .ok_or_else(|| eyre!("{option} requires a value\n\n{usage}"))
+}
+
+/// Attaches to `attach` or launches `program` until the module is loaded, `usage` is shown
+/// when there is neither or both. Returns the tracee as the user gave it.
+pub fn start(
+    ctx: &mut DebuggerCtx,
+    attach: Option<Pid>,
+    program: Option<LaunchSpec>,
+    usage: &str,
+) -> eyre::Result<String> {
+    match (attach, program) {
+        (Some(pid), None) => {
+            ctx.attach(pid)?;
+            eprintln!("Attached to {pid}");
+            Ok(format!("--attach {pid}"))
+        }
+        (None, Some(spec)) => {
+            ctx.run_command(&spec)?;
+            if !ctx.is_alive() {
+                return Err(eyre!("`{spec}` exited before loading the module"));
+            }
+            eprintln!("Started `{spec}` with pid {}", ctx.pid);
+            Ok(spec.to_string())
+        }
+        _ => Err(eyre!("either --attach or a program is required\n\n{usage}")),
+    }
+}
+
+/// Makes `Ctrl-C`, and the end of `duration` seconds if it's given, stop the session.
+///
+/// Unlike in the REPL, the signals interrupt the blocking wait for the tracee.
+pub fn stop_on_signals(duration: Option<u32>) -> eyre::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(on_stop_signal),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    unsafe {
+        signal::sigaction(Signal::SIGINT, &action)?;
+        signal::sigaction(Signal::SIGALRM, &action)?;
+    }
+    if let Some(secs) = duration {
+        unsafe { libc::alarm(secs) };
+    }
+
+    Ok(())
+}
+
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Stops the session from another thread, like `Ctrl-C`. The thread must block the signals of
+/// [`stop_on_signals`], so that the one that waits for the tracee is interrupted.
+pub fn request_stop() -> eyre::Result<()> {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+    signal::kill(Pid::this(), Signal::SIGALRM)?;
+    Ok(())
+}
+
+/// Stops the session when the tracee gets a `SIGINT`, which is the Ctrl-C of the user. Returns
+/// whether it did.
+pub fn handle_interrupt(ctx: &mut DebuggerCtx, reason: &StopReason) -> bool {
+    if *reason != StopReason::Signal(Signal::SIGINT) {
+        return false;
+    }
+    // the Ctrl-C was for us, not for the tracee
+    ctx.discard_signal();
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Blocks until the tracee stops. Returns `None` if the session is stopped meanwhile, the
+/// tracee is left stopped then.
+pub fn wait(ctx: &mut DebuggerCtx) -> eyre::Result<Option<TraceeState>> {
+    loop {
+        match ctx.poll(true) {
+            Ok(Some(TraceeState::Stopped(StopReason::Interrupted))) if stop_requested() => {
+                return Ok(None);
+            }
+            Ok(Some(state)) => return Ok(Some(state)),
+            Ok(None) => {}
+            Err(e) if e.downcast_ref::<Errno>() == Some(&Errno::EINTR) => {
+                if stop_requested() {
+                    ctx.interrupt()?;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(mut tracee: TraceeArgs, args: &[&str]) -> eyre::Result<(TraceeArgs, Vec<String>)> {
+        let mut rest = Vec::new();
+        let mut args = args.iter().map(|arg| arg.to_string());
+        while let Some(arg) = args.next() {
+            if !tracee.parse(&arg, &mut args)? {
+                rest.push(arg);
+            }
+        }
+        Ok((tracee, rest))
+    }
+
+    #[test]
+    fn parses_the_tracee_options() {
+        let (tracee, rest) = parse(
+            TraceeArgs::with_duration("usage"),
+            &[
+                "--attach",
+                "42",
+                "-o",
+                "--perfmap",
+                "/tmp/perf-42.map",
+                "--duration",
+                "5",
+            ],
+        )
+        .unwrap();
+        assert_eq!(tracee.attach, Some(Pid::from_raw(42)));
+        assert_eq!(tracee.perfmap, Some(PathBuf::from("/tmp/perf-42.map")));
+        assert_eq!(tracee.duration, Some(5));
+        assert!(tracee.program.is_none());
+        assert_eq!(rest, ["-o"]);
+    }
+
+    #[test]
+    fn takes_the_program_after_the_separator() {
+        let (tracee, rest) = parse(
+            TraceeArgs::new("usage"),
+            &["--", "wasmtime", "run", "--attach", "app.wasm"],
+        )
+        .unwrap();
+        let program = tracee.program.unwrap();
+        assert_eq!(program.program, "wasmtime");
+        assert_eq!(program.args, ["run", "--attach", "app.wasm"]);
+        assert!(tracee.attach.is_none());
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn duration_is_only_taken_when_asked() {
+        let (tracee, rest) = parse(TraceeArgs::new("usage"), &["--duration", "5"]).unwrap();
+        assert_eq!(tracee.duration, None);
+        assert_eq!(rest, ["--duration", "5"]);
+    }
+
+    #[test]
+    fn reports_invalid_values_with_the_usage() {
+        let error = |args: &[&str]| {
+            format!(
+                "{:#}",
+                parse(TraceeArgs::with_duration("usage: x"), args).unwrap_err()
+            )
+        };
+        assert_eq!(error(&["--attach", "me"]), "`me` is not a pid\n\nusage: x");
+        assert_eq!(
+            error(&["--attach"]),
+            "--attach requires a value\n\nusage: x"
+        );
+        assert_eq!(
+            error(&["--duration", "-1"]),
+            "`-1` is not a number of seconds\n\nusage: x"
+        );
+        assert_eq!(error(&["--"]), "no program is given after `--`\n\nusage: x");
+    }
+}
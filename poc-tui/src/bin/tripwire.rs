@@ -0,0 +1,5 @@
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    poc_tui::repl::run(std::env::args().skip(1))
+}
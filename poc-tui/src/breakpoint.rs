@@ -0,0 +1,412 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use color_eyre::eyre::{self, eyre};
+
+use crate::{expr::Expr, logpoint::Logpoint, script::Script};
+
+/// Identifies a breakpoint for the user, e.g. `delete 2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BreakpointId(pub u32);
+
+impl fmt::Display for BreakpointId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // honors the width, for the tables
+        f.pad(&self.0.to_string())
+    }
+}
+
+impl FromStr for BreakpointId {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        s.parse()
+            .map(BreakpointId)
+            .map_err(|_| eyre!("invalid breakpoint number `{s}`"))
+    }
+}
+
+/// Where to stop, as the user wrote it.
+///
+/// The JIT-compiled code ends up at a different address on every run, hence the breakpoints
+/// remember their locations and they are resolved again once the module is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// `*0x7f3a1c400010` or `0x7f3a1c400010`
+    Address(u64),
+    /// `$rip`, the current value of a register
+    Register(String),
+    /// `func`, `func+0x10` or `func+16`, where `func` is anything that
+    /// [`crate::function_mapping::FunctionMapping::get_function`] accepts
+    Function { name: String, offset: u64 },
+}
+
+impl Location {
+    pub fn parse(s: &str) -> eyre::Result<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(eyre!("a location is required"));
+        }
+
+        if let Some(register) = s.strip_prefix('$') {
+            return Ok(Location::Register(register.into()));
+        }
+
+        if let Some(addr) = s.strip_prefix('*') {
+            return parse_number(addr.trim()).map(Location::Address);
+        }
+
+        if s.starts_with("0x") || s.starts_with("0X") {
+            return parse_number(s).map(Location::Address);
+        }
+
+        // the names can't end with an offset, so anything after the last `+` that parses as a
+        // number is the offset
+        if let Some((name, offset)) = s.rsplit_once('+')
+            && let Ok(offset) = parse_number(offset.trim())
+        {
+            return Ok(Location::Function {
+                name: name.trim().into(),
+                offset,
+            });
+        }
+
+        Ok(Location::Function {
+            name: s.into(),
+            offset: 0,
+        })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Address(addr) => write!(f, "{addr:#x}"),
+            Location::Register(register) => write!(f, "${register}"),
+            Location::Function { name, offset: 0 } => write!(f, "{name}"),
+            Location::Function { name, offset } => write!(f, "{name}+{offset:#x}"),
+        }
+    }
+}
+
+/// Parses a hexadecimal number with the `0x` prefix, or a decimal number without it.
+pub fn parse_number(s: &str) -> eyre::Result<u64> {
+    let result = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    result.map_err(|e| eyre!("invalid number `{s}`: {e}"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: BreakpointId,
+    pub location: Location,
+    /// Where the trap is, `None` while the breakpoint is pending, i.e. the module is not
+    /// loaded yet
+    pub addr: Option<u64>,
+    /// A disabled breakpoint keeps its trap, but the hits are not counted
+    pub enabled: bool,
+    /// The tracee only stops when this holds, it's evaluated on every hit
+    pub condition: Option<Expr>,
+    /// Logs a message instead of stopping, when it's set
+    pub log: Option<Logpoint>,
+    /// Decides whether to stop on the hits that would stop, when it's set
+    pub script: Option<Script>,
+    /// How many times the tracee reached it while its condition held
+    pub hits: u64,
+    /// The next hits that don't stop
+    pub ignore_count: u64,
+    /// It's deleted after stopping this many more times, `Some(1)` for a temporary one
+    pub remaining_stops: Option<u64>,
+}
+
+/// What a hit does once the condition holds, see [`Breakpoint::hit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    /// One of the ignored hits, the tracee continues
+    Ignored,
+    /// The breakpoint stops or logs, and it's deleted afterwards if it's `expired`
+    Counted { expired: bool },
+}
+
+impl Breakpoint {
+    pub fn is_temporary(&self) -> bool {
+        self.remaining_stops == Some(1)
+    }
+
+    /// Counts a hit whose condition holds, which uses up the ignored hits first and then the
+    /// remaining stops.
+    pub fn hit(&mut self) -> Hit {
+        self.hits += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            return Hit::Ignored;
+        }
+        let expired = self.remaining_stops.as_mut().is_some_and(|remaining| {
+            *remaining = remaining.saturating_sub(1);
+            *remaining == 0
+        });
+
+        Hit::Counted { expired }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.addr {
+            Some(addr) => write!(f, "{:<4}{addr:#018x}  ", self.id)?,
+            None => write!(f, "{:<4}{:<18}  ", self.id, "<pending>")?,
+        }
+        write!(f, "{:<6}  {}", self.hits, self.location)?;
+        if let Some(condition) = &self.condition {
+            write!(f, " if {condition}")?;
+        }
+        if let Some(log) = &self.log {
+            write!(f, " log {} ({})", log.format, log.rate_limit)?;
+        }
+        if let Some(script) = &self.script {
+            write!(f, " script {script}")?;
+        }
+        if self.ignore_count > 0 {
+            write!(f, " (ignores the next {} hits)", self.ignore_count)?;
+        }
+        match self.remaining_stops {
+            Some(1) => write!(f, " (temporary)")?,
+            Some(stops) => write!(f, " (deleted after {stops} stops)")?,
+            None => {}
+        }
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The breakpoints of the user, in the order they are created.
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    breakpoints: BTreeMap<BreakpointId, Breakpoint>,
+    next_id: u32,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, location: Location, addr: Option<u64>) -> &Breakpoint {
+        self.next_id += 1;
+        let id = BreakpointId(self.next_id);
+        self.breakpoints.insert(
+            id,
+            Breakpoint {
+                id,
+                location,
+                addr,
+                enabled: true,
+                condition: None,
+                log: None,
+                script: None,
+                hits: 0,
+                ignore_count: 0,
+                remaining_stops: None,
+            },
+        );
+        &self.breakpoints[&id]
+    }
+
+    pub fn remove(&mut self, id: BreakpointId) -> Option<Breakpoint> {
+        self.breakpoints.remove(&id)
+    }
+
+    pub fn get(&self, id: BreakpointId) -> Option<&Breakpoint> {
+        self.breakpoints.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: BreakpointId) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&id)
+    }
+
+    /// The breakpoint whose trap is at `addr`. When there are more than one, the oldest.
+    pub fn at(&self, addr: u64) -> Option<&Breakpoint> {
+        self.iter().find(|bp| bp.addr == Some(addr))
+    }
+
+    /// All the breakpoints whose trap is at `addr`, the oldest first.
+    pub fn all_at(&self, addr: u64) -> impl Iterator<Item = &Breakpoint> {
+        self.iter().filter(move |bp| bp.addr == Some(addr))
+    }
+
+    pub fn ids(&self) -> Vec<BreakpointId> {
+        self.breakpoints.keys().copied().collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Breakpoint> {
+        self.breakpoints.values_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(name: &str, offset: u64) -> Location {
+        Location::Function {
+            name: name.into(),
+            offset,
+        }
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let parse = |s| Location::parse(s).unwrap();
+        assert_eq!(parse("0x7f3a1c400010"), Location::Address(0x7f3a1c400010));
+        assert_eq!(parse("0X10"), Location::Address(0x10));
+        assert_eq!(parse("*0x7f3a1c400010"), Location::Address(0x7f3a1c400010));
+        assert_eq!(parse("* 4096"), Location::Address(4096));
+        assert_eq!(parse("  *4096  "), Location::Address(4096));
+        assert_eq!(parse("$rip"), Location::Register("rip".into()));
+    }
+
+    #[test]
+    fn parses_functions_with_offsets() {
+        let parse = |s| Location::parse(s).unwrap();
+        assert_eq!(parse("leaf"), function("leaf", 0));
+        assert_eq!(
+            parse("wasm_binary::leaf+0x10"),
+            function("wasm_binary::leaf", 0x10)
+        );
+        assert_eq!(
+            parse("wasm_binary::leaf + 16"),
+            function("wasm_binary::leaf", 16)
+        );
+        // not an offset, so it's part of the name
+        assert_eq!(parse("operator+"), function("operator+", 0));
+        assert_eq!(parse("a+b"), function("a+b", 0));
+        assert_eq!(parse("operator+ +4"), function("operator+", 4));
+        // a decimal number on its own is a name, the addresses need `*`
+        assert_eq!(parse("4096"), function("4096", 0));
+    }
+
+    #[test]
+    fn rejects_malformed_locations() {
+        let error = |s| format!("{:#}", Location::parse(s).unwrap_err());
+        assert_eq!(error(""), "a location is required");
+        assert_eq!(error("   "), "a location is required");
+        assert!(error("*").starts_with("invalid number ``"));
+        assert!(error("*main").starts_with("invalid number `main`"));
+        assert!(error("0xzz").starts_with("invalid number `0xzz`"));
+        assert!(error("*0x1_0000_0000_0000_0000").starts_with("invalid number"));
+        assert!(error("0x10000000000000000").starts_with("invalid number"));
+    }
+
+    #[test]
+    fn displays_locations_as_they_parse() {
+        for s in ["0x1000", "$rsp", "leaf", "wasm_binary::leaf+0x10"] {
+            assert_eq!(Location::parse(s).unwrap().to_string(), s);
+        }
+        assert_eq!(Location::parse("leaf+16").unwrap().to_string(), "leaf+0x10");
+    }
+
+    #[test]
+    fn numbers_the_breakpoints_in_order() {
+        let mut breakpoints = Breakpoints::default();
+        let first = breakpoints.add(function("leaf", 0), Some(0x1000)).id;
+        let second = breakpoints.add(Location::Address(0x1000), Some(0x1000)).id;
+        let third = breakpoints.add(function("main", 0), None).id;
+        assert_eq!(
+            [first, second, third],
+            [BreakpointId(1), BreakpointId(2), BreakpointId(3)]
+        );
+
+        assert_eq!(breakpoints.at(0x1000).unwrap().id, first);
+        let here: Vec<_> = breakpoints.all_at(0x1000).map(|bp| bp.id).collect();
+        assert_eq!(here, [first, second]);
+        assert!(breakpoints.at(0x2000).is_none());
+
+        assert_eq!(breakpoints.remove(first).unwrap().id, first);
+        assert!(breakpoints.remove(first).is_none());
+        assert_eq!(breakpoints.at(0x1000).unwrap().id, second);
+        // the numbers are not reused
+        let fourth = breakpoints.add(function("leaf", 0), None).id;
+        assert_eq!(fourth, BreakpointId(4));
+        assert_eq!(breakpoints.ids(), [second, third, fourth]);
+    }
+
+    #[test]
+    fn counts_the_hits() {
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints.add(function("leaf", 0), Some(0x1000)).id;
+        let bp = breakpoints.get_mut(id).unwrap();
+
+        // a plain breakpoint stops every time and never expires
+        for hits in 1..=3 {
+            assert_eq!(bp.hit(), Hit::Counted { expired: false });
+            assert_eq!(bp.hits, hits);
+        }
+
+        // `ignore 2`
+        bp.ignore_count = 2;
+        assert_eq!(bp.hit(), Hit::Ignored);
+        assert_eq!(bp.hit(), Hit::Ignored);
+        assert_eq!(bp.ignore_count, 0);
+        assert_eq!(bp.hit(), Hit::Counted { expired: false });
+        assert_eq!(bp.hits, 6);
+    }
+
+    #[test]
+    fn expires_after_the_remaining_stops() {
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints.add(function("leaf", 0), Some(0x1000)).id;
+        let bp = breakpoints.get_mut(id).unwrap();
+
+        // `until 3` with `ignore 1`, the ignored hits don't count as stops
+        bp.remaining_stops = Some(3);
+        bp.ignore_count = 1;
+        assert_eq!(bp.hit(), Hit::Ignored);
+        assert_eq!(bp.remaining_stops, Some(3));
+        assert_eq!(bp.hit(), Hit::Counted { expired: false });
+        assert_eq!(bp.hit(), Hit::Counted { expired: false });
+        assert!(bp.is_temporary());
+        assert_eq!(bp.hit(), Hit::Counted { expired: true });
+        assert_eq!(bp.remaining_stops, Some(0));
+        assert_eq!(bp.hits, 4);
+
+        // `tbreak`
+        let id = breakpoints.add(function("main", 0), Some(0x2000)).id;
+        let bp = breakpoints.get_mut(id).unwrap();
+        bp.remaining_stops = Some(1);
+        assert!(bp.is_temporary());
+        assert_eq!(bp.hit(), Hit::Counted { expired: true });
+    }
+
+    #[test]
+    fn shows_the_bookkeeping() {
+        let mut breakpoints = Breakpoints::default();
+        let id = breakpoints
+            .add(function("leaf", 0), Some(0x7f0000001000))
+            .id;
+        let bp = breakpoints.get_mut(id).unwrap();
+        bp.hit();
+        assert_eq!(bp.to_string(), "1   0x00007f0000001000  1       leaf");
+
+        bp.ignore_count = 2;
+        bp.remaining_stops = Some(3);
+        bp.enabled = false;
+        assert_eq!(
+            bp.to_string(),
+            "1   0x00007f0000001000  1       leaf (ignores the next 2 hits) \
+             (deleted after 3 stops) (disabled)"
+        );
+
+        let id = breakpoints.add(function("main", 4), None).id;
+        let bp = breakpoints.get_mut(id).unwrap();
+        bp.remaining_stops = Some(1);
+        assert_eq!(
+            bp.to_string(),
+            "2   <pending>           0       main+0x4 (temporary)"
+        );
+    }
+}
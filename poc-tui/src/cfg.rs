@@ -0,0 +1,352 @@
+//! Recovers the control flow graph of a JIT-compiled function from its machine code.
+//!
+//! The code is decoded by following the jumps from the entry, rather than linearly, because
+//! cranelift puts data in the code: the jump tables of `br_table` right after the indirect
+//! `jmp`, and the constants at the end of the function.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use capstone::{
+    RegId,
+    arch::{
+        ArchOperand,
+        x86::{X86OperandType, X86Reg},
+    },
+};
+use color_eyre::eyre::{self, eyre};
+
+use crate::debugger_ctx::disassembler;
+
+/// How the control leaves a basic block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exit {
+    /// Falls through or jumps to the block at the address
+    Goto(u64),
+    /// A conditional jump or a jump table at `insn`, the last instruction of the block, which
+    /// goes to one of `targets`
+    Branch { insn: u64, targets: Vec<u64> },
+    /// Returns, traps, tail-calls another function or jumps to an unknown address
+    Leave,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: u64,
+    /// Exclusive
+    pub end: u64,
+    pub exit: Exit,
+}
+
+impl BasicBlock {
+    /// The blocks that the control can go to from this one.
+    pub fn successors(&self) -> &[u64] {
+        match &self.exit {
+            Exit::Goto(target) => std::slice::from_ref(target),
+            Exit::Branch { targets, .. } => targets,
+            Exit::Leave => &[],
+        }
+    }
+}
+
+/// What an instruction does to the control flow.
+enum Flow {
+    Next,
+    Jump(u64),
+    /// Either to the address or to the next instruction
+    Branch(u64),
+    Table(Vec<u64>),
+    Stop,
+}
+
+/// The basic blocks of the function whose machine code is `code` at `addr`, in the order of
+/// their addresses. The blocks that are only reachable through an unknown indirect jump are
+/// not found.
+pub fn recover(code: &[u8], addr: u64) -> eyre::Result<Vec<BasicBlock>> {
+    let cs = disassembler()?;
+    let range = addr..addr + code.len() as u64;
+
+    // the decoded instructions: their end and their flow
+    let mut insns: BTreeMap<u64, (u64, Flow)> = BTreeMap::new();
+    let mut leaders = BTreeSet::from([addr]);
+    let mut worklist = vec![addr];
+    while let Some(mut pc) = worklist.pop() {
+        // the target of the last `lea reg, [rip + disp]`, which loads a jump table
+        let mut table = None;
+        while range.contains(&pc) && !insns.contains_key(&pc) {
+            let offset = (pc - addr) as usize;
+            let decoded = cs
+                .disasm_count(&code[offset..], pc, 1)
+                .map_err(|e| eyre!("failed to disassemble at {pc:#x}: {e}"))?;
+            // data that is jumped over, or a jump into the middle of an instruction
+            let Some(insn) = decoded.first() else {
+                break;
+            };
+            let next = pc + insn.len() as u64;
+            let detail = cs
+                .insn_detail(insn)
+                .map_err(|e| eyre!("failed to decode at {pc:#x}: {e}"))?;
+            let operands = detail.arch_detail().operands();
+            let immediate = operands.iter().find_map(|op| match op {
+                ArchOperand::X86Operand(op) => match op.op_type {
+                    X86OperandType::Imm(imm) => Some(imm as u64),
+                    _ => None,
+                },
+                _ => None,
+            });
+
+            let mnemonic = insn.mnemonic().unwrap_or_default();
+            let flow = match mnemonic {
+                "jmp" => match immediate {
+                    Some(target) => Flow::Jump(target),
+                    None if table == Some(next) => Flow::Table(jump_table(code, addr, next)),
+                    None => Flow::Stop,
+                },
+                _ if mnemonic.starts_with('j') => match immediate {
+                    Some(target) => Flow::Branch(target),
+                    None => Flow::Stop,
+                },
+                _ if mnemonic.starts_with("ret") => Flow::Stop,
+                "ud2" | "int3" | "hlt" => Flow::Stop,
+                "lea" => {
+                    table = operands.iter().find_map(|op| match op {
+                        ArchOperand::X86Operand(op) => match op.op_type {
+                            X86OperandType::Mem(mem)
+                                if mem.base() == RegId(X86Reg::X86_REG_RIP as _) =>
+                            {
+                                Some(next.wrapping_add_signed(mem.disp()))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    });
+                    Flow::Next
+                }
+                _ => Flow::Next,
+            };
+
+            let mut targets = match &flow {
+                Flow::Next => {
+                    insns.insert(pc, (next, flow));
+                    pc = next;
+                    continue;
+                }
+                Flow::Jump(target) => vec![*target],
+                Flow::Branch(target) => vec![*target, next],
+                Flow::Table(targets) => targets.clone(),
+                Flow::Stop => Vec::new(),
+            };
+            targets.retain(|target| range.contains(target));
+            leaders.extend(&targets);
+            worklist.extend(targets);
+            insns.insert(pc, (next, flow));
+            break;
+        }
+    }
+
+    let mut blocks = Vec::new();
+    let mut insns = insns.into_iter().peekable();
+    while let Some((start, (mut end, mut flow))) = insns.next() {
+        let mut last = start;
+        // a block ends at a jump, before a leader or before a gap of undecoded bytes
+        while matches!(flow, Flow::Next) {
+            match insns.peek() {
+                Some((next, _)) if *next == end && !leaders.contains(next) => {
+                    (last, (end, flow)) = insns.next().expect("peeked");
+                }
+                Some((next, _)) if *next == end => {
+                    flow = Flow::Jump(end);
+                    break;
+                }
+                _ => {
+                    flow = Flow::Stop;
+                    break;
+                }
+            }
+        }
+
+        let exit = match flow {
+            Flow::Jump(target) if range.contains(&target) => Exit::Goto(target),
+            Flow::Branch(target) if range.contains(&target) => Exit::Branch {
+                insn: last,
+                targets: vec![target, end],
+            },
+            // the other side is a tail call or a trap island
+            Flow::Branch(_) => Exit::Goto(end),
+            Flow::Table(targets) if !targets.is_empty() => Exit::Branch {
+                insn: last,
+                targets,
+            },
+            _ => Exit::Leave,
+        };
+        blocks.push(BasicBlock { start, end, exit });
+    }
+
+    Ok(blocks)
+}
+
+/// The entries of the jump table that cranelift emits for `br_table` at `table`: 32-bit
+/// offsets of the blocks from the start of the table. The table ends where an entry is not a
+/// block of the function, i.e. where the code after it starts.
+fn jump_table(code: &[u8], addr: u64, table: u64) -> Vec<u64> {
+    let end = addr + code.len() as u64;
+    let mut targets = Vec::new();
+    let mut entry = table;
+    while entry + 4 <= end {
+        let offset = (entry - addr) as usize;
+        let value = i32::from_le_bytes(code[offset..offset + 4].try_into().expect("4 bytes"));
+        let target = table.wrapping_add_signed(value.into());
+        entry += 4;
+        if target < entry || target >= end {
+            break;
+        }
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: u64 = 0x7f00_0000_1000;
+
+    fn block(start: u64, end: u64, exit: Exit) -> BasicBlock {
+        BasicBlock {
+            start: ADDR + start,
+            end: ADDR + end,
+            exit,
+        }
+    }
+
+    fn goto(target: u64) -> Exit {
+        Exit::Goto(ADDR + target)
+    }
+
+    fn branch(insn: u64, targets: &[u64]) -> Exit {
+        Exit::Branch {
+            insn: ADDR + insn,
+            targets: targets.iter().map(|target| ADDR + target).collect(),
+        }
+    }
+
+    #[test]
+    fn splits_at_a_conditional_branch() {
+        let code = [
+            0x85, 0xff, // 0x0: test edi, edi
+            0x74, 0x03, // 0x2: je 0x7
+            0x31, 0xc0, // 0x4: xor eax, eax
+            0xc3, // 0x6: ret
+            0xb8, 0x01, 0x00, 0x00, 0x00, // 0x7: mov eax, 1
+            0xc3, // 0xc: ret
+        ];
+        let blocks = recover(&code, ADDR).unwrap();
+        assert_eq!(
+            blocks,
+            [
+                block(0x0, 0x4, branch(0x2, &[0x7, 0x4])),
+                block(0x4, 0x7, Exit::Leave),
+                block(0x7, 0xd, Exit::Leave),
+            ]
+        );
+        assert_eq!(blocks[0].successors(), [ADDR + 0x7, ADDR + 0x4]);
+        assert!(blocks[1].successors().is_empty());
+    }
+
+    #[test]
+    fn falls_through_a_branch_out_of_the_function() {
+        let code = [
+            0x85, 0xff, // 0x0: test edi, edi
+            0x0f, 0x84, 0xf8, 0x00, 0x00, 0x00, // 0x2: je 0x100
+            0xc3, // 0x8: ret
+        ];
+        assert_eq!(
+            recover(&code, ADDR).unwrap(),
+            [block(0x0, 0x8, goto(0x8)), block(0x8, 0x9, Exit::Leave)]
+        );
+    }
+
+    #[test]
+    fn skips_the_data_that_is_jumped_over() {
+        let code = [
+            0x31, 0xc0, // 0x0: xor eax, eax
+            0xeb, 0x04, // 0x2: jmp 0x8
+            0xef, 0xbe, 0xad, 0xde, // 0x4: a constant
+            0xff, 0xc0, // 0x8: inc eax
+            0xc3, // 0xa: ret
+        ];
+        let blocks = recover(&code, ADDR).unwrap();
+        assert_eq!(
+            blocks,
+            [block(0x0, 0x4, goto(0x8)), block(0x8, 0xb, Exit::Leave)]
+        );
+        assert_eq!(blocks[0].successors(), [ADDR + 0x8]);
+    }
+
+    #[test]
+    fn follows_a_jump_table() {
+        let code = [
+            0x83, 0xff, 0x02, // 0x00: cmp edi, 2
+            0x73, 0x28, // 0x03: jae 0x2d
+            0x48, 0x8d, 0x0d, 0x09, 0x00, 0x00, 0x00, // 0x05: lea rcx, [rip + 9]
+            0x48, 0x63, 0x04, 0xb9, // 0x0c: movsxd rax, dword ptr [rcx + rdi*4]
+            0x48, 0x01, 0xc8, // 0x10: add rax, rcx
+            0xff, 0xe0, // 0x13: jmp rax
+            0x0c, 0x00, 0x00, 0x00, // 0x15: the table, 0x21
+            0x12, 0x00, 0x00, 0x00, // 0x19: 0x27
+            0x18, 0x00, 0x00, 0x00, // 0x1d: 0x2d
+            0xb8, 0x00, 0x00, 0x00, 0x00, // 0x21: mov eax, 0
+            0xc3, // 0x26: ret
+            0xb8, 0x01, 0x00, 0x00, 0x00, // 0x27: mov eax, 1
+            0xc3, // 0x2c: ret
+            0x31, 0xc0, // 0x2d: xor eax, eax
+            0xc3, // 0x2f: ret
+        ];
+        assert_eq!(
+            recover(&code, ADDR).unwrap(),
+            [
+                block(0x00, 0x05, branch(0x03, &[0x2d, 0x05])),
+                block(0x05, 0x15, branch(0x13, &[0x21, 0x27, 0x2d])),
+                block(0x21, 0x27, Exit::Leave),
+                block(0x27, 0x2d, Exit::Leave),
+                block(0x2d, 0x30, Exit::Leave),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_the_jump_table_until_the_code() {
+        // the entries point into the function, until the code after the table
+        let code = [
+            0x08, 0x00, 0x00, 0x00, // 0x0: 0x8
+            0x08, 0x00, 0x00, 0x00, // 0x4: 0x8 again
+            0xc3, 0x90, 0x90, 0x90, // 0x8: ret, which is not an entry
+        ];
+        assert_eq!(jump_table(&code, ADDR, ADDR), [ADDR + 0x8]);
+        // a table that runs into the end of the function
+        assert!(jump_table(&code[..6], ADDR, ADDR).is_empty());
+    }
+
+    #[test]
+    fn ignores_the_unreachable_trap_islands() {
+        let code = [
+            0x85, 0xff, // 0x0: test edi, edi
+            0x74, 0x03, // 0x2: je 0x7
+            0x89, 0xf8, // 0x4: mov eax, edi
+            0xc3, // 0x6: ret
+            0x0f, 0x0b, // 0x7: ud2, the trap of the branch
+            0x0f, 0x0b, // 0x9: ud2, which nothing jumps to
+            0xcc, 0xcc, // 0xb: padding
+        ];
+        assert_eq!(
+            recover(&code, ADDR).unwrap(),
+            [
+                block(0x0, 0x4, branch(0x2, &[0x7, 0x4])),
+                block(0x4, 0x7, Exit::Leave),
+                block(0x7, 0x9, Exit::Leave),
+            ]
+        );
+    }
+}
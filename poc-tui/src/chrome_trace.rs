@@ -0,0 +1,176 @@
+//! The `poc-tui export` subcommand, which converts a trace of `poc-tui record` to the Chrome
+//! Trace Event format.
+//!
+//! Every call is a complete (`X`) event on the thread that made it, the syscalls and the traps
+//! are instant (`i`) events. The timestamps are in microseconds since the recording started.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use serde_json::{Value, json};
+
+use crate::{
+    batch::next_value,
+    trace::{EventKind, Trace, signal_name},
+};
+
+const USAGE: &str = "\
+usage: poc-tui export [-o FILE] TRACE
+
+Converts TRACE, which `poc-tui record` writes, to the Chrome Trace Event format. The result opens
+in Perfetto (https://ui.perfetto.dev) or in chrome://tracing.
+
+options:
+    -o, --output FILE   where to write the JSON, the standard output by default";
+
+/// Entry point of the `export` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut path = None;
+    let mut output = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(PathBuf::from(next_value(&mut args, &arg, USAGE)?)),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+    let path = path.ok_or_else(|| eyre!("a trace file is required\n\n{USAGE}"))?;
+
+    let trace = Trace::load(&path)?;
+    let json = to_json(&trace);
+    match output {
+        Some(output) => {
+            let file = File::create(&output)
+                .wrap_err_with(|| format!("failed to create `{}`", output.display()))?;
+            write(BufWriter::new(file), &json)
+                .wrap_err_with(|| format!("failed to write `{}`", output.display()))?;
+            eprintln!(
+                "Exported {} events to `{}`",
+                json["traceEvents"].as_array().map_or(0, Vec::len),
+                output.display()
+            );
+        }
+        None => write(io::stdout().lock(), &json)?,
+    }
+
+    Ok(())
+}
+
+fn write(mut writer: impl Write, json: &Value) -> eyre::Result<()> {
+    serde_json::to_writer(&mut writer, json)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// The trace as a JSON object, with the events in `traceEvents`.
+pub fn to_json(trace: &Trace) -> Value {
+    let pid = trace.header.pid;
+    let end = trace
+        .events
+        .last()
+        .map_or(Duration::ZERO, |event| event.time);
+
+    let mut events = vec![json!({
+        "name": "process_name",
+        "ph": "M",
+        "pid": pid,
+        "args": { "name": trace.header.command },
+    })];
+
+    for call in trace.calls() {
+        let mut args = serde_json::Map::new();
+        for (i, arg) in call.args.iter().enumerate() {
+            args.insert(format!("arg{i}"), json!(format!("{arg:#x}")));
+        }
+        match (call.ret, call.end) {
+            (Some(ret), _) => args.insert("ret".into(), json!(format!("{ret:#x}"))),
+            (None, Some(_)) => args.insert("unwound".into(), json!(true)),
+            (None, None) => args.insert("unfinished".into(), json!(true)),
+        };
+        events.push(json!({
+            "name": trace.function_name(call.function),
+            "cat": "wasm",
+            "ph": "X",
+            "ts": micros(call.start),
+            "dur": micros(call.end.unwrap_or(end) - call.start),
+            "pid": pid,
+            "tid": call.tid,
+            "args": args,
+        }));
+    }
+
+    // the index of the pending syscall of every thread, which gets the return value
+    let mut syscalls = HashMap::new();
+    for event in &trace.events {
+        let ts = micros(event.time);
+        match &event.kind {
+            EventKind::Syscall { tid, nr, args } => {
+                syscalls.insert(*tid, events.len());
+                events.push(json!({
+                    "name": format!("syscall {nr}"),
+                    "cat": "syscall",
+                    "ph": "i",
+                    "s": "t",
+                    "ts": ts,
+                    "pid": pid,
+                    "tid": tid,
+                    "args": { "args": args.map(|arg| format!("{arg:#x}")) },
+                }));
+            }
+            EventKind::SyscallReturn { tid, ret, .. } => {
+                if let Some(index) = syscalls.remove(tid) {
+                    events[index]["args"]["ret"] = json!(ret);
+                }
+            }
+            EventKind::Trap { tid, signal, pc } => events.push(json!({
+                "name": signal_name(*signal),
+                "cat": "trap",
+                "ph": "i",
+                "s": "t",
+                "ts": ts,
+                "pid": pid,
+                "tid": tid,
+                "args": {
+                    "pc": format!("{pc:#x}"),
+                    "function": trace.function_at(*pc).map(|function| &function.name),
+                },
+            })),
+            EventKind::End { reason } => events.push(json!({
+                "name": "end",
+                "ph": "i",
+                "s": "p",
+                "ts": ts,
+                "pid": pid,
+                "args": { "reason": reason },
+            })),
+            EventKind::Enter { .. } | EventKind::Exit { .. } | EventKind::Snapshot { .. } => {}
+        }
+    }
+
+    json!({
+        "traceEvents": events,
+        "displayTimeUnit": "ns",
+        "otherData": {
+            "command": trace.header.command,
+            "start": trace.header.start,
+        },
+    })
+}
+
+/// The timestamps of the format are in microseconds, the fraction keeps the nanoseconds.
+fn micros(time: Duration) -> f64 {
+    time.as_nanos() as f64 / 1000.0
+}
@@ -0,0 +1,665 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{self, eyre};
+use nix::unistd::Pid;
+use regex::Regex;
+
+use crate::{
+    breakpoint::{Breakpoint, BreakpointId, Location},
+    debugger_ctx::StopReason,
+    expr::Expr,
+    function_mapping::FunctionMapping,
+    launch::LaunchSpec,
+    logpoint::LogFormat,
+    registers,
+    script::Script,
+};
+
+pub const HELP: &str = "\
+run, r [COMMAND LINE]      start the program, the last one when no command line is given
+attach PID                 attach to a running process
+break, b LOCATION [if EXPR]
+                           stop at LOCATION: FUNC[+OFFSET], *ADDR or $REG, when EXPR holds
+dprintf LOCATION FORMAT    log FORMAT at LOCATION and continue, e.g. `len={arg1} ptr={arg0:x}`
+ratelimit N RATE           log at most RATE messages per second at N, 0 for unlimited
+tbreak LOCATION [if EXPR]  like `break`, but the breakpoint is deleted after it stops once
+advance LOCATION           continue until LOCATION, with a temporary breakpoint
+ignore N COUNT             don't stop at the breakpoint N for its next COUNT hits
+expire N COUNT             delete the breakpoint N after it stops COUNT more times
+condition N [EXPR]         stop at the breakpoint N only when EXPR holds, always without it
+onhit N [FILE]             run the Rhai script in FILE on the hits of N, it stops if it returns
+                           true; detach the script without FILE
+enable N, disable N        count the hits of the breakpoint N, or let the tracee pass it
+script FILE                run the Rhai script in FILE, which can also `cont()` the tracee
+delete, d [N]              delete the breakpoint N, all of them when N is not given
+continue, c                resume the tracee
+interrupt                  stop the tracee
+stepi, si                  execute a single instruction
+finish, fin                run until the current function returns
+itrace [calls] [FILE]      trace every instruction until the current function returns, with
+                           the registers that change; step into the calls with `calls`
+backtrace, bt              show the call stack
+x[/NFU] LOCATION           examine N units of U (b, h, w, g) as F (x, d, u) at LOCATION
+print, p EXPR              evaluate EXPR, e.g. `arg1 == 40 && *(u32*)(arg0 + 4) != $rax`
+info functions [REGEX]     list the functions that match REGEX
+info breakpoints           list the breakpoints
+kill, k                    kill the tracee
+help                       show this";
+
+/// Names of the commands, for the completion.
+const COMMANDS: &[&str] = &[
+    "advance",
+    "attach",
+    "backtrace",
+    "break",
+    "condition",
+    "continue",
+    "delete",
+    "disable",
+    "dprintf",
+    "enable",
+    "expire",
+    "finish",
+    "help",
+    "ignore",
+    "info",
+    "interrupt",
+    "itrace",
+    "kill",
+    "onhit",
+    "print",
+    "ratelimit",
+    "run",
+    "script",
+    "stepi",
+    "tbreak",
+    "x",
+];
+
+const INFO_SUBCOMMANDS: &[&str] = &["breakpoints", "functions"];
+
+/// A line in the console pane.
+#[derive(Debug, Clone)]
+pub struct ConsoleLine {
+    pub kind: ConsoleLineKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleLineKind {
+    /// A command that the user entered
+    Input,
+    Output,
+    Error,
+}
+
+/// A command that is typed into the `:` prompt, in the spirit of gdb.
+#[derive(Debug, Clone)]
+pub enum ConsoleCommand {
+    Run(Option<LaunchSpec>),
+    Attach(Pid),
+    Break {
+        location: Location,
+        condition: Option<Expr>,
+        temporary: bool,
+    },
+    /// Continue until the location is reached
+    Advance(Location),
+    Ignore {
+        id: BreakpointId,
+        count: u64,
+    },
+    Expire {
+        id: BreakpointId,
+        stops: u64,
+    },
+    Condition {
+        id: BreakpointId,
+        condition: Option<Expr>,
+    },
+    Dprintf {
+        location: Location,
+        format: LogFormat,
+    },
+    RateLimit {
+        id: BreakpointId,
+        per_second: u32,
+    },
+    /// Attach a script to a breakpoint, or detach it with `None`
+    OnHit {
+        id: BreakpointId,
+        script: Option<Script>,
+    },
+    Enable {
+        id: BreakpointId,
+        enabled: bool,
+    },
+    /// Run a session script
+    Script(Script),
+    Delete(Option<BreakpointId>),
+    Continue,
+    Interrupt,
+    StepInstruction,
+    Finish,
+    /// Trace the instructions until the current function returns, into `output` if it's given
+    InstructionTrace {
+        calls: bool,
+        output: Option<PathBuf>,
+    },
+    Backtrace,
+    Examine {
+        format: ExamineFormat,
+        location: Location,
+    },
+    Print(Expr),
+    InfoFunctions(Option<Regex>),
+    InfoBreakpoints,
+    Kill,
+    Help,
+}
+
+impl ConsoleCommand {
+    pub fn parse(line: &str) -> eyre::Result<Self> {
+        let line = line.trim();
+        let (name, rest) = line
+            .split_once(char::is_whitespace)
+            .map(|(name, rest)| (name, rest.trim()))
+            .unwrap_or((line, ""));
+
+        // `x/16xw` has no space before the format
+        if let Some(format) = name.strip_prefix("x/") {
+            return Ok(ConsoleCommand::Examine {
+                format: ExamineFormat::parse(format)?,
+                location: Location::parse(rest)?,
+            });
+        }
+
+        let command = match name {
+            "run" | "r" if rest.is_empty() => ConsoleCommand::Run(None),
+            "run" | "r" => ConsoleCommand::Run(Some(LaunchSpec::parse(rest)?)),
+            "attach" => {
+                let pid = rest
+                    .parse()
+                    .map_err(|_| eyre!("usage: attach PID, `{rest}` is not a pid"))?;
+                ConsoleCommand::Attach(Pid::from_raw(pid))
+            }
+            "break" | "b" | "tbreak" | "tb" => {
+                // the locations don't contain spaces
+                let (location, condition) = match rest.split_once(" if ") {
+                    Some((location, condition)) => (location, Some(Expr::parse(condition)?)),
+                    None => (rest, None),
+                };
+                ConsoleCommand::Break {
+                    location: Location::parse(location)?,
+                    condition,
+                    temporary: name.starts_with('t'),
+                }
+            }
+            "advance" => ConsoleCommand::Advance(Location::parse(rest)?),
+            "ignore" | "expire" => {
+                let usage = || eyre!("usage: {name} N COUNT");
+                let (id, count) = rest.split_once(char::is_whitespace).ok_or_else(usage)?;
+                let id = id.parse()?;
+                let count = count.trim().parse().map_err(|_| usage())?;
+                if name == "ignore" {
+                    ConsoleCommand::Ignore { id, count }
+                } else {
+                    ConsoleCommand::Expire { id, stops: count }
+                }
+            }
+            "condition" => {
+                let (id, condition) = rest
+                    .split_once(char::is_whitespace)
+                    .map(|(id, condition)| (id, condition.trim()))
+                    .unwrap_or((rest, ""));
+                if id.is_empty() {
+                    return Err(eyre!("usage: condition N [EXPR]"));
+                }
+                ConsoleCommand::Condition {
+                    id: id.parse()?,
+                    condition: (!condition.is_empty())
+                        .then(|| Expr::parse(condition))
+                        .transpose()?,
+                }
+            }
+            "dprintf" => {
+                let (location, format) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| eyre!("usage: dprintf LOCATION FORMAT"))?;
+                let format = format.trim();
+                // the quotes are optional
+                let format = format
+                    .strip_prefix('"')
+                    .and_then(|format| format.strip_suffix('"'))
+                    .unwrap_or(format);
+                ConsoleCommand::Dprintf {
+                    location: Location::parse(location)?,
+                    format: LogFormat::parse(format)?,
+                }
+            }
+            "ratelimit" => {
+                let usage = || eyre!("usage: ratelimit N RATE");
+                let (id, rate) = rest.split_once(char::is_whitespace).ok_or_else(usage)?;
+                ConsoleCommand::RateLimit {
+                    id: id.parse()?,
+                    per_second: rate.trim().parse().map_err(|_| usage())?,
+                }
+            }
+            "onhit" => {
+                let (id, path) = rest
+                    .split_once(char::is_whitespace)
+                    .map(|(id, path)| (id, path.trim()))
+                    .unwrap_or((rest, ""));
+                if id.is_empty() {
+                    return Err(eyre!("usage: onhit N [FILE]"));
+                }
+                ConsoleCommand::OnHit {
+                    id: id.parse()?,
+                    script: (!path.is_empty())
+                        .then(|| Script::load(Path::new(path)))
+                        .transpose()?,
+                }
+            }
+            "enable" | "disable" => ConsoleCommand::Enable {
+                id: rest.parse()?,
+                enabled: name == "enable",
+            },
+            "script" if rest.is_empty() => return Err(eyre!("usage: script FILE")),
+            "script" => ConsoleCommand::Script(Script::load(Path::new(rest))?),
+            "print" | "p" => ConsoleCommand::Print(Expr::parse(rest)?),
+            "delete" | "d" if rest.is_empty() => ConsoleCommand::Delete(None),
+            "delete" | "d" => ConsoleCommand::Delete(Some(rest.parse()?)),
+            "continue" | "c" => ConsoleCommand::Continue,
+            "interrupt" => ConsoleCommand::Interrupt,
+            "stepi" | "si" => ConsoleCommand::StepInstruction,
+            "finish" | "fin" => ConsoleCommand::Finish,
+            "itrace" => {
+                let (calls, path) = match rest.split_once(char::is_whitespace) {
+                    Some(("calls", path)) => (true, path.trim()),
+                    None if rest == "calls" => (true, ""),
+                    _ => (false, rest),
+                };
+                ConsoleCommand::InstructionTrace {
+                    calls,
+                    output: (!path.is_empty()).then(|| PathBuf::from(path)),
+                }
+            }
+            "backtrace" | "bt" | "where" => ConsoleCommand::Backtrace,
+            "x" => ConsoleCommand::Examine {
+                format: ExamineFormat::default(),
+                location: Location::parse(rest)?,
+            },
+            "info" | "i" => {
+                let (subcommand, arg) = rest
+                    .split_once(char::is_whitespace)
+                    .map(|(subcommand, arg)| (subcommand, arg.trim()))
+                    .unwrap_or((rest, ""));
+                match subcommand {
+                    "functions" | "func" => ConsoleCommand::InfoFunctions(
+                        (!arg.is_empty())
+                            .then(|| Regex::new(arg))
+                            .transpose()
+                            .map_err(|e| eyre!("invalid regex: {e}"))?,
+                    ),
+                    "breakpoints" | "break" | "b" => ConsoleCommand::InfoBreakpoints,
+                    _ => return Err(eyre!("usage: info (functions [REGEX] | breakpoints)")),
+                }
+            }
+            "kill" | "k" => ConsoleCommand::Kill,
+            "help" | "h" => ConsoleCommand::Help,
+            _ => return Err(eyre!("unknown command `{name}`, try `help`")),
+        };
+
+        Ok(command)
+    }
+}
+
+/// The most bytes that `x/NFU` shows at once.
+pub const MAX_EXAMINE_LEN: usize = 1 << 16;
+
+/// How to show the memory in `x/NFU`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExamineFormat {
+    /// How many units to show
+    pub count: usize,
+    pub radix: Radix,
+    /// The size of a unit in bytes
+    pub unit: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Hex,
+    Signed,
+    Unsigned,
+}
+
+impl Default for ExamineFormat {
+    fn default() -> Self {
+        ExamineFormat {
+            count: 1,
+            radix: Radix::Hex,
+            unit: 4,
+        }
+    }
+}
+
+impl ExamineFormat {
+    /// Parses the `NFU` in `x/NFU`, each of which is optional. At most [`MAX_EXAMINE_LEN`]
+    /// bytes are shown.
+    pub fn parse(s: &str) -> eyre::Result<Self> {
+        let mut format = ExamineFormat::default();
+        let too_long = || eyre!("`x/{s}` shows more than {MAX_EXAMINE_LEN} bytes");
+
+        let digits = s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 {
+            format.count = s[..digits].parse().map_err(|_| too_long())?;
+        }
+
+        for c in s[digits..].chars() {
+            match c {
+                'x' => format.radix = Radix::Hex,
+                'd' => format.radix = Radix::Signed,
+                'u' => format.radix = Radix::Unsigned,
+                'b' => format.unit = 1,
+                'h' => format.unit = 2,
+                'w' => format.unit = 4,
+                'g' => format.unit = 8,
+                _ => return Err(eyre!("unknown format letter `{c}` in `x/{s}`")),
+            }
+        }
+        format
+            .count
+            .checked_mul(format.unit)
+            .filter(|&len| len <= MAX_EXAMINE_LEN)
+            .ok_or_else(too_long)?;
+
+        Ok(format)
+    }
+
+    /// The number of bytes to read, at most [`MAX_EXAMINE_LEN`] when parsed.
+    pub fn byte_len(&self) -> usize {
+        self.count * self.unit
+    }
+
+    /// Formats `bytes` that are read from `addr` like gdb does, `symbolize` labels the lines.
+    pub fn format(
+        &self,
+        addr: u64,
+        bytes: &[u8],
+        symbolize: impl Fn(u64) -> Option<String>,
+    ) -> Vec<String> {
+        let per_line = match self.unit {
+            8 => 2,
+            4 => 4,
+            _ => 8,
+        };
+
+        bytes
+            .chunks(self.unit * per_line)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let line_addr = addr + (i * self.unit * per_line) as u64;
+                let mut line = format!("{line_addr:#x}");
+                if let Some(symbol) = symbolize(line_addr) {
+                    let _ = write!(line, " <{symbol}>");
+                }
+                line.push(':');
+
+                for unit in chunk.chunks(self.unit) {
+                    let mut le = [0u8; 8];
+                    le[..unit.len()].copy_from_slice(unit);
+                    let value = u64::from_le_bytes(le);
+
+                    let _ = match self.radix {
+                        Radix::Hex => {
+                            write!(line, "  {value:#0width$x}", width = 2 + 2 * self.unit)
+                        }
+                        Radix::Unsigned => write!(line, "  {value}"),
+                        Radix::Signed => {
+                            // sign-extend the unit
+                            let shift = 64 - 8 * self.unit as u32;
+                            write!(line, "  {}", ((value << shift) as i64) >> shift)
+                        }
+                    };
+                }
+
+                line
+            })
+            .collect()
+    }
+}
+
+/// Result of completing the command line with [`complete`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Completion {
+    /// The command line with the completed word
+    pub line: String,
+    /// Everything that matches when the word can't be completed unambiguously
+    pub candidates: Vec<String>,
+}
+
+/// Completes the last word of `line`: a command name, an `info` subcommand, a register or a
+/// function name.
+pub fn complete(line: &str, mapping: Option<&FunctionMapping>) -> Completion {
+    let word_start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let (head, word) = line.split_at(word_start);
+    let words: Vec<&str> = head.split_whitespace().collect();
+
+    let mut candidates: Vec<String> = match words.as_slice() {
+        [_, ..] if word.starts_with('$') => registers::NAMES
+            .iter()
+            .map(|name| format!("${name}"))
+            .filter(|name| name.starts_with(word))
+            .collect(),
+        [] => COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .map(|command| command.to_string())
+            .collect(),
+        ["info" | "i"] => INFO_SUBCOMMANDS
+            .iter()
+            .filter(|subcommand| subcommand.starts_with(word))
+            .map(|subcommand| subcommand.to_string())
+            .collect(),
+        [command, ..] if is_location_command(command) => mapping
+            .into_iter()
+            .flatten()
+            .flat_map(|meta| [meta.name.as_str(), meta.symbol.demangled.as_str()])
+            .filter(|name| name.starts_with(word))
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    };
+    candidates.sort();
+    candidates.dedup();
+
+    match candidates.as_slice() {
+        [] => Completion {
+            line: line.into(),
+            candidates: Vec::new(),
+        },
+        [only] => Completion {
+            line: format!("{head}{only} "),
+            candidates: Vec::new(),
+        },
+        [first, rest @ ..] => {
+            let mut common = rest.iter().fold(first.len(), |len, candidate| {
+                first
+                    .bytes()
+                    .zip(candidate.bytes())
+                    .take(len)
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            });
+            while !first.is_char_boundary(common) {
+                common -= 1;
+            }
+            Completion {
+                line: format!("{head}{}", &first[..common]),
+                candidates,
+            }
+        }
+    }
+}
+
+fn is_location_command(command: &str) -> bool {
+    matches!(
+        command,
+        "break" | "b" | "tbreak" | "tb" | "advance" | "dprintf" | "x"
+    ) || command.starts_with("x/")
+}
+
+/// ` in func+0xoff` if `addr` falls into a known function.
+pub fn in_function(addr: u64, mapping: Option<&FunctionMapping>) -> String {
+    mapping
+        .and_then(|mapping| mapping.symbolize(addr))
+        .map(|symbol| format!(" in {symbol}"))
+        .unwrap_or_default()
+}
+
+/// Reports where the tracee stopped, e.g. `Stopped (breakpoint 1) at 0x7f.. in func+0x0`.
+pub fn format_stop(
+    reason: &StopReason,
+    pc: Option<u64>,
+    mapping: Option<&FunctionMapping>,
+) -> String {
+    match pc {
+        Some(pc) => format!("Stopped ({reason}) at {pc:#x}{}", in_function(pc, mapping)),
+        None => format!("Stopped ({reason})"),
+    }
+}
+
+/// An evaluated [`Expr`], in decimal and in hexadecimal, e.g. `-1 (0xffffffffffffffff)`.
+pub fn format_value(value: i64) -> String {
+    format!("{value} ({value:#x})")
+}
+
+/// Reports a breakpoint that is just added.
+pub fn format_new_breakpoint(bp: &Breakpoint, mapping: Option<&FunctionMapping>) -> String {
+    let kind = if bp.log.is_some() {
+        "Logpoint"
+    } else if bp.is_temporary() {
+        "Temporary breakpoint"
+    } else {
+        "Breakpoint"
+    };
+    match bp.addr {
+        Some(addr) => format!(
+            "{kind} {} at {addr:#x}{}",
+            bp.id,
+            in_function(addr, mapping)
+        ),
+        None => format!(
+            "{kind} {} at `{}` is pending until the module is loaded",
+            bp.id, bp.location
+        ),
+    }
+}
+
+pub fn format_breakpoints<'a>(
+    breakpoints: impl IntoIterator<Item = &'a Breakpoint>,
+) -> Vec<String> {
+    let mut lines: Vec<String> = breakpoints.into_iter().map(|bp| bp.to_string()).collect();
+    if lines.is_empty() {
+        lines.push("No breakpoints.".into());
+    } else {
+        lines.insert(
+            0,
+            format!("{:<4}{:<18}  {:<6}  Location", "Num", "Address", "Hits"),
+        );
+    }
+
+    lines
+}
+
+/// Notes the breakpoints in the `len` bytes at `addr`, since `x` shows the code under their
+/// traps.
+pub fn format_breakpoints_in<'a>(
+    addr: u64,
+    len: usize,
+    breakpoints: impl IntoIterator<Item = &'a Breakpoint>,
+) -> Vec<String> {
+    let range = addr..addr + len as u64;
+    breakpoints
+        .into_iter()
+        .filter_map(|bp| {
+            let bp_addr = bp.addr.filter(|bp_addr| range.contains(bp_addr))?;
+            Some(format!("(breakpoint {} at {bp_addr:#x})", bp.id))
+        })
+        .collect()
+}
+
+/// The frames of [`crate::debugger_ctx::DebuggerCtx::backtrace`], innermost first.
+pub fn format_backtrace(frames: &[u64], mapping: Option<&FunctionMapping>) -> Vec<String> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, pc)| format!("#{i:<3} {pc:#018x}{}", in_function(*pc, mapping)))
+        .collect()
+}
+
+/// The functions whose name matches `regex`, sorted by their addresses.
+pub fn format_functions(mapping: &FunctionMapping, regex: Option<&Regex>) -> Vec<String> {
+    let mut lines: Vec<String> = mapping
+        .iter_by_addr()
+        .filter(|meta| {
+            regex.is_none_or(|regex| {
+                regex.is_match(&meta.symbol.demangled) || regex.is_match(&meta.name)
+            })
+        })
+        .map(|meta| format!("{:#018x} {:>8x}  {}", meta.addr, meta.size, meta.symbol))
+        .collect();
+    if lines.is_empty() {
+        lines.push("No matching functions.".into());
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_examine_formats() {
+        assert_eq!(ExamineFormat::parse("").unwrap(), ExamineFormat::default());
+        let format = ExamineFormat::parse("16xg").unwrap();
+        assert_eq!(
+            format,
+            ExamineFormat {
+                count: 16,
+                radix: Radix::Hex,
+                unit: 8,
+            }
+        );
+        assert_eq!(format.byte_len(), 128);
+        assert_eq!(ExamineFormat::parse("ub").unwrap().byte_len(), 1);
+        assert!(ExamineFormat::parse("4q").is_err());
+    }
+
+    #[test]
+    fn bounds_the_examined_bytes() {
+        let max = MAX_EXAMINE_LEN / 8;
+        assert_eq!(
+            ExamineFormat::parse(&format!("{max}g")).unwrap().byte_len(),
+            MAX_EXAMINE_LEN
+        );
+
+        let error = |s: &str| format!("{:#}", ExamineFormat::parse(s).unwrap_err());
+        assert_eq!(
+            error(&format!("{}g", max + 1)),
+            format!("`x/{}g` shows more than {MAX_EXAMINE_LEN} bytes", max + 1)
+        );
+        // overflows the multiplication
+        assert_eq!(
+            error("18446744073709551615g"),
+            format!("`x/18446744073709551615g` shows more than {MAX_EXAMINE_LEN} bytes")
+        );
+        // overflows the count itself
+        assert!(error("99999999999999999999999b").contains("shows more than"));
+    }
+}
@@ -0,0 +1,695 @@
+//! The `poc-tui coverage` subcommand, which reports the wasm functions that the tracee calls.
+//!
+//! Every function gets a temporary breakpoint at its entry, or at every basic block with
+//! `--blocks`. The breakpoints are gone after their first hit, hence the overhead drops as the
+//! coverage grows. The conditional jumps and the jump tables keep a breakpoint until every way
+//! out of them is taken: the tracee steps over them to see where they go.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt::Write as _,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use regex::Regex;
+
+use crate::{
+    address_map::AddressMap,
+    batch::{self, TraceeArgs, next_value},
+    breakpoint::{BreakpointId, Location},
+    cfg::{self, BasicBlock, Exit},
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    function_mapping::{FunctionId, FunctionMapping},
+    wasm_dwarf::{SourceLocation, WasmModule},
+    wasm_symbol::SymbolKind,
+};
+
+const USAGE: &str = "\
+usage: poc-tui coverage [OPTIONS] (--attach PID | -- PROGRAM [ARGS ...])
+
+Reports which wasm functions are called until the tracee exits, or until Ctrl-C. An attached
+tracee keeps running afterwards.
+
+options:
+    --attach PID        attach to the process PID
+    --perfmap PATH      use the perf map at PATH
+    --functions REGEX   only consider the functions whose name matches REGEX
+    --wasm PATH         the module that is run, to show where the functions are in the source
+                        when it has DWARF
+    --lcov FILE         write the coverage to FILE in the lcov format, requires --wasm, with
+                        the lines and the branches too with --blocks
+    --blocks            cover the basic blocks of the functions and the edges between them
+    --wasm-offsets      list the basic blocks with the offsets of the wasm instructions that
+                        they start with, implies --blocks
+    --uncovered         only list the functions that are not covered entirely
+    --duration SECS     stop after SECS seconds";
+
+/// Entry point of the `coverage` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut tracee = TraceeArgs::with_duration(USAGE);
+    let mut filter = None;
+    let mut wasm = None;
+    let mut lcov = None;
+    let mut uncovered_only = false;
+    let mut blocks = false;
+    let mut wasm_offsets = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if tracee.parse(&arg, &mut args)? => {}
+            "--functions" => {
+                let regex = next_value(&mut args, "--functions", USAGE)?;
+                filter = Some(Regex::new(&regex).map_err(|e| eyre!("invalid regex: {e}"))?);
+            }
+            "--wasm" => wasm = Some(PathBuf::from(next_value(&mut args, "--wasm", USAGE)?)),
+            "--lcov" => lcov = Some(PathBuf::from(next_value(&mut args, "--lcov", USAGE)?)),
+            "--blocks" => blocks = true,
+            "--wasm-offsets" => (blocks, wasm_offsets) = (true, true),
+            "--uncovered" => uncovered_only = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+
+    // the module is checked before running the tracee, it's quicker to fix
+    let module = wasm.as_deref().map(WasmModule::load).transpose()?;
+    if lcov.is_some() {
+        match &module {
+            None => return Err(eyre!("--lcov requires --wasm\n\n{USAGE}")),
+            Some(module) if !module.has_dwarf => {
+                return Err(eyre!(
+                    "the module has no DWARF, which lcov needs for the source files"
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+
+    let mut ctx = DebuggerCtx::new();
+    let duration = tracee.duration;
+    tracee.start(&mut ctx)?;
+    batch::stop_on_signals(duration)?;
+
+    let mapping = ctx.parse_perfmap(BIN_NAME)?;
+    let mut coverage = Coverage {
+        ctx,
+        mapping: mapping.clone(),
+        pending: HashMap::new(),
+        hits: BTreeMap::new(),
+        blocks: BTreeMap::new(),
+        covered: HashSet::new(),
+        branches: HashMap::new(),
+        taken: HashMap::new(),
+        address_maps: Vec::new(),
+    };
+    for meta in mapping.iter_by_addr() {
+        // the trampolines are not in the source
+        if meta.symbol.kind != SymbolKind::WasmFunction {
+            continue;
+        }
+        if let Some(filter) = &filter
+            && !filter.is_match(&meta.name)
+            && !filter.is_match(&meta.symbol.demangled)
+        {
+            continue;
+        }
+
+        coverage.hits.insert(meta.id, false);
+        if !blocks {
+            coverage.add_block(meta.id, meta.addr)?;
+            continue;
+        }
+
+        let code = coverage.ctx.read_memory(meta.addr, meta.size as usize)?;
+        let function_blocks = cfg::recover(&code, meta.addr)
+            .wrap_err_with(|| format!("failed to recover the blocks of `{}`", meta.symbol))?;
+        for block in &function_blocks {
+            coverage.add_block(meta.id, block.start)?;
+            if let Exit::Branch { insn, targets } = &block.exit {
+                let id = coverage.ctx.add_breakpoint(Location::Address(*insn))?.id;
+                coverage.branches.insert(*insn, (id, targets.clone()));
+                coverage.taken.insert(*insn, BTreeSet::new());
+            }
+        }
+        coverage.blocks.insert(meta.id, function_blocks);
+
+        // the lines of the blocks are found from their wasm offsets
+        if (wasm_offsets || lcov.is_some())
+            && !coverage
+                .address_maps
+                .iter()
+                .any(|map| map.contains(meta.addr))
+        {
+            let map = AddressMap::read(&coverage.ctx, meta.addr)
+                .wrap_err("failed to read the address map of wasmtime")?;
+            coverage.address_maps.push(map);
+        }
+    }
+    if coverage.hits.is_empty() {
+        return Err(eyre!("no function to cover"));
+    }
+    match blocks {
+        false => eprintln!("Covering {} functions", coverage.hits.len()),
+        true => eprintln!(
+            "Covering {} blocks of {} functions",
+            coverage.pending.len(),
+            coverage.hits.len()
+        ),
+    }
+
+    let result = coverage.run();
+    eprintln!("{}", result.as_deref().unwrap_or("stopped by an error"));
+
+    match blocks {
+        false => print!("{}", coverage.report(module.as_ref(), uncovered_only)),
+        true => print!(
+            "{}",
+            coverage.block_report(module.as_ref(), uncovered_only, wasm_offsets)
+        ),
+    }
+    if let (Some(path), Some(module)) = (&lcov, &module) {
+        fs::write(path, coverage.lcov(module))
+            .wrap_err_with(|| format!("failed to write `{}`", path.display()))?;
+        eprintln!("Wrote the lcov tracefile to `{}`", path.display());
+    }
+
+    // dropping the ctx kills the spawned tracee or detaches from the attached one
+    result.map(drop)
+}
+
+struct Coverage {
+    ctx: DebuggerCtx,
+    mapping: Arc<FunctionMapping>,
+    /// The blocks that are not hit yet, with their breakpoints and functions. Only the entry
+    /// blocks without `--blocks`.
+    pending: HashMap<u64, (BreakpointId, FunctionId)>,
+    /// Whether every function is called, in the order of the perf map
+    hits: BTreeMap<FunctionId, bool>,
+    /// The basic blocks of every function, with `--blocks`
+    blocks: BTreeMap<FunctionId, Vec<BasicBlock>>,
+    /// The blocks that are hit
+    covered: HashSet<u64>,
+    /// The breakpoints and the targets of the branches that are not taken every way yet, by
+    /// the address of the branch
+    branches: HashMap<u64, (BreakpointId, Vec<u64>)>,
+    /// The targets that every branch is taken to
+    taken: HashMap<u64, BTreeSet<u64>>,
+    /// The address maps of the compiled images, with `--wasm-offsets` or `--lcov`
+    address_maps: Vec<AddressMap>,
+}
+
+impl Coverage {
+    /// Resumes the tracee until it exits, every function is called, or the session is stopped.
+    /// Returns why it's over.
+    fn run(&mut self) -> eyre::Result<String> {
+        loop {
+            if self.pending.is_empty() && self.branches.is_empty() {
+                return Ok("everything is covered".into());
+            }
+            if batch::stop_requested() {
+                return Ok("stopped by the user".into());
+            }
+            self.ctx.resume()?;
+
+            let mut state = batch::wait(&mut self.ctx)?;
+            while let Some(TraceeState::Stopped(StopReason::Breakpoint(_))) = state {
+                state = self.visit()?;
+            }
+            let reason = match state {
+                None => return Ok("stopped by the user".into()),
+                Some(TraceeState::Exited(reason)) => {
+                    return Ok(format!("the tracee exited ({reason})"));
+                }
+                Some(TraceeState::Stopped(reason)) => reason,
+                Some(TraceeState::NotStarted | TraceeState::Running) => continue,
+            };
+            batch::handle_interrupt(&mut self.ctx, &reason);
+        }
+    }
+
+    /// Adds a temporary breakpoint at the block at `addr` of `function`.
+    fn add_block(&mut self, function: FunctionId, addr: u64) -> eyre::Result<()> {
+        let id = self.ctx.add_breakpoint(Location::Address(addr))?.id;
+        self.ctx.set_remaining_stops(id, Some(1))?;
+        self.pending.insert(addr, (id, function));
+        Ok(())
+    }
+
+    /// Records the blocks and the branches that the tracee goes through from a breakpoint,
+    /// stepping over the branches. Returns the stop that it can't handle, `Some(Running)`
+    /// once it's done.
+    fn visit(&mut self) -> eyre::Result<Option<TraceeState>> {
+        let mut addr = self.ctx.pc()?;
+        loop {
+            if let Some((id, function)) = self.pending.remove(&addr) {
+                // the breakpoint is deleted already if it's the one that stopped the tracee,
+                // but the ones that are reached by stepping are not hit
+                if self.ctx.breakpoints.get(id).is_some() {
+                    self.ctx.delete_breakpoint(id)?;
+                }
+                self.covered.insert(addr);
+                if self
+                    .mapping
+                    .function(function)
+                    .is_some_and(|f| f.addr == addr)
+                {
+                    self.hits.insert(function, true);
+                }
+            }
+
+            let Some((id, targets)) = self.branches.get(&addr) else {
+                return Ok(Some(TraceeState::Running));
+            };
+            let (id, targets) = (*id, targets.clone());
+            self.ctx.step_instruction()?;
+            match batch::wait(&mut self.ctx)? {
+                Some(TraceeState::Stopped(StopReason::Step)) => {}
+                other => return Ok(other),
+            }
+
+            let target = self.ctx.pc()?;
+            let taken = self.taken.get_mut(&addr).expect("a branch");
+            taken.insert(target);
+            if targets.iter().all(|target| taken.contains(target)) {
+                self.branches.remove(&addr);
+                self.ctx.delete_breakpoint(id)?;
+            }
+            addr = target;
+        }
+    }
+
+    /// Lists the functions in the order of their addresses, with their source locations when
+    /// `module` tells them.
+    fn report(&self, module: Option<&WasmModule>, uncovered_only: bool) -> String {
+        let covered = self.hits.values().filter(|&&hit| hit).count();
+        let mut report = format!(
+            "Covered {covered} of {} functions ({:.1}%)\n\n",
+            self.hits.len(),
+            100.0 * covered as f64 / self.hits.len() as f64
+        );
+
+        for meta in self.mapping.iter_by_addr() {
+            let Some(&hit) = self.hits.get(&meta.id) else {
+                continue;
+            };
+            if hit && uncovered_only {
+                continue;
+            }
+            let source = self
+                .source(module, meta.id)
+                .map(|source| format!("  {source}"))
+                .unwrap_or_default();
+            let _ = writeln!(
+                report,
+                "  {}  {}{source}",
+                if hit { "hit" } else { "---" },
+                meta.symbol.demangled
+            );
+        }
+
+        report
+    }
+
+    /// Lists the functions like [`Self::report`], with how many of their blocks and edges are
+    /// covered. With `wasm_offsets`, every block is listed too.
+    fn block_report(
+        &self,
+        module: Option<&WasmModule>,
+        uncovered_only: bool,
+        wasm_offsets: bool,
+    ) -> String {
+        // an edge out of a branch is covered once the branch is taken that way, the other
+        // edges once their block is
+        let edges = |block: &BasicBlock| match &block.exit {
+            Exit::Goto(_) => (usize::from(self.covered.contains(&block.start)), 1),
+            Exit::Branch { insn, targets } => {
+                let taken = self.taken.get(insn);
+                let covered = targets
+                    .iter()
+                    .filter(|target| taken.is_some_and(|taken| taken.contains(target)))
+                    .count();
+                (covered, targets.len())
+            }
+            Exit::Leave => (0, 0),
+        };
+        let percent = |covered: usize, total: usize| match total {
+            0 => 100.0,
+            total => 100.0 * covered as f64 / total as f64,
+        };
+
+        let mut lines = String::new();
+        let (mut blocks_covered, mut blocks_total) = (0, 0);
+        let (mut edges_covered, mut edges_total) = (0, 0);
+        for meta in self.mapping.iter_by_addr() {
+            let Some(blocks) = self.blocks.get(&meta.id) else {
+                continue;
+            };
+            let covered = blocks
+                .iter()
+                .filter(|block| self.covered.contains(&block.start))
+                .count();
+            let (edges_hit, edges_all) = blocks
+                .iter()
+                .map(edges)
+                .fold((0, 0), |(a, b), (c, d)| (a + c, b + d));
+            blocks_covered += covered;
+            blocks_total += blocks.len();
+            edges_covered += edges_hit;
+            edges_total += edges_all;
+            if uncovered_only && covered == blocks.len() && edges_hit == edges_all {
+                continue;
+            }
+
+            let source = self
+                .source(module, meta.id)
+                .map(|source| format!("  {source}"))
+                .unwrap_or_default();
+            let _ = writeln!(
+                lines,
+                "  {:>9}  {:>9}  {}{source}",
+                format!("{covered}/{}", blocks.len()),
+                format!("{edges_hit}/{edges_all}"),
+                meta.symbol.demangled
+            );
+            if !wasm_offsets {
+                continue;
+            }
+            for block in blocks {
+                let hit = self.covered.contains(&block.start);
+                if hit && uncovered_only {
+                    continue;
+                }
+                let wasm = self
+                    .address_maps
+                    .iter()
+                    .find_map(|map| map.wasm_offset(block.start))
+                    .map(|offset| format!("  wasm {offset:#x}"))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    lines,
+                    "      {}  {:#x}  +{:#x}{wasm}",
+                    if hit { "hit" } else { "---" },
+                    block.start,
+                    block.start - meta.addr
+                );
+            }
+        }
+
+        format!(
+            "Covered {blocks_covered} of {blocks_total} blocks ({:.1}%) and {edges_covered} of \
+             {edges_total} edges ({:.1}%) in {} functions\n\n     blocks      edges\n{lines}",
+            percent(blocks_covered, blocks_total),
+            percent(edges_covered, edges_total),
+            self.blocks.len()
+        )
+    }
+
+    /// The coverage as an lcov tracefile, with the function records of every source file, and
+    /// the line and the branch records of the blocks whose lines the DWARF tells. The
+    /// functions without a source location are left out.
+    fn lcov(&self, module: &WasmModule) -> String {
+        #[derive(Default)]
+        struct Record<'a> {
+            /// The line, the name and whether the function is called
+            functions: Vec<(u64, &'a str, bool)>,
+            /// Whether any block that starts on the line is hit
+            lines: BTreeMap<u64, bool>,
+            /// The line, the block and the branch numbers, and whether the branch is taken, if
+            /// the block of the branch is hit
+            branches: Vec<(u64, usize, usize, Option<bool>)>,
+        }
+
+        let mut files: BTreeMap<&str, Record> = BTreeMap::new();
+        for (&id, &hit) in &self.hits {
+            let Some(meta) = self.mapping.function(id) else {
+                continue;
+            };
+            if let Some(source) = self.source(Some(module), id) {
+                let record = files.entry(&source.file).or_default();
+                record
+                    .functions
+                    .push((source.line, &meta.symbol.demangled, hit));
+            }
+
+            for block in self.blocks.get(&id).into_iter().flatten() {
+                let hit = self.covered.contains(&block.start);
+                if let Some(source) = self.line_of(module, block.start) {
+                    let line = files
+                        .entry(&source.file)
+                        .or_default()
+                        .lines
+                        .entry(source.line)
+                        .or_default();
+                    *line |= hit;
+                }
+
+                let Exit::Branch { insn, targets } = &block.exit else {
+                    continue;
+                };
+                let Some(source) = self.line_of(module, *insn) else {
+                    continue;
+                };
+                let record = files.entry(&source.file).or_default();
+                let number = record.branches.len();
+                let taken = self.taken.get(insn);
+                for (branch, target) in targets.iter().enumerate() {
+                    let taken = hit.then(|| taken.is_some_and(|taken| taken.contains(target)));
+                    record.branches.push((source.line, number, branch, taken));
+                }
+            }
+        }
+
+        let mut lcov = String::new();
+        for (file, mut record) in files {
+            record.functions.sort();
+            record.branches.sort();
+            let _ = writeln!(lcov, "TN:\nSF:{file}");
+            for (line, name, _) in &record.functions {
+                let _ = writeln!(lcov, "FN:{line},{name}");
+            }
+            for (_, name, hit) in &record.functions {
+                let _ = writeln!(lcov, "FNDA:{},{name}", u8::from(*hit));
+            }
+            let _ = writeln!(
+                lcov,
+                "FNF:{}\nFNH:{}",
+                record.functions.len(),
+                record.functions.iter().filter(|(_, _, hit)| *hit).count()
+            );
+            if !record.branches.is_empty() {
+                for (line, block, branch, taken) in &record.branches {
+                    let taken = taken.map_or("-".into(), |taken| u8::from(taken).to_string());
+                    let _ = writeln!(lcov, "BRDA:{line},{block},{branch},{taken}");
+                }
+                let _ = writeln!(
+                    lcov,
+                    "BRF:{}\nBRH:{}",
+                    record.branches.len(),
+                    record
+                        .branches
+                        .iter()
+                        .filter(|(.., taken)| *taken == Some(true))
+                        .count()
+                );
+            }
+            if !record.lines.is_empty() {
+                for (line, hit) in &record.lines {
+                    let _ = writeln!(lcov, "DA:{line},{}", u8::from(*hit));
+                }
+                let _ = writeln!(
+                    lcov,
+                    "LF:{}\nLH:{}",
+                    record.lines.len(),
+                    record.lines.values().filter(|&&hit| hit).count()
+                );
+            }
+            let _ = writeln!(lcov, "end_of_record");
+        }
+
+        lcov
+    }
+
+    /// The source line of the code at `addr`, through the wasm instruction that it's compiled
+    /// from.
+    fn line_of<'a>(&self, module: &'a WasmModule, addr: u64) -> Option<&'a SourceLocation> {
+        let offset = self
+            .address_maps
+            .iter()
+            .find_map(|map| map.wasm_offset(addr))?;
+        module.line_of(offset)
+    }
+
+    fn source<'a>(
+        &self,
+        module: Option<&'a WasmModule>,
+        id: FunctionId,
+    ) -> Option<&'a SourceLocation> {
+        let meta = self.mapping.function(id)?;
+        module?.function_of(&meta.symbol)?.source.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_dwarf::tests::module_with_dwarf;
+
+    /// `main` is called and takes the first way of its branch, `leaf` is not called.
+    fn coverage(blocks: bool) -> Coverage {
+        let mapping = FunctionMapping::parse(
+            "wasm_binary",
+            "\
+0x1000 16 wasm[0]::function[1]::_ZN11wasm_binary4main17h0123456789abcdefE
+0x1010 16 wasm[0]::function[2]::leaf
+",
+        )
+        .unwrap();
+        let id = |addr| mapping.lookup(addr).unwrap().0.id;
+        let (main, leaf) = (id(0x1000), id(0x1010));
+        let mut coverage = Coverage {
+            ctx: DebuggerCtx::new(),
+            mapping: Arc::new(mapping),
+            pending: HashMap::new(),
+            hits: BTreeMap::from([(main, true), (leaf, false)]),
+            blocks: BTreeMap::new(),
+            covered: HashSet::new(),
+            branches: HashMap::new(),
+            taken: HashMap::new(),
+            address_maps: Vec::new(),
+        };
+        if !blocks {
+            return coverage;
+        }
+
+        let block = |start, end, exit| BasicBlock { start, end, exit };
+        let branch = |insn, targets: &[u64]| Exit::Branch {
+            insn,
+            targets: targets.to_vec(),
+        };
+        coverage.blocks = BTreeMap::from([
+            (
+                main,
+                vec![
+                    block(0x1000, 0x1008, branch(0x1006, &[0x1008, 0x100c])),
+                    block(0x1008, 0x100c, Exit::Leave),
+                    block(0x100c, 0x1010, Exit::Leave),
+                ],
+            ),
+            (
+                leaf,
+                vec![
+                    block(0x1010, 0x1014, branch(0x1012, &[0x1014, 0x1016])),
+                    block(0x1014, 0x1016, Exit::Leave),
+                    block(0x1016, 0x1020, Exit::Leave),
+                ],
+            ),
+        ]);
+        coverage.covered = HashSet::from([0x1000, 0x1008]);
+        coverage.taken = HashMap::from([
+            (0x1006, BTreeSet::from([0x1008])),
+            (0x1012, BTreeSet::new()),
+        ]);
+
+        // the code at 0xc is not compiled from an instruction
+        let code_start = module_with_dwarf().1;
+        let entries = [
+            (0x0, code_start + 2),
+            (0x6, code_start + 3),
+            (0x8, code_start + 5),
+            (0xc, u32::MAX),
+            (0x10, code_start + 8),
+            (0x14, code_start + 9),
+        ];
+        let mut addrmap = (entries.len() as u32).to_le_bytes().to_vec();
+        for (code, _) in entries {
+            addrmap.extend_from_slice(&u32::to_le_bytes(code));
+        }
+        for (_, wasm) in entries {
+            addrmap.extend_from_slice(&u32::to_le_bytes(wasm));
+        }
+        coverage.address_maps = vec![AddressMap::parse(0x1000..0x1020, &addrmap).unwrap()];
+
+        coverage
+    }
+
+    #[test]
+    fn the_lcov_of_the_functions() {
+        let module = WasmModule::parse(&module_with_dwarf().0).unwrap();
+
+        assert_eq!(
+            coverage(false).lcov(&module),
+            "\
+TN:
+SF:/rustc/lib/leaf.rs
+FN:10,leaf
+FNDA:0,leaf
+FNF:1
+FNH:0
+end_of_record
+TN:
+SF:/work/src/main.rs
+FN:3,wasm_binary::main
+FNDA:1,wasm_binary::main
+FNF:1
+FNH:1
+end_of_record
+"
+        );
+    }
+
+    #[test]
+    fn the_lcov_of_the_lines_and_the_branches() {
+        let module = WasmModule::parse(&module_with_dwarf().0).unwrap();
+
+        assert_eq!(
+            coverage(true).lcov(&module),
+            "\
+TN:
+SF:/rustc/lib/leaf.rs
+FN:10,leaf
+FNDA:0,leaf
+FNF:1
+FNH:0
+BRDA:11,0,0,-
+BRDA:11,0,1,-
+BRF:2
+BRH:0
+DA:11,0
+DA:12,0
+LF:2
+LH:0
+end_of_record
+TN:
+SF:/work/src/main.rs
+FN:3,wasm_binary::main
+FNDA:1,wasm_binary::main
+FNF:1
+FNH:1
+BRDA:4,0,0,1
+BRDA:4,0,1,0
+BRF:2
+BRH:1
+DA:3,1
+DA:5,1
+LF:2
+LH:2
+end_of_record
+"
+        );
+    }
+
+    #[test]
+    fn the_functions_without_a_source_are_left_out() {
+        let module = WasmModule::default();
+
+        assert_eq!(coverage(true).lcov(&module), "");
+    }
+}
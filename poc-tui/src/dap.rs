@@ -0,0 +1,1597 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use color_eyre::eyre::{self, WrapErr, eyre};
+use nix::unistd::Pid;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{
+    breakpoint::{BreakpointId, Location, parse_number},
+    debugger::BIN_NAME,
+    debugger_ctx::{self, DebuggerCtx, ExitReason, StopReason, TraceeState},
+    expr::Expr,
+    launch::{LaunchSpec, OutputLine, OutputStream},
+    registers, wasm_abi,
+};
+
+const USAGE: &str = "\
+usage: poc-tui dap
+
+Serves the Debug Adapter Protocol over the stdin and the stdout, for the editors.
+
+The launch request takes `program`, `args`, `cwd`, `env`, `stopOnEntry` and `perfmap`, the attach
+request takes `pid` (or `processId`), `stopOnEntry` and `perfmap`. The memory references that
+start with `wasm:` are offsets in the linear memory, the rest are addresses.";
+
+/// How often a running tracee is checked for a state change.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The `variablesReference` of the registers of the innermost frame.
+const REGISTERS_REFERENCE: u64 = 1;
+/// The `variablesReference` of the wasm arguments of the innermost frame.
+const ARGUMENTS_REFERENCE: u64 = 2;
+
+/// How many wasm arguments are shown. The signatures of the functions are not known, hence
+/// the ones in the registers are.
+const SHOWN_ARGUMENTS: usize = 4;
+
+/// The prefix of the memory references into the linear memory, e.g. `wasm:0x1000`.
+const LINEAR_MEMORY_PREFIX: &str = "wasm:";
+
+/// The largest message that is accepted from the client. Requests are tiny, a bigger
+/// `Content-Length` is a broken stream rather than a request.
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+/// The most bytes that a `readMemory` or `disassemble` request reads, the client asks again for
+/// the rest.
+const MAX_READ_LEN: usize = 1 << 20;
+
+/// Entry point of the `dap` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    if let Some(arg) = args.into_iter().next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        return Err(eyre!("unknown argument `{arg}`\n\n{USAGE}"));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let requests = sender.clone();
+    thread::spawn(move || read_requests(io::stdin().lock(), requests));
+
+    DapServer::new(sender, receiver, Box::new(io::stdout())).serve()
+}
+
+/// What the main loop of [`DapServer`] waits for.
+#[derive(Debug)]
+enum Incoming {
+    Request(Request),
+    ProgramOutput(OutputLine),
+    /// The client closed the stdin, or it can't be read anymore
+    Closed,
+}
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    seq: u64,
+    command: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LaunchArguments {
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    stop_on_entry: bool,
+    perfmap: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AttachArguments {
+    #[serde(alias = "processId")]
+    pid: i32,
+    #[serde(default)]
+    stop_on_entry: bool,
+    perfmap: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FunctionBreakpointsArguments {
+    breakpoints: Vec<FunctionBreakpoint>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionBreakpoint {
+    name: String,
+    /// See [`Expr`]
+    condition: Option<String>,
+    /// `N` or `>=N`, the first hit that stops
+    hit_condition: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StackTraceArguments {
+    #[serde(default)]
+    start_frame: usize,
+    levels: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopesArguments {
+    frame_id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VariablesArguments {
+    variables_reference: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReadMemoryArguments {
+    memory_reference: String,
+    #[serde(default)]
+    offset: i64,
+    count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DisassembleArguments {
+    memory_reference: String,
+    #[serde(default)]
+    offset: i64,
+    #[serde(default)]
+    instruction_offset: i64,
+    instruction_count: usize,
+    #[serde(default)]
+    resolve_symbols: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DisconnectArguments {
+    terminate_debuggee: Option<bool>,
+}
+
+/// Reads the requests until the input is closed or fails. The malformed requests are skipped.
+fn read_requests(mut reader: impl BufRead, sender: mpsc::Sender<Incoming>) {
+    loop {
+        let incoming = match read_message(&mut reader) {
+            Ok(Some(body)) => match serde_json::from_slice(&body) {
+                Ok(request) => Incoming::Request(request),
+                Err(e) => {
+                    // there is no request to respond to
+                    eprintln!("warning: malformed request: {e}");
+                    continue;
+                }
+            },
+            Ok(None) => Incoming::Closed,
+            Err(e) => {
+                // the framing is lost, nothing that follows can be trusted
+                eprintln!("error: failed to read a request: {e}");
+                Incoming::Closed
+            }
+        };
+
+        let closed = matches!(incoming, Incoming::Closed);
+        if sender.send(incoming).is_err() || closed {
+            break;
+        }
+    }
+}
+
+/// Reads the body of a `Content-Length: N\r\n\r\n{...}` message. Returns `None` at the end of
+/// the input.
+fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            let len = value.trim().parse::<usize>().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("bad `{line}`: {e}"))
+            })?;
+            content_length = Some(len);
+        }
+    }
+
+    let len = content_length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a message without `Content-Length`",
+        )
+    })?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("a message of {len} bytes, the limit is {MAX_MESSAGE_LEN}"),
+        ));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(body))
+}
+
+/// A debug adapter for a single tracee, on top of [`DebuggerCtx`].
+pub struct DapServer {
+    ctx: DebuggerCtx,
+    messages: mpsc::Receiver<Incoming>,
+    /// Handed to the threads that forward the output of the tracee
+    sender: mpsc::Sender<Incoming>,
+    /// Where the messages to the client go, the stdout
+    output: Box<dyn Write>,
+    /// The sequence number of the last message we sent
+    seq: u64,
+    /// The breakpoints of the last `setFunctionBreakpoints`
+    function_breakpoints: Vec<BreakpointId>,
+    /// The tracee is held where it's launched or attached to, until `configurationDone`
+    at_entry: bool,
+    configured: bool,
+    stop_on_entry: bool,
+}
+
+impl DapServer {
+    fn new(
+        sender: mpsc::Sender<Incoming>,
+        messages: mpsc::Receiver<Incoming>,
+        output: Box<dyn Write>,
+    ) -> Self {
+        DapServer {
+            ctx: DebuggerCtx::new(),
+            messages,
+            sender,
+            output,
+            seq: 0,
+            function_breakpoints: Vec::new(),
+            at_entry: false,
+            configured: false,
+            stop_on_entry: false,
+        }
+    }
+
+    /// Handles the requests until the client disconnects.
+    fn serve(&mut self) -> eyre::Result<()> {
+        loop {
+            let incoming = if self.ctx.is_running() {
+                match self.messages.recv_timeout(POLL_INTERVAL) {
+                    Ok(incoming) => Some(incoming),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match self.messages.recv() {
+                    Ok(incoming) => Some(incoming),
+                    Err(_) => break,
+                }
+            };
+
+            match incoming {
+                Some(Incoming::Request(request)) if !self.handle(&request)? => break,
+                Some(Incoming::Request(_)) => {}
+                Some(Incoming::ProgramOutput(line)) => {
+                    let category = match line.stream {
+                        OutputStream::Stdout => "stdout",
+                        OutputStream::Stderr => "stderr",
+                    };
+                    self.event(
+                        "output",
+                        json!({ "category": category, "output": format!("{}\n", line.text) }),
+                    )?;
+                }
+                Some(Incoming::Closed) => break,
+                None => {}
+            }
+
+            if self.ctx.is_running()
+                && let Some(state) = self.ctx.poll(false)?
+            {
+                self.handle_state(state)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Responds to `request`, returns `false` when the session is over.
+    fn handle(&mut self, request: &Request) -> eyre::Result<bool> {
+        let result = self.execute(request);
+        let keep_going = request.command != "disconnect";
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "command": request.command,
+        });
+        match result {
+            Ok(body) => {
+                response["success"] = true.into();
+                response["body"] = body;
+            }
+            Err(e) => {
+                response["success"] = false.into();
+                response["message"] = format!("{e:#}").into();
+            }
+        }
+        self.send(response)?;
+
+        // the events that follow the response
+        match request.command.as_str() {
+            "initialize" => self.event("initialized", json!({}))?,
+            "configurationDone" => {
+                self.configured = true;
+                if self.at_entry {
+                    self.leave_entry()?;
+                }
+            }
+            "launch" | "attach" if self.ctx.is_alive() => {
+                let start_method = &request.command;
+                self.event(
+                    "process",
+                    json!({
+                        "name": self.ctx.pid.to_string(),
+                        "systemProcessId": self.ctx.pid.as_raw(),
+                        "startMethod": start_method,
+                    }),
+                )?;
+                if self.at_entry && self.configured {
+                    self.leave_entry()?;
+                }
+            }
+            "terminate" if !self.ctx.is_alive() => self.report_exit()?,
+            _ => {}
+        }
+
+        Ok(keep_going)
+    }
+
+    fn execute(&mut self, request: &Request) -> eyre::Result<Value> {
+        let arguments = request.arguments.clone();
+        let body = match request.command.as_str() {
+            "initialize" => json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsHitConditionalBreakpoints": true,
+                "supportsDisassembleRequest": true,
+                "supportsReadMemoryRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsTerminateRequest": true,
+            }),
+            "launch" => {
+                self.launch(parse_arguments(arguments)?)?;
+                json!({})
+            }
+            "attach" => {
+                self.attach(parse_arguments(arguments)?)?;
+                json!({})
+            }
+            "configurationDone" | "setExceptionBreakpoints" => json!({}),
+            "setBreakpoints" => {
+                // there is no source information, the breakpoints are set on functions
+                let count = arguments["breakpoints"].as_array().map_or(0, Vec::len);
+                let breakpoints = vec![
+                    json!({
+                        "verified": false,
+                        "message": "there are no sources, use the function breakpoints",
+                    });
+                    count
+                ];
+                json!({ "breakpoints": breakpoints })
+            }
+            "setFunctionBreakpoints" => {
+                let breakpoints = self.set_function_breakpoints(parse_arguments(arguments)?)?;
+                json!({ "breakpoints": breakpoints })
+            }
+            "threads" => {
+                let threads: Vec<Value> = if self.ctx.is_alive() {
+                    vec![json!({ "id": self.ctx.pid.as_raw(), "name": "main" })]
+                } else {
+                    Vec::new()
+                };
+                json!({ "threads": threads })
+            }
+            "stackTrace" => self.stack_trace(parse_arguments(arguments)?)?,
+            "scopes" => {
+                let ScopesArguments { frame_id } = parse_arguments(arguments)?;
+                // the registers of the outer frames are not known
+                let scopes = if frame_id == 0 {
+                    vec![
+                        json!({
+                            "name": "Arguments",
+                            "presentationHint": "arguments",
+                            "variablesReference": ARGUMENTS_REFERENCE,
+                            "expensive": false,
+                        }),
+                        json!({
+                            "name": "Registers",
+                            "presentationHint": "registers",
+                            "variablesReference": REGISTERS_REFERENCE,
+                            "expensive": false,
+                        }),
+                    ]
+                } else {
+                    Vec::new()
+                };
+                json!({ "scopes": scopes })
+            }
+            "variables" => {
+                let VariablesArguments {
+                    variables_reference,
+                } = parse_arguments(arguments)?;
+                json!({ "variables": self.variables(variables_reference)? })
+            }
+            "readMemory" => self.read_memory(parse_arguments(arguments)?)?,
+            "disassemble" => {
+                let instructions = self.disassemble(parse_arguments(arguments)?)?;
+                json!({ "instructions": instructions })
+            }
+            "continue" => {
+                self.ctx.resume()?;
+                json!({ "allThreadsContinued": true })
+            }
+            "next" => {
+                self.ctx.next_instruction()?;
+                json!({})
+            }
+            "stepIn" => {
+                self.ctx.step_instruction()?;
+                json!({})
+            }
+            "stepOut" => {
+                self.ctx.finish()?;
+                json!({})
+            }
+            "pause" => {
+                self.ctx.interrupt()?;
+                json!({})
+            }
+            "terminate" => {
+                self.ctx.kill()?;
+                json!({})
+            }
+            "disconnect" => {
+                let args: DisconnectArguments = parse_arguments(arguments).unwrap_or_default();
+                if self.ctx.is_alive() {
+                    if args.terminate_debuggee.unwrap_or(!self.ctx.is_attached()) {
+                        self.ctx.kill()?;
+                    } else {
+                        self.ctx.detach()?;
+                    }
+                }
+                json!({})
+            }
+            other => return Err(eyre!("`{other}` is not supported")),
+        };
+
+        Ok(body)
+    }
+
+    fn launch(&mut self, args: LaunchArguments) -> eyre::Result<()> {
+        let spec = LaunchSpec {
+            program: args.program,
+            args: args.args,
+            env: args.env.into_iter().collect(),
+            cwd: args.cwd,
+            // the stdout is where we talk to the client
+            capture_output: true,
+        };
+
+        self.ctx.perfmap_path = args.perfmap;
+        self.stop_on_entry = args.stop_on_entry;
+        self.ctx.spawn(&spec)?;
+
+        let (stdout, stderr) = self.ctx.take_output();
+        if let Some(stdout) = stdout {
+            self.forward_output(stdout, OutputStream::Stdout);
+        }
+        if let Some(stderr) = stderr {
+            self.forward_output(stderr, OutputStream::Stderr);
+        }
+
+        Ok(())
+    }
+
+    fn attach(&mut self, args: AttachArguments) -> eyre::Result<()> {
+        self.ctx.perfmap_path = args.perfmap;
+        self.stop_on_entry = args.stop_on_entry;
+        self.ctx.attach(Pid::from_raw(args.pid))?;
+
+        // the module is usually loaded by the time we attach
+        if let Err(e) = self.load_module() {
+            eprintln!("warning: {e:#}");
+        }
+        self.at_entry = true;
+
+        Ok(())
+    }
+
+    fn forward_output(&self, pipe: impl Read + Send + 'static, stream: OutputStream) {
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let text = String::from_utf8_lossy(&line)
+                            .trim_end_matches(['\n', '\r'])
+                            .to_string();
+                        let output = Incoming::ProgramOutput(OutputLine { stream, text });
+                        if sender.send(output).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Discovers the functions of the module and arms the pending breakpoints.
+    fn load_module(&mut self) -> eyre::Result<()> {
+        self.ctx.parse_perfmap(BIN_NAME)?;
+
+        for error in self.ctx.arm_breakpoints() {
+            eprintln!("warning: {error:#}");
+        }
+        for id in self.function_breakpoints.clone() {
+            if let Some(addr) = self.ctx.breakpoints.get(id).and_then(|bp| bp.addr) {
+                self.event(
+                    "breakpoint",
+                    json!({
+                        "reason": "changed",
+                        "breakpoint": {
+                            "id": id.0,
+                            "verified": true,
+                            "instructionReference": format!("{addr:#x}"),
+                        },
+                    }),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resumes the tracee that is held at its entry, or reports the stop if the client asked
+    /// for it.
+    fn leave_entry(&mut self) -> eyre::Result<()> {
+        self.at_entry = false;
+
+        if self.stop_on_entry {
+            self.stopped("entry", None)
+        } else {
+            self.ctx.resume()
+        }
+    }
+
+    fn handle_state(&mut self, state: TraceeState) -> eyre::Result<()> {
+        match state {
+            TraceeState::Stopped(StopReason::ModuleLoaded) => {
+                self.load_module()?;
+                self.at_entry = true;
+                if self.configured {
+                    self.leave_entry()?;
+                }
+            }
+            TraceeState::Stopped(StopReason::Breakpoint(id)) => {
+                let reason = if self.function_breakpoints.contains(&id) {
+                    "function breakpoint"
+                } else {
+                    "breakpoint"
+                };
+                self.stopped(reason, Some(id))?;
+            }
+            TraceeState::Stopped(
+                ref reason @ (StopReason::ConditionError { id, ref error }
+                | StopReason::ScriptError { id, ref error }),
+            ) => {
+                let what = match reason {
+                    StopReason::ConditionError { .. } => "condition",
+                    _ => "script",
+                };
+                self.event(
+                    "stopped",
+                    json!({
+                        "reason": "breakpoint",
+                        "description": format!("the {what} of the breakpoint {id} failed"),
+                        "text": error,
+                        "hitBreakpointIds": [id.0],
+                        "threadId": self.ctx.pid.as_raw(),
+                        "allThreadsStopped": true,
+                    }),
+                )?;
+            }
+            // the adapter doesn't watch the returns or trace the syscalls
+            TraceeState::Stopped(
+                StopReason::Step
+                | StopReason::Finished
+                | StopReason::Returned { .. }
+                | StopReason::Syscall { .. }
+                | StopReason::SyscallReturn { .. },
+            ) => self.stopped("step", None)?,
+            TraceeState::Stopped(StopReason::Interrupted) => self.stopped("pause", None)?,
+            TraceeState::Stopped(StopReason::Signal(signal)) => {
+                self.event(
+                    "stopped",
+                    json!({
+                        "reason": "exception",
+                        "description": format!("received {}", signal.as_str()),
+                        "threadId": self.ctx.pid.as_raw(),
+                        "allThreadsStopped": true,
+                    }),
+                )?;
+            }
+            TraceeState::Exited(_) => self.report_exit()?,
+            TraceeState::NotStarted | TraceeState::Running => {}
+        }
+
+        Ok(())
+    }
+
+    fn stopped(&mut self, reason: &str, breakpoint: Option<BreakpointId>) -> eyre::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": self.ctx.pid.as_raw(),
+            "allThreadsStopped": true,
+        });
+        if let Some(id) = breakpoint {
+            body["hitBreakpointIds"] = json!([id.0]);
+        }
+
+        self.event("stopped", body)
+    }
+
+    fn report_exit(&mut self) -> eyre::Result<()> {
+        let exit_code = match &self.ctx.state {
+            TraceeState::Exited(ExitReason::Code(code)) => *code,
+            TraceeState::Exited(ExitReason::Signal(signal)) => 128 + *signal as i32,
+            _ => return Ok(()),
+        };
+
+        self.event("exited", json!({ "exitCode": exit_code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    /// Replaces the function breakpoints. A running tracee is interrupted for it.
+    fn set_function_breakpoints(
+        &mut self,
+        args: FunctionBreakpointsArguments,
+    ) -> eyre::Result<Vec<Value>> {
+        let interrupted = self.ctx.is_running() && self.ctx.function_mapping.is_some();
+        if interrupted {
+            self.ctx.interrupt()?;
+            while self.ctx.is_running() {
+                self.ctx.poll(true)?;
+            }
+            if self.ctx.state != TraceeState::Stopped(StopReason::Interrupted) {
+                // it stopped on its own before the interrupt
+                let state = self.ctx.state.clone();
+                self.handle_state(state)?;
+            }
+        }
+
+        for id in std::mem::take(&mut self.function_breakpoints) {
+            self.ctx.delete_breakpoint(id)?;
+        }
+
+        let mut breakpoints = Vec::new();
+        for FunctionBreakpoint {
+            name,
+            condition,
+            hit_condition,
+        } in args.breakpoints
+        {
+            let bp = Location::parse(&name).and_then(|location| {
+                // the conditions are checked before adding the breakpoint
+                let condition = condition.as_deref().map(Expr::parse).transpose()?;
+                let first_stop = hit_condition
+                    .as_deref()
+                    .map(parse_hit_condition)
+                    .transpose()?;
+                let bp = self.ctx.add_breakpoint(location)?;
+                self.ctx.set_condition(bp.id, condition)?;
+                self.ctx
+                    .set_ignore_count(bp.id, first_stop.map_or(0, |hit| hit - 1))
+            });
+            breakpoints.push(match bp {
+                Ok(bp) => {
+                    self.function_breakpoints.push(bp.id);
+                    match bp.addr {
+                        Some(addr) => json!({
+                            "id": bp.id.0,
+                            "verified": true,
+                            "instructionReference": format!("{addr:#x}"),
+                        }),
+                        None => json!({
+                            "id": bp.id.0,
+                            "verified": false,
+                            "message": "pending until the module is loaded",
+                        }),
+                    }
+                }
+                Err(e) => json!({ "verified": false, "message": format!("{e:#}") }),
+            });
+        }
+
+        if interrupted && self.ctx.state == TraceeState::Stopped(StopReason::Interrupted) {
+            self.ctx.resume()?;
+        }
+
+        Ok(breakpoints)
+    }
+
+    fn stack_trace(&self, args: StackTraceArguments) -> eyre::Result<Value> {
+        let frames = self.ctx.backtrace()?;
+        let mapping = self.ctx.function_mapping.as_deref();
+
+        let levels = args
+            .levels
+            .filter(|&levels| levels > 0)
+            .unwrap_or(frames.len());
+        let stack_frames: Vec<Value> = frames
+            .iter()
+            .enumerate()
+            .skip(args.start_frame)
+            .take(levels)
+            .map(|(i, &pc)| {
+                let symbol = mapping.and_then(|mapping| mapping.symbolize(pc));
+                let mut frame = json!({
+                    "id": i,
+                    "name": symbol.clone().unwrap_or_else(|| format!("{pc:#x}")),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{pc:#x}"),
+                });
+                if symbol.is_none() {
+                    // the runtime, not the wasm code
+                    frame["presentationHint"] = "subtle".into();
+                }
+                frame
+            })
+            .collect();
+
+        Ok(json!({ "stackFrames": stack_frames, "totalFrames": frames.len() }))
+    }
+
+    fn variables(&self, reference: u64) -> eyre::Result<Vec<Value>> {
+        let regs = self.ctx.registers()?;
+
+        let variables = match reference {
+            REGISTERS_REFERENCE => registers::NAMES
+                .iter()
+                .map(|name| {
+                    let value = registers::read(&regs, name).expect("a known register");
+                    let mut variable = json!({
+                        "name": name,
+                        "value": format!("{value:#x}"),
+                        "variablesReference": 0,
+                    });
+                    if matches!(*name, "rip" | "rsp" | "rbp") {
+                        variable["memoryReference"] = format!("{value:#x}").into();
+                    }
+                    variable
+                })
+                .collect(),
+            ARGUMENTS_REFERENCE => {
+                let mut variables = Vec::new();
+                for (name, register) in [
+                    ("vmctx", wasm_abi::VMCTX),
+                    ("caller_vmctx", wasm_abi::CALLER_VMCTX),
+                ] {
+                    let value = registers::read(&regs, register).expect("a known register");
+                    variables.push(json!({
+                        "name": name,
+                        "value": format!("{value:#x}"),
+                        "type": "pointer",
+                        "memoryReference": format!("{value:#x}"),
+                        "variablesReference": 0,
+                    }));
+                }
+                for index in 0..SHOWN_ARGUMENTS {
+                    let value = self.ctx.wasm_argument(index)?;
+                    // the pointers of the guest are offsets in the linear memory
+                    variables.push(json!({
+                        "name": format!("arg{index}"),
+                        "value": format!("{value} ({value:#x})"),
+                        "memoryReference": format!("{LINEAR_MEMORY_PREFIX}{:#x}", value as u32),
+                        "variablesReference": 0,
+                    }));
+                }
+                variables
+            }
+            other => return Err(eyre!("unknown variables reference {other}")),
+        };
+
+        Ok(variables)
+    }
+
+    /// Resolves a memory reference, see [`LINEAR_MEMORY_PREFIX`].
+    fn resolve_memory_reference(&self, reference: &str, offset: i64) -> eyre::Result<u64> {
+        let addr = match reference.strip_prefix(LINEAR_MEMORY_PREFIX) {
+            Some(wasm_addr) => self.ctx.linear_memory_base()? + parse_number(wasm_addr)?,
+            None => parse_number(reference)?,
+        };
+
+        Ok(addr.wrapping_add_signed(offset))
+    }
+
+    fn read_memory(&self, args: ReadMemoryArguments) -> eyre::Result<Value> {
+        let addr = self.resolve_memory_reference(&args.memory_reference, args.offset)?;
+
+        let count = args.count.min(MAX_READ_LEN);
+
+        // read until the first unreadable word, or the end of the address space
+        let word_len = size_of::<u64>();
+        let mut data = Vec::with_capacity(count);
+        while data.len() < count {
+            let Some(word_addr) = addr.checked_add(data.len() as u64) else {
+                break;
+            };
+            let len = word_len.min(count - data.len());
+            match self.ctx.read_memory(word_addr, len) {
+                Ok(bytes) => data.extend_from_slice(&bytes),
+                Err(_) => break,
+            }
+        }
+
+        Ok(json!({
+            "address": format!("{addr:#x}"),
+            "data": BASE64.encode(&data),
+            "unreadableBytes": count - data.len(),
+        }))
+    }
+
+    /// Disassembles `instructionCount` instructions around the reference. The instructions are
+    /// decoded from the start of the function that contains the reference, the ones that
+    /// can't be decoded are marked as invalid.
+    fn disassemble(&self, args: DisassembleArguments) -> eyre::Result<Vec<Value>> {
+        let reference = self.resolve_memory_reference(&args.memory_reference, args.offset)?;
+        let mapping = self.ctx.function_mapping.as_deref();
+        let start = mapping
+            .and_then(|mapping| mapping.lookup(reference))
+            .map_or(reference, |(meta, _)| meta.addr);
+
+        // an x86-64 instruction is at most 15 bytes
+        let instruction_count = args.instruction_count.min(MAX_READ_LEN) as i64;
+        let after = args
+            .instruction_offset
+            .saturating_add(instruction_count)
+            .max(0) as u64;
+        let len = (reference - start)
+            .saturating_add(after.saturating_mul(15))
+            .min(MAX_READ_LEN as u64) as usize;
+        let mut code = Vec::with_capacity(len);
+        while code.len() < len {
+            let Some(word_addr) = start.checked_add(code.len() as u64) else {
+                break;
+            };
+            match self.ctx.read_memory(word_addr, size_of::<u64>()) {
+                Ok(bytes) => code.extend_from_slice(&bytes),
+                Err(_) => break,
+            }
+        }
+
+        let cs = debugger_ctx::disassembler()?;
+        let insns = cs
+            .disasm_all(&code, start)
+            .map_err(|e| eyre!("failed to disassemble at {start:#x}: {e}"))?;
+        let index = insns
+            .iter()
+            .position(|insn| insn.address() >= reference)
+            .unwrap_or(insns.len()) as i64;
+
+        let first = index.saturating_add(args.instruction_offset);
+        let instructions = (first..first.saturating_add(instruction_count))
+            .map(
+                |i| match usize::try_from(i).ok().and_then(|i| insns.get(i)) {
+                    Some(insn) => {
+                        let addr = insn.address();
+                        let bytes: Vec<String> =
+                            insn.bytes().iter().map(|b| format!("{b:02x}")).collect();
+                        let mut instruction = json!({
+                            "address": format!("{addr:#x}"),
+                            "instructionBytes": bytes.join(" "),
+                            "instruction": format!(
+                                "{} {}",
+                                insn.mnemonic().unwrap_or_default(),
+                                insn.op_str().unwrap_or_default()
+                            ).trim_end(),
+                        });
+                        if args.resolve_symbols
+                            && let Some((meta, 0)) =
+                                mapping.and_then(|mapping| mapping.lookup(addr))
+                        {
+                            instruction["symbol"] = meta.symbol.to_string().into();
+                        }
+                        instruction
+                    }
+                    // unique addresses around the decoded ones
+                    None => {
+                        let addr = if i < 0 {
+                            start.wrapping_add_signed(i)
+                        } else {
+                            start
+                                .wrapping_add(code.len() as u64)
+                                .wrapping_add(i as u64 - insns.len() as u64)
+                        };
+                        json!({
+                            "address": format!("{addr:#x}"),
+                            "instruction": "??",
+                            "presentationHint": "invalid",
+                        })
+                    }
+                },
+            )
+            .collect();
+
+        Ok(instructions)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> eyre::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn send(&mut self, mut message: Value) -> eyre::Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+
+        let content = serde_json::to_string(&message)?;
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{content}",
+            content.len()
+        )?;
+        self.output.flush()?;
+
+        Ok(())
+    }
+}
+
+/// The hit that a breakpoint first stops at, from `N` or `>=N`.
+fn parse_hit_condition(hit_condition: &str) -> eyre::Result<u64> {
+    let hit = hit_condition.trim();
+    let hit = hit.strip_prefix(">=").unwrap_or(hit).trim();
+    match parse_number(hit) {
+        Ok(hit) if hit > 0 => Ok(hit),
+        _ => Err(eyre!(
+            "unsupported hit condition `{hit_condition}`, use `N` or `>=N`"
+        )),
+    }
+}
+
+fn parse_arguments<T: for<'de> Deserialize<'de>>(arguments: Value) -> eyre::Result<T> {
+    serde_json::from_value(arguments).wrap_err("invalid arguments")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        env, fs,
+        io::{Cursor, Seek, SeekFrom},
+        process::{Command, Stdio},
+        rc::Rc,
+    };
+
+    use super::*;
+
+    /// Collects what the server sends to the client.
+    #[derive(Clone, Default)]
+    struct Sent(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Sent {
+        /// The messages sent since the last call.
+        fn take(&self) -> Vec<Value> {
+            let mut reader = Cursor::new(std::mem::take(&mut *self.0.borrow_mut()));
+            let mut messages = Vec::new();
+            while let Some(body) = read_message(&mut reader).unwrap() {
+                messages.push(serde_json::from_slice(&body).unwrap());
+            }
+            messages
+        }
+    }
+
+    fn server() -> (DapServer, Sent) {
+        let (sender, receiver) = mpsc::channel();
+        let sent = Sent::default();
+        let server = DapServer::new(sender, receiver, Box::new(sent.clone()));
+        (server, sent)
+    }
+
+    /// Handles the request and returns the response followed by the events.
+    fn request(server: &mut DapServer, sent: &Sent, request: Value) -> Vec<Value> {
+        let request: Request = serde_json::from_value(request).unwrap();
+        server.handle(&request).unwrap();
+        sent.take()
+    }
+
+    /// A reader that always fails, like a closed terminal.
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("broken"))
+        }
+    }
+
+    /// Waits until `sleep` is blocked in its syscall, rather than still being loaded.
+    fn wait_until_asleep(pid: u32) {
+        for _ in 0..500 {
+            let stat = fs::read_to_string(format!("/proc/{pid}/stat")).unwrap();
+            if stat.contains("(sleep) S") {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("`sleep` didn't fall asleep");
+    }
+
+    fn frame(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    #[test]
+    fn reads_framed_messages() {
+        let mut reader = Cursor::new(format!(
+            "{}Content-Length: 3\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n[1]",
+            frame("{}")
+        ));
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"{}");
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), b"[1]");
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_broken_framing() {
+        let oversized = format!("Content-Length: {}\r\n\r\n", usize::MAX);
+        let err = read_message(&mut Cursor::new(oversized)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = read_message(&mut Cursor::new("Content-Type: x\r\n\r\n{}")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err = read_message(&mut Cursor::new("Content-Length: ten\r\n\r\n")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let truncated = "Content-Length: 10\r\n\r\n{}";
+        let err = read_message(&mut Cursor::new(truncated)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn skips_malformed_requests() {
+        let input = format!(
+            "{}{}",
+            frame("{not json"),
+            frame(r#"{"seq":2,"type":"request","command":"threads"}"#)
+        );
+        let (sender, receiver) = mpsc::channel();
+        read_requests(Cursor::new(input), sender);
+
+        let incoming: Vec<_> = receiver.iter().collect();
+        assert!(matches!(
+            &incoming[..],
+            [Incoming::Request(Request { seq: 2, .. }), Incoming::Closed]
+        ));
+    }
+
+    #[test]
+    fn stops_reading_on_io_errors() {
+        let (sender, receiver) = mpsc::channel();
+        read_requests(BufReader::new(Broken), sender);
+
+        let incoming: Vec<_> = receiver.iter().collect();
+        assert!(matches!(&incoming[..], [Incoming::Closed]));
+    }
+
+    #[test]
+    fn initialize() {
+        let (mut server, sent) = server();
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 1,
+                "type": "request",
+                "command": "initialize",
+                "arguments": { "adapterID": "poc-tui", "linesStartAt1": true },
+            }),
+        );
+
+        assert_eq!(
+            messages,
+            [
+                json!({
+                    "seq": 1,
+                    "type": "response",
+                    "request_seq": 1,
+                    "command": "initialize",
+                    "success": true,
+                    "body": {
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsFunctionBreakpoints": true,
+                        "supportsConditionalBreakpoints": true,
+                        "supportsHitConditionalBreakpoints": true,
+                        "supportsDisassembleRequest": true,
+                        "supportsReadMemoryRequest": true,
+                        "supportsSteppingGranularity": true,
+                        "supportsTerminateRequest": true,
+                    },
+                }),
+                json!({ "seq": 2, "type": "event", "event": "initialized", "body": {} }),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        let (mut server, sent) = server();
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({ "seq": 7, "type": "request", "command": "gotoTargets" }),
+        );
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["request_seq"], 7);
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[0]["message"], "`gotoTargets` is not supported");
+    }
+
+    #[test]
+    fn function_breakpoints_are_pending_until_the_module_is_loaded() {
+        let (mut server, sent) = server();
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 3,
+                "type": "request",
+                "command": "setFunctionBreakpoints",
+                "arguments": { "breakpoints": [
+                    { "name": "leaf", "condition": "arg0 == 1", "hitCondition": ">=2" },
+                    { "name": "mid", "hitCondition": "%2" },
+                    { "name": "main", "condition": "arg0 ==" },
+                ] },
+            }),
+        );
+
+        let breakpoints = &messages[0]["body"]["breakpoints"];
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(
+            breakpoints[0],
+            json!({ "id": 1, "verified": false, "message": "pending until the module is loaded" })
+        );
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert_eq!(
+            breakpoints[1]["message"],
+            "unsupported hit condition `%2`, use `N` or `>=N`"
+        );
+        assert_eq!(breakpoints[2]["verified"], false);
+        assert!(breakpoints[2].get("id").is_none());
+        assert_eq!(server.function_breakpoints, [BreakpointId(1)]);
+
+        // the previous ones are replaced
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 4,
+                "type": "request",
+                "command": "setFunctionBreakpoints",
+                "arguments": { "breakpoints": [] },
+            }),
+        );
+        assert_eq!(messages[0]["body"]["breakpoints"], json!([]));
+        assert!(server.function_breakpoints.is_empty());
+    }
+
+    #[test]
+    fn scopes() {
+        let (mut server, sent) = server();
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({ "seq": 5, "type": "request", "command": "scopes", "arguments": { "frameId": 0 } }),
+        );
+        assert_eq!(
+            messages[0]["body"]["scopes"],
+            json!([
+                {
+                    "name": "Arguments",
+                    "presentationHint": "arguments",
+                    "variablesReference": ARGUMENTS_REFERENCE,
+                    "expensive": false,
+                },
+                {
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": REGISTERS_REFERENCE,
+                    "expensive": false,
+                },
+            ])
+        );
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({ "seq": 6, "type": "request", "command": "scopes", "arguments": { "frameId": 1 } }),
+        );
+        assert_eq!(messages[0]["body"]["scopes"], json!([]));
+    }
+
+    #[test]
+    fn requests_without_a_tracee() {
+        let (mut server, sent) = server();
+        for command in ["stackTrace", "variables"] {
+            let messages = request(
+                &mut server,
+                &sent,
+                json!({
+                    "seq": 1,
+                    "type": "request",
+                    "command": command,
+                    "arguments": {
+                        "variablesReference": REGISTERS_REFERENCE,
+                        "memoryReference": "0x1000",
+                        "count": 8,
+                        "instructionCount": 1,
+                    },
+                }),
+            );
+            assert_eq!(messages[0]["success"], false, "{command}");
+        }
+
+        // nothing is readable
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 2,
+                "type": "request",
+                "command": "readMemory",
+                "arguments": { "memoryReference": "0x1000", "count": 8 },
+            }),
+        );
+        assert_eq!(
+            messages[0]["body"],
+            json!({ "address": "0x1000", "data": "", "unreadableBytes": 8 })
+        );
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 3,
+                "type": "request",
+                "command": "disassemble",
+                "arguments": { "memoryReference": "0x1000", "instructionCount": 1 },
+            }),
+        );
+        assert_eq!(
+            messages[0]["body"]["instructions"],
+            json!([{ "address": "0x1000", "instruction": "??", "presentationHint": "invalid" }])
+        );
+    }
+
+    #[test]
+    fn launch_forwards_the_output_and_the_exit() {
+        let (mut server, sent) = server();
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 1,
+                "type": "request",
+                "command": "launch",
+                // an `echo` on its own can exit before it's attached
+                "arguments": {
+                    "program": "sh",
+                    "args": ["-c", "sleep 0.5; echo $A"],
+                    "env": { "A": "hello" },
+                },
+            }),
+        );
+        assert_eq!(messages[0]["success"], true, "{}", messages[0]);
+        assert_eq!(messages[1]["event"], "process");
+        assert_eq!(messages[1]["body"]["startMethod"], "launch");
+        assert_eq!(
+            messages[1]["body"]["systemProcessId"],
+            server.ctx.pid.as_raw()
+        );
+
+        while server.ctx.is_alive() {
+            if let Some(state) = server.ctx.poll(true).unwrap() {
+                server.handle_state(state).unwrap();
+            }
+        }
+        assert_eq!(
+            sent.take(),
+            [
+                json!({ "seq": 3, "type": "event", "event": "exited", "body": { "exitCode": 0 } }),
+                json!({ "seq": 4, "type": "event", "event": "terminated", "body": {} }),
+            ]
+        );
+
+        let output = server.messages.recv_timeout(Duration::from_secs(5));
+        assert!(matches!(
+            output,
+            Ok(Incoming::ProgramOutput(OutputLine { stream: OutputStream::Stdout, ref text }))
+                if text == "hello"
+        ));
+    }
+
+    #[test]
+    fn launch_fails_for_missing_programs() {
+        let (mut server, sent) = server();
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 1,
+                "type": "request",
+                "command": "launch",
+                "arguments": { "program": "/nonexistent/program" },
+            }),
+        );
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["success"], false);
+    }
+
+    #[test]
+    fn inspects_an_attached_process() {
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        wait_until_asleep(child.id());
+        let perfmap = env::temp_dir().join(format!("poc-tui-dap-{}.map", child.id()));
+        let (mut server, sent) = server();
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 1,
+                "type": "request",
+                "command": "attach",
+                "arguments": { "processId": child.id(), "perfmap": perfmap },
+            }),
+        );
+        assert_eq!(messages[0]["success"], true);
+        assert_eq!(messages[1]["event"], "process");
+        assert_eq!(messages[1]["body"]["startMethod"], "attach");
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 2,
+                "type": "request",
+                "command": "variables",
+                "arguments": { "variablesReference": REGISTERS_REFERENCE },
+            }),
+        );
+        let variables = messages[0]["body"]["variables"].as_array().unwrap();
+        assert_eq!(variables.len(), registers::NAMES.len());
+        let rip = variables
+            .iter()
+            .find(|variable| variable["name"] == "rip")
+            .unwrap();
+        assert_eq!(rip["memoryReference"], rip["value"]);
+        let rip_reference = rip["value"].as_str().unwrap().to_string();
+        let rip = parse_number(&rip_reference).unwrap();
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 3,
+                "type": "request",
+                "command": "variables",
+                "arguments": { "variablesReference": ARGUMENTS_REFERENCE },
+            }),
+        );
+        let names: Vec<_> = messages[0]["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variable| variable["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["vmctx", "caller_vmctx", "arg0", "arg1", "arg2", "arg3"]
+        );
+
+        // a fake function around the stopped pc
+        let start = rip - 0x10;
+        fs::write(&perfmap, format!("{start:x} 40 wasm_binary::sleepy\n")).unwrap();
+        server.load_module().unwrap();
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({ "seq": 4, "type": "request", "command": "stackTrace", "arguments": { "levels": 1 } }),
+        );
+        let body = &messages[0]["body"];
+        assert_eq!(
+            body["stackFrames"],
+            json!([{
+                "id": 0,
+                "name": "wasm_binary::sleepy+0x10",
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": rip_reference,
+            }])
+        );
+        assert!(body["totalFrames"].as_u64().unwrap() >= 1);
+
+        let mut mem = fs::File::open(format!("/proc/{}/mem", child.id())).unwrap();
+        let mut read_raw = |addr: u64, buf: &mut [u8]| {
+            mem.seek(SeekFrom::Start(addr)).unwrap();
+            mem.read_exact(buf).unwrap();
+        };
+        let mut expected = vec![0; 0x20];
+        read_raw(start, &mut expected);
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 5,
+                "type": "request",
+                "command": "setFunctionBreakpoints",
+                "arguments": { "breakpoints": [{ "name": "sleepy" }] },
+            }),
+        );
+        assert_eq!(
+            messages[0]["body"]["breakpoints"],
+            json!([{ "id": 1, "verified": true, "instructionReference": format!("{start:#x}") }])
+        );
+
+        // the trap doesn't show up in the memory reads
+        let mut trap = [0];
+        read_raw(start, &mut trap);
+        assert_eq!(trap, [0xcc]);
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 6,
+                "type": "request",
+                "command": "readMemory",
+                "arguments": { "memoryReference": rip_reference, "offset": -0x10, "count": 0x20 },
+            }),
+        );
+        let body = &messages[0]["body"];
+        assert_eq!(body["address"], format!("{start:#x}"));
+        assert_eq!(body["unreadableBytes"], 0);
+        assert_eq!(
+            BASE64.decode(body["data"].as_str().unwrap()).unwrap(),
+            expected
+        );
+
+        // the huge reads are cut short, the ones at the end of the address space stop there
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 7,
+                "type": "request",
+                "command": "readMemory",
+                "arguments": { "memoryReference": rip_reference, "count": usize::MAX },
+            }),
+        );
+        let body = &messages[0]["body"];
+        let data = BASE64.decode(body["data"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            data.len() + body["unreadableBytes"].as_u64().unwrap() as usize,
+            MAX_READ_LEN
+        );
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 8,
+                "type": "request",
+                "command": "readMemory",
+                "arguments": { "memoryReference": "0xfffffffffffffffc", "count": 16 },
+            }),
+        );
+        assert_eq!(
+            messages[0]["body"],
+            json!({ "address": "0xfffffffffffffffc", "data": "", "unreadableBytes": 16 })
+        );
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 9,
+                "type": "request",
+                "command": "disassemble",
+                "arguments": {
+                    "memoryReference": format!("{start:#x}"),
+                    "instructionOffset": -1,
+                    "instructionCount": 4,
+                    "resolveSymbols": true,
+                },
+            }),
+        );
+        let instructions = messages[0]["body"]["instructions"].as_array().unwrap();
+        assert_eq!(instructions.len(), 4);
+        assert_eq!(instructions[0]["presentationHint"], "invalid");
+        assert_eq!(instructions[1]["address"], format!("{start:#x}"));
+        assert_eq!(instructions[1]["symbol"], "wasm_binary::sleepy");
+        // the original instruction, not the trap
+        assert_ne!(instructions[1]["instruction"], "int3");
+        for instruction in &instructions[1..] {
+            assert!(instruction["instructionBytes"].is_string());
+        }
+
+        let messages = request(
+            &mut server,
+            &sent,
+            json!({
+                "seq": 10,
+                "type": "request",
+                "command": "disconnect",
+                "arguments": { "terminateDebuggee": true },
+            }),
+        );
+        assert_eq!(messages[0]["success"], true);
+        assert!(!server.ctx.is_alive());
+
+        let _ = child.wait();
+        let _ = fs::remove_file(perfmap);
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    io::{BufRead, BufReader, Read},
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{self, eyre};
+use nix::unistd::Pid;
+
+use crate::{
+    breakpoint::{BreakpointId, Location},
+    console::{self, ExamineFormat},
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    event::{AppEvent, Event},
+    expr::Expr,
+    function_mapping::FunctionId,
+    itrace,
+    launch::{LaunchSpec, OutputLine, OutputStream},
+    logpoint::{LogFormat, Logpoint},
+    notification::{Notification, Severity},
+    registers::{self, Snapshot},
+    script::Script,
+};
+
+/// How often the worker checks a running tracee for a state change.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The name of the wasm binary whose functions we are interested in.
+// TODO(aeryz): make this configurable
+pub const BIN_NAME: &str = "wasm_binary";
+
+/// Requests to the debugger thread.
+#[derive(Debug, Clone)]
+pub enum DebuggerCommand {
+    /// Spawn the tracee and run it until the module is loaded.
+    Launch(LaunchSpec),
+    /// Attach to a running process and stop it.
+    Attach(Pid),
+    /// (Re)parse the perf map of the tracee.
+    ParsePerfMap,
+    /// Disassemble the function, replied with [`AppEvent::Disassembly`].
+    Disassemble(FunctionId),
+    Continue,
+    Interrupt,
+    Kill,
+    Break {
+        location: Location,
+        condition: Option<Expr>,
+        temporary: bool,
+    },
+    /// Continue until the location is reached. The temporary breakpoint for it is deleted at
+    /// the next stop, wherever it is.
+    Advance(Location),
+    Ignore {
+        id: BreakpointId,
+        count: u64,
+    },
+    /// Delete a breakpoint after it stops this many more times.
+    Expire {
+        id: BreakpointId,
+        stops: u64,
+    },
+    /// Add a logpoint.
+    Dprintf {
+        location: Location,
+        format: LogFormat,
+    },
+    RateLimit {
+        id: BreakpointId,
+        per_second: u32,
+    },
+    /// Replace the condition of a breakpoint, `None` makes it unconditional.
+    Condition {
+        id: BreakpointId,
+        condition: Option<Expr>,
+    },
+    /// Attach a script to a breakpoint, `None` detaches it.
+    OnHit {
+        id: BreakpointId,
+        script: Option<Script>,
+    },
+    Enable {
+        id: BreakpointId,
+        enabled: bool,
+    },
+    /// Run a session script, its output is replied with [`AppEvent::CommandOutput`]. The
+    /// worker is busy until the script is done.
+    Script(Script),
+    /// Delete a breakpoint, all of them when it's `None`.
+    Delete(Option<BreakpointId>),
+    StepInstruction,
+    Finish,
+    /// Trace the instructions until the current function returns, replied with
+    /// [`AppEvent::CommandOutput`] unless they are written into `output`.
+    InstructionTrace {
+        calls: bool,
+        output: Option<PathBuf>,
+    },
+    /// Write the register `name`, one of [`registers::PANE`], replied with
+    /// [`AppEvent::Registers`].
+    SetRegister {
+        name: &'static str,
+        value: Expr,
+    },
+    /// Replied with [`AppEvent::CommandOutput`].
+    Backtrace,
+    /// Read the memory, replied with [`AppEvent::CommandOutput`].
+    Examine {
+        format: ExamineFormat,
+        location: Location,
+    },
+    /// Evaluate an expression, replied with [`AppEvent::CommandOutput`].
+    Print(Expr),
+}
+
+/// Handle to the thread that owns the [`DebuggerCtx`].
+///
+/// ptrace only accepts requests from the thread that attached to the tracee, so all the ptrace
+/// work is done on a dedicated thread. This way, the UI keeps being responsive while the tracee
+/// runs. The results are published as [`AppEvent`]s.
+#[derive(Debug)]
+pub struct DebuggerHandle {
+    sender: mpsc::Sender<DebuggerCommand>,
+}
+
+impl DebuggerHandle {
+    /// Spawns the debugger thread, which publishes its events through `events`.
+    pub fn spawn(ctx: DebuggerCtx, events: mpsc::Sender<Event>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let actor = DebuggerThread {
+            ctx,
+            commands: receiver,
+            events,
+            advance: None,
+        };
+        thread::spawn(|| actor.run());
+        Self { sender }
+    }
+
+    pub fn send(&self, command: DebuggerCommand) {
+        // Ignore the result as the debugger thread only stops when this handle is dropped
+        let _ = self.sender.send(command);
+    }
+}
+
+struct DebuggerThread {
+    ctx: DebuggerCtx,
+    commands: mpsc::Receiver<DebuggerCommand>,
+    events: mpsc::Sender<Event>,
+    /// The temporary breakpoint of [`DebuggerCommand::Advance`]
+    advance: Option<BreakpointId>,
+}
+
+impl DebuggerThread {
+    fn run(mut self) {
+        loop {
+            let command = if self.ctx.is_running() {
+                match self.commands.recv_timeout(POLL_INTERVAL) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match self.commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                }
+            };
+
+            if let Some(command) = command
+                && let Err(e) = self.handle_command(command)
+            {
+                self.notify_error(e);
+            }
+
+            if self.ctx.is_running()
+                && let Err(e) = self.poll()
+            {
+                self.notify_error(e);
+            }
+
+            let log = self.ctx.take_log();
+            if !log.is_empty() {
+                let lines = log.iter().map(ToString::to_string).collect();
+                self.send(AppEvent::CommandOutput(lines));
+            }
+        }
+        // dropping the ctx kills the tracee if we spawned it
+    }
+
+    fn handle_command(&mut self, command: DebuggerCommand) -> eyre::Result<()> {
+        match command {
+            DebuggerCommand::Launch(spec) => {
+                self.ctx.spawn(&spec)?;
+                self.send(AppEvent::TraceeStarted(self.ctx.pid));
+
+                let (stdout, stderr) = self.ctx.take_output();
+                if let Some(stdout) = stdout {
+                    self.forward_output(stdout, OutputStream::Stdout);
+                }
+                if let Some(stderr) = stderr {
+                    self.forward_output(stderr, OutputStream::Stderr);
+                }
+            }
+            DebuggerCommand::Attach(pid) => {
+                self.ctx.attach(pid)?;
+                self.send(AppEvent::TraceeStarted(pid));
+                self.publish_state();
+
+                // the module is usually loaded by the time we attach
+                let mapping = self.ctx.parse_perfmap(BIN_NAME)?;
+                self.send(AppEvent::ModuleDiscovered(mapping));
+                self.arm_breakpoints();
+            }
+            DebuggerCommand::ParsePerfMap => {
+                let mapping = self.ctx.parse_perfmap(BIN_NAME)?;
+                self.send(AppEvent::ModuleDiscovered(mapping));
+            }
+            DebuggerCommand::Disassemble(id) => {
+                if self.ctx.is_running() {
+                    // the function will be disassembled when the user selects it again
+                    return Ok(());
+                }
+                let instructions = self.ctx.disassemble(id)?;
+                self.send(AppEvent::Disassembly { id, instructions });
+            }
+            DebuggerCommand::Continue => {
+                self.ctx.resume()?;
+                self.send(AppEvent::TraceeRunning);
+            }
+            DebuggerCommand::Interrupt => self.ctx.interrupt()?,
+            DebuggerCommand::Kill => {
+                self.ctx.kill()?;
+                self.publish_state();
+            }
+            DebuggerCommand::Break {
+                location,
+                condition,
+                temporary,
+            } => {
+                let mut bp = self.ctx.add_breakpoint(location)?;
+                if condition.is_some() {
+                    bp = self.ctx.set_condition(bp.id, condition)?;
+                }
+                if temporary {
+                    bp = self.ctx.set_remaining_stops(bp.id, Some(1))?;
+                }
+                let text =
+                    console::format_new_breakpoint(&bp, self.ctx.function_mapping.as_deref());
+                self.send(AppEvent::CommandOutput(vec![text]));
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::Advance(location) => {
+                let bp = self.ctx.add_breakpoint(location)?;
+                self.ctx.set_remaining_stops(bp.id, Some(1))?;
+                self.advance = Some(bp.id);
+                self.publish_breakpoints();
+                self.ctx.resume()?;
+                self.send(AppEvent::TraceeRunning);
+            }
+            DebuggerCommand::Ignore { id, count } => {
+                self.ctx.set_ignore_count(id, count)?;
+                let text = format!("Will ignore the next {count} hits of breakpoint {id}");
+                self.send(AppEvent::CommandOutput(vec![text]));
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::Expire { id, stops } => {
+                self.ctx.set_remaining_stops(id, Some(stops))?;
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::Dprintf { location, format } => {
+                let bp = self.ctx.add_breakpoint(location)?;
+                let bp = self.ctx.set_log(bp.id, Some(Logpoint::new(format)))?;
+                let text =
+                    console::format_new_breakpoint(&bp, self.ctx.function_mapping.as_deref());
+                self.send(AppEvent::CommandOutput(vec![text]));
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::RateLimit { id, per_second } => {
+                self.ctx.set_rate_limit(id, per_second)?;
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::Condition { id, condition } => {
+                self.ctx.set_condition(id, condition)?;
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::OnHit { id, script } => {
+                self.ctx.set_script(id, script)?;
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::Enable { id, enabled } => {
+                self.ctx.set_enabled(id, enabled)?;
+                self.publish_breakpoints();
+            }
+            DebuggerCommand::Script(script) => {
+                let events = self.events.clone();
+                let mapping = self.ctx.function_mapping.clone();
+                let result = script.run_session(&mut self.ctx, move |line| {
+                    let _ = events.send(Event::App(AppEvent::CommandOutput(vec![line])));
+                });
+
+                // the script may have loaded the module, moved the tracee or the breakpoints
+                if let Some(new) = &self.ctx.function_mapping
+                    && !mapping.is_some_and(|old| Arc::ptr_eq(&old, new))
+                {
+                    self.send(AppEvent::ModuleDiscovered(new.clone()));
+                }
+                self.publish_state();
+                self.publish_breakpoints();
+                result?;
+            }
+            DebuggerCommand::Delete(id) => {
+                let ids = match id {
+                    Some(id) => vec![id],
+                    None => self.ctx.breakpoints.ids(),
+                };
+                let result = ids.into_iter().try_for_each(|id| {
+                    self.ctx.delete_breakpoint(id)?;
+                    self.send(AppEvent::CommandOutput(vec![format!(
+                        "Deleted breakpoint {id}"
+                    )]));
+                    Ok::<_, eyre::Report>(())
+                });
+                self.publish_breakpoints();
+                result?;
+            }
+            DebuggerCommand::StepInstruction => {
+                self.ctx.step_instruction()?;
+                self.send(AppEvent::TraceeRunning);
+            }
+            DebuggerCommand::Finish => {
+                self.ctx.finish()?;
+                self.send(AppEvent::TraceeRunning);
+            }
+            DebuggerCommand::InstructionTrace { calls, output } => {
+                let mut lines = Vec::new();
+                let result = itrace::trace_into(
+                    &mut self.ctx,
+                    calls,
+                    output.as_deref(),
+                    || false,
+                    |line| lines.push(line),
+                );
+                self.send(AppEvent::CommandOutput(lines));
+                // the tracee stopped somewhere else, the breakpoints may have been hit
+                self.publish_state();
+                self.publish_breakpoints();
+                result?;
+            }
+            DebuggerCommand::SetRegister { name, value } => {
+                if !self.ctx.is_stopped() {
+                    return Err(eyre!("the tracee is not stopped ({})", self.ctx.state));
+                }
+                let value = value.eval(&self.ctx)?;
+                let mut regs = self.ctx.registers()?;
+                *registers::get_mut(&mut regs, name).expect("a general purpose register") =
+                    value as u64;
+                self.ctx.set_registers(regs)?;
+                self.publish_registers();
+            }
+            DebuggerCommand::Backtrace => {
+                let frames = self.ctx.backtrace()?;
+                let lines =
+                    console::format_backtrace(&frames, self.ctx.function_mapping.as_deref());
+                self.send(AppEvent::CommandOutput(lines));
+            }
+            DebuggerCommand::Examine { format, location } => {
+                let addr = self.ctx.resolve(&location)?;
+                let bytes = self.ctx.read_memory(addr, format.byte_len())?;
+                let mut lines = format.format(addr, &bytes, |addr| {
+                    self.ctx.function_mapping.as_ref()?.symbolize(addr)
+                });
+                lines.extend(console::format_breakpoints_in(
+                    addr,
+                    bytes.len(),
+                    self.ctx.breakpoints.iter(),
+                ));
+                self.send(AppEvent::CommandOutput(lines));
+            }
+            DebuggerCommand::Print(expr) => {
+                let text = console::format_value(expr.eval(&self.ctx)?);
+                self.send(AppEvent::CommandOutput(vec![text]));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn poll(&mut self) -> eyre::Result<()> {
+        if self.ctx.poll(false)?.is_none() {
+            return Ok(());
+        }
+
+        self.publish_state();
+
+        match &self.ctx.state {
+            // The perf map is complete by the time the module is loaded
+            TraceeState::Stopped(StopReason::ModuleLoaded) => {
+                let mapping = self.ctx.parse_perfmap(BIN_NAME)?;
+                self.send(AppEvent::ModuleDiscovered(mapping));
+                self.arm_breakpoints();
+            }
+            TraceeState::Stopped(_) | TraceeState::Exited(_) => {
+                // `advance` is over wherever the tracee stopped
+                if let Some(id) = self.advance.take()
+                    && self.ctx.breakpoints.get(id).is_some()
+                {
+                    self.ctx.delete_breakpoint(id)?;
+                }
+                // the hit counts changed
+                self.publish_breakpoints();
+            }
+            TraceeState::NotStarted | TraceeState::Running => {}
+        }
+
+        Ok(())
+    }
+
+    /// Publishes everything that the tracee writes to `pipe` line by line, until it's closed.
+    fn forward_output(&self, pipe: impl Read + Send + 'static, stream: OutputStream) {
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {
+                        let text = String::from_utf8_lossy(&line)
+                            .trim_end_matches(['\n', '\r'])
+                            .to_string();
+                        let event = AppEvent::ProgramOutput(OutputLine { stream, text });
+                        if events.send(Event::App(event)).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn publish_state(&self) {
+        match &self.ctx.state {
+            TraceeState::Stopped(reason) => {
+                self.send(AppEvent::TraceeStopped {
+                    pid: self.ctx.pid,
+                    reason: reason.clone(),
+                    pc: self.ctx.pc().ok(),
+                });
+                self.publish_registers();
+            }
+            TraceeState::Exited(reason) => {
+                self.send(AppEvent::TraceeExited {
+                    pid: self.ctx.pid,
+                    reason: *reason,
+                });
+                // the breakpoints are pending again
+                self.publish_breakpoints();
+            }
+            TraceeState::Running => self.send(AppEvent::TraceeRunning),
+            TraceeState::NotStarted => {}
+        }
+    }
+
+    /// Arms the pending breakpoints, warning about the ones that stay pending.
+    fn arm_breakpoints(&mut self) {
+        for error in self.ctx.arm_breakpoints() {
+            self.send(AppEvent::Notify(Notification::new(
+                Severity::Warning,
+                format!("{error:#}"),
+            )));
+        }
+        self.publish_breakpoints();
+    }
+
+    fn publish_registers(&self) {
+        // the tracee may be gone already
+        if let (Ok(regs), Ok(fpregs)) = (self.ctx.registers(), self.ctx.fp_registers()) {
+            self.send(AppEvent::Registers(Snapshot::new(&regs, &fpregs)));
+        }
+    }
+
+    fn publish_breakpoints(&self) {
+        self.send(AppEvent::BreakpointsChanged(
+            self.ctx.breakpoints.iter().cloned().collect(),
+        ));
+    }
+
+    fn notify_error(&self, error: eyre::Report) {
+        self.send(AppEvent::Notify(Notification::new(
+            Severity::Error,
+            format!("{error:#}"),
+        )));
+    }
+
+    fn send(&self, event: AppEvent) {
+        // Ignores the result because shutting down the app drops the receiver
+        let _ = self.events.send(Event::App(event));
+    }
+}
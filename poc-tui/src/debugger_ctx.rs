@@ -1,127 +1,1303 @@
 use capstone::prelude::*;
-use color_eyre::eyre;
+use color_eyre::eyre::{self, WrapErr, eyre};
+use libc::{user_fpregs_struct, user_regs_struct};
 use nix::{
     sys::{
-        ptrace,
-        wait::{WaitStatus, waitpid},
+        ptrace::{self, regset::NT_PRFPREG},
+        signal::{self, Signal},
+        wait::{WaitPidFlag, WaitStatus, waitpid},
     },
     unistd::Pid,
 };
-use std::{ffi::c_void, process::Command};
+use rhai::{Dynamic, Map};
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    fmt,
+    path::PathBuf,
+    process::{Child, ChildStderr, ChildStdout},
+    sync::Arc,
+    time::Instant,
+};
 
-use crate::function_mapping::FunctionMapping;
+use crate::{
+    breakpoint::{Breakpoint, BreakpointId, Breakpoints, Hit, Location},
+    disassembly::{self, Instruction},
+    expr::Expr,
+    function_mapping::{FunctionId, FunctionMapping},
+    launch::LaunchSpec,
+    logpoint::{LogMessage, Logpoint},
+    registers,
+    script::Script,
+    wasm_abi,
+};
 
 pub const WASM_MEMORY_IMAGE_IDENT: &str = "wasm-memory-image";
 
+const INT3: u8 = 0xCC;
+const RET: u8 = 0xC3;
+const PUSH_RBP: u8 = 0x55;
+
+/// The longest x86-64 instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// The maximum number of frames that [`DebuggerCtx::backtrace`] walks.
+const MAX_FRAMES: usize = 256;
+
+/// What the tracee is doing, from the point of view of the debugger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceeState {
+    /// There is no tracee yet.
+    NotStarted,
+    Running,
+    Stopped(StopReason),
+    Exited(ExitReason),
+}
+
+impl fmt::Display for TraceeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceeState::NotStarted => write!(f, "not started"),
+            TraceeState::Running => write!(f, "running"),
+            TraceeState::Stopped(reason) => write!(f, "stopped ({reason})"),
+            TraceeState::Exited(reason) => write!(f, "exited ({reason})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The runtime is about to execute the JIT-compiled module, see
+    /// [`DebuggerCtx::run_command`].
+    ModuleLoaded,
+    /// Stopped by [`DebuggerCtx::interrupt`].
+    Interrupted,
+    /// The tracee received a signal, which will be delivered when it's resumed.
+    Signal(Signal),
+    Breakpoint(BreakpointId),
+    /// The condition of the breakpoint can't be evaluated, which stops the tracee like gdb does.
+    ConditionError {
+        id: BreakpointId,
+        error: String,
+    },
+    /// The script of the breakpoint failed.
+    ScriptError {
+        id: BreakpointId,
+        error: String,
+    },
+    /// Executed a single instruction, see [`DebuggerCtx::step_instruction`].
+    Step,
+    /// The function returned, see [`DebuggerCtx::finish`].
+    Finished,
+    /// A function returned, see [`DebuggerCtx::watch_return`]. `sp` is the stack pointer
+    /// right after the return.
+    Returned {
+        sp: u64,
+    },
+    /// Entering a syscall, when [`DebuggerCtx::trace_syscalls`] is set.
+    Syscall {
+        nr: u64,
+        args: [u64; 6],
+    },
+    /// Leaving a syscall, when [`DebuggerCtx::trace_syscalls`] is set.
+    SyscallReturn {
+        ret: i64,
+    },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::ModuleLoaded => write!(f, "module loaded"),
+            StopReason::Interrupted => write!(f, "interrupted"),
+            StopReason::Signal(signal) => write!(f, "signal {}", signal.as_str()),
+            StopReason::Breakpoint(id) => write!(f, "breakpoint {id}"),
+            StopReason::ConditionError { id, error } => {
+                write!(f, "breakpoint {id}, its condition failed: {error}")
+            }
+            StopReason::ScriptError { id, error } => {
+                write!(f, "breakpoint {id}, its script failed: {error}")
+            }
+            StopReason::Step => write!(f, "step"),
+            StopReason::Finished => write!(f, "finished"),
+            StopReason::Returned { sp } => write!(f, "returned, sp {sp:#x}"),
+            StopReason::Syscall { nr, .. } => write!(f, "syscall {nr}"),
+            StopReason::SyscallReturn { ret } => write!(f, "syscall returned {ret}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    Code(i32),
+    Signal(Signal),
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitReason::Code(code) => write!(f, "code {code}"),
+            ExitReason::Signal(signal) => write!(f, "killed by {}", signal.as_str()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct DebuggerCtx {
     pub pid: Pid,
-    pub function_mapping: Option<FunctionMapping>,
+    pub function_mapping: Option<Arc<FunctionMapping>>,
+    /// Explicit location of the perf map, bypassing the lookup in [`crate::perf_map::locate`]
+    pub perfmap_path: Option<PathBuf>,
+    pub state: TraceeState,
+    /// The tracee, if it's spawned by us
+    child: Option<Child>,
+    /// We trace the syscalls until the runtime loads the module
+    waiting_for_module: bool,
+    /// The signal to deliver to the tracee when it's resumed
+    pending_signal: Option<Signal>,
+    pub breakpoints: Breakpoints,
+    /// The original bytes of the code that are replaced with traps, by their addresses
+    traps: HashMap<u64, u8>,
+    /// The single step in progress
+    step: Option<Step>,
+    /// The return that [`DebuggerCtx::finish`] waits for
+    finish: Option<Finish>,
+    /// The returns that [`DebuggerCtx::watch_return`] waits for, the innermost last
+    returns: Vec<Finish>,
+    /// Stop at the syscalls, reporting [`StopReason::Syscall`] and
+    /// [`StopReason::SyscallReturn`]
+    pub trace_syscalls: bool,
+    /// Any trap of the tracee consumes a pending interrupt, so the next stop is reported as
+    /// [`StopReason::Interrupted`] when this is set
+    interrupt_requested: bool,
+    /// The messages of the logpoints and the breakpoint scripts that are not taken yet
+    log: Vec<LogMessage>,
+    /// The `state` map of the scripts, which they share between their runs
+    pub script_state: Dynamic,
+}
+
+/// A single step in progress.
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    /// The trap that is lifted to execute the original instruction under it, it's put back
+    /// once the step is done
+    trap: Option<u64>,
+    /// Continue after the step instead of reporting a stop
+    then_continue: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Finish {
+    /// The return address
+    addr: u64,
+    /// The stack pointer right after the return. Reaching `addr` with a lower one means that a
+    /// recursive call returned, not the one we wait for.
+    sp: u64,
+}
+
+impl Default for DebuggerCtx {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DebuggerCtx {
-    pub fn run_command(&mut self, command: &str) -> eyre::Result<()> {
-        let child = Command::new(command).spawn()?;
+    pub fn new() -> Self {
+        DebuggerCtx {
+            pid: Pid::from_raw(0),
+            function_mapping: None,
+            perfmap_path: None,
+            state: TraceeState::NotStarted,
+            child: None,
+            waiting_for_module: false,
+            pending_signal: None,
+            breakpoints: Breakpoints::default(),
+            traps: HashMap::new(),
+            step: None,
+            finish: None,
+            returns: Vec::new(),
+            trace_syscalls: false,
+            interrupt_requested: false,
+            log: Vec::new(),
+            script_state: Dynamic::from_map(Map::new()).into_shared(),
+        }
+    }
+
+    /// Whether the tracee is executing, i.e. [`DebuggerCtx::poll`] needs to be called to
+    /// observe its next stop.
+    pub fn is_running(&self) -> bool {
+        self.state == TraceeState::Running
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.state, TraceeState::Stopped(_))
+    }
+
+    /// Whether there is a tracee, i.e. it's either running or stopped.
+    pub fn is_alive(&self) -> bool {
+        self.is_running() || self.is_stopped()
+    }
+
+    /// Whether the tracee is attached to rather than spawned by us, i.e. it outlives us.
+    pub fn is_attached(&self) -> bool {
+        self.is_alive() && self.child.is_none()
+    }
+
+    fn ensure_stopped(&self) -> eyre::Result<()> {
+        if !self.is_stopped() {
+            return Err(eyre!("the tracee is not stopped ({})", self.state));
+        }
+
+        Ok(())
+    }
+
+    /// Spawns the tracee and blocks until the runtime is about to execute the module.
+    pub fn run_command(&mut self, spec: &LaunchSpec) -> eyre::Result<()> {
+        self.spawn(spec)?;
+
+        while self.is_running() {
+            self.poll(true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Spawns the tracee and resumes it until the module is loaded. Use [`DebuggerCtx::poll`]
+    /// to observe when that happens.
+    pub fn spawn(&mut self, spec: &LaunchSpec) -> eyre::Result<()> {
+        if self.is_alive() {
+            return Err(eyre!("there is already a tracee with pid {}", self.pid));
+        }
+
+        let child = spec
+            .command()
+            .spawn()
+            .wrap_err_with(|| format!("failed to start `{spec}`"))?;
         let ptrace_pid = Pid::from_raw(child.id() as i32);
         self.pid = ptrace_pid;
-        ptrace::seize(ptrace_pid, ptrace::Options::PTRACE_O_TRACESYSGOOD)?;
+        self.child = Some(child);
+        self.function_mapping = None;
+        self.pending_signal = None;
+
+        // The tracee is killed if we go away without cleaning up
+        ptrace::seize(
+            ptrace_pid,
+            ptrace::Options::PTRACE_O_TRACESYSGOOD | ptrace::Options::PTRACE_O_EXITKILL,
+        )?;
         ptrace::interrupt(ptrace_pid)?;
         match waitpid(ptrace_pid, None)? {
             WaitStatus::Stopped(_, _) | WaitStatus::PtraceEvent(..) => {}
-            other => eprintln!("wait: {other:?}"),
-        }
-        loop {
-            ptrace::syscall(ptrace_pid, None)?;
-
-            match waitpid(ptrace_pid, None)? {
-                WaitStatus::PtraceSyscall(ptrace_pid) => {
-                    let syscall = ptrace::syscall_info(ptrace_pid)?;
-                    if syscall.op == libc::PTRACE_SYSCALL_INFO_ENTRY {
-                        let syscall = unsafe { syscall.u.entry };
-
-                        // wasmtime uses `memfd_create` to create an anonymous in-memory file. This happens
-                        // after the `perf` is written under `/tmp/perf-PID.map` and before executing the
-                        // WASM binary. This means we can inject our traps right at this moment.
-                        //
-                        // https://github.com/bytecodealliance/wasmtime/blob/ee7e125309f5bc784b8feb8969261ae41fb4703b/crates/wasmtime/src/runtime/vm/sys/unix/vm.rs#L120
-                        if syscall.nr == libc::SYS_memfd_create as u64 {
-                            let mut memory_name: Vec<u8> = Vec::new();
-                            let mut len = (WASM_MEMORY_IMAGE_IDENT.len() / size_of::<usize>()) + 1;
-                            if WASM_MEMORY_IMAGE_IDENT.len() % size_of::<usize>() == 0 {
-                                len -= 1;
-                            }
-                            for i in 0..len {
-                                let data = ptrace::read(
-                                    ptrace_pid,
-                                    ((syscall.args[0] as usize) + (i * size_of::<usize>()))
-                                        as *mut c_void,
-                                )?;
-                                memory_name
-                                    .extend(data.to_le_bytes().iter().take_while(|i| *i != &0u8));
-                            }
-
-                            if &memory_name == WASM_MEMORY_IMAGE_IDENT.as_bytes() {
-                                break;
-                            }
-                        }
+            other => return Err(eyre!("unexpected wait status after attaching: {other:?}")),
+        }
+
+        self.waiting_for_module = true;
+        self.resume()
+    }
+
+    /// Attaches to a running process and stops it.
+    ///
+    /// Only the thread `pid` is traced, which is the one that runs the module in a
+    /// single-threaded runtime.
+    pub fn attach(&mut self, pid: Pid) -> eyre::Result<()> {
+        if self.is_alive() {
+            return Err(eyre!("there is already a tracee with pid {}", self.pid));
+        }
+
+        // Unlike a spawned tracee, this one outlives us
+        ptrace::seize(pid, ptrace::Options::PTRACE_O_TRACESYSGOOD)
+            .wrap_err_with(|| format!("failed to attach to {pid}"))?;
+        ptrace::interrupt(pid)?;
+        match waitpid(pid, None)? {
+            WaitStatus::Stopped(_, _) | WaitStatus::PtraceEvent(..) => {}
+            other => return Err(eyre!("unexpected wait status after attaching: {other:?}")),
+        }
+
+        self.pid = pid;
+        self.child = None;
+        self.function_mapping = None;
+        self.pending_signal = None;
+        self.waiting_for_module = false;
+        self.state = TraceeState::Stopped(StopReason::Interrupted);
+
+        Ok(())
+    }
+
+    /// Removes the traps and lets the tracee run on its own.
+    pub fn detach(&mut self) -> eyre::Result<()> {
+        if self.is_running() {
+            self.interrupt()?;
+            while self.is_running() {
+                self.poll(true)?;
+            }
+        }
+        self.ensure_stopped()?;
+
+        self.cancel_finish()?;
+        for (addr, original) in std::mem::take(&mut self.traps) {
+            self.write_byte(addr, original)?;
+        }
+        ptrace::detach(self.pid, self.pending_signal.take())?;
+
+        self.forget_tracee();
+        self.state = TraceeState::NotStarted;
+
+        Ok(())
+    }
+
+    /// Takes the pipes of the tracee's stdout and stderr when it's spawned with
+    /// [`LaunchSpec::capture_output`].
+    pub fn take_output(&mut self) -> (Option<ChildStdout>, Option<ChildStderr>) {
+        match &mut self.child {
+            Some(child) => (child.stdout.take(), child.stderr.take()),
+            None => (None, None),
+        }
+    }
+
+    /// Resumes a stopped tracee.
+    pub fn resume(&mut self) -> eyre::Result<()> {
+        if !self.is_stopped() && !self.waiting_for_module {
+            return Err(eyre!("the tracee is not stopped ({})", self.state));
+        }
+
+        let signal = self.pending_signal.take();
+        let pc = self.pc()?;
+        if self.traps.contains_key(&pc) {
+            self.step_over_trap(pc, signal, true)?;
+        } else {
+            self.cont(signal)?;
+        }
+        self.state = TraceeState::Running;
+
+        Ok(())
+    }
+
+    /// Drops the signal that would be delivered when the tracee is resumed, like gdb does for
+    /// `SIGINT`.
+    pub fn discard_signal(&mut self) -> Option<Signal> {
+        self.pending_signal.take()
+    }
+
+    /// Replaces the signal that is delivered when the tracee is resumed.
+    pub fn set_pending_signal(&mut self, signal: Option<Signal>) {
+        self.pending_signal = signal;
+    }
+
+    /// Executes a single instruction of a stopped tracee. The stop is observed by the next
+    /// [`DebuggerCtx::poll`].
+    pub fn step_instruction(&mut self) -> eyre::Result<()> {
+        self.ensure_stopped()?;
+
+        let signal = self.pending_signal.take();
+        let pc = self.pc()?;
+        if self.traps.contains_key(&pc) {
+            self.step_over_trap(pc, signal, false)?;
+        } else {
+            ptrace::step(self.pid, signal)?;
+            self.step = Some(Step {
+                trap: None,
+                then_continue: false,
+            });
+        }
+        self.state = TraceeState::Running;
+
+        Ok(())
+    }
+
+    /// Executes a single instruction like [`DebuggerCtx::step_instruction`], but a call is run
+    /// until it returns, which is reported as [`StopReason::Finished`].
+    pub fn next_instruction(&mut self) -> eyre::Result<()> {
+        self.ensure_stopped()?;
+
+        let regs = self.registers()?;
+        let code = self.read_memory(regs.rip, MAX_INSTRUCTION_LEN)?;
+        let cs = disassembler()?;
+        let insns = cs
+            .disasm_count(&code, regs.rip, 1)
+            .map_err(|e| eyre!("failed to disassemble at {:#x}: {e}", regs.rip))?;
+        let Some(call) = insns
+            .first()
+            .filter(|insn| insn.mnemonic().is_some_and(|m| m.starts_with("call")))
+        else {
+            return self.step_instruction();
+        };
+
+        // the call returns right after itself, with the same stack pointer
+        let addr = regs.rip + call.len() as u64;
+        self.cancel_finish()?;
+        self.finish = Some(Finish { addr, sp: regs.rsp });
+        self.insert_trap(addr)?;
+
+        self.resume()
+    }
+
+    /// Runs the tracee until the current function returns.
+    pub fn finish(&mut self) -> eyre::Result<()> {
+        self.ensure_stopped()?;
+
+        let (addr, sp) = self.return_address()?;
+
+        self.cancel_finish()?;
+        self.finish = Some(Finish { addr, sp });
+        self.insert_trap(addr)?;
+
+        self.resume()
+    }
+
+    /// Where the current function returns to, and the stack pointer right after it returns.
+    pub fn return_address(&self) -> eyre::Result<(u64, u64)> {
+        let slot = self.return_address_slot(&self.registers()?)?;
+
+        Ok((self.read_word(slot)?, slot + 8))
+    }
+
+    /// Reports the return of the function that the tracee is at the entry of as
+    /// [`StopReason::Returned`], with the stack pointer that is returned here. Unlike
+    /// [`DebuggerCtx::finish`], the tracee is not resumed and the other stops don't cancel it.
+    pub fn watch_return(&mut self) -> eyre::Result<u64> {
+        let regs = self.registers()?;
+        let slot = self.return_address_slot(&regs)?;
+        let addr = self.read_word(slot)?;
+
+        let watch = Finish { addr, sp: slot + 8 };
+        self.returns.push(watch);
+        self.insert_trap(addr)?;
+
+        Ok(watch.sp)
+    }
+
+    /// Resumes the tracee, tracing the syscalls until the module is loaded.
+    fn cont(&self, signal: Option<Signal>) -> eyre::Result<()> {
+        if self.waiting_for_module || self.trace_syscalls {
+            ptrace::syscall(self.pid, signal)?;
+        } else {
+            ptrace::cont(self.pid, signal)?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes the original instruction under the trap at `addr`.
+    fn step_over_trap(
+        &mut self,
+        addr: u64,
+        signal: Option<Signal>,
+        then_continue: bool,
+    ) -> eyre::Result<()> {
+        if let Some(&original) = self.traps.get(&addr) {
+            self.write_byte(addr, original)?;
+        }
+        ptrace::step(self.pid, signal)?;
+        self.step = Some(Step {
+            trap: Some(addr),
+            then_continue,
+        });
+
+        Ok(())
+    }
+
+    /// Puts the lifted trap back when a single step is done.
+    fn end_step(&mut self) -> eyre::Result<Option<Step>> {
+        let Some(step) = self.step.take() else {
+            return Ok(None);
+        };
+
+        if let Some(addr) = step.trap
+            && self.traps.contains_key(&addr)
+        {
+            self.write_byte(addr, INT3)?;
+        }
+
+        Ok(Some(step))
+    }
+
+    fn cancel_finish(&mut self) -> eyre::Result<()> {
+        if let Some(finish) = self.finish.take() {
+            self.remove_trap(finish.addr)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops a running tracee. The stop is observed by the next [`DebuggerCtx::poll`].
+    pub fn interrupt(&mut self) -> eyre::Result<()> {
+        if !self.is_running() {
+            return Err(eyre!("the tracee is not running ({})", self.state));
+        }
+
+        ptrace::interrupt(self.pid)?;
+        self.interrupt_requested = true;
+
+        Ok(())
+    }
+
+    /// Kills the tracee and waits until it's gone.
+    pub fn kill(&mut self) -> eyre::Result<()> {
+        if !self.is_alive() {
+            return Err(eyre!("there is no tracee to kill ({})", self.state));
+        }
+
+        signal::kill(self.pid, Signal::SIGKILL)?;
+        while !matches!(self.state, TraceeState::Exited(_)) {
+            self.poll(true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the running tracee has stopped or exited, returns the new state if so.
+    ///
+    /// When `block` is set, this waits until the tracee changes its state.
+    pub fn poll(&mut self, block: bool) -> eyre::Result<Option<TraceeState>> {
+        let flags = (!block).then_some(WaitPidFlag::WNOHANG);
+
+        let new_state = match waitpid(self.pid, flags)? {
+            WaitStatus::StillAlive => return Ok(None),
+            WaitStatus::PtraceSyscall(pid) => {
+                if self.waiting_for_module && self.is_wasm_image_memfd(pid)? {
+                    self.waiting_for_module = false;
+                    TraceeState::Stopped(StopReason::ModuleLoaded)
+                } else if self.interrupt_requested {
+                    TraceeState::Stopped(StopReason::Interrupted)
+                } else if self.trace_syscalls {
+                    TraceeState::Stopped(syscall_stop(pid)?)
+                } else {
+                    self.cont(None)?;
+                    return Ok(None);
+                }
+            }
+            // a leftover of an interrupt that is already reported by another stop
+            WaitStatus::PtraceEvent(_, Signal::SIGTRAP, libc::PTRACE_EVENT_STOP)
+                if !self.interrupt_requested =>
+            {
+                if self.step.is_some() {
+                    // the step is not done yet
+                    ptrace::step(self.pid, None)?;
+                } else {
+                    self.cont(None)?;
+                }
+                return Ok(None);
+            }
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_STOP) => {
+                TraceeState::Stopped(StopReason::Interrupted)
+            }
+            // we don't subscribe to any other event, nothing to report
+            WaitStatus::PtraceEvent(..) => {
+                self.state = TraceeState::Stopped(StopReason::Interrupted);
+                self.resume()?;
+                return Ok(None);
+            }
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => match self.handle_trap()? {
+                Some(state) => state,
+                None => return Ok(None),
+            },
+            // like gdb, pass the signals that are a part of the normal operation silently
+            WaitStatus::Stopped(
+                pid,
+                signal @ (Signal::SIGCHLD | Signal::SIGWINCH | Signal::SIGURG),
+            ) => {
+                if self.interrupt_requested {
+                    self.end_step()?;
+                    self.pending_signal = Some(signal);
+                    return self.report(TraceeState::Stopped(StopReason::Interrupted));
+                }
+                if self.step.is_some() {
+                    // the step is not done yet
+                    ptrace::step(pid, signal)?;
+                } else {
+                    self.cont(Some(signal))?;
+                }
+                return Ok(None);
+            }
+            WaitStatus::Stopped(_, signal) => {
+                self.end_step()?;
+                self.pending_signal = Some(signal);
+                TraceeState::Stopped(StopReason::Signal(signal))
+            }
+            WaitStatus::Exited(_, code) => TraceeState::Exited(ExitReason::Code(code)),
+            WaitStatus::Signaled(_, signal, _) => TraceeState::Exited(ExitReason::Signal(signal)),
+            WaitStatus::Continued(_) => return Ok(None),
+        };
+
+        self.report(new_state)
+    }
+
+    /// Updates the state with a stop or an exit that is reported to the caller of
+    /// [`DebuggerCtx::poll`].
+    fn report(&mut self, new_state: TraceeState) -> eyre::Result<Option<TraceeState>> {
+        self.interrupt_requested = false;
+
+        match &new_state {
+            TraceeState::Exited(_) => self.forget_tracee(),
+            // like gdb, any other stop abandons `finish`
+            TraceeState::Stopped(reason) if *reason != StopReason::Finished => {
+                self.cancel_finish()?
+            }
+            _ => {}
+        }
+
+        self.state = new_state.clone();
+
+        Ok(Some(new_state))
+    }
+
+    /// Handles a `SIGTRAP`, which is the end of a single step, one of our traps or a signal
+    /// for the tracee. Returns `None` if the tracee is resumed silently.
+    fn handle_trap(&mut self) -> eyre::Result<Option<TraceeState>> {
+        if let Some(step) = self.end_step()? {
+            if !step.then_continue {
+                return Ok(Some(TraceeState::Stopped(StopReason::Step)));
+            }
+            if self.interrupt_requested {
+                return Ok(Some(TraceeState::Stopped(StopReason::Interrupted)));
+            }
+            self.cont(None)?;
+            return Ok(None);
+        }
+
+        let mut regs = self.registers()?;
+        // the trap is reported after executing `int3`
+        let addr = regs.rip.wrapping_sub(1);
+        if !self.traps.contains_key(&addr) {
+            self.pending_signal = Some(Signal::SIGTRAP);
+            return Ok(Some(TraceeState::Stopped(StopReason::Signal(
+                Signal::SIGTRAP,
+            ))));
+        }
+
+        regs.rip = addr;
+        ptrace::setregs(self.pid, regs)?;
+
+        if let Some(finish) = self.finish
+            && finish.addr == addr
+            && regs.rsp >= finish.sp
+        {
+            self.cancel_finish()?;
+            return Ok(Some(TraceeState::Stopped(StopReason::Finished)));
+        }
+
+        if let Some(i) = self
+            .returns
+            .iter()
+            .rposition(|watch| watch.addr == addr && regs.rsp >= watch.sp)
+        {
+            // the deeper ones are unwound without returning
+            let watches = self.returns.split_off(i);
+            for watch in &watches {
+                self.remove_trap(watch.addr)?;
+            }
+            let sp = watches[0].sp;
+            return Ok(Some(TraceeState::Stopped(StopReason::Returned { sp })));
+        }
+
+        let ids: Vec<BreakpointId> = self.breakpoints.all_at(addr).map(|bp| bp.id).collect();
+        for id in ids {
+            // a script of another breakpoint here may have deleted it
+            let Some(bp) = self.breakpoints.get(id).filter(|bp| bp.enabled) else {
+                continue;
+            };
+            match bp.condition.as_ref().map(|c| c.holds(self)) {
+                Some(Ok(false)) => continue,
+                Some(Err(e)) => {
+                    let error = format!("{e:#}");
+                    return Ok(Some(TraceeState::Stopped(StopReason::ConditionError {
+                        id,
+                        error,
+                    })));
+                }
+                None | Some(Ok(true)) => {}
+            }
+
+            let bp = self.breakpoints.get_mut(id).expect("at the trap");
+            let Hit::Counted { expired } = bp.hit() else {
+                continue;
+            };
+            let script = bp.script.clone();
+            let hits = bp.hits;
+            let mut stop = true;
+            // out of the breakpoint while the message renders with the ctx
+            if let Some(mut log) = bp.log.take() {
+                let message = log.log(Instant::now(), |format| format.render(self));
+                self.breakpoints.get_mut(id).expect("at the trap").log = Some(log);
+                if let Some((dropped, text)) = message {
+                    self.log.push(LogMessage {
+                        id,
+                        hits,
+                        dropped,
+                        text,
+                    });
+                }
+                stop = false;
+            }
+            if let Some(script) = script {
+                match script.run_on_hit(self, id) {
+                    Ok(stops) => stop = stops,
+                    Err(e) => {
+                        let error = format!("{e:#}");
+                        return Ok(Some(TraceeState::Stopped(StopReason::ScriptError {
+                            id,
+                            error,
+                        })));
                     }
                 }
-                _ => {}
+            }
+
+            if expired && self.breakpoints.remove(id).is_some() {
+                self.remove_trap(addr)?;
+            }
+            if stop {
+                return Ok(Some(TraceeState::Stopped(StopReason::Breakpoint(id))));
+            }
+        }
+
+        // only logpoints, the conditions don't hold or the scripts let the tracee continue, or a
+        // recursive call returned to the address that `finish` waits for
+        self.step_over_trap(addr, None, true)?;
+        Ok(None)
+    }
+
+    /// Resets everything that is only valid for the current tracee. The breakpoints are kept,
+    /// they become pending.
+    fn forget_tracee(&mut self) {
+        self.waiting_for_module = false;
+        self.pending_signal = None;
+        // the status is already reaped by `waitpid`
+        self.child = None;
+        self.function_mapping = None;
+        self.traps.clear();
+        self.step = None;
+        self.finish = None;
+        self.returns.clear();
+        for bp in self.breakpoints.iter_mut() {
+            bp.addr = None;
+        }
+    }
+
+    pub fn registers(&self) -> eyre::Result<user_regs_struct> {
+        Ok(ptrace::getregs(self.pid)?)
+    }
+
+    pub fn set_registers(&self, regs: user_regs_struct) -> eyre::Result<()> {
+        self.ensure_stopped()?;
+        Ok(ptrace::setregs(self.pid, regs)?)
+    }
+
+    /// The x87 and SSE registers, in the `fxsave` layout.
+    pub fn fp_registers(&self) -> eyre::Result<user_fpregs_struct> {
+        Ok(ptrace::getregset::<NT_PRFPREG>(self.pid)?)
+    }
+
+    pub fn set_fp_registers(&self, regs: user_fpregs_struct) -> eyre::Result<()> {
+        self.ensure_stopped()?;
+        Ok(ptrace::setregset::<NT_PRFPREG>(self.pid, regs)?)
+    }
+
+    pub fn pc(&self) -> eyre::Result<u64> {
+        Ok(self.registers()?.rip)
+    }
+
+    /// Reads `len` bytes of the tracee's memory at `addr`. The bytes under our traps are the
+    /// original ones, the breakpoints are only in [`DebuggerCtx::breakpoints`].
+    pub fn read_memory(&self, addr: u64, len: usize) -> eyre::Result<Vec<u8>> {
+        let end = addr
+            .checked_add(len as u64)
+            .ok_or_else(|| eyre!("{len:#x} bytes at {addr:#x} wrap around"))?;
+        let mut buf = self.read_live_memory(addr, len)?;
+        let range = addr..end;
+        for (&trap, &original) in &self.traps {
+            if range.contains(&trap) {
+                buf[(trap - addr) as usize] = original;
             }
         }
 
+        Ok(buf)
+    }
+
+    /// Reads the memory as it is, with our traps.
+    fn read_live_memory(&self, addr: u64, len: usize) -> eyre::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(len.next_multiple_of(size_of::<u64>()));
+        while buf.len() < len {
+            let word = self.read_word(addr + buf.len() as u64)?;
+            buf.extend_from_slice(&word.to_le_bytes());
+        }
+        buf.truncate(len);
+
+        Ok(buf)
+    }
+
+    /// Writes `bytes` to the tracee's memory at `addr`. The traps stay in place, the bytes
+    /// under them become the original ones that are put back when the traps are removed.
+    pub fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> eyre::Result<()> {
+        self.ensure_stopped()?;
+
+        let range = addr..addr + bytes.len() as u64;
+        let word_len = size_of::<u64>() as u64;
+        let start = addr - addr % word_len;
+        for word_addr in (start..range.end).step_by(word_len as usize) {
+            let mut word = self.read_word(word_addr)?.to_le_bytes();
+            for (byte_addr, byte) in (word_addr..).zip(&mut word) {
+                if !range.contains(&byte_addr) {
+                    continue;
+                }
+                let new = bytes[(byte_addr - addr) as usize];
+                match self.traps.get_mut(&byte_addr) {
+                    Some(original) => *original = new,
+                    None => *byte = new,
+                }
+            }
+            ptrace::write(self.pid, word_addr as *mut c_void, i64::from_le_bytes(word))
+                .wrap_err_with(|| format!("failed to write the memory at {word_addr:#x}"))?;
+        }
+
         Ok(())
     }
 
-    pub fn parse_perfmap(&mut self, bin_name: &str) -> eyre::Result<()> {
-        self.function_mapping = Some(FunctionMapping::generate_from_perfmap_file_with_pid(
-            bin_name,
-            self.pid.as_raw() as u32,
-        )?);
+    /// The wasm argument `index` of the current function, see [`wasm_abi::argument`].
+    pub fn wasm_argument(&self, index: usize) -> eyre::Result<u64> {
+        wasm_abi::argument(&self.registers()?, index, |addr| self.read_word(addr))
+    }
+
+    /// See [`wasm_abi::locate_linear_memory`].
+    pub fn linear_memory_base(&self) -> eyre::Result<u64> {
+        if !self.is_alive() {
+            return Err(eyre!("there is no tracee ({})", self.state));
+        }
+        wasm_abi::locate_linear_memory(self.pid)
+    }
+
+    fn read_word(&self, addr: u64) -> eyre::Result<u64> {
+        let word = ptrace::read(self.pid, addr as *mut c_void)
+            .wrap_err_with(|| format!("failed to read the memory at {addr:#x}"))?;
+
+        Ok(word as u64)
+    }
+
+    /// Note that ptrace writes one word, *NOT* one byte. Hence, the whole word is read first,
+    /// then the byte is changed.
+    fn write_byte(&self, addr: u64, byte: u8) -> eyre::Result<()> {
+        let word = self.read_word(addr)?;
+        ptrace::write(
+            self.pid,
+            addr as *mut c_void,
+            ((word & !0xFF) | byte as u64) as i64,
+        )
+        .wrap_err_with(|| format!("failed to write the memory at {addr:#x}"))?;
 
         Ok(())
     }
 
-    pub fn disassemble(&self, index: usize) -> eyre::Result<String> {
-        let Some(mapping) = &self.function_mapping else {
-            return Ok("".into());
+    /// A byte of the code as it's compiled, i.e. without our traps.
+    fn read_code_byte(&self, addr: u64) -> eyre::Result<u8> {
+        match self.traps.get(&addr) {
+            Some(original) => Ok(*original),
+            None => Ok(self.read_word(addr)? as u8),
+        }
+    }
+
+    /// Replaces the instruction at `addr` with `int3`, unless there is already a trap.
+    fn insert_trap(&mut self, addr: u64) -> eyre::Result<()> {
+        if self.traps.contains_key(&addr) {
+            return Ok(());
+        }
+
+        let original = self.read_word(addr)? as u8;
+        self.write_byte(addr, INT3)?;
+        self.traps.insert(addr, original);
+
+        Ok(())
+    }
+
+    /// Puts the original byte back at `addr`, unless a breakpoint, `finish` or a watched return
+    /// needs the trap.
+    fn remove_trap(&mut self, addr: u64) -> eyre::Result<()> {
+        if self.breakpoints.at(addr).is_some()
+            || self.finish.is_some_and(|f| f.addr == addr)
+            || self.returns.iter().any(|watch| watch.addr == addr)
+        {
+            return Ok(());
+        }
+
+        if let Some(original) = self.traps.remove(&addr) {
+            self.write_byte(addr, original)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `location` to an address in the tracee.
+    pub fn resolve(&self, location: &Location) -> eyre::Result<u64> {
+        match location {
+            Location::Address(addr) => Ok(*addr),
+            Location::Register(name) => {
+                self.ensure_stopped()?;
+                registers::read(&self.registers()?, name)
+                    .ok_or_else(|| eyre!("unknown register `${name}`"))
+            }
+            Location::Function { name, offset } => {
+                let mapping = self
+                    .function_mapping
+                    .as_ref()
+                    .ok_or_else(|| eyre!("the module is not loaded yet"))?;
+                let meta = mapping
+                    .get_function(name)
+                    .ok_or_else(|| eyre!("no function named `{name}`"))?;
+                if *offset >= meta.size {
+                    return Err(eyre!(
+                        "`{name}` is only {:#x} bytes long, the offset {offset:#x} is out of it",
+                        meta.size
+                    ));
+                }
+
+                Ok(meta.addr + offset)
+            }
+        }
+    }
+
+    /// Adds a breakpoint. It's pending until the module is loaded if it refers to a function,
+    /// see [`DebuggerCtx::arm_breakpoints`].
+    pub fn add_breakpoint(&mut self, location: Location) -> eyre::Result<Breakpoint> {
+        let addr = match &location {
+            Location::Function { .. } if self.function_mapping.is_none() => None,
+            Location::Address(_) if !self.is_alive() => None,
+            _ => {
+                self.ensure_stopped()?;
+                let addr = self.resolve(&location)?;
+                self.insert_trap(addr)?;
+                Some(addr)
+            }
         };
 
-        let (_, meta) = (&mapping).into_iter().skip(index).next().unwrap();
+        Ok(self.breakpoints.add(location, addr).clone())
+    }
+
+    /// Replaces the condition of a breakpoint, `None` makes it unconditional.
+    pub fn set_condition(
+        &mut self,
+        id: BreakpointId,
+        condition: Option<Expr>,
+    ) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        bp.condition = condition;
+
+        Ok(bp.clone())
+    }
+
+    /// Turns a breakpoint into a logpoint, or back into a breakpoint with `None`.
+    pub fn set_log(&mut self, id: BreakpointId, log: Option<Logpoint>) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        bp.log = log;
+
+        Ok(bp.clone())
+    }
+
+    /// Attaches a script that decides whether to stop at a breakpoint, see [`Script`].
+    pub fn set_script(
+        &mut self,
+        id: BreakpointId,
+        script: Option<Script>,
+    ) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        bp.script = script;
+
+        Ok(bp.clone())
+    }
+
+    /// A disabled breakpoint lets the tracee pass without counting the hits.
+    pub fn set_enabled(&mut self, id: BreakpointId, enabled: bool) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        bp.enabled = enabled;
+
+        Ok(bp.clone())
+    }
+
+    /// Lets the tracee pass the next `count` hits of a breakpoint without stopping.
+    pub fn set_ignore_count(&mut self, id: BreakpointId, count: u64) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        bp.ignore_count = count;
+
+        Ok(bp.clone())
+    }
+
+    /// Deletes a breakpoint once it stops `stops` more times, never with `None`. A temporary
+    /// breakpoint is deleted after its first stop.
+    pub fn set_remaining_stops(
+        &mut self,
+        id: BreakpointId,
+        stops: Option<u64>,
+    ) -> eyre::Result<Breakpoint> {
+        if stops == Some(0) {
+            return Err(eyre!("the breakpoint needs to stop at least once"));
+        }
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        bp.remaining_stops = stops;
+
+        Ok(bp.clone())
+    }
+
+    /// Limits the messages of a logpoint per second, zero means unlimited.
+    pub fn set_rate_limit(
+        &mut self,
+        id: BreakpointId,
+        per_second: u32,
+    ) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get_mut(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        let log = bp
+            .log
+            .as_mut()
+            .ok_or_else(|| eyre!("breakpoint {id} is not a logpoint"))?;
+        log.rate_limit.per_second = per_second;
+
+        Ok(bp.clone())
+    }
+
+    /// The messages that the logpoints and the breakpoint scripts logged since the last call.
+    pub fn take_log(&mut self) -> Vec<LogMessage> {
+        std::mem::take(&mut self.log)
+    }
+
+    /// Logs a line that the script of a breakpoint printed on its hit number `hits`.
+    pub fn log_line(&mut self, id: BreakpointId, hits: u64, text: String) {
+        self.log.push(LogMessage {
+            id,
+            hits,
+            dropped: 0,
+            text,
+        });
+    }
+
+    pub fn delete_breakpoint(&mut self, id: BreakpointId) -> eyre::Result<Breakpoint> {
+        let bp = self
+            .breakpoints
+            .get(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        if bp.addr.is_some() {
+            self.ensure_stopped()?;
+        }
+
+        let bp = self.breakpoints.remove(id).expect("checked above");
+        if let Some(addr) = bp.addr {
+            self.remove_trap(addr)?;
+        }
+
+        Ok(bp)
+    }
 
-        let mut buf = Vec::new();
-        let mut read_len = meta.size / size_of::<usize>() as u64 + 1;
-        if meta.size % (size_of::<usize>() as u64) == 0 {
-            read_len -= 1;
+    /// Resolves the pending breakpoints and inserts their traps. The ones that can't be
+    /// resolved stay pending, the reasons are returned.
+    pub fn arm_breakpoints(&mut self) -> Vec<eyre::Report> {
+        if !self.is_stopped() {
+            return Vec::new();
         }
 
-        for i in 0..read_len {
-            let read_data = ptrace::read(self.pid, (meta.addr + (8 * i)) as *mut c_void).unwrap();
+        let mut errors = Vec::new();
+        for id in self.breakpoints.ids() {
+            let Some(bp) = self.breakpoints.get(id).filter(|bp| bp.addr.is_none()) else {
+                continue;
+            };
+            let location = bp.location.clone();
 
-            buf.extend_from_slice(&read_data.to_le_bytes());
+            let addr = self
+                .resolve(&location)
+                .and_then(|addr| self.insert_trap(addr).map(|()| addr));
+            match addr {
+                Ok(addr) => {
+                    if let Some(bp) = self.breakpoints.get_mut(id) {
+                        bp.addr = Some(addr);
+                    }
+                }
+                Err(e) => errors
+                    .push(e.wrap_err(format!("breakpoint {id} at `{location}` stays pending"))),
+            }
         }
 
-        let cs = Capstone::new()
-            .x86()
-            .mode(arch::x86::ArchMode::Mode64)
-            .syntax(arch::x86::ArchSyntax::Intel)
-            .detail(true)
-            .build()
-            .expect("Failed to create Capstone object");
+        errors
+    }
 
-        let mut read_size = 0;
-        let mut disas_str = String::new();
+    /// The program counter of the innermost frame, followed by the return addresses of the
+    /// outer ones, found by walking the frame pointer chain.
+    pub fn backtrace(&self) -> eyre::Result<Vec<u64>> {
+        self.backtrace_from(&self.registers()?)
+    }
+
+    /// Like [`DebuggerCtx::backtrace`], from the registers of another stopped thread of the
+    /// tracee, whose memory is read through the traced one.
+    pub fn backtrace_from(&self, regs: &user_regs_struct) -> eyre::Result<Vec<u64>> {
+        self.ensure_stopped()?;
+
+        let mut frames = vec![regs.rip];
 
-        let mut disas_iter = cs.disasm_iter(&buf, 0).unwrap();
+        let slot = self.return_address_slot(regs)?;
+        frames.push(self.read_word(slot)?);
+
+        // unless the innermost frame is set up, `rbp` still points to the caller's frame
+        let mut fp = if slot == regs.rbp + 8 {
+            self.read_word(regs.rbp)?
+        } else {
+            regs.rbp
+        };
 
-        while let Some(instr) = disas_iter.next() {
-            if read_size >= meta.size {
+        while frames.len() < MAX_FRAMES && fp != 0 {
+            let (Ok(ret), Ok(next)) = (self.read_word(fp + 8), self.read_word(fp)) else {
+                break;
+            };
+            if ret == 0 {
+                break;
+            }
+            frames.push(ret);
+
+            // the callers' frames are above on the stack, anything else is not a frame pointer
+            if next <= fp {
                 break;
             }
-            read_size += instr.len() as u64;
-            disas_str += &format!("{instr}\n");
+            fp = next;
+        }
+
+        Ok(frames)
+    }
+
+    /// Where the return address of the innermost frame is stored.
+    ///
+    /// Cranelift keeps the frame pointers on x86-64, so this is `rbp + 8` except before the
+    /// prologue sets up the frame and after the epilogue tears it down.
+    fn return_address_slot(&self, regs: &user_regs_struct) -> eyre::Result<u64> {
+        let pc = regs.rip;
+        let offset = self
+            .function_mapping
+            .as_ref()
+            .and_then(|mapping| mapping.lookup(pc))
+            .map(|(_, offset)| offset);
+
+        if offset == Some(0) || self.read_code_byte(pc)? == RET {
+            return Ok(regs.rsp);
+        }
+
+        // `push rbp` is done, `mov rbp, rsp` is not
+        if offset == Some(1) && self.read_code_byte(pc - 1)? == PUSH_RBP {
+            return Ok(regs.rsp + 8);
         }
 
-        Ok(disas_str)
+        Ok(regs.rbp + 8)
+    }
+
+    /// wasmtime uses `memfd_create` to create an anonymous in-memory file. This happens
+    /// after the `perf` is written under `/tmp/perf-PID.map` and before executing the
+    /// WASM binary. This means we can inject our traps right at this moment.
+    ///
+    /// https://github.com/bytecodealliance/wasmtime/blob/ee7e125309f5bc784b8feb8969261ae41fb4703b/crates/wasmtime/src/runtime/vm/sys/unix/vm.rs#L120
+    fn is_wasm_image_memfd(&self, pid: Pid) -> eyre::Result<bool> {
+        let syscall = ptrace::syscall_info(pid)?;
+        if syscall.op != libc::PTRACE_SYSCALL_INFO_ENTRY {
+            return Ok(false);
+        }
+        let syscall = unsafe { syscall.u.entry };
+
+        if syscall.nr != libc::SYS_memfd_create as u64 {
+            return Ok(false);
+        }
+
+        let mut memory_name: Vec<u8> = Vec::new();
+        let len = WASM_MEMORY_IMAGE_IDENT.len().div_ceil(size_of::<usize>());
+        for i in 0..len {
+            let data = ptrace::read(
+                pid,
+                ((syscall.args[0] as usize) + (i * size_of::<usize>())) as *mut c_void,
+            )?;
+            memory_name.extend(data.to_le_bytes().iter().take_while(|i| *i != &0u8));
+        }
+
+        Ok(memory_name == WASM_MEMORY_IMAGE_IDENT.as_bytes())
+    }
+
+    pub fn parse_perfmap(&mut self, bin_name: &str) -> eyre::Result<Arc<FunctionMapping>> {
+        let mapping = Arc::new(FunctionMapping::generate_from_perfmap_file_with_pid(
+            bin_name,
+            self.pid.as_raw() as u32,
+            self.perfmap_path.as_deref(),
+        )?);
+        self.function_mapping = Some(mapping.clone());
+
+        Ok(mapping)
+    }
+
+    /// The instructions of the function, see [`disassembly::disassemble`].
+    pub fn disassemble(&self, id: FunctionId) -> eyre::Result<Vec<Instruction>> {
+        let Some(mapping) = &self.function_mapping else {
+            return Ok(Vec::new());
+        };
+
+        let meta = mapping
+            .function(id)
+            .ok_or_else(|| eyre!("unknown function {id}"))?;
+
+        let code = self
+            .read_memory(meta.addr, meta.size as usize)
+            .wrap_err_with(|| format!("failed to read the code of `{}`", meta.symbol))?;
+
+        disassembly::disassemble(&code, meta.addr, mapping, |addr, len| {
+            self.read_memory(addr, len).ok()
+        })
+        .wrap_err_with(|| format!("failed to disassemble `{}`", meta.symbol))
+    }
+}
+
+/// The syscall that the tracee is entering or leaving.
+fn syscall_stop(pid: Pid) -> eyre::Result<StopReason> {
+    let info = ptrace::syscall_info(pid)?;
+    match info.op {
+        libc::PTRACE_SYSCALL_INFO_ENTRY => {
+            let entry = unsafe { info.u.entry };
+            Ok(StopReason::Syscall {
+                nr: entry.nr,
+                args: entry.args,
+            })
+        }
+        libc::PTRACE_SYSCALL_INFO_EXIT => {
+            let exit = unsafe { info.u.exit };
+            Ok(StopReason::SyscallReturn { ret: exit.sval })
+        }
+        op => Err(eyre!("unexpected syscall stop {op}")),
+    }
+}
+
+/// A disassembler for the JIT-compiled x86-64 code, in the Intel syntax.
+pub fn disassembler() -> eyre::Result<Capstone> {
+    Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode64)
+        .syntax(arch::x86::ArchSyntax::Intel)
+        .detail(true)
+        .build()
+        .map_err(|e| eyre!("failed to create the disassembler: {e}"))
+}
+
+impl Drop for DebuggerCtx {
+    fn drop(&mut self) {
+        // Don't leave a stopped tracee behind when we spawned it, and don't leave the traps in
+        // a tracee that we attached to.
+        if self.child.is_some() && self.is_alive() {
+            let _ = self.kill();
+        } else if self.is_alive() {
+            let _ = self.detach();
+        }
     }
 }
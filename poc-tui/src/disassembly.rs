@@ -0,0 +1,116 @@
+//! The disassembly of a function as the code pane shows it: the instructions at their real
+//! addresses, with the targets of the calls and the jumps, and the RIP-relative operands,
+//! resolved.
+
+use capstone::{
+    RegId,
+    arch::{
+        ArchOperand,
+        x86::{X86OperandType, X86Reg},
+    },
+};
+use color_eyre::eyre::{self, eyre};
+
+use crate::{debugger_ctx::disassembler, function_mapping::FunctionMapping};
+
+/// A disassembled instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub addr: u64,
+    pub mnemonic: String,
+    pub operands: String,
+    /// What the operands refer to, e.g. `wasm_binary::leaf+0x0` for a call or
+    /// `[0x7f0000001180] = 0x3ff0000000000000` for a constant
+    pub comment: Option<String>,
+}
+
+impl Instruction {
+    /// Whether the instruction changes the control flow, i.e. a jump, a call or a return.
+    pub fn is_branch(&self) -> bool {
+        self.mnemonic.starts_with('j')
+            || self.mnemonic.starts_with("call")
+            || self.mnemonic.starts_with("ret")
+    }
+}
+
+/// Disassembles `code`, which is at `addr`, linearly. `read` reads the memory of the tracee
+/// for the RIP-relative operands, `None` if it can't.
+pub fn disassemble(
+    code: &[u8],
+    addr: u64,
+    mapping: &FunctionMapping,
+    read: impl Fn(u64, usize) -> Option<Vec<u8>>,
+) -> eyre::Result<Vec<Instruction>> {
+    let cs = disassembler()?;
+    let insns = cs
+        .disasm_all(code, addr)
+        .map_err(|e| eyre!("failed to disassemble at {addr:#x}: {e}"))?;
+
+    let mut instructions = Vec::with_capacity(insns.len());
+    for insn in insns.iter() {
+        let pc = insn.address();
+        let next = pc + insn.len() as u64;
+        let mnemonic = insn.mnemonic().unwrap_or_default();
+        let detail = cs
+            .insn_detail(insn)
+            .map_err(|e| eyre!("failed to decode at {pc:#x}: {e}"))?;
+
+        let mut comment = None;
+        for op in detail.arch_detail().operands() {
+            let ArchOperand::X86Operand(op) = op else {
+                continue;
+            };
+            comment = match op.op_type {
+                X86OperandType::Imm(target)
+                    if mnemonic.starts_with('j') || mnemonic.starts_with("call") =>
+                {
+                    mapping.symbolize(target as u64)
+                }
+                X86OperandType::Mem(mem) if mem.base() == RegId(X86Reg::X86_REG_RIP as _) => {
+                    let target = next.wrapping_add_signed(mem.disp());
+                    let symbol = mapping
+                        .symbolize(target)
+                        .map(|symbol| format!(" <{symbol}>"))
+                        .unwrap_or_default();
+                    if mnemonic == "lea" {
+                        Some(format!("{target:#x}{symbol}"))
+                    } else {
+                        let value = read(target, op.size as usize)
+                            .map(|bytes| format_constant(&bytes, mapping))
+                            .unwrap_or_else(|| "?".into());
+                        Some(format!("[{target:#x}]{symbol} = {value}"))
+                    }
+                }
+                _ => continue,
+            };
+            break;
+        }
+
+        instructions.push(Instruction {
+            addr: pc,
+            mnemonic: mnemonic.into(),
+            operands: insn.op_str().unwrap_or_default().into(),
+            comment,
+        });
+    }
+
+    Ok(instructions)
+}
+
+/// A little-endian constant in hexadecimal, with the function that it points into if any.
+fn format_constant(bytes: &[u8], mapping: &FunctionMapping) -> String {
+    let digits: String = bytes
+        .iter()
+        .rev()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if bytes.len() != size_of::<u64>() {
+        return format!("0x{digits}");
+    }
+
+    let value = u64::from_le_bytes(bytes.try_into().expect("8 bytes"));
+    match mapping.symbolize(value) {
+        Some(symbol) => format!("{value:#x} <{symbol}>"),
+        None => format!("{value:#x}"),
+    }
+}
@@ -1,11 +1,22 @@
 use color_eyre::eyre::WrapErr;
 use crossterm::event::{self, Event as CrosstermEvent};
+use nix::unistd::Pid;
 use std::{
-    sync::mpsc,
+    sync::{Arc, mpsc},
     thread,
     time::{Duration, Instant},
 };
 
+use crate::{
+    breakpoint::Breakpoint,
+    debugger_ctx::{ExitReason, StopReason},
+    disassembly::Instruction,
+    function_mapping::{FunctionId, FunctionMapping},
+    launch::OutputLine,
+    notification::Notification,
+    registers::Snapshot,
+};
+
 /// The frequency at which tick events are emitted.
 const TICK_FPS: f64 = 30.0;
 
@@ -35,6 +46,35 @@ pub enum Event {
 pub enum AppEvent {
     /// Quit the application.
     Quit,
+    /// The debugger spawned the tracee, or attached to it.
+    TraceeStarted(Pid),
+    /// The tracee is resumed.
+    TraceeRunning,
+    TraceeStopped {
+        pid: Pid,
+        reason: StopReason,
+        pc: Option<u64>,
+    },
+    TraceeExited {
+        pid: Pid,
+        reason: ExitReason,
+    },
+    /// The functions of the JIT-compiled module are known.
+    ModuleDiscovered(Arc<FunctionMapping>),
+    Disassembly {
+        id: FunctionId,
+        instructions: Vec<Instruction>,
+    },
+    /// The registers of the stopped tracee, also after one is written.
+    Registers(Snapshot),
+    /// The breakpoints are added, removed, armed or they became pending.
+    BreakpointsChanged(Vec<Breakpoint>),
+    /// The result of a console command.
+    CommandOutput(Vec<String>),
+    /// The tracee printed a line.
+    ProgramOutput(OutputLine),
+    /// Something to report to the user, e.g. a failed debugger command.
+    Notify(Notification),
 }
 
 /// Terminal event handler.
@@ -46,6 +86,12 @@ pub struct EventHandler {
     receiver: mpsc::Receiver<Event>,
 }
 
+impl Default for EventHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EventHandler {
     /// Constructs a new instance of [`EventHandler`] and spawns a new thread to handle events.
     pub fn new() -> Self {
@@ -68,6 +114,11 @@ impl EventHandler {
         Ok(self.receiver.recv()?)
     }
 
+    /// Returns a sender that can be used to emit events from other threads.
+    pub fn sender(&self) -> mpsc::Sender<Event> {
+        self.sender.clone()
+    }
+
     /// Queue an app event to be sent to the event receiver.
     ///
     /// This is useful for sending events to the event handler which will be processed by the next
@@ -0,0 +1,682 @@
+use std::fmt;
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+
+use crate::{breakpoint::parse_number, debugger_ctx::DebuggerCtx, registers};
+
+/// An expression over the state of a stopped tracee, e.g. the condition of a breakpoint:
+///
+/// ```text
+/// arg1 == 40 && *(u32*)(arg0 + 4) != 0 || $rax > 0x10
+/// ```
+///
+/// - `$REG` is a register, `argN` is the wasm argument `N`, see [`crate::wasm_abi::argument`]
+/// - `*(TYPE*)ADDR` reads a `TYPE` from the linear memory at the wasm address `ADDR`
+/// - `(TYPE)VALUE` truncates `VALUE` to `TYPE`, and sign-extends it for the signed types
+/// - the operators of C, with its precedence: `|| && | ^ & == != < <= > >= << >> + - * / %`
+///   and the unary `! - ~`
+///
+/// The types are `u8`, `u16`, `u32`, `u64`, `i8`, `i16`, `i32` and `i64`. Every value is a
+/// 64-bit signed integer, the comparisons are `1` or `0`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    /// As the user wrote it, for the display
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> eyre::Result<Self> {
+        let source = source.trim();
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.parse_binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(eyre!("unexpected `{token}` in `{source}`"));
+        }
+
+        Ok(Expr {
+            source: source.into(),
+            root,
+        })
+    }
+
+    /// Evaluates the expression in the stopped tracee of `ctx`.
+    pub fn eval(&self, ctx: &DebuggerCtx) -> eyre::Result<i64> {
+        self.root
+            .eval(ctx)
+            .wrap_err_with(|| format!("failed to evaluate `{}`", self.source))
+    }
+
+    /// Evaluates the expression as a condition, which holds when it's not zero.
+    pub fn holds(&self, ctx: &DebuggerCtx) -> eyre::Result<bool> {
+        Ok(self.eval(ctx)? != 0)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Self> {
+        let ty = match name {
+            "u8" => Type::U8,
+            "u16" => Type::U16,
+            "u32" => Type::U32,
+            "u64" => Type::U64,
+            "i8" => Type::I8,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            _ => return None,
+        };
+
+        Some(ty)
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::U8 | Type::I8 => 1,
+            Type::U16 | Type::I16 => 2,
+            Type::U32 | Type::I32 => 4,
+            Type::U64 | Type::I64 => 8,
+        }
+    }
+
+    fn is_signed(self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64)
+    }
+
+    /// Truncates `value` to the type, sign-extending the signed ones.
+    fn convert(self, value: u64) -> i64 {
+        let shift = 64 - 8 * self.size() as u32;
+        if self.is_signed() {
+            ((value << shift) as i64) >> shift
+        } else {
+            ((value << shift) >> shift) as i64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinaryOp {
+    /// The operators by their precedence, the loosest first.
+    const LEVELS: &[&[(&str, BinaryOp)]] = &[
+        &[("||", BinaryOp::Or)],
+        &[("&&", BinaryOp::And)],
+        &[("|", BinaryOp::BitOr)],
+        &[("^", BinaryOp::BitXor)],
+        &[("&", BinaryOp::BitAnd)],
+        &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+        &[
+            ("<", BinaryOp::Lt),
+            ("<=", BinaryOp::Le),
+            (">", BinaryOp::Gt),
+            (">=", BinaryOp::Ge),
+        ],
+        &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+        &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+        &[
+            ("*", BinaryOp::Mul),
+            ("/", BinaryOp::Div),
+            ("%", BinaryOp::Rem),
+        ],
+    ];
+
+    fn apply(self, lhs: i64, rhs: i64) -> eyre::Result<i64> {
+        let value = match self {
+            // short-circuited by `Node::eval`
+            BinaryOp::Or => (lhs != 0 || rhs != 0) as i64,
+            BinaryOp::And => (lhs != 0 && rhs != 0) as i64,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXor => lhs ^ rhs,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::Eq => (lhs == rhs) as i64,
+            BinaryOp::Ne => (lhs != rhs) as i64,
+            BinaryOp::Lt => (lhs < rhs) as i64,
+            BinaryOp::Le => (lhs <= rhs) as i64,
+            BinaryOp::Gt => (lhs > rhs) as i64,
+            BinaryOp::Ge => (lhs >= rhs) as i64,
+            BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::Shr => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            BinaryOp::Div | BinaryOp::Rem if rhs == 0 => return Err(eyre!("division by zero")),
+            BinaryOp::Div => lhs.wrapping_div(rhs),
+            BinaryOp::Rem => lhs.wrapping_rem(rhs),
+        };
+
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Literal(i64),
+    Register(String),
+    Argument(usize),
+    /// `*(TYPE*)ADDR`, from the linear memory
+    Read {
+        ty: Type,
+        addr: Box<Node>,
+    },
+    Cast {
+        ty: Type,
+        value: Box<Node>,
+    },
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, ctx: &DebuggerCtx) -> eyre::Result<i64> {
+        let value = match self {
+            Node::Literal(value) => *value,
+            Node::Register(name) => {
+                let regs = ctx.registers()?;
+                registers::read(&regs, name).ok_or_else(|| eyre!("unknown register `${name}`"))?
+                    as i64
+            }
+            Node::Argument(index) => ctx.wasm_argument(*index)? as i64,
+            Node::Read { ty, addr } => {
+                // wasm32 addresses are 32 bits
+                let addr = addr.eval(ctx)? as u32;
+                let bytes = ctx
+                    .linear_memory_base()
+                    .and_then(|base| ctx.read_memory(base + addr as u64, ty.size()))
+                    .wrap_err_with(|| format!("failed to read at the wasm address {addr:#x}"))?;
+                let mut le = [0; 8];
+                le[..bytes.len()].copy_from_slice(&bytes);
+                ty.convert(u64::from_le_bytes(le))
+            }
+            Node::Cast { ty, value } => ty.convert(value.eval(ctx)? as u64),
+            Node::Unary(op, operand) => {
+                let operand = operand.eval(ctx)?;
+                match op {
+                    UnaryOp::Not => (operand == 0) as i64,
+                    UnaryOp::Neg => operand.wrapping_neg(),
+                    UnaryOp::BitNot => !operand,
+                }
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(ctx)?;
+                match op {
+                    BinaryOp::Or if lhs != 0 => 1,
+                    BinaryOp::And if lhs == 0 => 0,
+                    _ => op.apply(lhs, rhs.eval(ctx)?)?,
+                }
+            }
+        };
+
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Identifier(String),
+    Register(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{value}"),
+            Token::Identifier(name) => write!(f, "{name}"),
+            Token::Register(name) => write!(f, "${name}"),
+            Token::Punct(punct) => write!(f, "{punct}"),
+        }
+    }
+}
+
+/// The punctuation, the longer ones first so that `<=` is not read as `<`.
+const PUNCTS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")",
+];
+
+fn tokenize(source: &str) -> eyre::Result<Vec<Token>> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() {
+            let len = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+            tokens.push(Token::Number(parse_number(&rest[..len])?));
+            len
+        } else if is_word(c) {
+            let len = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+            tokens.push(Token::Identifier(rest[..len].into()));
+            len
+        } else if c == '$' {
+            let len = rest[1..].find(|c| !is_word(c)).unwrap_or(rest.len() - 1) + 1;
+            if len == 1 {
+                return Err(eyre!("a register name is required after `$` in `{source}`"));
+            }
+            tokens.push(Token::Register(rest[1..len].into()));
+            len
+        } else if let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) {
+            tokens.push(Token::Punct(punct));
+            punct.len()
+        } else {
+            return Err(eyre!("unexpected `{c}` in `{source}`"));
+        };
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// A precedence climbing parser.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> eyre::Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| eyre!("unexpected end of the expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, punct: &'static str) -> bool {
+        let found = self.peek() == Some(&Token::Punct(punct));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, punct: &'static str) -> eyre::Result<()> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        match self.peek() {
+            Some(token) => Err(eyre!("expected `{punct}`, found `{token}`")),
+            None => Err(eyre!("expected `{punct}` at the end of the expression")),
+        }
+    }
+
+    /// The type in `(TYPE)` when the next tokens are one.
+    fn peek_type(&self) -> Option<Type> {
+        match (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) {
+            (Some(Token::Punct("(")), Some(Token::Identifier(name))) => Type::parse(name),
+            _ => None,
+        }
+    }
+
+    /// Parses the binary operators of [`BinaryOp::LEVELS`] from `level` on.
+    fn parse_binary(&mut self, level: usize) -> eyre::Result<Node> {
+        let Some(operators) = BinaryOp::LEVELS.get(level) else {
+            return self.parse_unary();
+        };
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        while let Some(&(_, op)) = operators
+            .iter()
+            .find(|(punct, _)| self.peek() == Some(&Token::Punct(punct)))
+        {
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> eyre::Result<Node> {
+        for (punct, op) in [
+            ("!", UnaryOp::Not),
+            ("-", UnaryOp::Neg),
+            ("~", UnaryOp::BitNot),
+        ] {
+            if self.eat(punct) {
+                return Ok(Node::Unary(op, Box::new(self.parse_unary()?)));
+            }
+        }
+
+        if self.eat("*") {
+            let ty = self
+                .peek_type()
+                .ok_or_else(|| eyre!("a read needs a type, e.g. `*(u32*)ADDR`"))?;
+            self.pos += 2;
+            self.expect("*")?;
+            self.expect(")")?;
+            let addr = Box::new(self.parse_unary()?);
+            return Ok(Node::Read { ty, addr });
+        }
+
+        if let Some(ty) = self.peek_type() {
+            self.pos += 2;
+            self.expect(")")?;
+            let value = Box::new(self.parse_unary()?);
+            return Ok(Node::Cast { ty, value });
+        }
+
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> eyre::Result<Node> {
+        let node = match self.next()? {
+            Token::Number(value) => Node::Literal(value as i64),
+            Token::Register(name) => {
+                if !registers::NAMES.contains(&name.as_str()) {
+                    return Err(eyre!("unknown register `${name}`"));
+                }
+                Node::Register(name)
+            }
+            Token::Identifier(name) => match name
+                .strip_prefix("arg")
+                .and_then(|index| index.parse().ok())
+            {
+                Some(index) => Node::Argument(index),
+                None => return Err(eyre!("unknown name `{name}`, e.g. `arg0` or `$rdi`")),
+            },
+            Token::Punct("(") => {
+                let node = self.parse_binary(0)?;
+                self.expect(")")?;
+                node
+            }
+            token => return Err(eyre!("unexpected `{token}`")),
+        };
+
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates an expression that doesn't need a tracee.
+    fn eval(source: &str) -> i64 {
+        Expr::parse(source)
+            .unwrap()
+            .eval(&DebuggerCtx::new())
+            .unwrap_or_else(|e| panic!("`{source}`: {e:#}"))
+    }
+
+    fn parse(source: &str) -> Node {
+        Expr::parse(source).unwrap().root
+    }
+
+    fn parse_error(source: &str) -> String {
+        format!("{:#}", Expr::parse(source).unwrap_err())
+    }
+
+    fn literal(value: i64) -> Box<Node> {
+        Box::new(Node::Literal(value))
+    }
+
+    #[test]
+    fn follows_the_precedence_of_c() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("1 | 2 ^ 3 & 4"), 3);
+        assert_eq!(eval("6 & 3 == 3"), 0);
+        assert_eq!(eval("1 < 2 == 1"), 1);
+        assert_eq!(eval("2 + 3 > 4 && 0x10 >> 4 <= 1"), 1);
+        assert_eq!(eval("0 || 1 && 0"), 0);
+        assert_eq!(eval("1 || 0 && 0"), 1);
+
+        assert_eq!(
+            parse("1 + 2 * 3"),
+            Node::Binary(
+                BinaryOp::Add,
+                literal(1),
+                Box::new(Node::Binary(BinaryOp::Mul, literal(2), literal(3)))
+            )
+        );
+    }
+
+    #[test]
+    fn binary_operators_are_left_associative() {
+        assert_eq!(eval("10 - 3 - 2"), 5);
+        assert_eq!(eval("100 / 10 / 5"), 2);
+        assert_eq!(eval("7 % 4 % 2"), 1);
+        assert_eq!(eval("2 << 1 << 2"), 16);
+
+        assert_eq!(
+            parse("1 - 2 - 3"),
+            Node::Binary(
+                BinaryOp::Sub,
+                Box::new(Node::Binary(BinaryOp::Sub, literal(1), literal(2))),
+                literal(3)
+            )
+        );
+    }
+
+    #[test]
+    fn short_circuits() {
+        assert_eq!(eval("1 || 1 / 0"), 1);
+        assert_eq!(eval("0 && 1 / 0"), 0);
+        assert_eq!(eval("5 && 7"), 1);
+
+        let err = Expr::parse("1 / 0")
+            .unwrap()
+            .eval(&DebuggerCtx::new())
+            .unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "failed to evaluate `1 / 0`: division by zero"
+        );
+    }
+
+    #[test]
+    fn applies_the_unary_operators() {
+        assert_eq!(eval("-5"), -5);
+        assert_eq!(eval("--5"), 5);
+        assert_eq!(eval("-2 * 3"), -6);
+        assert_eq!(eval("-(1 + 2)"), -3);
+        assert_eq!(eval("!0"), 1);
+        assert_eq!(eval("!7"), 0);
+        assert_eq!(eval("!1 + 1"), 1);
+        assert_eq!(eval("!!42"), 1);
+        assert_eq!(eval("~0"), -1);
+        assert_eq!(eval("~0x0f & 0xff"), 0xf0);
+        assert_eq!(eval("-0x8000000000000000"), i64::MIN);
+    }
+
+    #[test]
+    fn casts_truncate_and_sign_extend() {
+        assert_eq!(eval("(u8)0x1ff"), 0xff);
+        assert_eq!(eval("(i8)0xff"), -1);
+        assert_eq!(eval("(u16)-1"), 0xffff);
+        assert_eq!(eval("(i16)0x8000"), -0x8000);
+        assert_eq!(eval("(u32)-1"), 0xffff_ffff);
+        assert_eq!(eval("(i32)0xffffffff"), -1);
+        assert_eq!(eval("(u64)-1"), -1);
+        assert_eq!(eval("(i64)0x7fffffffffffffff"), i64::MAX);
+        // a cast binds tighter than the binary operators
+        assert_eq!(eval("(u8)0x100 + 1"), 1);
+        assert_eq!(eval("(u8)(0x100 + 1)"), 1);
+        assert_eq!(eval("(1)"), 1);
+    }
+
+    #[test]
+    fn parses_the_reads() {
+        assert_eq!(
+            parse("*(u32*)(arg0 + 4)"),
+            Node::Read {
+                ty: Type::U32,
+                addr: Box::new(Node::Binary(
+                    BinaryOp::Add,
+                    Box::new(Node::Argument(0)),
+                    literal(4)
+                )),
+            }
+        );
+        // without the parentheses, only the operand is the address
+        assert_eq!(
+            parse("*(i8*)arg1 == -1"),
+            Node::Binary(
+                BinaryOp::Eq,
+                Box::new(Node::Read {
+                    ty: Type::I8,
+                    addr: Box::new(Node::Argument(1)),
+                }),
+                Box::new(Node::Unary(UnaryOp::Neg, literal(1)))
+            )
+        );
+        assert_eq!(
+            parse("*(u64*)*(u32*)0x10"),
+            Node::Read {
+                ty: Type::U64,
+                addr: Box::new(Node::Read {
+                    ty: Type::U32,
+                    addr: literal(0x10),
+                }),
+            }
+        );
+        assert_eq!(
+            parse("2 * *(u16*)8"),
+            Node::Binary(
+                BinaryOp::Mul,
+                literal(2),
+                Box::new(Node::Read {
+                    ty: Type::U16,
+                    addr: literal(8),
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn reads_need_a_tracee() {
+        let err = Expr::parse("*(u32*)0x10")
+            .unwrap()
+            .eval(&DebuggerCtx::new())
+            .unwrap_err();
+        let err = format!("{err:#}");
+        assert!(
+            err.starts_with(
+                "failed to evaluate `*(u32*)0x10`: failed to read at the wasm address 0x10"
+            ),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn parses_the_operands() {
+        assert_eq!(parse("$rax"), Node::Register("rax".into()));
+        assert_eq!(parse("$eflags"), Node::Register("eflags".into()));
+        assert_eq!(parse("arg0"), Node::Argument(0));
+        assert_eq!(parse("arg12"), Node::Argument(12));
+        assert_eq!(parse("0x2a"), Node::Literal(42));
+        assert_eq!(
+            parse("$rdi != arg0"),
+            Node::Binary(
+                BinaryOp::Ne,
+                Box::new(Node::Register("rdi".into())),
+                Box::new(Node::Argument(0))
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_operands() {
+        assert_eq!(parse_error("$bogus"), "unknown register `$bogus`");
+        // the symbols are not resolved, only the registers and the arguments are names
+        assert_eq!(
+            parse_error("leaf + 1"),
+            "unknown name `leaf`, e.g. `arg0` or `$rdi`"
+        );
+        assert_eq!(
+            parse_error("argx"),
+            "unknown name `argx`, e.g. `arg0` or `$rdi`"
+        );
+        assert_eq!(
+            parse_error("(f32)1"),
+            "unknown name `f32`, e.g. `arg0` or `$rdi`"
+        );
+    }
+
+    #[test]
+    fn reports_malformed_input() {
+        assert_eq!(parse_error(""), "unexpected end of the expression");
+        assert_eq!(parse_error("1 +"), "unexpected end of the expression");
+        assert_eq!(
+            parse_error("(1 + 2"),
+            "expected `)` at the end of the expression"
+        );
+        assert_eq!(parse_error("(1 2"), "expected `)`, found `2`");
+        assert_eq!(parse_error("1 2"), "unexpected `2` in `1 2`");
+        assert_eq!(parse_error("1 @ 2"), "unexpected `@` in `1 @ 2`");
+        assert_eq!(parse_error(")"), "unexpected `)`");
+        assert_eq!(parse_error("1 == )"), "unexpected `)`");
+        assert_eq!(
+            parse_error("$ + 1"),
+            "a register name is required after `$` in `$ + 1`"
+        );
+        assert_eq!(
+            parse_error("*arg0"),
+            "a read needs a type, e.g. `*(u32*)ADDR`"
+        );
+        assert_eq!(parse_error("*(u32)arg0"), "expected `*`, found `)`");
+        assert_eq!(parse_error("(u32*)arg0"), "expected `)`, found `*`");
+        assert!(parse_error("0xzz").starts_with("invalid number `0xzz`"));
+    }
+
+    #[test]
+    fn displays_the_source() {
+        let expr = Expr::parse("  arg1 == 40 && *(u32*)(arg0 + 4) != 0  ").unwrap();
+        assert_eq!(expr.to_string(), "arg1 == 40 && *(u32*)(arg0 + 4) != 0");
+    }
+}
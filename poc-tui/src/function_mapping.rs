@@ -1,16 +1,43 @@
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, fmt, fs, ops::Range, path::Path};
 
-use color_eyre::eyre;
+use color_eyre::eyre::{self, WrapErr};
+
+use crate::{perf_map, wasm_symbol::WasmSymbol};
+
+/// Identifies a function within a [`FunctionMapping`].
+///
+/// Function names are not unique (e.g. two modules can define a function with the same name,
+/// and the trampolines might not have a name at all), hence the functions are keyed by this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FunctionId(pub usize);
+
+impl fmt::Display for FunctionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
 
 #[derive(Debug)]
 pub struct FunctionMapping {
-    pub name_to_meta: HashMap<String, FunctionMetadata>,
+    /// Functions in the order they appear in the perf map, indexed by [`FunctionId`]
+    functions: Vec<FunctionMetadata>,
+    /// All the names that a function can be referred with: its raw symbol, demangled path and
+    /// short name
+    name_to_ids: HashMap<String, Vec<FunctionId>>,
+    /// Ids of the functions, sorted by the address of the function
+    addr_index: Vec<FunctionId>,
+    /// The furthest end of the functions up to each one in `addr_index`, which tells when
+    /// none of the earlier functions can contain an address
+    max_end: Vec<u64>,
 }
 
 #[derive(Debug)]
 pub struct FunctionMetadata {
+    pub id: FunctionId,
+    /// The short name of the function (see [`WasmSymbol::short_name`])
+    pub name: String,
     /// The full symbol that is assigned to the function
-    pub symbol: String,
+    pub symbol: WasmSymbol,
     /// The address of the function relative to the base memory
     /// of the JIT-compiled wasm binary
     pub addr: u64,
@@ -21,53 +48,405 @@ pub struct FunctionMetadata {
     pub size: u64,
 }
 
+impl FunctionMetadata {
+    pub fn range(&self) -> Range<u64> {
+        self.addr..self.addr + self.size
+    }
+}
+
+/// An inconsistency in the layout of the functions in the memory.
+#[derive(Debug)]
+pub enum LayoutIssue<'a> {
+    /// `second` starts before `first` ends.
+    Overlap {
+        first: &'a FunctionMetadata,
+        second: &'a FunctionMetadata,
+    },
+    /// There is no function mapped to `range` between two functions.
+    Gap {
+        range: Range<u64>,
+        before: &'a FunctionMetadata,
+        after: &'a FunctionMetadata,
+    },
+}
+
 impl FunctionMapping {
-    pub fn generate_from_perfmap_file_with_pid(bin_name: &str, pid: u32) -> eyre::Result<Self> {
-        let mut name_to_meta = HashMap::new();
+    /// Locates the perf map of the process `pid` (see [`perf_map::locate`]) and parses it.
+    pub fn generate_from_perfmap_file_with_pid(
+        bin_name: &str,
+        pid: u32,
+        override_path: Option<&Path>,
+    ) -> eyre::Result<Self> {
+        let path = perf_map::locate(pid, override_path)?;
+
+        Self::generate_from_perfmap_file(bin_name, &path)
+    }
+
+    pub fn generate_from_perfmap_file(bin_name: &str, path: &Path) -> eyre::Result<Self> {
+        let data = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read the perf map `{}`", path.display()))?;
+
+        Self::parse(bin_name, &data)
+    }
 
-        // TODO: make this configurable
-        let data = fs::read_to_string(format!("/tmp/perf-{pid}.map"))?;
+    /// Parses the contents of a perf map. Only the functions of the module `bin_name` and the
+    /// code that wasmtime generates are kept (see [`WasmSymbol::belongs_to`]).
+    pub fn parse(bin_name: &str, data: &str) -> eyre::Result<Self> {
+        let mut functions = Vec::new();
 
         for line in data.lines() {
-            // Example: "7f3a1c400000 00000034 world"
-            let mut it = line.split_whitespace();
+            // Example: "0x7f3a1c400000 34 wasm_binary::world"
+            // Note that the names can contain whitespaces.
+            let mut it = line.trim().splitn(3, char::is_whitespace);
             let addr = it.next();
             let size = it.next();
-            let name = it.next();
+            let name = it.next().map(str::trim);
 
             if let (Some(addr), Some(size), Some(name)) = (addr, size, name) {
-                if name.starts_with(bin_name) {
-                    let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16)?;
-                    let size = u64::from_str_radix(size, 16)?;
-
-                    let maybe_name = name.split(":").last().unwrap_or(name);
-
-                    let _ = name_to_meta.insert(
-                        maybe_name.into(),
-                        FunctionMetadata {
-                            symbol: name.into(),
-                            addr,
-                            size,
-                        },
-                    );
+                let addr = u64::from_str_radix(addr.trim_start_matches("0x"), 16)?;
+                let size = u64::from_str_radix(size, 16)?;
+
+                let symbol = WasmSymbol::parse(name);
+                if !symbol.belongs_to(bin_name) {
+                    continue;
                 }
+
+                functions.push(FunctionMetadata {
+                    id: FunctionId(functions.len()),
+                    name: symbol.short_name().into(),
+                    symbol,
+                    addr,
+                    size,
+                });
+            }
+        }
+
+        Ok(Self::new(functions))
+    }
+
+    fn new(functions: Vec<FunctionMetadata>) -> Self {
+        let mut name_to_ids: HashMap<String, Vec<FunctionId>> = HashMap::new();
+        for meta in &functions {
+            let mut names = vec![
+                meta.symbol.raw.as_str(),
+                meta.symbol.demangled.as_str(),
+                meta.name.as_str(),
+            ];
+            names.dedup();
+            for name in names {
+                name_to_ids.entry(name.into()).or_default().push(meta.id);
             }
         }
 
-        Ok(FunctionMapping { name_to_meta })
+        let mut addr_index: Vec<FunctionId> = functions.iter().map(|meta| meta.id).collect();
+        addr_index.sort_by_key(|id| (functions[id.0].addr, functions[id.0].size));
+
+        let max_end = addr_index
+            .iter()
+            .scan(0, |max_end, id| {
+                *max_end = functions[id.0].range().end.max(*max_end);
+                Some(*max_end)
+            })
+            .collect();
+
+        FunctionMapping {
+            functions,
+            name_to_ids,
+            addr_index,
+            max_end,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
     }
 
+    pub fn function(&self, id: FunctionId) -> Option<&FunctionMetadata> {
+        self.functions.get(id.0)
+    }
+
+    /// Finds a function by its raw symbol, demangled path or short name.
+    ///
+    /// When the name is ambiguous, the wasm functions are preferred over the trampolines and
+    /// the ones that come first in the perf map are preferred over the rest.
+    /// Use [`FunctionMapping::functions_named`] to get all of them.
     pub fn get_function(&self, name: &str) -> Option<&FunctionMetadata> {
-        self.name_to_meta.get(name)
+        let mut candidates = self.functions_named(name);
+        let first = candidates.next()?;
+        if !first.symbol.kind.is_trampoline() {
+            return Some(first);
+        }
+        candidates
+            .find(|meta| !meta.symbol.kind.is_trampoline())
+            .or(Some(first))
+    }
+
+    pub fn functions_named(&self, name: &str) -> impl Iterator<Item = &FunctionMetadata> {
+        self.name_to_ids
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|id| &self.functions[id.0])
+    }
+
+    /// Finds the function that contains `addr`, together with the offset of `addr` within it.
+    ///
+    /// Note that if there are overlapping functions, the one that starts the latest wins.
+    pub fn lookup(&self, addr: u64) -> Option<(&FunctionMetadata, u64)> {
+        // index of the first function that starts after `addr`
+        let idx = self
+            .addr_index
+            .partition_point(|id| self.functions[id.0].addr <= addr);
+
+        // a function that ends before `addr` might be nested in one that contains it
+        (0..idx)
+            .rev()
+            .take_while(|&i| self.max_end[i] > addr)
+            .map(|i| &self.functions[self.addr_index[i].0])
+            .find(|meta| meta.range().contains(&addr))
+            .map(|meta| (meta, addr - meta.addr))
+    }
+
+    /// Formats `addr` as `func+0xoff` if it falls into a known function.
+    pub fn symbolize(&self, addr: u64) -> Option<String> {
+        self.lookup(addr)
+            .map(|(meta, offset)| format!("{}+{offset:#x}", meta.symbol))
+    }
+
+    /// Functions sorted by their addresses.
+    pub fn iter_by_addr(&self) -> impl Iterator<Item = &FunctionMetadata> {
+        self.addr_index.iter().map(|id| &self.functions[id.0])
+    }
+
+    /// Reports the overlaps and the gaps between the consecutive functions.
+    pub fn layout_issues(&self) -> Vec<LayoutIssue<'_>> {
+        let mut issues = Vec::new();
+
+        let mut it = self.iter_by_addr();
+        let Some(mut prev) = it.next() else {
+            return issues;
+        };
+
+        for cur in it {
+            let prev_end = prev.range().end;
+            if cur.addr < prev_end {
+                issues.push(LayoutIssue::Overlap {
+                    first: prev,
+                    second: cur,
+                });
+            } else if cur.addr > prev_end {
+                issues.push(LayoutIssue::Gap {
+                    range: prev_end..cur.addr,
+                    before: prev,
+                    after: cur,
+                });
+            }
+
+            // A function that is fully covered by the previous one should not hide the
+            // overlaps with the ones after it.
+            if cur.range().end >= prev_end {
+                prev = cur;
+            }
+        }
+
+        issues
     }
 }
 
 impl<'a> IntoIterator for &'a FunctionMapping {
-    type Item = <&'a HashMap<String, FunctionMetadata> as IntoIterator>::Item;
+    type Item = &'a FunctionMetadata;
 
-    type IntoIter = <&'a HashMap<String, FunctionMetadata> as IntoIterator>::IntoIter;
+    type IntoIter = std::slice::Iter<'a, FunctionMetadata>;
 
     fn into_iter(self) -> Self::IntoIter {
-        (&self.name_to_meta).into_iter()
+        self.functions.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_symbol::SymbolKind;
+
+    #[test]
+    fn keeps_the_module_and_the_wasmtime_code() {
+        let mapping = FunctionMapping::parse(
+            "wasm_binary",
+            "\
+0x1000 10 wasm_binary::leaf
+0x1010 10 wasm[0]::function[1]
+0x1020 10 wasm[0]::array_to_wasm_trampoline[0]
+0x1030 10 signatures[2]::wasm_to_array_trampoline
+0x1040 10 wasmtime_builtin_memory_grow
+0x1050 10 other_crate::helper
+",
+        )
+        .unwrap();
+
+        let kinds: Vec<_> = mapping
+            .iter_by_addr()
+            .map(|meta| &meta.symbol.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                &SymbolKind::WasmFunction,
+                &SymbolKind::WasmFunction,
+                &SymbolKind::ArrayToWasmTrampoline,
+                &SymbolKind::WasmToArrayTrampoline { signature: 2 },
+                &SymbolKind::WasmToBuiltin {
+                    builtin: "memory_grow".into()
+                },
+            ]
+        );
+        assert_eq!(mapping.get_function("leaf").unwrap().addr, 0x1000);
+        assert_eq!(
+            mapping
+                .get_function("wasm[0]::function[1]")
+                .unwrap()
+                .symbol
+                .func_index,
+            Some(1)
+        );
+        assert!(mapping.get_function("helper").is_none());
+    }
+
+    #[test]
+    fn filters_mangled_symbols_on_the_demangled_module() {
+        let mapping = FunctionMapping::parse(
+            "wasm_binary",
+            "\
+0x1000 10 _ZN11wasm_binary4main17h0123456789abcdefE
+0x1010 10 _RNvCs15kBYyAo9fc_11wasm_binary4leaf
+0x1020 10 _ZN11other_crate6helper17h0123456789abcdefE
+",
+        )
+        .unwrap();
+
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping.get_function("main").unwrap().addr, 0x1000);
+        assert_eq!(
+            mapping.get_function("wasm_binary::leaf").unwrap().addr,
+            0x1010
+        );
+        assert!(mapping.get_function("other_crate::helper").is_none());
+    }
+
+    fn mapping(perf_map: &str) -> FunctionMapping {
+        FunctionMapping::parse("", perf_map).unwrap()
+    }
+
+    fn lookup(mapping: &FunctionMapping, addr: u64) -> Option<(&str, u64)> {
+        mapping
+            .lookup(addr)
+            .map(|(meta, offset)| (meta.name.as_str(), offset))
+    }
+
+    #[test]
+    fn looks_up_addresses() {
+        let mapping = mapping(
+            "\
+1000 10 a
+1010 20 b
+1040 8 c
+",
+        );
+
+        assert_eq!(lookup(&mapping, 0xfff), None);
+        assert_eq!(lookup(&mapping, 0x1000), Some(("a", 0)));
+        assert_eq!(lookup(&mapping, 0x100f), Some(("a", 0xf)));
+        assert_eq!(lookup(&mapping, 0x1010), Some(("b", 0)));
+        assert_eq!(lookup(&mapping, 0x102f), Some(("b", 0x1f)));
+        // the gap between `b` and `c`
+        assert_eq!(lookup(&mapping, 0x1030), None);
+        assert_eq!(lookup(&mapping, 0x103f), None);
+        assert_eq!(lookup(&mapping, 0x1047), Some(("c", 7)));
+        assert_eq!(lookup(&mapping, 0x1048), None);
+        assert_eq!(lookup(&mapping, u64::MAX), None);
+
+        assert_eq!(mapping.symbolize(0x1012).as_deref(), Some("b+0x2"));
+        assert_eq!(mapping.symbolize(0x1030), None);
+    }
+
+    #[test]
+    fn looks_up_overlapping_functions() {
+        let mapping = mapping(
+            "\
+1000 100 outer
+1010 10 inner
+1020 0 empty
+2000 20 first
+2010 30 second
+",
+        );
+
+        assert_eq!(lookup(&mapping, 0x1008), Some(("outer", 8)));
+        assert_eq!(lookup(&mapping, 0x1010), Some(("inner", 0)));
+        // after the nested function, still in the enclosing one
+        assert_eq!(lookup(&mapping, 0x1020), Some(("outer", 0x20)));
+        assert_eq!(lookup(&mapping, 0x10ff), Some(("outer", 0xff)));
+        assert_eq!(lookup(&mapping, 0x1100), None);
+
+        assert_eq!(lookup(&mapping, 0x2008), Some(("first", 8)));
+        assert_eq!(lookup(&mapping, 0x2018), Some(("second", 8)));
+        assert_eq!(lookup(&mapping, 0x2030), Some(("second", 0x20)));
+        assert_eq!(lookup(&mapping, 0x2040), None);
+    }
+
+    #[test]
+    fn looks_up_nothing_in_an_empty_mapping() {
+        let mapping = mapping("");
+        assert!(mapping.is_empty());
+        assert_eq!(lookup(&mapping, 0x1000), None);
+        assert!(mapping.layout_issues().is_empty());
+    }
+
+    #[test]
+    fn reports_the_layout_issues() {
+        let mapping = mapping(
+            "\
+1000 100 outer
+1010 10 inner
+10f0 20 next
+1110 10 adjacent
+1200 10 after_gap
+",
+        );
+
+        let issues: Vec<String> = mapping
+            .layout_issues()
+            .iter()
+            .map(|issue| match issue {
+                LayoutIssue::Overlap { first, second } => {
+                    format!("overlap {} {}", first.name, second.name)
+                }
+                LayoutIssue::Gap {
+                    range,
+                    before,
+                    after,
+                } => format!(
+                    "gap {:#x}..{:#x} {} {}",
+                    range.start, range.end, before.name, after.name
+                ),
+            })
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                "overlap outer inner",
+                // `inner` doesn't hide that `next` overlaps with `outer`
+                "overlap outer next",
+                "gap 0x1120..0x1200 adjacent after_gap",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        assert!(FunctionMapping::parse("", "0xzz 10 wasm_binary::leaf").is_err());
     }
 }
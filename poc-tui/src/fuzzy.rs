@@ -0,0 +1,112 @@
+/// Scores how well `candidate` matches `query`, `None` if it doesn't match at all.
+///
+/// A candidate matches when all the characters of the query appear in it in order
+/// (case-insensitive). Consecutive matches and matches at the start of a path segment or a
+/// word score higher, the gaps in between score lower.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let mut query = query.chars().map(|c| c.to_ascii_lowercase()).peekable();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    let mut gap = 0;
+
+    for c in candidate.chars() {
+        let Some(&q) = query.peek() else {
+            break;
+        };
+
+        if c.to_ascii_lowercase() == q {
+            query.next();
+
+            score += 10;
+            // more than a word boundary, so that `leaf` ranks `leaf` above `l_e_a_f`
+            if prev_matched {
+                score += 25;
+            }
+            if prev.is_none_or(|p| matches!(p, ':' | '_' | '<' | ' ' | '-' | '.')) {
+                score += 20;
+            }
+            score -= gap.min(10);
+
+            prev_matched = true;
+            gap = 0;
+        } else {
+            prev_matched = false;
+            gap += 1;
+        }
+
+        prev = Some(c);
+    }
+
+    if query.peek().is_some() {
+        return None;
+    }
+
+    // prefer the shorter candidates when everything else is the same
+    Some(score * 16 - candidate.len() as i64 / 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The candidates that match `query`, best first, like the function list sorts them.
+    fn rank<'a>(query: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+        let mut matches: Vec<_> = candidates
+            .iter()
+            .filter_map(|&candidate| Some((score(query, candidate)?, candidate)))
+            .collect();
+        matches.sort_by_key(|&(score, _)| -score);
+        matches
+            .into_iter()
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_subsequence() {
+        assert_eq!(score("leaf", "wasm_binary::fael"), None);
+        assert_eq!(score("leaff", "wasm_binary::leaf"), None);
+        assert_eq!(score("x", ""), None);
+        assert_eq!(score("", "wasm_binary::leaf"), Some(0));
+        assert!(score("LEAF", "wasm_binary::leaf").is_some());
+    }
+
+    #[test]
+    fn prefers_contiguous_matches() {
+        assert!(score("leaf", "a::leaf_x") > score("leaf", "a::l_e_a_f"));
+        assert!(score("leaf", "a::leaf_x") > score("leaf", "a::lxeyazf"));
+        assert_eq!(
+            rank("leaf", &["app::lxeyazf", "app::l_e_a_f", "app::leaf"]),
+            ["app::leaf", "app::l_e_a_f", "app::lxeyazf"]
+        );
+    }
+
+    #[test]
+    fn prefers_word_boundaries_and_prefixes() {
+        // `read` starts a segment in the first one, it's in the middle of a word in the other
+        assert!(score("read", "io::read_exact") > score("read", "io::bread_exact"));
+        // `main` is a prefix of the candidate
+        assert!(score("main", "main_loop") > score("main", "domain_loop"));
+        // both at a word boundary, then contiguous, then neither
+        assert_eq!(
+            rank("ml", &["app::formula", "app::html", "app::main_loop"]),
+            ["app::main_loop", "app::html", "app::formula"]
+        );
+    }
+
+    #[test]
+    fn prefers_shorter_candidates_on_ties() {
+        assert_eq!(
+            rank(
+                "leaf",
+                &["wasm_binary::leaf_with_a_long_name", "wasm_binary::leaf"]
+            ),
+            ["wasm_binary::leaf", "wasm_binary::leaf_with_a_long_name"]
+        );
+    }
+}
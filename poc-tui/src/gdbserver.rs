@@ -0,0 +1,1271 @@
+use std::{
+    env,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    ops::Range,
+    path::PathBuf,
+    time::Duration,
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+
+use crate::{
+    batch::{TraceeArgs, next_value},
+    breakpoint::Location,
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, ExitReason, StopReason, TraceeState},
+    registers, symbol_file,
+};
+
+const USAGE: &str = "\
+usage: poc-tui gdbserver [OPTIONS] HOST:PORT (--attach PID | -- PROGRAM [ARGS ...])
+
+Serves the tracee to gdb or lldb over the remote serial protocol, e.g. `target remote HOST:PORT`.
+A spawned PROGRAM runs until the module is loaded before the debugger is accepted.
+
+The JIT-compiled functions are written to a symbol file that is announced as a library. A gdb
+that has loaded the runtime binary ignores it, use `add-symbol-file PATH` there.
+
+options:
+    --attach PID          attach to the process PID
+    --perfmap PATH        use the perf map at PATH
+    --symbol-file PATH    where to write the symbol file, /tmp/tripwire-PID.elf by default";
+
+/// What is announced in `qSupported`. The packets are small, except for `g`.
+const FEATURES: &str = "PacketSize=4000;QStartNoAckMode+;qXfer:features:read+;\
+                        qXfer:libraries:read+;swbreak+;vContSupported+";
+
+/// The most bytes that `m` reads, which fill a packet of the announced size in hex.
+const MAX_MEMORY_LEN: usize = 0x4000 / 2;
+
+/// How often a running tracee is checked for a state change.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Signal numbers of gdb, which are not the ones of Linux.
+///
+/// https://sourceware.org/git/?p=binutils-gdb.git;a=blob;f=include/gdb/signals.def
+const GDB_SIGNALS: &[(Signal, u8)] = &[
+    (Signal::SIGHUP, 1),
+    (Signal::SIGINT, 2),
+    (Signal::SIGQUIT, 3),
+    (Signal::SIGILL, 4),
+    (Signal::SIGTRAP, 5),
+    (Signal::SIGABRT, 6),
+    (Signal::SIGFPE, 8),
+    (Signal::SIGKILL, 9),
+    (Signal::SIGBUS, 10),
+    (Signal::SIGSEGV, 11),
+    (Signal::SIGSYS, 12),
+    (Signal::SIGPIPE, 13),
+    (Signal::SIGALRM, 14),
+    (Signal::SIGTERM, 15),
+    (Signal::SIGURG, 16),
+    (Signal::SIGSTOP, 17),
+    (Signal::SIGTSTP, 18),
+    (Signal::SIGCONT, 19),
+    (Signal::SIGCHLD, 20),
+    (Signal::SIGTTIN, 21),
+    (Signal::SIGTTOU, 22),
+    (Signal::SIGIO, 23),
+    (Signal::SIGXCPU, 24),
+    (Signal::SIGXFSZ, 25),
+    (Signal::SIGVTALRM, 26),
+    (Signal::SIGPROF, 27),
+    (Signal::SIGWINCH, 28),
+    (Signal::SIGUSR1, 30),
+    (Signal::SIGUSR2, 31),
+    (Signal::SIGPWR, 32),
+];
+
+const GDB_SIGNAL_UNKNOWN: u8 = 143;
+
+/// Entry point of the `gdbserver` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut address = None;
+    let mut tracee = TraceeArgs::new(USAGE);
+    let mut symbol_file = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if tracee.parse(&arg, &mut args)? => {}
+            "--symbol-file" => {
+                symbol_file = Some(PathBuf::from(next_value(
+                    &mut args,
+                    "--symbol-file",
+                    USAGE,
+                )?))
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if address.is_none() && !arg.starts_with('-') => address = Some(arg),
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+    let address = address.ok_or_else(|| eyre!("HOST:PORT is required\n\n{USAGE}"))?;
+
+    let mut ctx = DebuggerCtx::new();
+    tracee.start(&mut ctx)?;
+
+    let symbol_file =
+        symbol_file.unwrap_or_else(|| env::temp_dir().join(format!("tripwire-{}.elf", ctx.pid)));
+
+    let listener =
+        TcpListener::bind(&address).wrap_err_with(|| format!("failed to listen on `{address}`"))?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    eprintln!("Remote debugging from {peer}");
+
+    let mut server = GdbServer::new(ctx, Connection::new(stream)?, symbol_file);
+    // the module is usually loaded by the time we attach
+    if let Err(e) = server.load_module() {
+        eprintln!("warning: {e:#}");
+    }
+
+    server.serve()
+}
+
+/// What the debugger sent.
+#[derive(Debug)]
+enum Incoming {
+    Packet(String),
+    /// `Ctrl-C`, a single `0x03` byte outside of the packets
+    Interrupt,
+}
+
+/// The transport of the remote serial protocol: `$data#checksum` packets, acknowledged with
+/// `+` unless `QStartNoAckMode` is negotiated.
+struct Connection {
+    stream: TcpStream,
+    /// The bytes that are received but not parsed yet
+    received: Vec<u8>,
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> eyre::Result<Self> {
+        stream.set_nodelay(true)?;
+
+        Ok(Connection {
+            stream,
+            received: Vec::new(),
+            no_ack: false,
+        })
+    }
+
+    /// Blocks until the next packet or interrupt, returns `None` when the debugger is gone.
+    fn receive(&mut self) -> eyre::Result<Option<Incoming>> {
+        loop {
+            if let Some(incoming) = self.parse()? {
+                return Ok(Some(incoming));
+            }
+            if !self.fill(None)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads what the debugger sent within `timeout`, forever if it's `None`. Returns `false`
+    /// when the debugger is gone.
+    fn fill(&mut self, timeout: Option<Duration>) -> eyre::Result<bool> {
+        self.stream.set_read_timeout(timeout)?;
+
+        let mut buf = [0; 4096];
+        match self.stream.read(&mut buf) {
+            Ok(0) => Ok(false),
+            Ok(len) => {
+                self.received.extend_from_slice(&buf[..len]);
+                Ok(true)
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether an interrupt is received. Only the interrupts are expected while the tracee is
+    /// running.
+    fn take_interrupt(&mut self) -> bool {
+        let Some(pos) = self.received.iter().position(|&byte| byte == 0x03) else {
+            return false;
+        };
+        self.received.drain(..=pos);
+
+        true
+    }
+
+    fn parse(&mut self) -> eyre::Result<Option<Incoming>> {
+        loop {
+            match self.received.first() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    self.received.remove(0);
+                    return Ok(Some(Incoming::Interrupt));
+                }
+                Some(b'$') => {
+                    let Some(end) = self.received.iter().position(|&byte| byte == b'#') else {
+                        return Ok(None);
+                    };
+                    if self.received.len() < end + 3 {
+                        return Ok(None);
+                    }
+
+                    let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                    if !self.no_ack {
+                        if checksum != Some(checksum_of(data)) {
+                            self.stream.write_all(b"-")?;
+                            continue;
+                        }
+                        self.stream.write_all(b"+")?;
+                    }
+
+                    return Ok(Some(Incoming::Packet(
+                        String::from_utf8_lossy(&unescape(data)).into_owned(),
+                    )));
+                }
+                // the acks of our packets, and the garbage between the packets
+                Some(_) => {
+                    self.received.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> eyre::Result<()> {
+        let mut escaped = Vec::with_capacity(data.len());
+        for &byte in data.as_bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                escaped.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                escaped.push(byte);
+            }
+        }
+
+        let mut packet = Vec::with_capacity(escaped.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum_of(&escaped)).as_bytes());
+        self.stream.write_all(&packet)?;
+
+        Ok(())
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(data.len());
+    let mut it = data.iter();
+    while let Some(&byte) = it.next() {
+        match byte {
+            b'}' => unescaped.extend(it.next().map(|byte| byte ^ 0x20)),
+            _ => unescaped.push(byte),
+        }
+    }
+
+    unescaped
+}
+
+/// What to do after handling a packet.
+enum Reply {
+    Packet(String),
+    /// The tracee is resumed, the stop reply is sent once it stops
+    Resumed,
+    /// The session is over, after sending the packet if there is one
+    Close(Option<String>),
+}
+
+/// A `gdbserver` for a single tracee, on top of [`DebuggerCtx`].
+pub struct GdbServer {
+    ctx: DebuggerCtx,
+    conn: Connection,
+    /// Where the symbols of the JIT-compiled functions are written, see [`symbol_file`]
+    symbol_file: PathBuf,
+    /// The debugger understands the `swbreak` stop reason
+    swbreak: bool,
+}
+
+impl GdbServer {
+    fn new(ctx: DebuggerCtx, conn: Connection, symbol_file: PathBuf) -> Self {
+        GdbServer {
+            ctx,
+            conn,
+            symbol_file,
+            swbreak: false,
+        }
+    }
+
+    /// Handles the packets until the debugger disconnects, detaches or kills the tracee.
+    fn serve(&mut self) -> eyre::Result<()> {
+        while let Some(incoming) = self.conn.receive()? {
+            // an interrupt while the tracee is stopped is a no-op
+            let Incoming::Packet(packet) = incoming else {
+                continue;
+            };
+
+            let reply = self.handle(&packet).unwrap_or_else(|e| {
+                eprintln!("warning: `{packet}` failed: {e:#}");
+                Reply::Packet("E01".into())
+            });
+            match reply {
+                Reply::Packet(data) => self.conn.send(&data)?,
+                Reply::Resumed => {
+                    if !self.wait()? {
+                        break;
+                    }
+                    let stop = self.stop_reply();
+                    self.conn.send(&stop)?;
+                }
+                Reply::Close(data) => {
+                    if let Some(data) = data {
+                        self.conn.send(&data)?;
+                    }
+                    break;
+                }
+            }
+
+            if packet == "QStartNoAckMode" {
+                self.conn.no_ack = true;
+            }
+        }
+
+        eprintln!("The remote debugger is gone");
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> eyre::Result<Reply> {
+        let Some(command) = packet.chars().next() else {
+            return Ok(Reply::Packet(String::new()));
+        };
+        let args = &packet[command.len_utf8()..];
+
+        let reply = match command {
+            '?' => self.stop_reply(),
+            'g' => encode_hex(&self.read_registers()?),
+            'G' => {
+                self.write_registers(&decode_hex(args)?)?;
+                "OK".into()
+            }
+            'p' => {
+                let range = register_range(parse_hex(args)? as usize)?;
+                encode_hex(&self.read_registers()?[range])
+            }
+            'P' => {
+                let (regnum, value) = args
+                    .split_once('=')
+                    .ok_or_else(|| eyre!("malformed packet"))?;
+                let range = register_range(parse_hex(regnum)? as usize)?;
+                let value = decode_hex(value)?;
+                if value.len() != range.len() {
+                    return Err(eyre!("the register is {} bytes", range.len()));
+                }
+
+                let mut buf = self.read_registers()?;
+                buf[range].copy_from_slice(&value);
+                self.write_registers(&buf)?;
+                "OK".into()
+            }
+            'm' => {
+                let (addr, len) = args
+                    .split_once(',')
+                    .ok_or_else(|| eyre!("malformed packet"))?;
+                let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+                if len > MAX_MEMORY_LEN as u64 || addr.checked_add(len).is_none() {
+                    return Err(eyre!("{len:#x} bytes can't be read at {addr:#x}"));
+                }
+                encode_hex(&self.ctx.read_memory(addr, len as usize)?)
+            }
+            'M' => {
+                let (addr, data) = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((range.split_once(',')?.0, data)))
+                    .ok_or_else(|| eyre!("malformed packet"))?;
+                self.ctx
+                    .write_memory(parse_hex(addr)?, &decode_hex(data)?)?;
+                "OK".into()
+            }
+            'Z' | 'z' => self.software_breakpoint(command == 'Z', args)?,
+            'c' | 's' | 'C' | 'S' => {
+                self.resume(command, args)?;
+                return Ok(Reply::Resumed);
+            }
+            'v' => return self.handle_v(packet),
+            'q' | 'Q' => self.query(packet)?,
+            // there is a single thread
+            'H' | 'T' => "OK".into(),
+            'k' => {
+                if self.ctx.is_alive() {
+                    self.ctx.kill()?;
+                }
+                return Ok(Reply::Close(None));
+            }
+            'D' => {
+                self.ctx.detach()?;
+                return Ok(Reply::Close(Some("OK".into())));
+            }
+            _ => String::new(),
+        };
+
+        Ok(Reply::Packet(reply))
+    }
+
+    fn handle_v(&mut self, packet: &str) -> eyre::Result<Reply> {
+        if packet == "vCont?" {
+            return Ok(Reply::Packet("vCont;c;C;s;S".into()));
+        }
+
+        if let Some(actions) = packet.strip_prefix("vCont;") {
+            // there is a single thread, so the first action applies to it
+            let action = actions.split(';').next().unwrap_or_default();
+            let action = action.split_once(':').map_or(action, |(action, _)| action);
+            let Some(command) = action.chars().next() else {
+                return Err(eyre!("malformed packet"));
+            };
+            if !matches!(command, 'c' | 's' | 'C' | 'S') {
+                return Ok(Reply::Packet("E01".into()));
+            }
+
+            self.resume(command, &action[1..])?;
+            return Ok(Reply::Resumed);
+        }
+
+        if packet.starts_with("vKill") {
+            if self.ctx.is_alive() {
+                self.ctx.kill()?;
+            }
+            return Ok(Reply::Close(Some("OK".into())));
+        }
+
+        Ok(Reply::Packet(String::new()))
+    }
+
+    fn query(&mut self, packet: &str) -> eyre::Result<String> {
+        if let Some(features) = packet.strip_prefix("qSupported") {
+            self.swbreak = features.contains("swbreak+");
+            return Ok(FEATURES.into());
+        }
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:") {
+            let (annex, range) = args
+                .split_once(':')
+                .ok_or_else(|| eyre!("malformed packet"))?;
+            if annex != "target.xml" {
+                return Ok("E00".into());
+            }
+            return xfer(&target_xml(), range);
+        }
+
+        if let Some(range) = packet.strip_prefix("qXfer:libraries:read::") {
+            return xfer(&self.library_list(), range);
+        }
+
+        let pid = self.ctx.pid.as_raw();
+        let reply = match packet.split(':').next().unwrap_or_default() {
+            "QStartNoAckMode" | "qSymbol" => "OK".into(),
+            "qAttached" => if self.ctx.is_attached() { "1" } else { "0" }.into(),
+            "qC" => format!("QC{pid:x}"),
+            "qfThreadInfo" => format!("m{pid:x}"),
+            "qsThreadInfo" => "l".into(),
+            // lldb asks for these before reading the target description
+            "qHostInfo" => format!(
+                "triple:{};ptrsize:8;endian:little;",
+                encode_hex(b"x86_64-pc-linux-gnu")
+            ),
+            "qProcessInfo" => format!(
+                "pid:{pid:x};triple:{};ostype:linux;ptrsize:8;endian:little;",
+                encode_hex(b"x86_64-pc-linux-gnu")
+            ),
+            _ => String::new(),
+        };
+
+        Ok(reply)
+    }
+
+    /// `c[ADDR]`, `s[ADDR]`, `CSIG[;ADDR]` and `SSIG[;ADDR]`, where `SIG` is the signal to
+    /// deliver. Like gdbserver, the signal that stopped the tracee is discarded unless the
+    /// debugger passes it back.
+    fn resume(&mut self, command: char, args: &str) -> eyre::Result<()> {
+        let (signal, addr) = match command {
+            'C' | 'S' => {
+                let (signal, addr) = args.split_once(';').unwrap_or((args, ""));
+                (from_gdb_signal(parse_hex(signal)? as u8), addr)
+            }
+            _ => (None, args),
+        };
+
+        if !addr.is_empty() {
+            let mut regs = self.ctx.registers()?;
+            regs.rip = parse_hex(addr)?;
+            self.ctx.set_registers(regs)?;
+        }
+
+        self.ctx.set_pending_signal(signal);
+        match command {
+            'c' | 'C' => self.ctx.resume(),
+            _ => self.ctx.step_instruction(),
+        }
+    }
+
+    /// `Z0,ADDR,KIND` and `z0,ADDR,KIND`. The other kinds are not supported, gdb falls back to
+    /// writing the traps itself.
+    fn software_breakpoint(&mut self, insert: bool, args: &str) -> eyre::Result<String> {
+        let mut it = args.split(',');
+        if it.next() != Some("0") {
+            return Ok(String::new());
+        }
+        let addr = parse_hex(it.next().ok_or_else(|| eyre!("malformed packet"))?)?;
+
+        let existing = self.ctx.breakpoints.at(addr).map(|bp| bp.id);
+        match (insert, existing) {
+            (true, None) => {
+                self.ctx.add_breakpoint(Location::Address(addr))?;
+            }
+            (false, Some(id)) => {
+                self.ctx.delete_breakpoint(id)?;
+            }
+            _ => {}
+        }
+
+        Ok("OK".into())
+    }
+
+    /// Blocks until the resumed tracee stops, interrupting it when the debugger asks so.
+    /// Returns `false` when the debugger is gone.
+    fn wait(&mut self) -> eyre::Result<bool> {
+        while self.ctx.is_running() {
+            if !self.conn.fill(Some(POLL_INTERVAL))? {
+                return Ok(false);
+            }
+            if self.conn.take_interrupt() {
+                self.ctx.interrupt()?;
+            }
+
+            if let Some(TraceeState::Stopped(StopReason::ModuleLoaded)) = self.ctx.poll(false)? {
+                self.load_module()?;
+                self.ctx.resume()?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Discovers the functions of the module and writes their symbols.
+    fn load_module(&mut self) -> eyre::Result<()> {
+        let mapping = self.ctx.parse_perfmap(BIN_NAME)?;
+        symbol_file::write(&mapping, &self.symbol_file)?;
+        eprintln!(
+            "Wrote the symbols of {} functions to `{}`",
+            mapping.len(),
+            self.symbol_file.display()
+        );
+
+        Ok(())
+    }
+
+    fn stop_reply(&self) -> String {
+        let pid = self.ctx.pid.as_raw();
+        match &self.ctx.state {
+            TraceeState::Stopped(reason) => {
+                let signal = match reason {
+                    StopReason::Interrupted => gdb_signal(Signal::SIGINT),
+                    StopReason::Signal(signal) => gdb_signal(*signal),
+                    _ => gdb_signal(Signal::SIGTRAP),
+                };
+
+                let mut reply = format!("T{signal:02x}thread:{pid:x};");
+                if self.swbreak
+                    && matches!(
+                        reason,
+                        StopReason::Breakpoint(_)
+                            | StopReason::ConditionError { .. }
+                            | StopReason::ScriptError { .. }
+                    )
+                {
+                    reply += "swbreak:;";
+                }
+                reply
+            }
+            TraceeState::Exited(ExitReason::Code(code)) => format!("W{:02x}", *code as u8),
+            TraceeState::Exited(ExitReason::Signal(signal)) => {
+                format!("X{:02x}", gdb_signal(*signal))
+            }
+            // the tracee is detached
+            TraceeState::NotStarted | TraceeState::Running => "W00".into(),
+        }
+    }
+
+    fn read_registers(&self) -> eyre::Result<Vec<u8>> {
+        Ok(encode_registers(
+            &self.ctx.registers()?,
+            &self.ctx.fp_registers()?,
+        ))
+    }
+
+    fn write_registers(&mut self, buf: &[u8]) -> eyre::Result<()> {
+        let mut regs = self.ctx.registers()?;
+        let mut fp_regs = self.ctx.fp_registers()?;
+        decode_registers(buf, &mut regs, &mut fp_regs)?;
+
+        self.ctx.set_registers(regs)?;
+        self.ctx.set_fp_registers(fp_regs)
+    }
+
+    /// The symbol file as the only library, see `qXfer:libraries:read`.
+    fn library_list(&self) -> String {
+        let mut list = String::from("<library-list>");
+        if let Some(mapping) = &self.ctx.function_mapping {
+            let text = symbol_file::text_range(mapping);
+            let _ = write!(
+                list,
+                r#"<library name="{}"><segment address="{:#x}"/></library>"#,
+                escape_xml(&self.symbol_file.display().to_string()),
+                text.start
+            );
+        }
+        list += "</library-list>";
+
+        list
+    }
+}
+
+/// Serves the part `OFFSET,LENGTH` of a `qXfer` object.
+fn xfer(object: &str, range: &str) -> eyre::Result<String> {
+    let (offset, len) = range
+        .split_once(',')
+        .ok_or_else(|| eyre!("malformed packet"))?;
+    let offset = (parse_hex(offset)? as usize).min(object.len());
+    let end = offset.saturating_add(parse_hex(len)? as usize);
+
+    Ok(match object.get(offset..end) {
+        Some(chunk) => format!("m{chunk}"),
+        None => format!("l{}", &object[offset..]),
+    })
+}
+
+/// Sizes of the registers in the `g` packet, in bytes, by their numbers in [`target_xml`].
+fn register_size(regnum: usize) -> Option<usize> {
+    let size = match regnum {
+        // rax..r15, rip
+        0..=16 => 8,
+        // eflags, cs, ss, ds, es, fs, gs
+        17..=23 => 4,
+        // st0..st7
+        24..=31 => 10,
+        // fctrl, fstat, ftag, fiseg, fioff, foseg, fooff, fop
+        32..=39 => 4,
+        // xmm0..xmm15
+        40..=55 => 16,
+        // mxcsr
+        56 => 4,
+        // orig_rax, fs_base, gs_base
+        57..=59 => 8,
+        _ => return None,
+    };
+
+    Some(size)
+}
+
+fn register_range(regnum: usize) -> eyre::Result<Range<usize>> {
+    let size = register_size(regnum).ok_or_else(|| eyre!("unknown register {regnum}"))?;
+    let start = (0..regnum).filter_map(register_size).sum::<usize>();
+
+    Ok(start..start + size)
+}
+
+/// The target description, in the layout of gdb's amd64 Linux target.
+fn target_xml() -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<architecture>i386:x86-64</architecture>
+<feature name="org.gnu.gdb.i386.core">
+"#,
+    );
+
+    let mut reg = |name: &str, bitsize: usize, kind: &str, group: Option<&str>| {
+        let group = group.map(|group| format!(r#" group="{group}""#));
+        let _ = writeln!(
+            xml,
+            r#"<reg name="{name}" bitsize="{bitsize}" type="{kind}"{}/>"#,
+            group.unwrap_or_default()
+        );
+    };
+    for &name in &registers::NAMES[..16] {
+        let kind = match name {
+            "rbp" | "rsp" => "data_ptr",
+            _ => "int64",
+        };
+        reg(name, 64, kind, None);
+    }
+    reg("rip", 64, "code_ptr", None);
+    for &name in &registers::NAMES[17..24] {
+        reg(name, 32, "int32", None);
+    }
+    for i in 0..8 {
+        reg(&format!("st{i}"), 80, "i387_ext", None);
+    }
+    for name in [
+        "fctrl", "fstat", "ftag", "fiseg", "fioff", "foseg", "fooff", "fop",
+    ] {
+        reg(name, 32, "int", Some("float"));
+    }
+    xml += r#"</feature>
+<feature name="org.gnu.gdb.i386.sse">
+<vector id="v4f" type="ieee_single" count="4"/>
+<vector id="v2d" type="ieee_double" count="2"/>
+<vector id="v16i8" type="int8" count="16"/>
+<vector id="v8i16" type="int16" count="8"/>
+<vector id="v4i32" type="int32" count="4"/>
+<vector id="v2i64" type="int64" count="2"/>
+<union id="vec128">
+<field name="v4_float" type="v4f"/>
+<field name="v2_double" type="v2d"/>
+<field name="v16_int8" type="v16i8"/>
+<field name="v8_int16" type="v8i16"/>
+<field name="v4_int32" type="v4i32"/>
+<field name="v2_int64" type="v2i64"/>
+<field name="uint128" type="uint128"/>
+</union>
+"#;
+    for i in 0..16 {
+        let _ = writeln!(
+            xml,
+            r#"<reg name="xmm{i}" bitsize="128" type="vec128" group="vector"/>"#
+        );
+    }
+    xml += r#"<reg name="mxcsr" bitsize="32" type="int" group="vector"/>
+</feature>
+<feature name="org.gnu.gdb.i386.linux">
+<reg name="orig_rax" bitsize="64" type="int"/>
+</feature>
+<feature name="org.gnu.gdb.i386.segments">
+<reg name="fs_base" bitsize="64" type="int"/>
+<reg name="gs_base" bitsize="64" type="int"/>
+</feature>
+</target>
+"#;
+
+    xml
+}
+
+/// Serializes the registers for the `g` packet, see [`register_size`].
+fn encode_registers(regs: &user_regs_struct, fp_regs: &user_fpregs_struct) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    for name in &registers::NAMES[..17] {
+        buf.extend_from_slice(
+            &registers::read(regs, name)
+                .unwrap_or_default()
+                .to_le_bytes(),
+        );
+    }
+    for name in &registers::NAMES[17..24] {
+        let value = registers::read(regs, name).unwrap_or_default() as u32;
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for st in fp_regs.st_space.chunks(4) {
+        buf.extend_from_slice(&words_to_bytes(st)[..10]);
+    }
+    // in 64-bit mode, `fxsave` stores the full instruction and operand pointers where the
+    // segments and the offsets are
+    for value in [
+        fp_regs.cwd as u32,
+        fp_regs.swd as u32,
+        full_tag_word(fp_regs.ftw),
+        (fp_regs.rip >> 32) as u32,
+        fp_regs.rip as u32,
+        (fp_regs.rdp >> 32) as u32,
+        fp_regs.rdp as u32,
+        fp_regs.fop as u32,
+    ] {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    for xmm in fp_regs.xmm_space.chunks(4) {
+        buf.extend_from_slice(&words_to_bytes(xmm));
+    }
+    buf.extend_from_slice(&fp_regs.mxcsr.to_le_bytes());
+
+    for value in [regs.orig_rax, regs.fs_base, regs.gs_base] {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    buf
+}
+
+/// The inverse of [`encode_registers`].
+fn decode_registers(
+    buf: &[u8],
+    regs: &mut user_regs_struct,
+    fp_regs: &mut user_fpregs_struct,
+) -> eyre::Result<()> {
+    let expected = register_range(59)?.end;
+    if buf.len() != expected {
+        return Err(eyre!(
+            "expected {expected} bytes of registers, got {}",
+            buf.len()
+        ));
+    }
+
+    let mut offset = 0;
+    let mut take = |len: usize| {
+        let bytes = &buf[offset..offset + len];
+        offset += len;
+        bytes
+    };
+    let u64_of = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().expect("8 bytes"));
+    let u32_of = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().expect("4 bytes"));
+
+    for name in &registers::NAMES[..17] {
+        let value = u64_of(take(8));
+        *registers::get_mut(regs, name).expect("a general purpose register") = value;
+    }
+    for name in &registers::NAMES[17..24] {
+        let value = u32_of(take(4)) as u64;
+        *registers::get_mut(regs, name).expect("a general purpose register") = value;
+    }
+
+    for st in fp_regs.st_space.chunks_mut(4) {
+        let mut bytes = words_to_bytes(st);
+        bytes[..10].copy_from_slice(take(10));
+        bytes_to_words(&bytes, st);
+    }
+    fp_regs.cwd = u32_of(take(4)) as u16;
+    fp_regs.swd = u32_of(take(4)) as u16;
+    fp_regs.ftw = abridged_tag_word(u32_of(take(4)));
+    let fiseg = u32_of(take(4)) as u64;
+    fp_regs.rip = fiseg << 32 | u32_of(take(4)) as u64;
+    let foseg = u32_of(take(4)) as u64;
+    fp_regs.rdp = foseg << 32 | u32_of(take(4)) as u64;
+    fp_regs.fop = u32_of(take(4)) as u16;
+
+    for xmm in fp_regs.xmm_space.chunks_mut(4) {
+        let bytes: [u8; 16] = take(16).try_into().expect("16 bytes");
+        bytes_to_words(&bytes, xmm);
+    }
+    fp_regs.mxcsr = u32_of(take(4));
+
+    regs.orig_rax = u64_of(take(8));
+    regs.fs_base = u64_of(take(8));
+    regs.gs_base = u64_of(take(8));
+
+    Ok(())
+}
+
+fn words_to_bytes(words: &[u32]) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    for (chunk, word) in bytes.chunks_mut(4).zip(words) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    bytes
+}
+
+fn bytes_to_words(bytes: &[u8; 16], words: &mut [u32]) {
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().expect("4 bytes"));
+    }
+}
+
+/// `fxsave` only keeps whether the x87 registers are empty, gdb wants the full tag word.
+/// The non-empty ones are reported as valid.
+fn full_tag_word(abridged: u16) -> u32 {
+    (0..8)
+        .map(|i| {
+            if abridged & (1 << i) != 0 {
+                0
+            } else {
+                0b11 << (2 * i)
+            }
+        })
+        .fold(0, |tags, tag| tags | tag)
+}
+
+fn abridged_tag_word(full: u32) -> u16 {
+    (0..8)
+        .filter(|i| (full >> (2 * i)) & 0b11 != 0b11)
+        .fold(0, |tags, i| tags | (1 << i))
+}
+
+fn gdb_signal(signal: Signal) -> u8 {
+    GDB_SIGNALS
+        .iter()
+        .find(|(linux, _)| *linux == signal)
+        .map_or(GDB_SIGNAL_UNKNOWN, |(_, gdb)| *gdb)
+}
+
+fn from_gdb_signal(signal: u8) -> Option<Signal> {
+    GDB_SIGNALS
+        .iter()
+        .find(|(_, gdb)| *gdb == signal)
+        .map(|(linux, _)| *linux)
+}
+
+fn parse_hex(s: &str) -> eyre::Result<u64> {
+    u64::from_str_radix(s, 16).map_err(|e| eyre!("invalid hex number `{s}`: {e}"))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn decode_hex(hex: &str) -> eyre::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(eyre!("odd number of hex digits"));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| eyre!("invalid hex `{hex}`: {e}"))
+        })
+        .collect()
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{BufRead, BufReader, Seek, SeekFrom},
+        net::SocketAddr,
+        process::{Child, Command},
+        thread,
+    };
+
+    use nix::unistd::Pid;
+
+    use super::*;
+
+    #[test]
+    fn unescapes_packets() {
+        assert_eq!(unescape(b"a}\x03b}]"), b"a#b}");
+        assert_eq!(checksum_of(b"OK"), 0x9a);
+    }
+
+    #[test]
+    fn serves_objects_in_chunks() {
+        assert_eq!(xfer("abcdef", "0,4").unwrap(), "mabcd");
+        assert_eq!(xfer("abcdef", "4,4").unwrap(), "lef");
+        assert_eq!(xfer("abcdef", "10,4").unwrap(), "l");
+        assert!(xfer("abcdef", "0").is_err());
+    }
+
+    #[test]
+    fn lays_out_the_registers_like_gdb() {
+        assert_eq!(register_range(0).unwrap(), 0..8);
+        assert_eq!(register_range(16).unwrap(), 128..136);
+        assert_eq!(register_range(17).unwrap(), 136..140);
+        assert_eq!(register_range(40).unwrap(), 276..292);
+        assert_eq!(register_range(59).unwrap(), 552..560);
+        assert!(register_range(60).is_err());
+    }
+
+    #[test]
+    fn round_trips_the_registers() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        let mut fp_regs: user_fpregs_struct = unsafe { std::mem::zeroed() };
+        regs.rax = 0x1122334455667788;
+        regs.rip = 0x7f00_0000_1146;
+        regs.eflags = 0x246;
+        regs.fs_base = 0x7f00_dead_0000;
+        fp_regs.cwd = 0x37f;
+        // st0 and st1 are in use
+        fp_regs.ftw = 0b11;
+        fp_regs.xmm_space[4] = 0x3ff0_0000;
+        fp_regs.mxcsr = 0x1f80;
+
+        let buf = encode_registers(&regs, &fp_regs);
+        assert_eq!(buf.len(), register_range(59).unwrap().end);
+        assert_eq!(
+            buf[register_range(16).unwrap()],
+            0x7f00_0000_1146u64.to_le_bytes()
+        );
+        assert_eq!(
+            buf[register_range(34).unwrap()],
+            0xfff0u32.to_le_bytes(),
+            "the full tag word"
+        );
+
+        let mut decoded_regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        let mut decoded_fp_regs: user_fpregs_struct = unsafe { std::mem::zeroed() };
+        decode_registers(&buf, &mut decoded_regs, &mut decoded_fp_regs).unwrap();
+        assert_eq!(decoded_regs.rax, regs.rax);
+        assert_eq!(decoded_regs.rip, regs.rip);
+        assert_eq!(decoded_regs.eflags, regs.eflags);
+        assert_eq!(decoded_regs.fs_base, regs.fs_base);
+        assert_eq!(decoded_fp_regs.cwd, fp_regs.cwd);
+        assert_eq!(decoded_fp_regs.ftw, fp_regs.ftw);
+        assert_eq!(decoded_fp_regs.xmm_space, fp_regs.xmm_space);
+        assert_eq!(decoded_fp_regs.mxcsr, fp_regs.mxcsr);
+
+        assert!(decode_registers(&buf[1..], &mut decoded_regs, &mut decoded_fp_regs).is_err());
+    }
+
+    #[test]
+    fn maps_the_signals() {
+        assert_eq!(gdb_signal(Signal::SIGTRAP), 5);
+        assert_eq!(gdb_signal(Signal::SIGUSR1), 30);
+        assert_eq!(gdb_signal(Signal::SIGSTKFLT), GDB_SIGNAL_UNKNOWN);
+        assert_eq!(from_gdb_signal(11), Some(Signal::SIGSEGV));
+        assert_eq!(from_gdb_signal(0), None);
+    }
+
+    /// The debugger side of the protocol.
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        no_ack: bool,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Self {
+            let writer = TcpStream::connect(addr).unwrap();
+            writer
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            Client {
+                reader: BufReader::new(writer.try_clone().unwrap()),
+                writer,
+                no_ack: false,
+            }
+        }
+
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0];
+            self.reader.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        /// Sends a packet with the given checksum and returns the ack.
+        fn send_raw(&mut self, data: &str, checksum: u8) -> Option<u8> {
+            write!(self.writer, "${data}#{checksum:02x}").unwrap();
+            (!self.no_ack).then(|| self.read_byte())
+        }
+
+        fn send(&mut self, data: &str) {
+            assert_eq!(
+                self.send_raw(data, checksum_of(data.as_bytes())),
+                Some(b'+')
+            );
+        }
+
+        fn receive(&mut self) -> String {
+            assert_eq!(self.read_byte(), b'$');
+            let mut packet = Vec::new();
+            self.reader.read_until(b'#', &mut packet).unwrap();
+            packet.pop();
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum).unwrap();
+
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16);
+            assert_eq!(checksum, Ok(checksum_of(&packet)));
+            if !self.no_ack {
+                self.writer.write_all(b"+").unwrap();
+            }
+
+            String::from_utf8(unescape(&packet)).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            if self.no_ack {
+                self.send_raw(data, checksum_of(data.as_bytes()));
+            } else {
+                self.send(data);
+            }
+            self.receive()
+        }
+    }
+
+    /// A process that spins in the user space, so that it can be stepped and interrupted.
+    fn spin() -> Child {
+        let child = Command::new("sh")
+            .args(["-c", "while :; do :; done"])
+            .spawn()
+            .unwrap();
+        // until it runs the shell rather than a copy of us
+        let exe = env::current_exe().unwrap();
+        while fs::read_link(format!("/proc/{}/exe", child.id()))
+            .ok()
+            .as_ref()
+            == Some(&exe)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(50));
+        child
+    }
+
+    fn read_raw(pid: u32, addr: u64, len: usize) -> Vec<u8> {
+        let mut mem = fs::File::open(format!("/proc/{pid}/mem")).unwrap();
+        mem.seek(SeekFrom::Start(addr)).unwrap();
+        let mut buf = vec![0; len];
+        mem.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    /// The start of a readable mapping of at least `len` bytes.
+    fn readable_mapping(pid: u32, len: u64) -> u64 {
+        fs::read_to_string(format!("/proc/{pid}/maps"))
+            .unwrap()
+            .lines()
+            .find_map(|line| {
+                let (range, perms) = line.split_once(' ')?;
+                let (start, end) = range.split_once('-')?;
+                let start = u64::from_str_radix(start, 16).ok()?;
+                let end = u64::from_str_radix(end, 16).ok()?;
+                (perms.starts_with('r') && end - start >= len).then_some(start)
+            })
+            .expect("a large enough mapping")
+    }
+
+    #[test]
+    fn serves_a_scripted_session() {
+        let mut child = spin();
+        let pid = child.id();
+        let perfmap = env::temp_dir().join(format!("poc-tui-gdbserver-{pid}.map"));
+        let symbol_file = env::temp_dir().join(format!("poc-tui-gdbserver-{pid}.elf"));
+        fs::write(&perfmap, "1000 40 wasm_binary::leaf\n").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = {
+            let (perfmap, symbol_file) = (perfmap.clone(), symbol_file.clone());
+            // the tracer is the thread that attaches
+            thread::spawn(move || -> eyre::Result<()> {
+                let mut tracee = TraceeArgs::new(USAGE);
+                tracee.attach = Some(Pid::from_raw(pid as i32));
+                tracee.perfmap = Some(perfmap);
+                let mut ctx = DebuggerCtx::new();
+                tracee.start(&mut ctx)?;
+                let (stream, _) = listener.accept()?;
+                let mut server = GdbServer::new(ctx, Connection::new(stream)?, symbol_file);
+                server.load_module()?;
+                server.serve()
+            })
+        };
+        let mut client = Client::connect(addr);
+
+        // a corrupted packet is asked again
+        assert_eq!(client.send_raw("?", 0), Some(b'-'));
+        assert_eq!(client.request("?"), format!("T02thread:{pid:x};"));
+
+        assert_eq!(
+            client.request("qSupported:multiprocess+;swbreak+;xmlRegisters=i386"),
+            FEATURES
+        );
+        assert_eq!(client.request("qAttached"), "1");
+        assert_eq!(client.request("qC"), format!("QC{pid:x}"));
+        assert_eq!(client.request("vCont?"), "vCont;c;C;s;S");
+        assert_eq!(client.request("vMustReplyEmpty"), "");
+
+        let xml = target_xml();
+        assert_eq!(
+            client.request("qXfer:features:read:target.xml:0,20"),
+            format!("m{}", &xml[..0x20])
+        );
+        assert_eq!(
+            client.request(&format!("qXfer:features:read:target.xml:{:x},1000", 0x20)),
+            format!("l{}", &xml[0x20..])
+        );
+        assert_eq!(client.request("qXfer:features:read:other.xml:0,20"), "E00");
+        assert_eq!(
+            client.request("qXfer:libraries:read::0,1000"),
+            format!(
+                r#"l<library-list><library name="{}"><segment address="0x1000"/></library></library-list>"#,
+                symbol_file.display()
+            )
+        );
+
+        // the registers
+        let g = decode_hex(&client.request("g")).unwrap();
+        assert_eq!(g.len(), register_range(59).unwrap().end);
+        let rip = u64::from_le_bytes(g[register_range(16).unwrap()].try_into().unwrap());
+        let rsp = u64::from_le_bytes(g[register_range(7).unwrap()].try_into().unwrap());
+        assert_eq!(client.request("p10"), encode_hex(&rip.to_le_bytes()));
+        assert_eq!(client.request(&format!("G{}", encode_hex(&g))), "OK");
+        assert_eq!(decode_hex(&client.request("g")).unwrap(), g);
+        assert_eq!(client.request("G00"), "E01");
+
+        // the memory
+        let code = read_raw(pid, rip, 4);
+        assert_eq!(client.request(&format!("m{rip:x},4")), encode_hex(&code));
+        let scratch = rsp - 0x200;
+        assert_eq!(client.request(&format!("M{scratch:x},4:deadbeef")), "OK");
+        assert_eq!(client.request(&format!("m{scratch:x},4")), "deadbeef");
+        assert_eq!(client.request("m0,4"), "E01");
+        // up to a full packet of hex is read
+        let mapping = readable_mapping(pid, 0x2001);
+        assert_eq!(
+            client.request(&format!("m{mapping:x},2000")),
+            encode_hex(&read_raw(pid, mapping, 0x2000))
+        );
+        assert_eq!(client.request(&format!("m{mapping:x},2001")), "E01");
+        assert_eq!(client.request("m0,ffffffffffffffff"), "E01");
+        assert_eq!(client.request("mfffffffffffffffe,2"), "E01");
+
+        // the traps are hidden from the memory reads
+        let addr = rip - 0x10;
+        let original = read_raw(pid, addr, 1);
+        assert_eq!(client.request(&format!("Z0,{addr:x},1")), "OK");
+        assert_eq!(read_raw(pid, addr, 1), [0xcc]);
+        assert_eq!(
+            client.request(&format!("m{addr:x},1")),
+            encode_hex(&original)
+        );
+        assert_eq!(client.request(&format!("z0,{addr:x},1")), "OK");
+        assert_eq!(read_raw(pid, addr, 1), original);
+        assert_eq!(client.request(&format!("Z1,{addr:x},1")), "");
+
+        // stepping and interrupting
+        assert_eq!(
+            client.request(&format!("vCont;s:{pid:x}")),
+            format!("T05thread:{pid:x};")
+        );
+        client.send(&format!("vCont;c:{pid:x}"));
+        thread::sleep(Duration::from_millis(50));
+        client.writer.write_all(&[0x03]).unwrap();
+        assert_eq!(client.receive(), format!("T02thread:{pid:x};"));
+
+        assert_eq!(client.request("QStartNoAckMode"), "OK");
+        client.no_ack = true;
+        assert_eq!(client.request("qfThreadInfo"), format!("m{pid:x}"));
+        assert_eq!(client.request("qsThreadInfo"), "l");
+
+        assert_eq!(client.request(&format!("vKill;{pid:x}")), "OK");
+        server.join().unwrap().unwrap();
+
+        let _ = child.wait();
+        let _ = fs::remove_file(perfmap);
+        let _ = fs::remove_file(symbol_file);
+    }
+}
@@ -0,0 +1,236 @@
+//! Instruction-level traces, i.e. `itrace`: every instruction that the current function
+//! executes until it returns, with the registers that it changes. This is what pins down a
+//! miscompilation when the breakpoints only show that the result is wrong.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use libc::user_regs_struct;
+
+use crate::{
+    debugger_ctx::{self, DebuggerCtx, ExitReason, MAX_INSTRUCTION_LEN, StopReason, TraceeState},
+    function_mapping::FunctionMapping,
+    registers,
+};
+
+/// The most instructions that are traced, so that an endless loop doesn't trace forever.
+pub const MAX_INSTRUCTIONS: usize = 100_000;
+
+/// An executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedInstruction {
+    pub addr: u64,
+    /// How many calls deep below the traced function it is
+    pub depth: usize,
+    /// e.g. `mov rax, qword ptr [rdi + 8]`
+    pub text: String,
+    /// The registers that the instruction changed with their new values, except `rip`
+    pub changes: Vec<(&'static str, u64)>,
+}
+
+impl TracedInstruction {
+    /// e.g. `0x00007f0000001146 leaf+0x4   add eax, 1   rax=0x2a eflags=0x202`, indented by
+    /// the depth.
+    pub fn format(&self, mapping: Option<&FunctionMapping>) -> String {
+        let symbol = mapping
+            .and_then(|mapping| mapping.symbolize(self.addr))
+            .unwrap_or_default();
+        let mut line = format!(
+            "{:#018x} {:indent$}{symbol:<24} {:<40}",
+            self.addr,
+            "",
+            self.text,
+            indent = 2 * self.depth
+        );
+        for (name, value) in &self.changes {
+            line.push_str(&format!(" {name}={value:#x}"));
+        }
+
+        line.trim_end().into()
+    }
+}
+
+/// Why a trace is over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEnd {
+    /// The function returned, the tracee is stopped at the return address.
+    Returned,
+    /// [`MAX_INSTRUCTIONS`] are traced.
+    Limit,
+    /// Stopped by the user.
+    Interrupted,
+    /// The tracee stopped for something else, e.g. a breakpoint in a call or a signal.
+    Stopped(StopReason),
+    Exited(ExitReason),
+}
+
+impl fmt::Display for TraceEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceEnd::Returned => write!(f, "until the function returned"),
+            TraceEnd::Limit => write!(f, "up to the limit"),
+            TraceEnd::Interrupted => write!(f, "until interrupted"),
+            TraceEnd::Stopped(reason) => write!(f, "until the tracee stopped ({reason})"),
+            TraceEnd::Exited(reason) => write!(f, "until the tracee exited ({reason})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub instructions: usize,
+    pub end: TraceEnd,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Traced {} instructions {}", self.instructions, self.end)
+    }
+}
+
+/// Single-steps the stopped tracee until the current function returns, passing every executed
+/// instruction to `on_instruction`. The calls are stepped into with `calls`, otherwise they
+/// run at full speed as a single instruction. `stop` is checked between the instructions.
+pub fn trace(
+    ctx: &mut DebuggerCtx,
+    calls: bool,
+    stop: impl Fn() -> bool,
+    mut on_instruction: impl FnMut(TracedInstruction) -> eyre::Result<()>,
+) -> eyre::Result<Summary> {
+    if !ctx.is_stopped() {
+        return Err(eyre!("the tracee is not stopped ({})", ctx.state));
+    }
+
+    let (return_addr, return_sp) = ctx.return_address()?;
+    let cs = debugger_ctx::disassembler()?;
+    let mut regs = ctx.registers()?;
+    let mut depth = 0;
+    let mut instructions = 0;
+    let end = loop {
+        if regs.rip == return_addr && regs.rsp >= return_sp {
+            break TraceEnd::Returned;
+        }
+        if instructions == MAX_INSTRUCTIONS {
+            break TraceEnd::Limit;
+        }
+        if stop() {
+            break TraceEnd::Interrupted;
+        }
+
+        let addr = regs.rip;
+        let code = ctx.read_memory(addr, MAX_INSTRUCTION_LEN)?;
+        let insns = cs
+            .disasm_count(&code, addr, 1)
+            .map_err(|e| eyre!("failed to disassemble at {addr:#x}: {e}"))?;
+        let insn = insns
+            .first()
+            .ok_or_else(|| eyre!("no valid instruction at {addr:#x}"))?;
+        let mnemonic = insn.mnemonic().unwrap_or_default();
+        let text = match insn.op_str() {
+            Some(operands) if !operands.is_empty() => format!("{mnemonic} {operands}"),
+            _ => mnemonic.to_string(),
+        };
+        let enters = calls && mnemonic.starts_with("call");
+        let leaves = mnemonic.starts_with("ret");
+
+        if calls {
+            ctx.step_instruction()?;
+        } else {
+            ctx.next_instruction()?;
+        }
+        let state = wait(ctx)?;
+
+        // a faulting instruction is traced too, without changing anything
+        let new_regs = match state {
+            TraceeState::Stopped(_) => ctx.registers()?,
+            _ => regs,
+        };
+        instructions += 1;
+        on_instruction(TracedInstruction {
+            addr,
+            depth,
+            text,
+            changes: changes(&regs, &new_regs),
+        })?;
+        regs = new_regs;
+
+        match state {
+            TraceeState::Stopped(StopReason::Step | StopReason::Finished) => {}
+            TraceeState::Stopped(reason) => break TraceEnd::Stopped(reason),
+            TraceeState::Exited(reason) => break TraceEnd::Exited(reason),
+            TraceeState::NotStarted | TraceeState::Running => unreachable!("reported by poll"),
+        }
+
+        if enters {
+            depth += 1;
+        } else if leaves {
+            depth = depth.saturating_sub(1);
+        }
+    };
+
+    Ok(Summary { instructions, end })
+}
+
+/// Traces like [`trace`], and writes the instructions into `output`, or passes them to `print`.
+/// The summary is passed to `print` in any case.
+pub fn trace_into(
+    ctx: &mut DebuggerCtx,
+    calls: bool,
+    output: Option<&Path>,
+    stop: impl Fn() -> bool,
+    mut print: impl FnMut(String),
+) -> eyre::Result<()> {
+    let mapping = ctx.function_mapping.clone();
+    let mut file = output
+        .map(|path| {
+            File::create(path)
+                .map(BufWriter::new)
+                .wrap_err_with(|| format!("failed to create `{}`", path.display()))
+        })
+        .transpose()?;
+
+    let summary = trace(ctx, calls, stop, |insn| {
+        let line = insn.format(mapping.as_deref());
+        match &mut file {
+            Some(file) => writeln!(file, "{line}")?,
+            None => print(line),
+        }
+        Ok(())
+    })?;
+    if let Some(mut file) = file {
+        file.flush()?;
+    }
+
+    match output {
+        Some(path) => print(format!("{summary} into `{}`", path.display())),
+        None => print(summary.to_string()),
+    }
+
+    Ok(())
+}
+
+/// Blocks until the stepped tracee stops or exits.
+fn wait(ctx: &mut DebuggerCtx) -> eyre::Result<TraceeState> {
+    loop {
+        if let Some(state) = ctx.poll(true)? {
+            return Ok(state);
+        }
+    }
+}
+
+/// The registers that differ in `new`, see [`TracedInstruction::changes`].
+fn changes(old: &user_regs_struct, new: &user_regs_struct) -> Vec<(&'static str, u64)> {
+    registers::NAMES
+        .iter()
+        .filter(|&&name| name != "rip")
+        .filter_map(|&name| {
+            let value = registers::read(new, name)?;
+            (registers::read(old, name) != Some(value)).then_some((name, value))
+        })
+        .collect()
+}
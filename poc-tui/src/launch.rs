@@ -0,0 +1,210 @@
+use std::{
+    fmt,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use color_eyre::eyre::{self, eyre};
+
+/// How to start a tracee, parsed from a shell-like command line:
+///
+/// ```text
+/// [cd DIR &&] [VAR=value ...] program [args ...]
+/// ```
+///
+/// Quoting and escaping work as in a POSIX shell, but no other shell feature (pipes,
+/// redirections, globs, variable expansion) is supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaunchSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Variables that are set on top of the environment of the debugger
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<PathBuf>,
+    /// Pipe the stdout and the stderr of the tracee to the debugger instead of inheriting them
+    pub capture_output: bool,
+}
+
+impl LaunchSpec {
+    pub fn parse(command_line: &str) -> eyre::Result<Self> {
+        let mut tokens = shlex::split(command_line)
+            .ok_or_else(|| eyre!("unbalanced quotes in `{command_line}`"))?
+            .into_iter()
+            .peekable();
+
+        let mut cwd = None;
+        if tokens.peek().map(String::as_str) == Some("cd") {
+            tokens.next();
+            let dir = tokens
+                .next()
+                .ok_or_else(|| eyre!("`cd` requires a directory"))?;
+            if tokens.next().as_deref() != Some("&&") {
+                return Err(eyre!("expected `&&` after `cd {dir}`"));
+            }
+            cwd = Some(PathBuf::from(dir));
+        }
+
+        let mut env = Vec::new();
+        while let Some(token) = tokens.peek() {
+            match token.split_once('=') {
+                Some((name, value)) if is_env_var_name(name) => {
+                    env.push((name.to_string(), value.to_string()));
+                    tokens.next();
+                }
+                _ => break,
+            }
+        }
+
+        let program = tokens
+            .next()
+            .ok_or_else(|| eyre!("no program is given in `{command_line}`"))?;
+
+        Ok(LaunchSpec {
+            program,
+            args: tokens.collect(),
+            env,
+            cwd,
+            capture_output: true,
+        })
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command.envs(self.env.iter().map(|(k, v)| (k, v)));
+        if let Some(cwd) = &self.cwd {
+            command.current_dir(cwd);
+        }
+        if self.capture_output {
+            // the terminal belongs to the debugger
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped());
+        }
+        command
+    }
+}
+
+impl fmt::Display for LaunchSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let quote = |s: &str| {
+            shlex::try_quote(s)
+                .map(|q| q.into_owned())
+                .unwrap_or(s.into())
+        };
+
+        if let Some(cwd) = &self.cwd {
+            write!(f, "cd {} && ", quote(&cwd.to_string_lossy()))?;
+        }
+        for (name, value) in &self.env {
+            write!(f, "{name}={} ", quote(value))?;
+        }
+        write!(f, "{}", quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", quote(arg))?;
+        }
+        Ok(())
+    }
+}
+
+/// Which stream of the tracee an output line comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A line that the tracee printed, without the line terminator.
+#[derive(Debug, Clone)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub text: String,
+}
+
+fn is_env_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(command_line: &str) -> String {
+        format!("{:#}", LaunchSpec::parse(command_line).unwrap_err())
+    }
+
+    #[test]
+    fn parses_a_plain_command() {
+        assert_eq!(
+            LaunchSpec::parse("wasmtime run app.wasm").unwrap(),
+            LaunchSpec {
+                program: "wasmtime".into(),
+                args: vec!["run".into(), "app.wasm".into()],
+                env: Vec::new(),
+                cwd: None,
+                capture_output: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_quotes_and_escapes() {
+        let spec =
+            LaunchSpec::parse(r#"'my program' "an arg" 'it''s' a\ b "say \"hi\"" ''"#).unwrap();
+        assert_eq!(spec.program, "my program");
+        assert_eq!(spec.args, ["an arg", "its", "a b", r#"say "hi""#, ""]);
+    }
+
+    #[test]
+    fn parses_the_cwd_and_the_environment() {
+        let spec = LaunchSpec::parse(
+            "cd '/srv/my app' && RUST_LOG=debug _X1= NAME='a b' wasmtime A=1 run",
+        )
+        .unwrap();
+        assert_eq!(spec.cwd, Some(PathBuf::from("/srv/my app")));
+        assert_eq!(
+            spec.env,
+            [
+                ("RUST_LOG".into(), "debug".into()),
+                ("_X1".into(), "".into()),
+                ("NAME".into(), "a b".into()),
+            ]
+        );
+        assert_eq!(spec.program, "wasmtime");
+        // only the assignments before the program set variables
+        assert_eq!(spec.args, ["A=1", "run"]);
+
+        // not a variable name, so it's the program
+        assert_eq!(LaunchSpec::parse("1X=2 run").unwrap().program, "1X=2");
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let command_line = "cd '/srv/my app' && NAME='a b' wasmtime run 'app v2.wasm'";
+        let spec = LaunchSpec::parse(command_line).unwrap();
+        assert_eq!(spec.to_string(), command_line);
+        assert_eq!(LaunchSpec::parse(&spec.to_string()).unwrap(), spec);
+    }
+
+    #[test]
+    fn rejects_malformed_command_lines() {
+        assert_eq!(error(""), "no program is given in ``");
+        assert_eq!(error("A=1 B=2"), "no program is given in `A=1 B=2`");
+        assert_eq!(error("cd /srv &&"), "no program is given in `cd /srv &&`");
+        assert_eq!(error("cd"), "`cd` requires a directory");
+        assert_eq!(error("cd /srv wasmtime"), "expected `&&` after `cd /srv`");
+        assert_eq!(
+            error("wasmtime 'run"),
+            "unbalanced quotes in `wasmtime 'run`"
+        );
+        assert_eq!(
+            error(r#"wasmtime "run"#),
+            r#"unbalanced quotes in `wasmtime "run`"#
+        );
+    }
+}
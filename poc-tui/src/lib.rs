@@ -0,0 +1,38 @@
+pub mod address_map;
+pub mod app;
+pub mod batch;
+pub mod breakpoint;
+pub mod cfg;
+pub mod chrome_trace;
+pub mod console;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod debugger_ctx;
+pub mod disassembly;
+pub mod event;
+pub mod expr;
+pub mod function_mapping;
+pub mod fuzzy;
+pub mod gdbserver;
+pub mod itrace;
+pub mod launch;
+pub mod logpoint;
+pub mod notification;
+pub mod perf_map;
+pub mod pprof;
+pub mod profile;
+pub mod record;
+pub mod registers;
+pub mod repl;
+pub mod script;
+pub mod scroll_buffer;
+pub mod stats;
+pub mod symbol_file;
+pub mod symbolize;
+pub mod trace;
+pub mod trace_viewer;
+pub mod ui;
+pub mod wasm_abi;
+pub mod wasm_dwarf;
+pub mod wasm_symbol;
@@ -0,0 +1,341 @@
+use std::{
+    fmt::{self, Write as _},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{self, eyre};
+
+use crate::{breakpoint::BreakpointId, debugger_ctx::DebuggerCtx, expr::Expr};
+
+/// How many messages a logpoint emits per second by default, the rest are dropped.
+pub const DEFAULT_RATE_LIMIT: u32 = 100;
+
+/// A breakpoint that logs a message on every hit, then lets the tracee continue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Logpoint {
+    pub format: LogFormat,
+    pub rate_limit: RateLimit,
+}
+
+impl Logpoint {
+    pub fn new(format: LogFormat) -> Self {
+        Logpoint {
+            format,
+            rate_limit: RateLimit::new(DEFAULT_RATE_LIMIT),
+        }
+    }
+
+    /// The message of a hit at `now` with how many were dropped before it, `None` if the rate
+    /// limit drops it. Only the messages that get through are passed to `render`, which reads
+    /// the tracee.
+    pub fn log(
+        &mut self,
+        now: Instant,
+        render: impl FnOnce(&LogFormat) -> String,
+    ) -> Option<(u64, String)> {
+        let dropped = self.rate_limit.admit(now)?;
+        Some((dropped, render(&self.format)))
+    }
+}
+
+/// A message with the expressions to evaluate in it, e.g. `trim called with len={arg1}`.
+///
+/// `{EXPR}` is replaced with the value of an [`Expr`] in decimal, `{EXPR:x}` in hexadecimal.
+/// `{{` and `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFormat {
+    /// As the user wrote it, for the display
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Value { expr: Expr, hex: bool },
+}
+
+impl LogFormat {
+    pub fn parse(source: &str) -> eyre::Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => placeholder.push(c),
+                            None => return Err(eyre!("unclosed `{{` in `{source}`")),
+                        }
+                    }
+                    let (expr, hex) = match placeholder.rsplit_once(':') {
+                        Some((expr, "x")) => (expr, true),
+                        Some((_, spec)) => {
+                            return Err(eyre!("unknown format `:{spec}`, only `:x` is supported"));
+                        }
+                        None => (placeholder.as_str(), false),
+                    };
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Value {
+                        expr: Expr::parse(expr)?,
+                        hex,
+                    });
+                }
+                '}' => return Err(eyre!("unmatched `}}` in `{source}`, use `}}}}`")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(LogFormat {
+            source: source.into(),
+            parts,
+        })
+    }
+
+    /// Evaluates the expressions in the stopped tracee. The ones that fail are replaced with
+    /// the error, the tracee is not stopped for them.
+    pub fn render(&self, ctx: &DebuggerCtx) -> String {
+        let mut message = String::new();
+        for part in &self.parts {
+            let _ = match part {
+                Part::Text(text) => write!(message, "{text}"),
+                Part::Value { expr, hex } => match expr.eval(ctx) {
+                    Ok(value) if *hex => write!(message, "{value:#x}"),
+                    Ok(value) => write!(message, "{value}"),
+                    Err(e) => write!(message, "<{e:#}>"),
+                },
+            };
+        }
+
+        message
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+/// Drops the messages beyond `per_second` in every second, counting them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Zero means unlimited
+    pub per_second: u32,
+    window_start: Option<Instant>,
+    in_window: u32,
+    /// The messages dropped since the last one that got through
+    dropped: u64,
+}
+
+impl RateLimit {
+    pub fn new(per_second: u32) -> Self {
+        RateLimit {
+            per_second,
+            window_start: None,
+            in_window: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Whether a message can be emitted `now`. If so, returns how many were dropped before it.
+    pub fn admit(&mut self, now: Instant) -> Option<u64> {
+        if self.per_second == 0 {
+            return Some(0);
+        }
+
+        let expired = self
+            .window_start
+            .is_none_or(|start| now.duration_since(start) >= Duration::from_secs(1));
+        if expired {
+            self.window_start = Some(now);
+            self.in_window = 0;
+        }
+
+        if self.in_window >= self.per_second {
+            self.dropped += 1;
+            return None;
+        }
+        self.in_window += 1;
+
+        Some(std::mem::take(&mut self.dropped))
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.per_second {
+            0 => write!(f, "unlimited"),
+            per_second => write!(f, "{per_second}/s"),
+        }
+    }
+}
+
+/// A message of a logpoint, see [`DebuggerCtx::take_log`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogMessage {
+    pub id: BreakpointId,
+    /// The hit count of the logpoint, including this hit
+    pub hits: u64,
+    /// The messages of the logpoint that are dropped right before this one
+    pub dropped: u64,
+    pub text: String,
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} #{}] {}", self.id, self.hits, self.text)?;
+        if self.dropped > 0 {
+            write!(f, " ({} dropped by the rate limit)", self.dropped)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn error(source: &str) -> String {
+        format!("{:#}", LogFormat::parse(source).unwrap_err())
+    }
+
+    #[test]
+    fn parses_text_and_values() {
+        let format = LogFormat::parse("len={arg1} at {$rip:x}!").unwrap();
+        assert_eq!(
+            format.parts,
+            [
+                Part::Text("len=".into()),
+                Part::Value {
+                    expr: Expr::parse("arg1").unwrap(),
+                    hex: false,
+                },
+                Part::Text(" at ".into()),
+                Part::Value {
+                    expr: Expr::parse("$rip").unwrap(),
+                    hex: true,
+                },
+                Part::Text("!".into()),
+            ]
+        );
+        assert_eq!(format.to_string(), r#""len={arg1} at {$rip:x}!""#);
+    }
+
+    #[test]
+    fn renders_escapes_and_hex() {
+        let ctx = DebuggerCtx::new();
+        let render = |source| LogFormat::parse(source).unwrap().render(&ctx);
+        assert_eq!(render("{{literal}} {1 + 2} {255:x}"), "{literal} 3 0xff");
+        assert_eq!(render("{{{7}}}"), "{7}");
+        assert_eq!(render(""), "");
+        // the failing expressions are rendered as their error, without a tracee here
+        assert!(render("v={*(u32*)0x1000}").starts_with("v=<"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert_eq!(error("len={arg1"), "unclosed `{` in `len={arg1`");
+        assert_eq!(error("{"), "unclosed `{` in `{`");
+        assert_eq!(error("len=arg1}"), "unmatched `}` in `len=arg1}`, use `}}`");
+        assert_eq!(
+            error("{arg1:d}"),
+            "unknown format `:d`, only `:x` is supported"
+        );
+        assert!(LogFormat::parse("{}").is_err());
+    }
+
+    #[test]
+    fn limits_the_rate_per_window() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut limit = RateLimit::new(2);
+        assert_eq!(limit.admit(at(0)), Some(0));
+        assert_eq!(limit.admit(at(10)), Some(0));
+        assert_eq!(limit.admit(at(20)), None);
+        assert_eq!(limit.admit(at(999)), None);
+        // a new window starts, with the dropped count
+        assert_eq!(limit.admit(at(1000)), Some(2));
+        assert_eq!(limit.admit(at(1500)), Some(0));
+        assert_eq!(limit.admit(at(1600)), None);
+        // the window starts at its first message, not on a second boundary
+        assert_eq!(limit.admit(at(2600)), Some(1));
+        assert_eq!(limit.admit(at(3599)), Some(0));
+        assert_eq!(limit.admit(at(3600)), Some(0));
+    }
+
+    #[test]
+    fn zero_is_unlimited() {
+        let now = Instant::now();
+        let mut limit = RateLimit::new(0);
+        assert!((0..1000).all(|_| limit.admit(now) == Some(0)));
+        assert_eq!(limit.to_string(), "unlimited");
+        assert_eq!(RateLimit::new(5).to_string(), "5/s");
+    }
+
+    #[test]
+    fn only_renders_the_admitted_messages() {
+        let mut log = Logpoint::new(LogFormat::parse("hit").unwrap());
+        log.rate_limit.per_second = 2;
+        let renders = Cell::new(0);
+        let render = |format: &LogFormat| {
+            renders.set(renders.get() + 1);
+            format.source.clone()
+        };
+
+        let now = Instant::now();
+        let messages: Vec<_> = (0..5).map(|_| log.log(now, render)).collect();
+        assert_eq!(
+            messages,
+            [
+                Some((0, "hit".into())),
+                Some((0, "hit".into())),
+                None,
+                None,
+                None,
+            ]
+        );
+        assert_eq!(renders.get(), 2);
+
+        assert_eq!(
+            log.log(now + Duration::from_secs(1), render),
+            Some((3, "hit".into()))
+        );
+        assert_eq!(renders.get(), 3);
+    }
+
+    #[test]
+    fn shows_the_dropped_count() {
+        let message = |dropped| LogMessage {
+            id: BreakpointId(3),
+            hits: 12,
+            dropped,
+            text: "len=5".into(),
+        };
+        assert_eq!(message(0).to_string(), "[3 #12] len=5");
+        assert_eq!(
+            message(4).to_string(),
+            "[3 #12] len=5 (4 dropped by the rate limit)"
+        );
+    }
+}
@@ -1,13 +1,27 @@
-use crate::app::App;
-
-pub mod app;
-pub mod debugger_ctx;
-pub mod event;
-pub mod function_mapping;
-pub mod ui;
+use poc_tui::{
+    app::App, chrome_trace, coverage, dap, gdbserver, profile, record, stats, symbolize,
+    trace_viewer,
+};
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    if let Some(subcommand) = args.next() {
+        return match subcommand.as_str() {
+            "symbolize" => symbolize::run(args),
+            "gdbserver" => gdbserver::run(args),
+            "dap" => dap::run(args),
+            "record" => record::run(args),
+            "view" => trace_viewer::run(args),
+            "export" => chrome_trace::run(args),
+            "coverage" => coverage::run(args),
+            "profile" => profile::run(args),
+            "stats" => stats::run(args),
+            other => Err(color_eyre::eyre::eyre!("unknown subcommand `{other}`")),
+        };
+    }
+
     let terminal = ratatui::init();
     let result = App::new().run(terminal);
     ratatui::restore();
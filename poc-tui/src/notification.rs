@@ -0,0 +1,55 @@
+use std::{fmt, time::SystemTime};
+
+use crate::scroll_buffer::ScrollBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warn"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub time: SystemTime,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Notification {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Notification {
+            time: SystemTime::now(),
+            severity,
+            message: message.into(),
+        }
+    }
+
+    /// `HH:MM:SS` in UTC, which is enough to correlate the messages with each other.
+    pub fn timestamp(&self) -> String {
+        let secs = self
+            .time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        format!(
+            "{:02}:{:02}:{:02}",
+            (secs / 3600) % 24,
+            (secs / 60) % 60,
+            secs % 60
+        )
+    }
+}
+
+/// Scrollable log of the notifications, the newest one is the last.
+pub type NotificationLog = ScrollBuffer<Notification>;
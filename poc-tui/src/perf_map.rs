@@ -0,0 +1,200 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{self, eyre};
+
+/// Environment variable that can be used to point to the perf map explicitly.
+pub const PERFMAP_PATH_ENV: &str = "TRIPWIRE_PERFMAP";
+
+/// Finds the perf map that the runtime of the process `pid` has written.
+///
+/// wasmtime writes `/tmp/perf-{pid}.map` from the point of view of the tracee. When the
+/// tracee lives in another pid and/or mount namespace (e.g. a container), both the pid and
+/// `/tmp` differ from ours, so we resolve the namespaced pid through `NSpid` and look under
+/// `/proc/{pid}/root` first.
+///
+/// `override_path` (or [`PERFMAP_PATH_ENV`] if it's not given) always wins over the lookup.
+pub fn locate(pid: u32, override_path: Option<&Path>) -> eyre::Result<PathBuf> {
+    let override_path = override_path
+        .map(Path::to_path_buf)
+        .or_else(|| env::var_os(PERFMAP_PATH_ENV).map(PathBuf::from));
+
+    if let Some(path) = override_path {
+        if !path.is_file() {
+            return Err(eyre!(
+                "the given perf map `{}` does not exist",
+                path.display()
+            ));
+        }
+        return Ok(path);
+    }
+
+    let proc_dir = PathBuf::from(format!("/proc/{pid}"));
+    if !proc_dir.exists() {
+        return Err(eyre!("process {pid} does not exist"));
+    }
+
+    let candidates = candidates(pid, &proc_dir);
+
+    candidates
+        .iter()
+        .find(|path| path.is_file())
+        .cloned()
+        .ok_or_else(|| {
+            let searched = candidates
+                .iter()
+                .map(|p| format!("  {}", p.display()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            eyre!(
+                "could not find the perf map of process {pid}, searched:\n{searched}\n\
+                 make sure the runtime has perf maps enabled (e.g. \
+                 `Config::profiler(ProfilingStrategy::PerfMap)` or `wasmtime run --profile=perfmap`), \
+                 or set `{PERFMAP_PATH_ENV}` to the map's path"
+            )
+        })
+}
+
+/// All the places where the perf map might be, in the order of preference.
+fn candidates(pid: u32, proc_dir: &Path) -> Vec<PathBuf> {
+    // The innermost pid is the one that the tracee sees as its own, hence the one that
+    // the runtime used in the file name.
+    let ns_pid = namespaced_pid(proc_dir).unwrap_or(pid);
+    let root = proc_dir.join("root");
+
+    let mut tmp_dirs = Vec::new();
+    if let Some(tmp_dir) = tracee_tmpdir(proc_dir) {
+        tmp_dirs.push(tmp_dir);
+    }
+    tmp_dirs.push(PathBuf::from("/tmp"));
+
+    let mut candidates = Vec::new();
+    for tmp_dir in &tmp_dirs {
+        let relative = tmp_dir.strip_prefix("/").unwrap_or(tmp_dir);
+        candidates.push(root.join(relative).join(format!("perf-{ns_pid}.map")));
+    }
+    // `/proc/{pid}/root` is not accessible when we don't have the permissions to read
+    // the tracee's root. The map is still reachable from here when we share the mount
+    // namespace.
+    candidates.push(env::temp_dir().join(format!("perf-{pid}.map")));
+    candidates.push(PathBuf::from(format!("/tmp/perf-{pid}.map")));
+
+    candidates.dedup();
+    candidates
+}
+
+/// Reads the innermost pid from the `NSpid` field of `/proc/{pid}/status`.
+fn namespaced_pid(proc_dir: &Path) -> Option<u32> {
+    let status = fs::read_to_string(proc_dir.join("status")).ok()?;
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("NSpid:"))?
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+/// Reads `TMPDIR` from the environment of the tracee.
+fn tracee_tmpdir(proc_dir: &Path) -> Option<PathBuf> {
+    let environ = fs::read(proc_dir.join("environ")).ok()?;
+
+    environ
+        .split(|b| *b == 0)
+        .find_map(|var| var.strip_prefix(b"TMPDIR="))
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(String::from_utf8_lossy(dir).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `/proc/{pid}` with the given `status` and `environ`, under the temporary directory.
+    fn fake_proc(name: &str, status: &str, environ: Option<&[u8]>) -> PathBuf {
+        let proc_dir = env::temp_dir().join(format!("poc-tui-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&proc_dir);
+        fs::create_dir_all(proc_dir.join("root/tmp")).unwrap();
+        fs::write(proc_dir.join("status"), status).unwrap();
+        if let Some(environ) = environ {
+            fs::write(proc_dir.join("environ"), environ).unwrap();
+        }
+        proc_dir
+    }
+
+    #[test]
+    fn reads_the_innermost_namespaced_pid() {
+        let proc_dir = fake_proc(
+            "nspid",
+            "Name:\twasmtime\nPid:\t1234\nNSpid:\t1234\t56\t7\nTgid:\t1234\n",
+            None,
+        );
+        assert_eq!(namespaced_pid(&proc_dir), Some(7));
+
+        fs::write(proc_dir.join("status"), "Name:\twasmtime\nPid:\t1234\n").unwrap();
+        assert_eq!(namespaced_pid(&proc_dir), None);
+        fs::remove_dir_all(&proc_dir).unwrap();
+    }
+
+    #[test]
+    fn reads_the_tmpdir_of_the_tracee() {
+        let proc_dir = fake_proc(
+            "tmpdir",
+            "",
+            Some(b"HOME=/root\0TMPDIR=/scratch\0PATH=/bin\0"),
+        );
+        assert_eq!(tracee_tmpdir(&proc_dir), Some(PathBuf::from("/scratch")));
+
+        fs::write(proc_dir.join("environ"), b"TMPDIR=\0").unwrap();
+        assert_eq!(tracee_tmpdir(&proc_dir), None);
+        fs::remove_dir_all(&proc_dir).unwrap();
+    }
+
+    #[test]
+    fn finds_the_map_in_the_host_namespace() {
+        let proc_dir = fake_proc("host-ns", "Pid:\t1234\nNSpid:\t1234\n", Some(b"\0"));
+        let map = proc_dir.join("root/tmp/perf-1234.map");
+        fs::write(&map, "").unwrap();
+
+        let candidates = candidates(1234, &proc_dir);
+        assert_eq!(candidates[0], map);
+        assert!(candidates.contains(&PathBuf::from("/tmp/perf-1234.map")));
+        assert_eq!(candidates.iter().find(|path| path.is_file()), Some(&map));
+        fs::remove_dir_all(&proc_dir).unwrap();
+    }
+
+    #[test]
+    fn finds_the_map_in_a_nested_namespace() {
+        let proc_dir = fake_proc(
+            "nested-ns",
+            "Pid:\t1234\nNSpid:\t1234\t7\n",
+            Some(b"TMPDIR=/scratch\0"),
+        );
+        fs::create_dir_all(proc_dir.join("root/scratch")).unwrap();
+        let map = proc_dir.join("root/scratch/perf-7.map");
+        fs::write(&map, "").unwrap();
+        // a map of another process of the container, which has our pid in there
+        fs::write(proc_dir.join("root/tmp/perf-1234.map"), "").unwrap();
+
+        let candidates = candidates(1234, &proc_dir);
+        assert_eq!(
+            candidates[..2],
+            [map.clone(), proc_dir.join("root/tmp/perf-7.map")]
+        );
+        assert_eq!(candidates.iter().find(|path| path.is_file()), Some(&map));
+
+        // without a `TMPDIR` the map is in the `/tmp` of the container
+        fs::remove_file(proc_dir.join("environ")).unwrap();
+        fs::rename(&map, proc_dir.join("root/tmp/perf-7.map")).unwrap();
+        assert_eq!(
+            super::candidates(1234, &proc_dir)
+                .iter()
+                .find(|path| path.is_file()),
+            Some(&proc_dir.join("root/tmp/perf-7.map"))
+        );
+        fs::remove_dir_all(&proc_dir).unwrap();
+    }
+}
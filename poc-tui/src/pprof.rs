@@ -0,0 +1,443 @@
+//! Encodes a [`Profile`] in the protobuf format of pprof, uncompressed, which `pprof` and
+//! `go tool pprof` read as well as the gzipped one.
+//!
+//! https://github.com/google/pprof/blob/main/proto/profile.proto
+
+use std::collections::HashMap;
+
+use crate::profile::{Frame, Profile};
+
+/// The fields of the messages that are written, see `profile.proto`
+mod field {
+    pub const PROFILE_SAMPLE_TYPE: u32 = 1;
+    pub const PROFILE_SAMPLE: u32 = 2;
+    pub const PROFILE_LOCATION: u32 = 4;
+    pub const PROFILE_FUNCTION: u32 = 5;
+    pub const PROFILE_STRING_TABLE: u32 = 6;
+    pub const PROFILE_TIME_NANOS: u32 = 9;
+    pub const PROFILE_DURATION_NANOS: u32 = 10;
+    pub const PROFILE_PERIOD_TYPE: u32 = 11;
+    pub const PROFILE_PERIOD: u32 = 12;
+
+    pub const VALUE_TYPE_TYPE: u32 = 1;
+    pub const VALUE_TYPE_UNIT: u32 = 2;
+
+    pub const SAMPLE_LOCATION_ID: u32 = 1;
+    pub const SAMPLE_VALUE: u32 = 2;
+
+    pub const LOCATION_ID: u32 = 1;
+    pub const LOCATION_ADDRESS: u32 = 3;
+    pub const LOCATION_LINE: u32 = 4;
+
+    pub const LINE_FUNCTION_ID: u32 = 1;
+
+    pub const FUNCTION_ID: u32 = 1;
+    pub const FUNCTION_NAME: u32 = 2;
+    pub const FUNCTION_SYSTEM_NAME: u32 = 3;
+}
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_LEN: u64 = 2;
+
+/// A protobuf message that is being encoded.
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, field: u32, value: u64) {
+        self.key(field, WIRE_VARINT);
+        self.raw_varint(value);
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, WIRE_LEN);
+        self.raw_varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn message(&mut self, field: u32, message: Message) {
+        self.bytes(field, &message.0);
+    }
+
+    fn packed(&mut self, field: u32, values: impl IntoIterator<Item = u64>) {
+        let mut packed = Message::default();
+        for value in values {
+            packed.raw_varint(value);
+        }
+        self.bytes(field, &packed.0);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        self.raw_varint(((field as u64) << 3) | wire_type);
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+/// The strings of the profile, which the messages refer to by their index. The first one is
+/// the empty string.
+struct StringTable {
+    strings: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        StringTable {
+            strings: vec![String::new()],
+            indices: HashMap::from([(String::new(), 0)]),
+        }
+    }
+
+    fn index(&mut self, s: &str) -> u64 {
+        if let Some(&index) = self.indices.get(s) {
+            return index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(s.into());
+        self.indices.insert(s.into(), index);
+        index
+    }
+}
+
+/// Encodes the samples with two values: the number of samples and the wall time that they
+/// stand for. Every address of the samples is a location, in a function of the perf map or
+/// in the host.
+pub fn encode(profile: &Profile) -> Vec<u8> {
+    let mut strings = StringTable::new();
+    let mut message = Message::default();
+
+    let value_type = |strings: &mut StringTable, kind: &str, unit: &str| {
+        let mut value_type = Message::default();
+        value_type.varint(field::VALUE_TYPE_TYPE, strings.index(kind));
+        value_type.varint(field::VALUE_TYPE_UNIT, strings.index(unit));
+        value_type
+    };
+    message.message(
+        field::PROFILE_SAMPLE_TYPE,
+        value_type(&mut strings, "samples", "count"),
+    );
+    message.message(
+        field::PROFILE_SAMPLE_TYPE,
+        value_type(&mut strings, "wall", "nanoseconds"),
+    );
+
+    // the ids start at 1, 0 is no id
+    let mut locations: HashMap<Frame, u64> = HashMap::new();
+    let mut functions: HashMap<&str, u64> = HashMap::new();
+    let mut location_messages = Vec::new();
+    let mut function_messages = Vec::new();
+    let period = profile.interval.as_nanos() as u64;
+    for sample in &profile.samples {
+        let mut ids = Vec::with_capacity(sample.stack.len());
+        for frame in &sample.stack {
+            if let Some(&id) = locations.get(frame) {
+                ids.push(id);
+                continue;
+            }
+
+            let name = profile.frame_name(frame);
+            let function_id = match functions.get(name) {
+                Some(&id) => id,
+                None => {
+                    let id = functions.len() as u64 + 1;
+                    functions.insert(name, id);
+                    let system_name = match frame {
+                        Frame::Wasm { function, .. } => profile
+                            .mapping
+                            .function(*function)
+                            .map_or(name, |meta| &meta.symbol.raw),
+                        Frame::Host => name,
+                    };
+                    let mut function = Message::default();
+                    function.varint(field::FUNCTION_ID, id);
+                    function.varint(field::FUNCTION_NAME, strings.index(name));
+                    function.varint(field::FUNCTION_SYSTEM_NAME, strings.index(system_name));
+                    function_messages.push(function);
+                    id
+                }
+            };
+
+            let id = locations.len() as u64 + 1;
+            locations.insert(*frame, id);
+            let mut line = Message::default();
+            line.varint(field::LINE_FUNCTION_ID, function_id);
+            let mut location = Message::default();
+            location.varint(field::LOCATION_ID, id);
+            if let Frame::Wasm { addr, .. } = frame {
+                location.varint(field::LOCATION_ADDRESS, *addr);
+            }
+            location.message(field::LOCATION_LINE, line);
+            location_messages.push(location);
+            ids.push(id);
+        }
+
+        let mut message_sample = Message::default();
+        message_sample.packed(field::SAMPLE_LOCATION_ID, ids);
+        message_sample.packed(field::SAMPLE_VALUE, [1, period]);
+        message.message(field::PROFILE_SAMPLE, message_sample);
+    }
+    for location in location_messages {
+        message.message(field::PROFILE_LOCATION, location);
+    }
+    for function in function_messages {
+        message.message(field::PROFILE_FUNCTION, function);
+    }
+
+    let start = profile
+        .start
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    message.varint(field::PROFILE_TIME_NANOS, start);
+    message.varint(
+        field::PROFILE_DURATION_NANOS,
+        profile.duration.as_nanos() as u64,
+    );
+    let period_type = value_type(&mut strings, "wall", "nanoseconds");
+    message.message(field::PROFILE_PERIOD_TYPE, period_type);
+    message.varint(field::PROFILE_PERIOD, period);
+
+    for s in &strings.strings {
+        message.bytes(field::PROFILE_STRING_TABLE, s.as_bytes());
+    }
+
+    message.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::{function_mapping::FunctionMapping, profile::Sample};
+
+    /// A field of a decoded message
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    fn read_varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first().expect("truncated varint");
+            *bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+        }
+        panic!("varint longer than 10 bytes")
+    }
+
+    fn decode(mut bytes: &[u8]) -> Vec<(u32, Value)> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = read_varint(&mut bytes);
+            let value = match key & 7 {
+                WIRE_VARINT => Value::Varint(read_varint(&mut bytes)),
+                WIRE_LEN => {
+                    let len = read_varint(&mut bytes) as usize;
+                    let (value, rest) = bytes.split_at(len);
+                    bytes = rest;
+                    Value::Bytes(value.to_vec())
+                }
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+            fields.push(((key >> 3) as u32, value));
+        }
+        fields
+    }
+
+    /// The messages in the `field` of `fields`, decoded.
+    fn messages(fields: &[(u32, Value)], field: u32) -> Vec<Vec<(u32, Value)>> {
+        fields
+            .iter()
+            .filter(|(f, _)| *f == field)
+            .map(|(_, value)| match value {
+                Value::Bytes(bytes) => decode(bytes),
+                Value::Varint(_) => panic!("field {field} is not a message"),
+            })
+            .collect()
+    }
+
+    fn varint(fields: &[(u32, Value)], field: u32) -> u64 {
+        match fields.iter().find(|(f, _)| *f == field) {
+            Some((_, Value::Varint(value))) => *value,
+            other => panic!("field {field} is not a varint: {other:?}"),
+        }
+    }
+
+    fn packed(fields: &[(u32, Value)], field: u32) -> Vec<u64> {
+        match fields.iter().find(|(f, _)| *f == field) {
+            Some((_, Value::Bytes(bytes))) => {
+                let mut bytes = bytes.as_slice();
+                let mut values = Vec::new();
+                while !bytes.is_empty() {
+                    values.push(read_varint(&mut bytes));
+                }
+                values
+            }
+            other => panic!("field {field} is not packed: {other:?}"),
+        }
+    }
+
+    /// `main` calls `leaf` twice from the same instruction, and once it's on its own.
+    fn profile() -> Profile {
+        let mapping = FunctionMapping::parse(
+            "wasm_binary",
+            "\
+0x1000 16 wasm_binary::main::h0123456789abcdef
+0x1010 16 wasm_binary::leaf::hfedcba9876543210
+",
+        )
+        .unwrap();
+        let main = |addr| Frame::Wasm {
+            function: mapping.lookup(0x1000).unwrap().0.id,
+            addr,
+        };
+        let leaf = Frame::Wasm {
+            function: mapping.lookup(0x1010).unwrap().0.id,
+            addr: 0x1014,
+        };
+        let sample = |stack: &[Frame]| Sample {
+            tid: 10,
+            time: Duration::ZERO,
+            cpu: Duration::ZERO,
+            stack: stack.to_vec(),
+        };
+
+        Profile {
+            command: "wasm_binary".into(),
+            pid: 10,
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+            duration: Duration::from_millis(30),
+            interval: Duration::from_millis(10),
+            samples: vec![
+                sample(&[leaf, main(0x1008), Frame::Host]),
+                sample(&[main(0x1004), Frame::Host]),
+                sample(&[leaf, main(0x1008), Frame::Host]),
+            ],
+            threads: BTreeMap::from([(10, "wasm_binary".into())]),
+            mapping: Arc::new(mapping),
+        }
+    }
+
+    #[test]
+    fn encodes_the_string_table() {
+        let fields = decode(&encode(&profile()));
+        let strings: Vec<String> = fields
+            .iter()
+            .filter(|(f, _)| *f == field::PROFILE_STRING_TABLE)
+            .map(|(_, value)| match value {
+                Value::Bytes(bytes) => String::from_utf8(bytes.clone()).unwrap(),
+                Value::Varint(_) => panic!("a string is a varint"),
+            })
+            .collect();
+
+        assert_eq!(
+            strings,
+            [
+                "",
+                "samples",
+                "count",
+                "wall",
+                "nanoseconds",
+                "wasm_binary::leaf",
+                "wasm_binary::leaf::hfedcba9876543210",
+                "wasm_binary::main",
+                "wasm_binary::main::h0123456789abcdef",
+                "[host]",
+            ]
+        );
+        let value_types: Vec<(u64, u64)> = messages(&fields, field::PROFILE_SAMPLE_TYPE)
+            .iter()
+            .chain(&messages(&fields, field::PROFILE_PERIOD_TYPE))
+            .map(|value_type| {
+                (
+                    varint(value_type, field::VALUE_TYPE_TYPE),
+                    varint(value_type, field::VALUE_TYPE_UNIT),
+                )
+            })
+            .collect();
+        assert_eq!(value_types, [(1, 2), (3, 4), (3, 4)]);
+    }
+
+    #[test]
+    fn encodes_a_location_per_address_and_a_function_per_name() {
+        let fields = decode(&encode(&profile()));
+
+        let samples: Vec<(Vec<u64>, Vec<u64>)> = messages(&fields, field::PROFILE_SAMPLE)
+            .iter()
+            .map(|sample| {
+                (
+                    packed(sample, field::SAMPLE_LOCATION_ID),
+                    packed(sample, field::SAMPLE_VALUE),
+                )
+            })
+            .collect();
+        let value = vec![1, 10_000_000];
+        assert_eq!(
+            samples,
+            [
+                (vec![1, 2, 3], value.clone()),
+                (vec![4, 3], value.clone()),
+                (vec![1, 2, 3], value),
+            ]
+        );
+
+        // the host has no address
+        let locations: Vec<(u64, Option<u64>, u64)> = messages(&fields, field::PROFILE_LOCATION)
+            .iter()
+            .map(|location| {
+                let address = location
+                    .iter()
+                    .any(|(f, _)| *f == field::LOCATION_ADDRESS)
+                    .then(|| varint(location, field::LOCATION_ADDRESS));
+                let lines = messages(location, field::LOCATION_LINE);
+                assert_eq!(lines.len(), 1);
+                (
+                    varint(location, field::LOCATION_ID),
+                    address,
+                    varint(&lines[0], field::LINE_FUNCTION_ID),
+                )
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                (1, Some(0x1014), 1),
+                (2, Some(0x1008), 2),
+                (3, None, 3),
+                (4, Some(0x1004), 2),
+            ]
+        );
+
+        let functions: Vec<(u64, u64, u64)> = messages(&fields, field::PROFILE_FUNCTION)
+            .iter()
+            .map(|function| {
+                (
+                    varint(function, field::FUNCTION_ID),
+                    varint(function, field::FUNCTION_NAME),
+                    varint(function, field::FUNCTION_SYSTEM_NAME),
+                )
+            })
+            .collect();
+        assert_eq!(functions, [(1, 5, 6), (2, 7, 8), (3, 9, 9)]);
+
+        assert_eq!(varint(&fields, field::PROFILE_TIME_NANOS), 2_000_000_000);
+        assert_eq!(varint(&fields, field::PROFILE_DURATION_NANOS), 30_000_000);
+        assert_eq!(varint(&fields, field::PROFILE_PERIOD), 10_000_000);
+    }
+}
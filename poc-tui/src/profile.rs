@@ -0,0 +1,740 @@
+//! The `poc-tui profile` subcommand, a sampling profiler for the JIT-compiled functions.
+//!
+//! Unlike `record`, there are no breakpoints: the tracee is interrupted with
+//! `PTRACE_INTERRUPT` at a fixed frequency, and the stack is walked along the frame pointers
+//! at every interruption. Every thread of the tracee is sampled, the ones besides the main
+//! thread are seized as they show up in `/proc/PID/task`. The time between the samples is
+//! wall time, the CPU time that a thread spends in between is read from its `schedstat`.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write as _,
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use fxprof_processed_profile::{
+    CategoryColor, CpuDelta, FrameFlags, FrameInfo, ReferenceTimestamp, SamplingInterval, Timestamp,
+};
+use libc::user_regs_struct;
+use nix::{
+    errno::Errno,
+    sys::{
+        ptrace,
+        signal::Signal,
+        wait::{WaitPidFlag, WaitStatus, waitpid},
+    },
+    unistd::Pid,
+};
+
+use crate::{
+    batch::{self, TraceeArgs, next_value},
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    function_mapping::{FunctionId, FunctionMapping},
+    pprof,
+};
+
+const USAGE: &str = "\
+usage: poc-tui profile [OPTIONS] (--attach PID | -- PROGRAM [ARGS ...])
+
+Samples the stack of the tracee until it exits, or until Ctrl-C, and reports where the time is
+spent. An attached tracee keeps running afterwards.
+
+options:
+    --attach PID        attach to the process PID
+    --perfmap PATH      use the perf map at PATH
+    --frequency HZ      take HZ samples per second, 99 by default
+    --duration SECS     stop after SECS seconds
+    --folded FILE       write the folded stacks to FILE, for flamegraph.pl and inferno
+    --pprof FILE        write the profile to FILE in the protobuf format of pprof
+    --firefox FILE      write the profile to FILE in the format of the Firefox Profiler";
+
+const DEFAULT_FREQUENCY: u32 = 99;
+/// The number of functions in the summary
+const TOP_FUNCTIONS: usize = 20;
+/// The name of the frames outside the JIT-compiled code
+pub const HOST_FRAME: &str = "[host]";
+/// How often the tracee is checked while it's waited for
+const WAIT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A frame of a sampled stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Frame {
+    /// In a JIT-compiled function, at the sampled instruction or at the call of the inner frame
+    Wasm { function: FunctionId, addr: u64 },
+    /// In the runtime or in a library, the consecutive ones are merged
+    Host,
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// The thread that is sampled
+    pub tid: u32,
+    /// Since the start of the profile
+    pub time: Duration,
+    /// The CPU time of the thread since its previous sample
+    pub cpu: Duration,
+    /// The innermost frame first
+    pub stack: Vec<Frame>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub command: String,
+    pub pid: u32,
+    pub start: SystemTime,
+    pub duration: Duration,
+    /// The intended time between the samples
+    pub interval: Duration,
+    pub samples: Vec<Sample>,
+    /// The names of the sampled threads by their tid, the main one's is `pid`
+    pub threads: BTreeMap<u32, String>,
+    pub mapping: Arc<FunctionMapping>,
+}
+
+impl Profile {
+    pub fn frame_name(&self, frame: &Frame) -> &str {
+        match frame {
+            Frame::Wasm { function, .. } => self
+                .mapping
+                .function(*function)
+                .map_or(HOST_FRAME, |meta| &meta.symbol.demangled),
+            Frame::Host => HOST_FRAME,
+        }
+    }
+
+    /// The stacks in the folded format: the frames from the outermost one, separated by `;`,
+    /// followed by the number of samples.
+    pub fn folded(&self) -> String {
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        for sample in &self.samples {
+            let names: Vec<&str> = sample
+                .stack
+                .iter()
+                .rev()
+                .map(|frame| self.frame_name(frame))
+                .collect();
+            *stacks.entry(names.join(";")).or_default() += 1;
+        }
+
+        let mut folded = String::new();
+        for (stack, count) in stacks {
+            let _ = writeln!(folded, "{stack} {count}");
+        }
+        folded
+    }
+
+    /// The profile in the format of the Firefox Profiler, with a thread for every sampled
+    /// thread, a category for the wasm frames and one for the host frames.
+    pub fn firefox(&self) -> fxprof_processed_profile::Profile {
+        let mut profile = fxprof_processed_profile::Profile::new(
+            &self.command,
+            ReferenceTimestamp::from_system_time(self.start),
+            SamplingInterval::from_nanos(self.interval.as_nanos() as u64),
+        );
+        let wasm = profile.add_category("Wasm", CategoryColor::Blue).into();
+        let host = profile.add_category("Host", CategoryColor::Gray).into();
+        let start = Timestamp::from_nanos_since_reference(0);
+        let process = profile.add_process(&self.command, self.pid, start);
+        let mut threads = BTreeMap::new();
+        for sample in &self.samples {
+            let thread = *threads.entry(sample.tid).or_insert_with(|| {
+                let thread = profile.add_thread(process, sample.tid, start, sample.tid == self.pid);
+                if let Some(name) = self.threads.get(&sample.tid) {
+                    profile.set_thread_name(thread, name);
+                }
+                thread
+            });
+            let frames: Vec<FrameInfo> = sample
+                .stack
+                .iter()
+                .rev()
+                .map(|frame| FrameInfo {
+                    frame: fxprof_processed_profile::Frame::Label(
+                        profile.intern_string(self.frame_name(frame)),
+                    ),
+                    category_pair: match frame {
+                        Frame::Wasm { .. } => wasm,
+                        Frame::Host => host,
+                    },
+                    flags: FrameFlags::empty(),
+                })
+                .collect();
+            let stack = profile.intern_stack_frames(thread, frames.into_iter());
+            profile.add_sample(
+                thread,
+                Timestamp::from_nanos_since_reference(sample.time.as_nanos() as u64),
+                stack,
+                CpuDelta::from_nanos(sample.cpu.as_nanos() as u64),
+                1,
+            );
+        }
+        let end = Timestamp::from_nanos_since_reference(self.duration.as_nanos() as u64);
+        for thread in threads.into_values() {
+            profile.set_thread_end_time(thread, end);
+        }
+
+        profile
+    }
+
+    /// The functions that most samples are in, by the samples that they are the innermost
+    /// frame of (self) and by the samples that they are anywhere on the stack of (total).
+    fn summary(&self) -> String {
+        let mut own: HashMap<&str, u64> = HashMap::new();
+        let mut total: HashMap<&str, u64> = HashMap::new();
+        for sample in &self.samples {
+            if let Some(frame) = sample.stack.first() {
+                *own.entry(self.frame_name(frame)).or_default() += 1;
+            }
+            // the recursive calls count once
+            let names: HashSet<&str> = sample.stack.iter().map(|f| self.frame_name(f)).collect();
+            for name in names {
+                *total.entry(name).or_default() += 1;
+            }
+        }
+
+        let mut functions: Vec<(&str, u64)> = total.into_iter().collect();
+        functions.sort_by_key(|&(name, total)| {
+            (
+                std::cmp::Reverse(own.get(name).copied().unwrap_or(0)),
+                std::cmp::Reverse(total),
+                name,
+            )
+        });
+        let percent = |count: u64| 100.0 * count as f64 / self.samples.len() as f64;
+
+        let mut summary = String::from("    self   total  function\n");
+        for (name, total) in functions.into_iter().take(TOP_FUNCTIONS) {
+            let _ = writeln!(
+                summary,
+                "  {:>5.1}%  {:>5.1}%  {name}",
+                percent(own.get(name).copied().unwrap_or(0)),
+                percent(total)
+            );
+        }
+        summary
+    }
+}
+
+/// Entry point of the `profile` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut tracee = TraceeArgs::with_duration(USAGE);
+    let mut frequency = DEFAULT_FREQUENCY;
+    let mut folded = None;
+    let mut pprof = None;
+    let mut firefox = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if tracee.parse(&arg, &mut args)? => {}
+            "--frequency" => {
+                let hz = next_value(&mut args, "--frequency", USAGE)?;
+                frequency = hz
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&hz| hz > 0)
+                    .ok_or_else(|| eyre!("`{hz}` is not a frequency\n\n{USAGE}"))?;
+            }
+            "--folded" => folded = Some(PathBuf::from(next_value(&mut args, "--folded", USAGE)?)),
+            "--pprof" => pprof = Some(PathBuf::from(next_value(&mut args, "--pprof", USAGE)?)),
+            "--firefox" => {
+                firefox = Some(PathBuf::from(next_value(&mut args, "--firefox", USAGE)?))
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+
+    let mut ctx = DebuggerCtx::new();
+    let duration = tracee.duration;
+    let command = tracee.start(&mut ctx)?;
+    batch::stop_on_signals(duration)?;
+
+    let mapping = ctx.parse_perfmap(BIN_NAME)?;
+    let mut sampler = Sampler::new(ctx, command, Duration::from_secs(1) / frequency, mapping);
+    eprintln!(
+        "Sampling {} threads at {frequency} Hz",
+        sampler.profile.threads.len()
+    );
+
+    let result = sampler.sample_all();
+    eprintln!("{}", result.as_deref().unwrap_or("stopped by an error"));
+    let profile = &mut sampler.profile;
+    profile.duration = sampler.start.elapsed();
+    eprintln!(
+        "Collected {} samples in {:.1}s",
+        profile.samples.len(),
+        profile.duration.as_secs_f64()
+    );
+    if profile.samples.is_empty() {
+        return result.map(drop);
+    }
+
+    if let Some(path) = &folded {
+        fs::write(path, profile.folded())
+            .wrap_err_with(|| format!("failed to write `{}`", path.display()))?;
+        eprintln!("Wrote the folded stacks to `{}`", path.display());
+    }
+    if let Some(path) = &pprof {
+        fs::write(path, pprof::encode(profile))
+            .wrap_err_with(|| format!("failed to write `{}`", path.display()))?;
+        eprintln!("Wrote the pprof profile to `{}`", path.display());
+    }
+    if let Some(path) = &firefox {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create `{}`", path.display()))?;
+        serde_json::to_writer(BufWriter::new(file), &profile.firefox())
+            .wrap_err_with(|| format!("failed to write `{}`", path.display()))?;
+        eprintln!("Wrote the Firefox profile to `{}`", path.display());
+    }
+    if folded.is_none() && pprof.is_none() && firefox.is_none() {
+        print!("{}", profile.summary());
+    }
+
+    // dropping the ctx kills the spawned tracee or detaches from the attached one
+    result.map(drop)
+}
+
+/// The time that the thread `tid` of `pid` has spent on a CPU, see `sched(7)`.
+fn cpu_time(pid: Pid, tid: Pid) -> Option<Duration> {
+    let schedstat = fs::read_to_string(format!("/proc/{pid}/task/{tid}/schedstat")).ok()?;
+    let nanos = schedstat.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_nanos(nanos))
+}
+
+/// The name of the thread `tid` of `pid`, its tid if it's gone.
+fn thread_name(pid: Pid, tid: Pid) -> String {
+    fs::read_to_string(format!("/proc/{pid}/task/{tid}/comm"))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_else(|_| tid.to_string())
+}
+
+/// The threads of the tracee besides the one that the [`DebuggerCtx`] traces. They are
+/// seized when they show up, and interrupted with it at every sample.
+#[derive(Debug, Default)]
+struct Threads {
+    /// With the signal to deliver when the thread is resumed
+    seized: BTreeMap<Pid, Option<Signal>>,
+}
+
+impl Threads {
+    /// Seizes the threads of `pid` that are new since the last time, and returns them.
+    fn seize_new(&mut self, pid: Pid) -> Vec<Pid> {
+        let Ok(tasks) = fs::read_dir(format!("/proc/{pid}/task")) else {
+            return Vec::new();
+        };
+
+        let mut seized = Vec::new();
+        for task in tasks.flatten() {
+            let Some(tid) = task.file_name().to_str().and_then(|tid| tid.parse().ok()) else {
+                continue;
+            };
+            let tid = Pid::from_raw(tid);
+            if tid == pid || self.seized.contains_key(&tid) {
+                continue;
+            }
+            // it may be gone already
+            if ptrace::seize(tid, ptrace::Options::PTRACE_O_TRACESYSGOOD).is_ok() {
+                self.seized.insert(tid, None);
+                seized.push(tid);
+            }
+        }
+
+        seized
+    }
+
+    /// Interrupts all the threads and returns their registers. The ones that exited are
+    /// forgotten.
+    fn stop(&mut self) -> Vec<(Pid, user_regs_struct)> {
+        self.seized.retain(|&tid, _| ptrace::interrupt(tid).is_ok());
+
+        let mut stopped = Vec::new();
+        self.seized.retain(|&tid, signal| {
+            match waitpid(tid, Some(WaitPidFlag::__WALL)) {
+                Ok(WaitStatus::PtraceEvent(..)) => {}
+                // the signal came before the interruption, it's delivered on resume
+                Ok(WaitStatus::Stopped(_, received)) => *signal = Some(received),
+                _ => return false,
+            }
+            match ptrace::getregs(tid) {
+                Ok(regs) => {
+                    stopped.push((tid, regs));
+                    true
+                }
+                Err(_) => false,
+            }
+        });
+
+        stopped
+    }
+
+    /// Resumes the threads that [`Threads::stop`] stopped.
+    fn resume(&mut self) {
+        self.seized
+            .retain(|&tid, signal| ptrace::cont(tid, signal.take()).is_ok());
+    }
+
+    /// Reaps the threads that exited while running, which hold back the exit of the main
+    /// thread, and resumes the ones that stopped for a signal.
+    fn reap(&mut self) {
+        self.seized.retain(|&tid, _| {
+            match waitpid(tid, Some(WaitPidFlag::__WALL | WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => true,
+                Ok(WaitStatus::Stopped(_, signal)) => ptrace::cont(tid, signal).is_ok(),
+                // a late interruption
+                Ok(WaitStatus::PtraceEvent(..)) => ptrace::cont(tid, None).is_ok(),
+                _ => false,
+            }
+        });
+    }
+}
+
+impl Drop for Threads {
+    fn drop(&mut self) {
+        self.stop();
+        for (&tid, &signal) in &self.seized {
+            let _ = ptrace::detach(tid, signal);
+        }
+    }
+}
+
+struct Sampler {
+    /// Before the ctx, so that they are detached before the tracee is killed or detached
+    threads: Threads,
+    ctx: DebuggerCtx,
+    start: Instant,
+    /// The time and the CPU time of every thread at its previous sample, or when it was
+    /// seized. The CPU time is `None` if the kernel doesn't keep the scheduler statistics.
+    previous: HashMap<Pid, (Duration, Option<Duration>)>,
+    profile: Profile,
+}
+
+impl Sampler {
+    /// Starts a profile of the stopped tracee, and seizes its other threads.
+    fn new(
+        ctx: DebuggerCtx,
+        command: String,
+        interval: Duration,
+        mapping: Arc<FunctionMapping>,
+    ) -> Self {
+        let pid = ctx.pid;
+        let mut sampler = Sampler {
+            threads: Threads::default(),
+            ctx,
+            start: Instant::now(),
+            previous: HashMap::from([(pid, (Duration::ZERO, cpu_time(pid, pid)))]),
+            profile: Profile {
+                command,
+                pid: pid.as_raw() as u32,
+                start: SystemTime::now(),
+                duration: Duration::ZERO,
+                interval,
+                samples: Vec::new(),
+                threads: BTreeMap::from([(pid.as_raw() as u32, thread_name(pid, pid))]),
+                mapping,
+            },
+        };
+        sampler.seize_threads();
+
+        sampler
+    }
+
+    /// Seizes the threads that are new since the previous sample. Their CPU time counts from
+    /// now on.
+    fn seize_threads(&mut self) {
+        let pid = self.ctx.pid;
+        let time = self.start.elapsed();
+        for tid in self.threads.seize_new(pid) {
+            self.previous.insert(tid, (time, cpu_time(pid, tid)));
+            self.profile
+                .threads
+                .insert(tid.as_raw() as u32, thread_name(pid, tid));
+        }
+    }
+
+    /// Resumes the tracee and interrupts it at every interval, until it exits or the session
+    /// is stopped. Returns why it's over.
+    fn sample_all(&mut self) -> eyre::Result<String> {
+        loop {
+            if batch::stop_requested() {
+                return Ok("stopped by the user".into());
+            }
+            self.ctx.resume()?;
+            thread::sleep(self.profile.interval);
+
+            // the tracee may have stopped by itself meanwhile
+            let state = match self.ctx.poll(false)? {
+                Some(state) => Some(state),
+                None => {
+                    match self.ctx.interrupt() {
+                        // it's exiting, the exit is reaped below
+                        Err(e) if e.downcast_ref::<Errno>() == Some(&Errno::ESRCH) => {}
+                        result => result?,
+                    }
+                    self.wait()?
+                }
+            };
+            let reason = match state {
+                None => return Ok("stopped by the user".into()),
+                Some(TraceeState::Exited(reason)) => {
+                    return Ok(format!("the tracee exited ({reason})"));
+                }
+                Some(TraceeState::Stopped(reason)) => reason,
+                Some(TraceeState::NotStarted | TraceeState::Running) => continue,
+            };
+            match reason {
+                StopReason::Interrupted => self.sample()?,
+                // the other signals are delivered when the tracee is resumed
+                reason => {
+                    batch::handle_interrupt(&mut self.ctx, &reason);
+                }
+            }
+        }
+    }
+
+    /// Like [`batch::wait`], but the other threads are reaped meanwhile, since the exit of the
+    /// main thread is only reported once they are.
+    fn wait(&mut self) -> eyre::Result<Option<TraceeState>> {
+        loop {
+            match self.ctx.poll(false)? {
+                Some(TraceeState::Stopped(StopReason::Interrupted)) if batch::stop_requested() => {
+                    return Ok(None);
+                }
+                Some(state) => return Ok(Some(state)),
+                None => {}
+            }
+            self.threads.reap();
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+
+    /// Adds the stacks of the interrupted tracee and of all its other threads to the profile.
+    fn sample(&mut self) -> eyre::Result<()> {
+        let time = self.start.elapsed();
+
+        // the stack can't be walked in some prologues of the host code
+        let addrs = self
+            .ctx
+            .backtrace()
+            .or_else(|_| self.ctx.pc().map(|pc| vec![pc]))?;
+        self.add_sample(self.ctx.pid, time, &addrs);
+
+        for (tid, regs) in self.threads.stop() {
+            let addrs = self
+                .ctx
+                .backtrace_from(&regs)
+                .unwrap_or_else(|_| vec![regs.rip]);
+            self.add_sample(tid, time, &addrs);
+        }
+        self.seize_threads();
+        self.threads.resume();
+
+        Ok(())
+    }
+
+    fn add_sample(&mut self, tid: Pid, time: Duration, addrs: &[u64]) {
+        let now = cpu_time(self.ctx.pid, tid);
+        let (previous, before) = self
+            .previous
+            .insert(tid, (time, now))
+            .unwrap_or((Duration::ZERO, None));
+        // the CPU time can't be more than the wall time, up to the precision of the clocks
+        let cpu = match (before, now) {
+            (Some(before), Some(now)) => now.saturating_sub(before).min(time - previous),
+            _ => time - previous,
+        };
+
+        let mut stack: Vec<Frame> = Vec::with_capacity(addrs.len());
+        for (i, &addr) in addrs.iter().enumerate() {
+            // a return address is right after the call
+            let lookup = if i == 0 { addr } else { addr.wrapping_sub(1) };
+            let frame = match self.profile.mapping.lookup(lookup) {
+                Some((meta, _)) => Frame::Wasm {
+                    function: meta.id,
+                    addr: lookup,
+                },
+                None => Frame::Host,
+            };
+            if frame != Frame::Host || stack.last() != Some(&Frame::Host) {
+                stack.push(frame);
+            }
+        }
+
+        self.profile.samples.push(Sample {
+            tid: tid.as_raw() as u32,
+            time,
+            cpu,
+            stack,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Child, Command, Stdio};
+
+    use super::*;
+
+    /// `main` calls `leaf` on the main thread 10, and the thread 11 is in the host.
+    fn profile() -> Profile {
+        let mapping = FunctionMapping::parse(
+            "wasm_binary",
+            "\
+0x1000 16 wasm_binary::main::h0123456789abcdef
+0x1010 16 wasm_binary::leaf::hfedcba9876543210
+",
+        )
+        .unwrap();
+        let id = |addr| mapping.lookup(addr).unwrap().0.id;
+        let main = |addr| Frame::Wasm {
+            function: id(0x1000),
+            addr,
+        };
+        let leaf = Frame::Wasm {
+            function: id(0x1010),
+            addr: 0x1014,
+        };
+        let sample = |tid, millis, stack: &[Frame]| Sample {
+            tid,
+            time: Duration::from_millis(millis),
+            cpu: Duration::from_millis(10),
+            stack: stack.to_vec(),
+        };
+
+        Profile {
+            command: "wasm_binary".into(),
+            pid: 10,
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            duration: Duration::from_millis(40),
+            interval: Duration::from_millis(10),
+            samples: vec![
+                sample(10, 10, &[leaf, main(0x1008), Frame::Host]),
+                sample(11, 10, &[Frame::Host]),
+                sample(10, 20, &[leaf, main(0x1008), Frame::Host]),
+                sample(10, 30, &[main(0x1004), Frame::Host]),
+            ],
+            threads: BTreeMap::from([(10, "wasm_binary".into()), (11, "worker".into())]),
+            mapping: Arc::new(mapping),
+        }
+    }
+
+    #[test]
+    fn folds_the_stacks_from_the_outermost_frame() {
+        assert_eq!(
+            profile().folded(),
+            "\
+[host] 1
+[host];wasm_binary::main 1
+[host];wasm_binary::main;wasm_binary::leaf 2
+"
+        );
+    }
+
+    #[test]
+    fn the_firefox_profile_has_a_thread_per_tid() {
+        let profile = serde_json::to_value(profile().firefox()).unwrap();
+        let threads = profile["threads"].as_array().unwrap();
+
+        let tids: Vec<_> = threads.iter().map(|thread| &thread["tid"]).collect();
+        assert_eq!(tids, ["10", "11"]);
+        let names: Vec<_> = threads.iter().map(|thread| &thread["name"]).collect();
+        assert_eq!(names, ["wasm_binary", "worker"]);
+        let samples: Vec<_> = threads
+            .iter()
+            .map(|thread| thread["samples"]["length"].as_u64().unwrap())
+            .collect();
+        assert_eq!(samples, [3, 1]);
+    }
+
+    /// Kills the tracee when the test is done, even if it fails.
+    struct Tracee(Child);
+
+    impl Drop for Tracee {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    /// A process with a main thread and two busy ones, once they are all started.
+    fn spawn_threads() -> Tracee {
+        let mut tracee = Tracee(
+            Command::new("python3")
+                .args([
+                    "-c",
+                    "import threading, time\n\
+                     def spin():\n    while True: pass\n\
+                     for _ in range(2): threading.Thread(target=spin, daemon=True).start()\n\
+                     print(flush=True)\n\
+                     time.sleep(30)\n",
+                ])
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        let mut line = String::new();
+        let stdout = tracee.0.stdout.as_mut().unwrap();
+        std::io::BufRead::read_line(&mut std::io::BufReader::new(stdout), &mut line).unwrap();
+
+        tracee
+    }
+
+    #[test]
+    fn stops_and_resumes_the_other_threads() {
+        let tracee = spawn_threads();
+        let pid = Pid::from_raw(tracee.0.id() as i32);
+
+        let mut threads = Threads::default();
+        let seized = threads.seize_new(pid);
+        assert_eq!(seized.len(), 2);
+        assert!(!seized.contains(&pid));
+        assert!(threads.seize_new(pid).is_empty());
+
+        for _ in 0..3 {
+            let stopped = threads.stop();
+            let tids: Vec<Pid> = stopped.iter().map(|&(tid, _)| tid).collect();
+            assert_eq!(tids, seized);
+            threads.resume();
+            threads.reap();
+        }
+
+        // the threads aren't left in a tracing stop once they are detached, they take turns
+        // on the GIL instead
+        drop(threads);
+        thread::sleep(Duration::from_millis(100));
+        for tid in seized {
+            let stat = fs::read_to_string(format!("/proc/{pid}/task/{tid}/stat")).unwrap();
+            let state = stat.rsplit(')').next().unwrap().split_whitespace().next();
+            assert_ne!(state, Some("t"), "{tid} is stopped");
+            assert!(
+                fs::read_to_string(format!("/proc/{pid}/task/{tid}/status"))
+                    .unwrap()
+                    .contains("TracerPid:\t0\n")
+            );
+        }
+    }
+
+    #[test]
+    fn forgets_the_threads_that_exited() {
+        let mut tracee = spawn_threads();
+        let pid = Pid::from_raw(tracee.0.id() as i32);
+
+        let mut threads = Threads::default();
+        assert_eq!(threads.seize_new(pid).len(), 2);
+        tracee.0.kill().unwrap();
+        // the exits of the threads are reaped here, the main thread's by the child
+        assert!(threads.stop().is_empty());
+        assert!(threads.seized.is_empty());
+        tracee.0.wait().unwrap();
+    }
+}
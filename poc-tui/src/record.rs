@@ -0,0 +1,339 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::eyre::{self, eyre};
+use nix::sys::signal::Signal;
+use regex::Regex;
+
+use crate::{
+    batch::{self, TraceeArgs, next_value},
+    breakpoint::{BreakpointId, Location},
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    expr::Expr,
+    trace::{EventKind, TraceEvent, TraceFunction, TraceHeader, TraceWriter},
+};
+
+const USAGE: &str = "\
+usage: poc-tui record [OPTIONS] -o FILE (--attach PID | -- PROGRAM [ARGS ...])
+
+Records the calls of the JIT-compiled functions to FILE until the tracee exits, or until Ctrl-C.
+An attached tracee keeps running afterwards. See `poc-tui view FILE` and `poc-tui export FILE`.
+
+options:
+    -o, --output FILE           where to write the trace
+    --attach PID                attach to the process PID
+    --perfmap PATH              use the perf map at PATH
+    --functions REGEX           only record the functions whose name matches REGEX
+    --args N                    record the first N wasm arguments of the calls, 4 by default
+    --syscalls                  record the syscalls too
+    --snapshot FUNC:EXPR:LEN    record LEN bytes of the linear memory at EXPR when FUNC is
+                                called, e.g. `parse:arg0:64`
+    --duration SECS             stop recording after SECS seconds";
+
+const DEFAULT_ARG_COUNT: usize = 4;
+
+/// Memory to record when a function is called.
+#[derive(Debug, Clone)]
+struct SnapshotRule {
+    function: String,
+    /// An address in the linear memory
+    addr: Expr,
+    len: usize,
+}
+
+impl SnapshotRule {
+    fn parse(s: &str) -> eyre::Result<Self> {
+        let usage = || eyre!("`{s}` is not FUNC:EXPR:LEN");
+        // the function names can contain colons
+        let mut parts = s.rsplitn(3, ':');
+        let len = parts.next().ok_or_else(usage)?;
+        let addr = parts.next().ok_or_else(usage)?;
+        let function = parts.next().ok_or_else(usage)?;
+
+        Ok(SnapshotRule {
+            function: function.into(),
+            addr: Expr::parse(addr)?,
+            len: len.trim().parse().map_err(|_| usage())?,
+        })
+    }
+}
+
+/// Entry point of the `record` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut output = None;
+    let mut tracee = TraceeArgs::with_duration(USAGE);
+    let mut filter = None;
+    let mut arg_count = DEFAULT_ARG_COUNT;
+    let mut snapshots = Vec::new();
+    let mut trace_syscalls = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if tracee.parse(&arg, &mut args)? => {}
+            "-o" | "--output" => output = Some(PathBuf::from(next_value(&mut args, &arg, USAGE)?)),
+            "--functions" => {
+                let regex = next_value(&mut args, "--functions", USAGE)?;
+                filter = Some(Regex::new(&regex).map_err(|e| eyre!("invalid regex: {e}"))?);
+            }
+            "--args" => {
+                let count = next_value(&mut args, "--args", USAGE)?;
+                arg_count = count
+                    .parse()
+                    .map_err(|_| eyre!("`{count}` is not a number\n\n{USAGE}"))?;
+            }
+            "--syscalls" => trace_syscalls = true,
+            "--snapshot" => snapshots.push(SnapshotRule::parse(&next_value(
+                &mut args,
+                "--snapshot",
+                USAGE,
+            )?)?),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+    let output = output.ok_or_else(|| eyre!("-o FILE is required\n\n{USAGE}"))?;
+
+    let mut ctx = DebuggerCtx::new();
+    let duration = tracee.duration;
+    let command = tracee.start(&mut ctx)?;
+    batch::stop_on_signals(duration)?;
+
+    let start = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    let header = TraceHeader {
+        start,
+        pid: ctx.pid.as_raw() as u32,
+        command,
+    };
+    let mut recorder = Recorder {
+        writer: TraceWriter::create(&output, &header)?,
+        ctx,
+        start: Instant::now(),
+        filter,
+        arg_count,
+        snapshots,
+        entries: HashMap::new(),
+        snapshots_at: HashMap::new(),
+        memory_base: None,
+        stack: Vec::new(),
+        syscall: None,
+        calls: 0,
+        events: 0,
+    };
+    recorder.load_module()?;
+    recorder.ctx.trace_syscalls = trace_syscalls;
+
+    let result = recorder.record();
+    recorder.writer.flush()?;
+    eprintln!(
+        "Recorded {} calls of {} functions, {} events in total, to `{}`",
+        recorder.calls,
+        recorder.entries.len(),
+        recorder.events,
+        output.display()
+    );
+
+    // dropping the ctx kills the spawned tracee or detaches from the attached one
+    result
+}
+
+struct Recorder {
+    ctx: DebuggerCtx,
+    writer: TraceWriter<BufWriter<File>>,
+    start: Instant,
+    filter: Option<Regex>,
+    arg_count: usize,
+    snapshots: Vec<SnapshotRule>,
+    /// The trace index of the function of every entry breakpoint
+    entries: HashMap<BreakpointId, u32>,
+    /// The snapshot rules by the trace index of their functions
+    snapshots_at: HashMap<u32, Vec<SnapshotRule>>,
+    /// See [`DebuggerCtx::linear_memory_base`], it's located on the first snapshot
+    memory_base: Option<u64>,
+    /// The calls that didn't return yet, innermost last: the trace index of the function and
+    /// the stack pointer after the return
+    stack: Vec<(u32, u64)>,
+    /// The syscall that the tracee is in
+    syscall: Option<u64>,
+    calls: u64,
+    events: u64,
+}
+
+impl Recorder {
+    /// Declares the functions of the module in the trace, with a breakpoint at the entry of
+    /// the ones to record.
+    fn load_module(&mut self) -> eyre::Result<()> {
+        let mapping = self.ctx.parse_perfmap(BIN_NAME)?;
+        for meta in mapping.iter_by_addr() {
+            if let Some(filter) = &self.filter
+                && !filter.is_match(&meta.name)
+                && !filter.is_match(&meta.symbol.demangled)
+            {
+                continue;
+            }
+
+            let index = self.entries.len() as u32;
+            let bp = self.ctx.add_breakpoint(Location::Address(meta.addr))?;
+            self.entries.insert(bp.id, index);
+            self.writer.function(&TraceFunction {
+                index,
+                addr: meta.addr,
+                size: meta.size,
+                name: meta.name.clone(),
+            })?;
+
+            let rules = self.snapshots.iter().filter(|rule| {
+                mapping
+                    .functions_named(&rule.function)
+                    .any(|m| m.id == meta.id)
+            });
+            self.snapshots_at
+                .entry(index)
+                .or_default()
+                .extend(rules.cloned());
+        }
+        if self.entries.is_empty() {
+            return Err(eyre!("no function to record"));
+        }
+        eprintln!("Recording {} functions", self.entries.len());
+
+        Ok(())
+    }
+
+    /// Resumes the tracee and records its stops, until it exits or the recording is stopped.
+    fn record(&mut self) -> eyre::Result<()> {
+        let reason = loop {
+            if batch::stop_requested() {
+                break "stopped by the user".to_string();
+            }
+            self.ctx.resume()?;
+
+            let state = match batch::wait(&mut self.ctx)? {
+                Some(state) => state,
+                None => break "stopped by the user".to_string(),
+            };
+            match state {
+                TraceeState::Exited(reason) => break format!("the tracee exited ({reason})"),
+                TraceeState::Stopped(reason) => self.record_stop(reason)?,
+                TraceeState::NotStarted | TraceeState::Running => {}
+            }
+        };
+
+        self.event(EventKind::End { reason })
+    }
+
+    fn record_stop(&mut self, reason: StopReason) -> eyre::Result<()> {
+        if batch::handle_interrupt(&mut self.ctx, &reason) {
+            return Ok(());
+        }
+
+        let tid = self.ctx.pid.as_raw() as u32;
+        match reason {
+            StopReason::Breakpoint(id) => {
+                let Some(&function) = self.entries.get(&id) else {
+                    return Ok(());
+                };
+                // the arguments that can't be read are left out, e.g. on the stack
+                let args = (0..self.arg_count)
+                    .map_while(|i| self.ctx.wasm_argument(i).ok())
+                    .collect();
+                let sp = self.ctx.watch_return()?;
+                self.stack.push((function, sp));
+                self.calls += 1;
+                self.event(EventKind::Enter {
+                    tid,
+                    function,
+                    args,
+                })?;
+                if let Err(e) = self.snapshot(function) {
+                    eprintln!(
+                        "warning: no more snapshots at `{}`: {e:#}",
+                        self.snapshots_at[&function][0].function
+                    );
+                    self.snapshots_at.remove(&function);
+                }
+            }
+            StopReason::Returned { sp } => {
+                // the calls above the one that returns are unwound, e.g. by a trap
+                let Some(depth) = self.stack.iter().rposition(|&(_, s)| s == sp) else {
+                    return Ok(());
+                };
+                let (function, _) = self.stack[depth];
+                self.stack.truncate(depth);
+                let ret = self.ctx.registers()?.rax;
+                self.event(EventKind::Exit { tid, function, ret })?;
+            }
+            StopReason::Syscall { nr, args } => {
+                self.syscall = Some(nr);
+                self.event(EventKind::Syscall { tid, nr, args })?;
+            }
+            StopReason::SyscallReturn { ret } => {
+                if let Some(nr) = self.syscall.take() {
+                    self.event(EventKind::SyscallReturn { tid, nr, ret })?;
+                }
+            }
+            StopReason::Signal(
+                signal @ (Signal::SIGILL | Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGFPE),
+            ) => {
+                // the signal is still delivered, the runtime turns it into a trap and unwinds the
+                // wasm frames
+                let pc = self.ctx.registers()?.rip;
+                self.stack.clear();
+                self.event(EventKind::Trap {
+                    tid,
+                    signal: signal as i32,
+                    pc,
+                })?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Records the memory that the snapshot rules of `function` ask for.
+    fn snapshot(&mut self, function: u32) -> eyre::Result<()> {
+        let Some(rules) = self.snapshots_at.get(&function).filter(|r| !r.is_empty()) else {
+            return Ok(());
+        };
+
+        let base = match self.memory_base {
+            Some(base) => base,
+            None => *self.memory_base.insert(self.ctx.linear_memory_base()?),
+        };
+        let mut snapshots = Vec::new();
+        for rule in rules {
+            let addr = rule.addr.eval(&self.ctx)? as u32 as u64;
+            let bytes = self.ctx.read_memory(base + addr, rule.len)?;
+            snapshots.push((addr, bytes));
+        }
+
+        let tid = self.ctx.pid.as_raw() as u32;
+        for (addr, bytes) in snapshots {
+            self.event(EventKind::Snapshot { tid, addr, bytes })?;
+        }
+
+        Ok(())
+    }
+
+    fn event(&mut self, kind: EventKind) -> eyre::Result<()> {
+        self.events += 1;
+        self.writer.event(&TraceEvent {
+            time: self.start.elapsed(),
+            kind,
+        })?;
+
+        Ok(())
+    }
+}
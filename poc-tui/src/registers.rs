@@ -0,0 +1,116 @@
+use libc::{user_fpregs_struct, user_regs_struct};
+
+/// Names of the general purpose registers, in the order gdb shows them.
+pub const NAMES: &[&str] = &[
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "cs", "ss", "ds", "es", "fs", "gs", "fs_base", "gs_base",
+];
+
+/// The registers that the registers pane shows and edits, in its order.
+pub const PANE: &[&str] = &[
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "eflags", "fs_base", "gs_base",
+];
+
+/// The flags of `eflags` by their bits.
+const FLAGS: &[(u32, &str)] = &[
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+];
+
+/// The registers of a stopped tracee, as the registers pane shows them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The registers of [`PANE`] with their values
+    pub general: Vec<(&'static str, u64)>,
+    /// `xmm0` to `xmm15`
+    pub xmm: Vec<u128>,
+}
+
+impl Snapshot {
+    pub fn new(regs: &user_regs_struct, fpregs: &user_fpregs_struct) -> Self {
+        let general = PANE
+            .iter()
+            .map(|&name| (name, read(regs, name).expect("a general purpose register")))
+            .collect();
+        // 4 little-endian words per register
+        let xmm = fpregs
+            .xmm_space
+            .chunks(4)
+            .map(|words| {
+                words
+                    .iter()
+                    .rev()
+                    .fold(0u128, |value, &word| (value << 32) | word as u128)
+            })
+            .collect();
+
+        Snapshot { general, xmm }
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.general
+            .iter()
+            .find(|(general, _)| *general == name)
+            .map(|&(_, value)| value)
+    }
+}
+
+/// The flags that are set in `eflags`, e.g. `[ PF ZF IF ]` like gdb shows them.
+pub fn format_flags(eflags: u64) -> String {
+    let set: Vec<&str> = FLAGS
+        .iter()
+        .filter(|&&(bit, _)| eflags & (1 << bit) != 0)
+        .map(|&(_, name)| name)
+        .collect();
+
+    format!("[ {} ]", set.join(" "))
+}
+
+/// Reads the register `name`, which is one of [`NAMES`] or the aliases `pc`, `sp` and `fp`.
+pub fn read(regs: &user_regs_struct, name: &str) -> Option<u64> {
+    let mut regs = *regs;
+    get_mut(&mut regs, name).copied()
+}
+
+/// The register `name`, see [`read`].
+pub fn get_mut<'a>(regs: &'a mut user_regs_struct, name: &str) -> Option<&'a mut u64> {
+    let value = match name {
+        "rax" => &mut regs.rax,
+        "rbx" => &mut regs.rbx,
+        "rcx" => &mut regs.rcx,
+        "rdx" => &mut regs.rdx,
+        "rsi" => &mut regs.rsi,
+        "rdi" => &mut regs.rdi,
+        "rbp" | "fp" => &mut regs.rbp,
+        "rsp" | "sp" => &mut regs.rsp,
+        "r8" => &mut regs.r8,
+        "r9" => &mut regs.r9,
+        "r10" => &mut regs.r10,
+        "r11" => &mut regs.r11,
+        "r12" => &mut regs.r12,
+        "r13" => &mut regs.r13,
+        "r14" => &mut regs.r14,
+        "r15" => &mut regs.r15,
+        "rip" | "pc" => &mut regs.rip,
+        "eflags" => &mut regs.eflags,
+        "cs" => &mut regs.cs,
+        "ss" => &mut regs.ss,
+        "ds" => &mut regs.ds,
+        "es" => &mut regs.es,
+        "fs" => &mut regs.fs,
+        "gs" => &mut regs.gs,
+        "fs_base" => &mut regs.fs_base,
+        "gs_base" => &mut regs.gs_base,
+        _ => return None,
+    };
+
+    Some(value)
+}
@@ -0,0 +1,493 @@
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use nix::{
+    sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal},
+    unistd::Pid,
+};
+
+use crate::{
+    batch::{TraceeArgs, next_value},
+    console::{self, ConsoleCommand},
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    itrace,
+    launch::LaunchSpec,
+    logpoint::Logpoint,
+};
+
+const USAGE: &str = "\
+usage: tripwire [OPTIONS] [-- PROGRAM [ARGS ...]]
+
+A gdb-like debugger for JIT-compiled wasm. PROGRAM is what `run` starts when it's not given
+a command line.
+
+options:
+    -x FILE           execute the commands in FILE
+    -ex COMMAND       execute COMMAND
+    --batch           exit after executing the -x files and the -ex commands
+    --attach PID      attach to the process PID before anything else
+    --perfmap PATH    use the perf map at PATH
+    -h, --help        show this";
+
+const PROMPT: &str = "(tripwire) ";
+
+/// How often a running tracee is checked for a state change.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Set by `Ctrl-C`, which interrupts the tracee.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// What to execute before the interactive session, in the order they are given.
+enum Source {
+    File(std::path::PathBuf),
+    Command(String),
+}
+
+/// What to do after a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// Entry point of the `tripwire` binary. `args` doesn't contain the name of the binary.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut sources = Vec::new();
+    let mut batch = false;
+    let mut tracee = TraceeArgs::new(USAGE);
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if tracee.parse(&arg, &mut args)? => {}
+            "-x" => sources.push(Source::File(next_value(&mut args, "-x", USAGE)?.into())),
+            "-ex" => sources.push(Source::Command(next_value(&mut args, "-ex", USAGE)?)),
+            "--batch" => batch = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+
+    let mut repl = Repl::new();
+    repl.ctx.perfmap_path = tracee.perfmap;
+    repl.program = tracee.program;
+
+    // Ctrl-C stops the tracee instead of the debugger
+    let action = SigAction::new(
+        SigHandler::Handler(on_sigint),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { signal::sigaction(Signal::SIGINT, &action)? };
+
+    let mut failed = false;
+    if let Some(pid) = tracee.attach {
+        failed |= report(repl.attach(pid));
+    }
+
+    for source in sources {
+        let flow = match source {
+            Source::File(path) => repl.source(&path),
+            Source::Command(line) => {
+                echo(&line);
+                repl.execute(&line)
+            }
+        };
+        match flow {
+            Ok(Flow::Quit) => return Ok(()),
+            result => failed |= report(result),
+        }
+    }
+
+    if batch {
+        // dropping the debugger kills or detaches the tracee
+        drop(repl);
+        if failed {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    repl.interact()
+}
+
+/// Prints a command that is not typed by the user, so that the output of a script is readable.
+fn echo(line: &str) {
+    println!("{PROMPT}{line}");
+}
+
+/// Runs the commands of [`console`] directly on a [`DebuggerCtx`], blocking until the tracee
+/// stops after resuming it.
+pub struct Repl {
+    ctx: DebuggerCtx,
+    /// What `run` starts when it's not given a command line
+    program: Option<LaunchSpec>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            ctx: DebuggerCtx::new(),
+            program: None,
+        }
+    }
+
+    /// Reads the commands from the stdin until `quit` or the end of the input. Like in gdb,
+    /// an empty line repeats the last command.
+    fn interact(&mut self) -> eyre::Result<()> {
+        let interactive = io::stdin().is_terminal();
+        let mut last_line = String::new();
+
+        let mut lines = io::stdin().lock().lines();
+        loop {
+            if interactive {
+                print!("{PROMPT}");
+                io::stdout().flush()?;
+            }
+
+            let Some(line) = lines.next().transpose()? else {
+                break;
+            };
+            let line = if line.trim().is_empty() && interactive {
+                last_line.clone()
+            } else {
+                line
+            };
+            if !interactive {
+                echo(&line);
+            }
+
+            match self.execute(&line) {
+                Ok(Flow::Quit) => break,
+                result => {
+                    report(result);
+                }
+            }
+            last_line = line;
+        }
+
+        Ok(())
+    }
+
+    /// Executes the commands in the file at `path`. Like in gdb, an error stops the execution
+    /// of the file.
+    fn source(&mut self, path: &Path) -> eyre::Result<Flow> {
+        let script = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read `{}`", path.display()))?;
+
+        for (i, line) in script.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            echo(line);
+            let flow = self
+                .execute(line)
+                .wrap_err_with(|| format!("{}:{}", path.display(), i + 1))?;
+            if flow == Flow::Quit {
+                return Ok(Flow::Quit);
+            }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn execute(&mut self, line: &str) -> eyre::Result<Flow> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(Flow::Continue);
+        }
+        if matches!(line, "quit" | "q") {
+            return Ok(Flow::Quit);
+        }
+
+        match ConsoleCommand::parse(line)? {
+            ConsoleCommand::Run(spec) => {
+                let mut spec = spec
+                    .or_else(|| self.program.clone())
+                    .ok_or_else(|| eyre!("nothing to run yet, use `run PROGRAM [ARGS ...]`"))?;
+                // the tracee shares the terminal with us
+                spec.capture_output = false;
+                self.program = Some(spec.clone());
+
+                self.ctx.spawn(&spec)?;
+                println!("Started `{spec}` with pid {}", self.ctx.pid);
+                self.wait()?;
+            }
+            ConsoleCommand::Attach(pid) => self.attach(pid)?,
+            ConsoleCommand::Break {
+                location,
+                condition,
+                temporary,
+            } => {
+                let mut bp = self.ctx.add_breakpoint(location)?;
+                if condition.is_some() {
+                    bp = self.ctx.set_condition(bp.id, condition)?;
+                }
+                if temporary {
+                    bp = self.ctx.set_remaining_stops(bp.id, Some(1))?;
+                }
+                println!(
+                    "{}",
+                    console::format_new_breakpoint(&bp, self.ctx.function_mapping.as_deref())
+                );
+            }
+            ConsoleCommand::Advance(location) => {
+                let bp = self.ctx.add_breakpoint(location)?;
+                self.ctx.set_remaining_stops(bp.id, Some(1))?;
+                self.ctx.resume()?;
+                let result = self.wait();
+                // it's over wherever the tracee stopped
+                if self.ctx.breakpoints.get(bp.id).is_some() {
+                    self.ctx.delete_breakpoint(bp.id)?;
+                }
+                result?;
+            }
+            ConsoleCommand::Ignore { id, count } => {
+                self.ctx.set_ignore_count(id, count)?;
+                println!("Will ignore the next {count} hits of breakpoint {id}");
+            }
+            ConsoleCommand::Expire { id, stops } => {
+                self.ctx.set_remaining_stops(id, Some(stops))?;
+                println!("Breakpoint {id} is deleted after {stops} stops");
+            }
+            ConsoleCommand::Dprintf { location, format } => {
+                let bp = self.ctx.add_breakpoint(location)?;
+                let bp = self.ctx.set_log(bp.id, Some(Logpoint::new(format)))?;
+                println!(
+                    "{}",
+                    console::format_new_breakpoint(&bp, self.ctx.function_mapping.as_deref())
+                );
+            }
+            ConsoleCommand::RateLimit { id, per_second } => {
+                let bp = self.ctx.set_rate_limit(id, per_second)?;
+                if let Some(log) = bp.log {
+                    println!("Logpoint {id} logs at most {}", log.rate_limit);
+                }
+            }
+            ConsoleCommand::Condition { id, condition } => {
+                let bp = self.ctx.set_condition(id, condition)?;
+                match bp.condition {
+                    Some(condition) => println!("Breakpoint {id} stops if {condition}"),
+                    None => println!("Breakpoint {id} is now unconditional"),
+                }
+            }
+            ConsoleCommand::OnHit { id, script } => {
+                let bp = self.ctx.set_script(id, script)?;
+                match bp.script {
+                    Some(script) => println!("Breakpoint {id} runs `{script}` on its hits"),
+                    None => println!("Breakpoint {id} has no script"),
+                }
+            }
+            ConsoleCommand::Enable { id, enabled } => {
+                self.ctx.set_enabled(id, enabled)?;
+            }
+            ConsoleCommand::Script(script) => {
+                let result = script.run_session(&mut self.ctx, |line| println!("{line}"));
+                if self.ctx.state != TraceeState::NotStarted {
+                    self.print_state();
+                }
+                result?;
+            }
+            ConsoleCommand::Delete(id) => {
+                let ids = match id {
+                    Some(id) => vec![id],
+                    None => self.ctx.breakpoints.ids(),
+                };
+                for id in ids {
+                    self.ctx.delete_breakpoint(id)?;
+                    println!("Deleted breakpoint {id}");
+                }
+            }
+            ConsoleCommand::Continue => {
+                self.ctx.resume()?;
+                self.wait()?;
+            }
+            ConsoleCommand::Interrupt => {
+                return Err(eyre!("the tracee is not running, use Ctrl-C to stop it"));
+            }
+            ConsoleCommand::StepInstruction => {
+                self.ctx.step_instruction()?;
+                self.wait()?;
+            }
+            ConsoleCommand::Finish => {
+                self.ctx.finish()?;
+                self.wait()?;
+            }
+            ConsoleCommand::InstructionTrace { calls, output } => {
+                INTERRUPTED.store(false, Ordering::Relaxed);
+                let result = itrace::trace_into(
+                    &mut self.ctx,
+                    calls,
+                    output.as_deref(),
+                    || INTERRUPTED.load(Ordering::Relaxed),
+                    |line| println!("{line}"),
+                );
+                for message in self.ctx.take_log() {
+                    println!("{message}");
+                }
+                if self.ctx.state == TraceeState::Stopped(StopReason::Signal(Signal::SIGINT)) {
+                    // the Ctrl-C was for us, not for the tracee
+                    self.ctx.discard_signal();
+                }
+                self.print_state();
+                result?;
+            }
+            ConsoleCommand::Backtrace => {
+                let frames = self.ctx.backtrace()?;
+                print_lines(console::format_backtrace(
+                    &frames,
+                    self.ctx.function_mapping.as_deref(),
+                ));
+            }
+            ConsoleCommand::Examine { format, location } => {
+                let addr = self.ctx.resolve(&location)?;
+                let bytes = self.ctx.read_memory(addr, format.byte_len())?;
+                let mapping = self.ctx.function_mapping.as_deref();
+                print_lines(format.format(addr, &bytes, |addr| mapping?.symbolize(addr)));
+                print_lines(console::format_breakpoints_in(
+                    addr,
+                    bytes.len(),
+                    self.ctx.breakpoints.iter(),
+                ));
+            }
+            ConsoleCommand::Print(expr) => {
+                println!("{}", console::format_value(expr.eval(&self.ctx)?))
+            }
+            ConsoleCommand::InfoFunctions(regex) => {
+                let mapping = self
+                    .ctx
+                    .function_mapping
+                    .as_deref()
+                    .ok_or_else(|| eyre!("the module is not loaded yet"))?;
+                print_lines(console::format_functions(mapping, regex.as_ref()));
+            }
+            ConsoleCommand::InfoBreakpoints => {
+                print_lines(console::format_breakpoints(self.ctx.breakpoints.iter()))
+            }
+            ConsoleCommand::Kill => {
+                self.ctx.kill()?;
+                self.print_state();
+            }
+            ConsoleCommand::Help => {
+                println!("{}", console::HELP);
+                println!("{:<27}exit, killing or detaching the tracee", "quit, q");
+            }
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    fn attach(&mut self, pid: Pid) -> eyre::Result<()> {
+        self.ctx.attach(pid)?;
+        println!("Attached to {pid}");
+
+        // the module is usually loaded by the time we attach
+        if let Err(e) = self.load_module() {
+            eprintln!("warning: {e:#}");
+        }
+        self.print_state();
+
+        Ok(())
+    }
+
+    /// Blocks until the resumed tracee stops or exits. The stop at the module load is not
+    /// reported, it's where the breakpoints are armed.
+    fn wait(&mut self) -> eyre::Result<()> {
+        INTERRUPTED.store(false, Ordering::Relaxed);
+
+        while self.ctx.is_running() {
+            // an attached tracee doesn't get the Ctrl-C of our terminal
+            if INTERRUPTED.swap(false, Ordering::Relaxed) {
+                self.ctx.interrupt()?;
+            }
+
+            let state = self.ctx.poll(false)?;
+            for message in self.ctx.take_log() {
+                println!("{message}");
+            }
+            match state {
+                None => thread::sleep(POLL_INTERVAL),
+                Some(TraceeState::Stopped(StopReason::ModuleLoaded)) => {
+                    self.load_module()?;
+                    self.ctx.resume()?;
+                }
+                Some(TraceeState::Stopped(StopReason::Signal(Signal::SIGINT))) => {
+                    // the Ctrl-C was for us, not for the tracee
+                    self.ctx.discard_signal();
+                }
+                Some(_) => {}
+            }
+        }
+
+        self.print_state();
+
+        Ok(())
+    }
+
+    /// Discovers the functions of the module and arms the pending breakpoints.
+    fn load_module(&mut self) -> eyre::Result<()> {
+        let mapping = self.ctx.parse_perfmap(BIN_NAME)?;
+        println!("Loaded the module with {} functions", mapping.len());
+
+        for error in self.ctx.arm_breakpoints() {
+            eprintln!("warning: {error:#}");
+        }
+
+        Ok(())
+    }
+
+    fn print_state(&self) {
+        match &self.ctx.state {
+            TraceeState::Stopped(reason) => println!(
+                "{}",
+                console::format_stop(
+                    reason,
+                    self.ctx.pc().ok(),
+                    self.ctx.function_mapping.as_deref()
+                )
+            ),
+            TraceeState::Exited(reason) => println!("[{} exited: {reason}]", self.ctx.pid),
+            state => println!("[{state}]"),
+        }
+    }
+}
+
+/// Prints the error if `result` is one, returns whether it is.
+fn report<T>(result: eyre::Result<T>) -> bool {
+    match result {
+        Ok(_) => false,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            true
+        }
+    }
+}
+
+fn print_lines(lines: Vec<String>) {
+    for line in lines {
+        println!("{line}");
+    }
+}
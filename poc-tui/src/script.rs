@@ -0,0 +1,355 @@
+use std::{
+    fmt, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use rhai::{AST, Array, Blob, Dynamic, Engine, EvalAltResult, Scope};
+
+use crate::{
+    breakpoint::{BreakpointId, Location},
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    expr::Expr,
+    registers,
+};
+
+/// The budget of a script that runs on a breakpoint hit, so that a runaway loop doesn't hang
+/// the tracee forever.
+const MAX_HIT_OPERATIONS: u64 = 1_000_000;
+
+/// A [Rhai](https://rhai.rs) script that drives the debugger.
+///
+/// A script attached to a breakpoint runs on every hit that would stop, and the tracee only
+/// stops if it returns `true`. A session script runs on its own and can also resume the
+/// tracee with `cont()`, `stepi()` and `finish()`, which block until the next stop.
+///
+/// The bindings:
+/// - `reg(name)`, `set_reg(name, value)`, `pc()` and `arg(n)`, the wasm argument `n`
+/// - `eval(expr)` evaluates an [`Expr`], like `print`
+/// - `read_u8(addr)` .. `read_i64(addr)`, `read_bytes(addr, len)`, `write_u8(addr, value)` ..
+///   `write_u64(addr, value)` and `write_bytes(addr, blob)` on the native addresses, plus
+///   `memory_base()`, where the linear memory starts
+/// - `symbolize(addr)`, `function_addr(name)` and `backtrace()`
+/// - `break_at(location)`, `delete(id)`, `enable(id)`, `disable(id)`, `condition(id, expr)`,
+///   `ignore(id, count)` and `hits(id)`
+///
+/// `state` is a map that is kept between the runs of all the scripts, `bp` and `hits` are the
+/// breakpoint and its hit count in a breakpoint script.
+#[derive(Clone)]
+pub struct Script {
+    /// Where it's loaded from, for the display
+    name: String,
+    ast: Arc<AST>,
+}
+
+impl Script {
+    pub fn compile(name: &str, source: &str) -> eyre::Result<Self> {
+        let ast = Engine::new()
+            .compile(source)
+            .map_err(|e| eyre!("failed to compile `{name}`: {e}"))?;
+
+        Ok(Script {
+            name: name.into(),
+            ast: Arc::new(ast),
+        })
+    }
+
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let source = fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read `{}`", path.display()))?;
+
+        Self::compile(&path.display().to_string(), &source)
+    }
+
+    /// Runs the script of the breakpoint `id`, which the tracee is stopped at. Returns whether
+    /// the tracee stays stopped, the printed lines go to the log of the debugger.
+    pub fn run_on_hit(&self, ctx: &mut DebuggerCtx, id: BreakpointId) -> eyre::Result<bool> {
+        let hits = ctx.breakpoints.get(id).map_or(0, |bp| bp.hits);
+
+        let mut scope = Scope::new();
+        scope.push("bp", id.0 as i64);
+        scope.push("hits", hits as i64);
+
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let output = {
+            let lines = lines.clone();
+            move |line: String| lines.lock().expect("not poisoned").push(line)
+        };
+
+        // the bindings need a stopped tracee, and nothing else happens to it meanwhile
+        let state = std::mem::replace(
+            &mut ctx.state,
+            TraceeState::Stopped(StopReason::Breakpoint(id)),
+        );
+        let result = self.run(ctx, scope, Some(MAX_HIT_OPERATIONS), output);
+        ctx.state = state;
+
+        let lines = std::mem::take(&mut *lines.lock().expect("not poisoned"));
+        for line in lines {
+            ctx.log_line(id, hits, line);
+        }
+
+        Ok(result?.as_bool().unwrap_or(false))
+    }
+
+    /// Runs the script on its own, passing the printed lines and the log of the debugger to
+    /// `output` as they come.
+    pub fn run_session(
+        &self,
+        ctx: &mut DebuggerCtx,
+        output: impl Fn(String) + Send + Sync + 'static,
+    ) -> eyre::Result<()> {
+        let mut scope = Scope::new();
+        scope.push("bp", ());
+        scope.push("hits", 0_i64);
+
+        self.run(ctx, scope, None, output).map(drop)
+    }
+
+    /// Lends `ctx` to the bindings for the run. Run control is only available to the session
+    /// scripts, which are the ones without an operation limit.
+    fn run(
+        &self,
+        ctx: &mut DebuggerCtx,
+        mut scope: Scope,
+        max_operations: Option<u64>,
+        output: impl Fn(String) + Send + Sync + 'static,
+    ) -> eyre::Result<Dynamic> {
+        scope.push_dynamic("state", ctx.script_state.clone());
+
+        let output = Arc::new(output);
+        let tracee = Tracee {
+            ctx: Arc::new(Mutex::new(std::mem::take(ctx))),
+            output: output.clone(),
+        };
+
+        let mut engine = Engine::new();
+        engine.on_print(move |text| output(text.into()));
+        bind(&mut engine, &tracee);
+        if let Some(max_operations) = max_operations {
+            engine.set_max_operations(max_operations);
+        } else {
+            bind_run_control(&mut engine, &tracee);
+        }
+
+        let result = engine.eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast);
+
+        drop(engine);
+        let Tracee { ctx: shared, .. } = tracee;
+        *ctx = Arc::into_inner(shared)
+            .expect("the engine is dropped")
+            .into_inner()
+            .expect("not poisoned");
+
+        result.map_err(|e| eyre!("`{}`: {e}", self.name))
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Script").field(&self.name).finish()
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.ast, &other.ast)
+    }
+}
+
+impl Eq for Script {}
+
+/// The debugger, as the bindings see it during a run.
+#[derive(Clone)]
+struct Tracee {
+    ctx: Arc<Mutex<DebuggerCtx>>,
+    output: Arc<dyn Fn(String) + Send + Sync>,
+}
+
+impl Tracee {
+    fn with<T>(
+        &self,
+        f: impl FnOnce(&mut DebuggerCtx) -> eyre::Result<T>,
+    ) -> Result<T, Box<EvalAltResult>> {
+        let mut ctx = self.ctx.lock().expect("not poisoned");
+        f(&mut ctx).map_err(|e| format!("{e:#}").into())
+    }
+}
+
+/// Registers a function named `$name` whose body gets the [`DebuggerCtx`] as `$ctx`.
+macro_rules! bind {
+    ($engine:ident, $tracee:ident, $name:expr, |$ctx:ident $(, $arg:ident: $ty:ty)*| $body:expr) => {{
+        let tracee = $tracee.clone();
+        $engine.register_fn($name, move |$($arg: $ty),*| tracee.with(|$ctx| $body));
+    }};
+}
+
+/// Registers `read_$ty(addr)` and `write_$ty(addr, value)` for the integer types.
+macro_rules! bind_integers {
+    ($engine:ident, $tracee:ident, $($ty:ident),*) => {$(
+        bind!($engine, $tracee, concat!("read_", stringify!($ty)), |ctx, addr: i64| {
+            let bytes = ctx.read_memory(addr as u64, size_of::<$ty>())?;
+            Ok($ty::from_le_bytes(bytes.try_into().expect("the size of the type")) as i64)
+        });
+        bind!($engine, $tracee, concat!("write_", stringify!($ty)), |ctx, addr: i64, value: i64| {
+            ctx.write_memory(addr as u64, &(value as $ty).to_le_bytes())
+        });
+    )*};
+}
+
+fn bind(engine: &mut Engine, tracee: &Tracee) {
+    bind!(engine, tracee, "reg", |ctx, name: &str| {
+        let regs = ctx.registers()?;
+        let value =
+            registers::read(&regs, name).ok_or_else(|| eyre!("unknown register `{name}`"))?;
+        Ok(value as i64)
+    });
+    bind!(engine, tracee, "set_reg", |ctx, name: &str, value: i64| {
+        let mut regs = ctx.registers()?;
+        let register = registers::get_mut(&mut regs, name)
+            .ok_or_else(|| eyre!("unknown register `{name}`"))?;
+        *register = value as u64;
+        ctx.set_registers(regs)
+    });
+    bind!(engine, tracee, "pc", |ctx| Ok(ctx.pc()? as i64));
+    bind!(engine, tracee, "arg", |ctx, index: i64| {
+        Ok(ctx.wasm_argument(index as usize)? as i64)
+    });
+    bind!(engine, tracee, "eval", |ctx, expr: &str| Expr::parse(expr)?
+        .eval(ctx));
+
+    bind_integers!(engine, tracee, u8, u16, u32, u64, i8, i16, i32, i64);
+    bind!(engine, tracee, "read_bytes", |ctx, addr: i64, len: i64| {
+        let blob: Blob = ctx.read_memory(addr as u64, len as usize)?;
+        Ok(blob)
+    });
+    bind!(
+        engine,
+        tracee,
+        "write_bytes",
+        |ctx, addr: i64, bytes: Blob| { ctx.write_memory(addr as u64, &bytes) }
+    );
+    bind!(engine, tracee, "memory_base", |ctx| Ok(
+        ctx.linear_memory_base()? as i64
+    ));
+
+    bind!(engine, tracee, "symbolize", |ctx, addr: i64| {
+        let symbol = ctx
+            .function_mapping
+            .as_ref()
+            .and_then(|mapping| mapping.symbolize(addr as u64));
+        Ok(symbol.map_or(Dynamic::UNIT, Dynamic::from))
+    });
+    bind!(engine, tracee, "function_addr", |ctx, name: &str| {
+        let mapping = ctx
+            .function_mapping
+            .as_ref()
+            .ok_or_else(|| eyre!("the module is not loaded yet"))?;
+        let meta = mapping
+            .get_function(name)
+            .ok_or_else(|| eyre!("no function named `{name}`"))?;
+        Ok(meta.addr as i64)
+    });
+    bind!(engine, tracee, "backtrace", |ctx| {
+        let frames: Array = ctx
+            .backtrace()?
+            .into_iter()
+            .map(|addr| Dynamic::from(addr as i64))
+            .collect();
+        Ok(frames)
+    });
+
+    bind!(engine, tracee, "break_at", |ctx, location: &str| {
+        let bp = ctx.add_breakpoint(Location::parse(location)?)?;
+        Ok(bp.id.0 as i64)
+    });
+    bind!(engine, tracee, "delete", |ctx, id: i64| {
+        ctx.delete_breakpoint(breakpoint_id(id)?).map(drop)
+    });
+    bind!(engine, tracee, "enable", |ctx, id: i64| {
+        ctx.set_enabled(breakpoint_id(id)?, true).map(drop)
+    });
+    bind!(engine, tracee, "disable", |ctx, id: i64| {
+        ctx.set_enabled(breakpoint_id(id)?, false).map(drop)
+    });
+    bind!(
+        engine,
+        tracee,
+        "condition",
+        |ctx, id: i64, condition: &str| {
+            let condition = (!condition.trim().is_empty())
+                .then(|| Expr::parse(condition))
+                .transpose()?;
+            ctx.set_condition(breakpoint_id(id)?, condition).map(drop)
+        }
+    );
+    bind!(engine, tracee, "ignore", |ctx, id: i64, count: i64| {
+        ctx.set_ignore_count(breakpoint_id(id)?, count.max(0) as u64)
+            .map(drop)
+    });
+    bind!(engine, tracee, "hits", |ctx, id: i64| {
+        let id = breakpoint_id(id)?;
+        let bp = ctx
+            .breakpoints
+            .get(id)
+            .ok_or_else(|| eyre!("no breakpoint number {id}"))?;
+        Ok(bp.hits as i64)
+    });
+}
+
+/// `cont()`, `stepi()` and `finish()` block until the tracee stops again, and return how it
+/// stopped or exited as a string.
+fn bind_run_control(engine: &mut Engine, tracee: &Tracee) {
+    let output = tracee.output.clone();
+    bind!(engine, tracee, "cont", |ctx| {
+        ctx.resume()?;
+        wait(ctx, &*output)
+    });
+    let output = tracee.output.clone();
+    bind!(engine, tracee, "stepi", |ctx| {
+        ctx.step_instruction()?;
+        wait(ctx, &*output)
+    });
+    let output = tracee.output.clone();
+    bind!(engine, tracee, "finish", |ctx| {
+        ctx.finish()?;
+        wait(ctx, &*output)
+    });
+}
+
+/// Blocks until the resumed tracee stops or exits. Like in the REPL, the breakpoints are
+/// armed when the module is loaded, which is not reported.
+fn wait(ctx: &mut DebuggerCtx, output: &dyn Fn(String)) -> eyre::Result<String> {
+    loop {
+        let state = ctx.poll(true)?;
+        for message in ctx.take_log() {
+            output(message.to_string());
+        }
+
+        match state {
+            Some(TraceeState::Stopped(StopReason::ModuleLoaded)) => {
+                ctx.parse_perfmap(BIN_NAME)?;
+                for error in ctx.arm_breakpoints() {
+                    output(format!("warning: {error:#}"));
+                }
+                ctx.resume()?;
+            }
+            Some(state) => return Ok(state.to_string()),
+            None => {}
+        }
+    }
+}
+
+fn breakpoint_id(id: i64) -> eyre::Result<BreakpointId> {
+    u32::try_from(id)
+        .map(BreakpointId)
+        .map_err(|_| eyre!("invalid breakpoint number {id}"))
+}
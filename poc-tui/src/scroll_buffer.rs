@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+
+/// The maximum number of entries that a [`ScrollBuffer`] keeps by default.
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// Bounded list of entries with a scroll position, the newest entry is the last.
+///
+/// The view sticks to the bottom unless it's scrolled up, in which case it stays on the same
+/// entries while new ones are pushed.
+#[derive(Debug)]
+pub struct ScrollBuffer<T> {
+    entries: VecDeque<T>,
+    capacity: usize,
+    /// How many entries the view is scrolled up from the bottom
+    scroll: usize,
+}
+
+impl<T> Default for ScrollBuffer<T> {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl<T> ScrollBuffer<T> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        ScrollBuffer {
+            entries: VecDeque::new(),
+            capacity,
+            scroll: 0,
+        }
+    }
+
+    pub fn push(&mut self, entry: T) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.entries.len() - 1);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.scroll = 0;
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.entries.back()
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut T> {
+        self.entries.back_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries.iter()
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// The entries that fit into `height` lines with the current scroll.
+    pub fn visible(&self, height: usize) -> impl Iterator<Item = &T> {
+        let end = self.entries.len() - self.scroll.min(self.entries.len());
+        let start = end.saturating_sub(height);
+        self.entries.range(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visible(buffer: &ScrollBuffer<u32>, height: usize) -> Vec<u32> {
+        buffer.visible(height).copied().collect()
+    }
+
+    #[test]
+    fn evicts_the_oldest_entries() {
+        let mut buffer = ScrollBuffer::with_capacity(3);
+        assert!(buffer.is_empty());
+        for i in 0..5 {
+            buffer.push(i);
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.iter().copied().collect::<Vec<_>>(), [2, 3, 4]);
+        assert_eq!(buffer.last(), Some(&4));
+
+        *buffer.last_mut().unwrap() = 40;
+        assert_eq!(visible(&buffer, 10), [2, 3, 40]);
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.last(), None);
+        assert!(visible(&buffer, 10).is_empty());
+    }
+
+    #[test]
+    fn sticks_to_the_bottom() {
+        let mut buffer = ScrollBuffer::default();
+        for i in 0..10 {
+            buffer.push(i);
+        }
+        assert_eq!(visible(&buffer, 3), [7, 8, 9]);
+        buffer.push(10);
+        assert_eq!(buffer.scroll(), 0);
+        assert_eq!(visible(&buffer, 3), [8, 9, 10]);
+    }
+
+    #[test]
+    fn scrolls_within_the_entries() {
+        let mut buffer = ScrollBuffer::default();
+        for i in 0..10 {
+            buffer.push(i);
+        }
+
+        buffer.scroll_up(2);
+        assert_eq!(visible(&buffer, 3), [5, 6, 7]);
+        buffer.scroll_up(100);
+        assert_eq!(buffer.scroll(), 9);
+        assert_eq!(visible(&buffer, 3), [0]);
+
+        buffer.scroll_down(4);
+        assert_eq!(visible(&buffer, 3), [2, 3, 4]);
+        buffer.scroll_down(100);
+        assert_eq!(buffer.scroll(), 0);
+        assert_eq!(visible(&buffer, 3), [7, 8, 9]);
+
+        // an empty buffer doesn't scroll
+        let mut empty = ScrollBuffer::<u32>::default();
+        empty.scroll_up(3);
+        assert_eq!(empty.scroll(), 0);
+    }
+
+    #[test]
+    fn keeps_the_scrolled_view_on_the_same_entries() {
+        let mut buffer = ScrollBuffer::with_capacity(5);
+        for i in 0..5 {
+            buffer.push(i);
+        }
+        buffer.scroll_up(1);
+        assert_eq!(visible(&buffer, 2), [2, 3]);
+
+        // 0 is evicted, the view doesn't move
+        buffer.push(5);
+        assert_eq!(buffer.scroll(), 2);
+        assert_eq!(visible(&buffer, 2), [2, 3]);
+
+        // until the view reaches the oldest entry
+        buffer.push(6);
+        buffer.push(7);
+        buffer.push(8);
+        assert_eq!(buffer.scroll(), 4);
+        assert_eq!(visible(&buffer, 2), [4]);
+    }
+}
@@ -0,0 +1,799 @@
+//! The `poc-tui stats` subcommand, which measures how long the calls of the JIT-compiled
+//! functions take.
+//!
+//! Every function gets a breakpoint at its entry and a trap at the return address of every
+//! call, like in `record`. The ptrace stops make the calls look slower than they are, hence
+//! their overhead is subtracted from the durations. It's calibrated before the session, by
+//! measuring the calls of an empty function in a child process the same way.
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{self, eyre};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use nix::{
+    sys::{
+        signal::{self, SigSet, Signal},
+        wait::waitpid,
+    },
+    unistd::{ForkResult, fork},
+};
+use ratatui::{
+    DefaultTerminal,
+    layout::{Alignment, Constraint},
+    style::{Color, Modifier, Style, Stylize},
+    widgets::{Block, BorderType, Row, Table},
+};
+use regex::Regex;
+
+use crate::{
+    batch::{self, TraceeArgs, next_value},
+    breakpoint::{BreakpointId, Location},
+    debugger::BIN_NAME,
+    debugger_ctx::{DebuggerCtx, StopReason, TraceeState},
+    function_mapping::FunctionId,
+    trace_viewer::format_duration,
+};
+
+const USAGE: &str = "\
+usage: poc-tui stats [OPTIONS] (--attach PID | -- PROGRAM [ARGS ...])
+
+Measures the calls of the JIT-compiled functions until the tracee exits, or until Ctrl-C, and
+shows them in a table sorted by self time, which is updated live. The final table is printed
+when the session is over. An attached tracee keeps running afterwards.
+
+options:
+    --attach PID        attach to the process PID
+    --perfmap PATH      use the perf map at PATH
+    --functions REGEX   only measure the functions whose name matches REGEX, the time in the
+                        other ones counts as the self time of their callers
+    --duration SECS     stop after SECS seconds
+    --no-tui            only print the final table
+    --raw               don't subtract the overhead of the ptrace stops from the durations
+
+keys:
+    q, Esc, Ctrl-C      stop the session";
+
+/// How often the live table is redrawn
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// The number of calls that the overhead is calibrated with
+const CALIBRATION_CALLS: usize = 1000;
+
+/// Entry point of the `stats` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut tracee = TraceeArgs::with_duration(USAGE);
+    let mut filter = None;
+    let mut tui = true;
+    let mut compensate = true;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if tracee.parse(&arg, &mut args)? => {}
+            "--functions" => {
+                let regex = next_value(&mut args, "--functions", USAGE)?;
+                filter = Some(Regex::new(&regex).map_err(|e| eyre!("invalid regex: {e}"))?);
+            }
+            "--no-tui" => tui = false,
+            "--raw" => compensate = false,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+
+    // before there is a tracee or another thread, which the child would inherit
+    let overhead = if compensate {
+        calibrate()?
+    } else {
+        Overhead::default()
+    };
+
+    let mut ctx = DebuggerCtx::new();
+    let duration = tracee.duration;
+    tracee.start(&mut ctx)?;
+    batch::stop_on_signals(duration)?;
+
+    let mapping = ctx.parse_perfmap(BIN_NAME)?;
+    let mut stats = Stats {
+        functions: HashMap::new(),
+        overhead,
+        compensated: compensate,
+        start: Instant::now(),
+        end: None,
+    };
+    let mut entries = HashMap::new();
+    for meta in mapping.iter_by_addr() {
+        if let Some(filter) = &filter
+            && !filter.is_match(&meta.name)
+            && !filter.is_match(&meta.symbol.demangled)
+        {
+            continue;
+        }
+        let bp = ctx.add_breakpoint(Location::Address(meta.addr))?;
+        entries.insert(bp.id, meta.id);
+        stats
+            .functions
+            .insert(meta.id, FunctionStats::new(meta.symbol.demangled.clone()));
+    }
+    if entries.is_empty() {
+        return Err(eyre!("no function to measure"));
+    }
+    eprintln!("Measuring {} functions", entries.len());
+
+    let stats = Arc::new(Mutex::new(stats));
+    let done = Arc::new(AtomicBool::new(false));
+    let ui = tui.then(|| {
+        let (stats, done) = (stats.clone(), done.clone());
+        thread::spawn(move || show(&stats, &done))
+    });
+
+    let mut meter = Meter {
+        ctx,
+        stats: stats.clone(),
+        entries,
+        calls: Calls::new(overhead),
+    };
+    let result = meter.measure(|_| false);
+    stats.lock().expect("not poisoned").end = Some(Instant::now());
+    done.store(true, Ordering::Relaxed);
+    if let Some(ui) = ui {
+        ui.join().expect("the table doesn't panic")?;
+    }
+
+    eprintln!("{}", result.as_deref().unwrap_or("stopped by an error"));
+    print!("{}", stats.lock().expect("not poisoned").table());
+
+    // dropping the ctx kills the spawned tracee or detaches from the attached one
+    result.map(drop)
+}
+
+/// How long the ptrace stops make the calls look.
+#[derive(Debug, Clone, Copy, Default)]
+struct Overhead {
+    /// Between the stop at the entry of a call and the stop at its return
+    call: Duration,
+    /// Of a call that is made by the one that is measured
+    callee: Duration,
+}
+
+/// The function that is measured in the calibration. It returns right away, which is what
+/// [`DebuggerCtx::watch_return`] expects at the entry of a function that is not in the perf
+/// map.
+#[unsafe(naked)]
+extern "C" fn calibration_target() {
+    std::arch::naked_asm!("ret")
+}
+
+/// Measures the overhead with the calls of [`calibration_target`] in a child process.
+fn calibrate() -> eyre::Result<Overhead> {
+    let child = match unsafe { fork() }? {
+        ForkResult::Child => loop {
+            calibration_target();
+        },
+        ForkResult::Parent { child } => child,
+    };
+
+    let result = (|| {
+        let mut ctx = DebuggerCtx::new();
+        ctx.attach(child)?;
+        let bp = ctx.add_breakpoint(Location::Address(calibration_target as *const () as u64))?;
+        let function = FunctionId(0);
+        let mut meter = Meter {
+            ctx,
+            stats: Arc::new(Mutex::new(Stats {
+                functions: HashMap::from([(function, FunctionStats::new(String::new()))]),
+                overhead: Overhead::default(),
+                compensated: false,
+                start: Instant::now(),
+                end: None,
+            })),
+            entries: HashMap::from([(bp.id, function)]),
+            calls: Calls::new(Overhead::default()),
+        };
+        let start = Instant::now();
+        meter.measure(|stats| stats.functions[&function].durations.len() >= CALIBRATION_CALLS)?;
+        let elapsed = start.elapsed();
+
+        let mut durations = meter.stats.lock().expect("not poisoned").functions[&function]
+            .durations
+            .clone();
+        durations.sort_unstable();
+        // a callee takes both stops, and the time from one to the other
+        Ok(Overhead {
+            call: durations[durations.len() / 2],
+            callee: elapsed / durations.len() as u32,
+        })
+    })();
+    // the child is detached when the ctx is dropped, before it's killed
+    let _ = signal::kill(child, Signal::SIGKILL);
+    let _ = waitpid(child, None);
+    result
+}
+
+/// What is measured of a function.
+#[derive(Debug, Clone)]
+struct FunctionStats {
+    name: String,
+    /// The duration of every call that returned
+    durations: Vec<Duration>,
+    /// The durations of the calls that are not in a call of the same function
+    total: Duration,
+    /// The durations without the callees that are measured
+    own: Duration,
+    /// The calls that are running, to tell the recursive ones
+    active: usize,
+}
+
+impl FunctionStats {
+    fn new(name: String) -> Self {
+        FunctionStats {
+            name,
+            durations: Vec::new(),
+            total: Duration::ZERO,
+            own: Duration::ZERO,
+            active: 0,
+        }
+    }
+}
+
+/// The statistics of the session, which the table shows while they are collected.
+struct Stats {
+    functions: HashMap<FunctionId, FunctionStats>,
+    overhead: Overhead,
+    compensated: bool,
+    start: Instant,
+    end: Option<Instant>,
+}
+
+/// A row of the table.
+struct FunctionRow<'a> {
+    name: &'a str,
+    calls: usize,
+    total: Duration,
+    own: Duration,
+    min: Duration,
+    p50: Duration,
+    p99: Duration,
+    max: Duration,
+}
+
+impl FunctionRow<'_> {
+    const HEADER: [&'static str; 8] = [
+        "self", "total", "calls", "min", "p50", "p99", "max", "function",
+    ];
+
+    fn cells(&self) -> [String; 8] {
+        [
+            format_duration(self.own),
+            format_duration(self.total),
+            self.calls.to_string(),
+            format_duration(self.min),
+            format_duration(self.p50),
+            format_duration(self.p99),
+            format_duration(self.max),
+            self.name.to_string(),
+        ]
+    }
+}
+
+impl Stats {
+    /// The functions that are called, by their self time.
+    fn rows(&self) -> Vec<FunctionRow<'_>> {
+        let mut rows: Vec<FunctionRow> = self
+            .functions
+            .values()
+            .filter(|function| !function.durations.is_empty())
+            .map(|function| {
+                let mut durations = function.durations.clone();
+                durations.sort_unstable();
+                FunctionRow {
+                    name: &function.name,
+                    calls: durations.len(),
+                    total: function.total,
+                    own: function.own,
+                    min: durations[0],
+                    p50: percentile(&durations, 0.5),
+                    p99: percentile(&durations, 0.99),
+                    max: durations[durations.len() - 1],
+                }
+            })
+            .collect();
+        rows.sort_by(|a, b| b.own.cmp(&a.own).then_with(|| a.name.cmp(b.name)));
+        rows
+    }
+
+    fn summary(&self) -> String {
+        let elapsed = self.end.unwrap_or_else(Instant::now) - self.start;
+        let calls: usize = self.functions.values().map(|f| f.durations.len()).sum();
+        format!(
+            "{calls} calls in {}, {}",
+            format_duration(elapsed),
+            match self.compensated {
+                true => format!(
+                    "{} per call and {} per callee are subtracted",
+                    format_duration(self.overhead.call),
+                    format_duration(self.overhead.callee)
+                ),
+                false => "with the overhead of the ptrace stops".into(),
+            }
+        )
+    }
+
+    /// The table as text, with aligned columns.
+    fn table(&self) -> String {
+        let rows: Vec<[String; 8]> = self.rows().iter().map(FunctionRow::cells).collect();
+        let mut widths = FunctionRow::HEADER.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut table = format!("{}\n\n", self.summary());
+        for row in std::iter::once(FunctionRow::HEADER.map(String::from)).chain(rows) {
+            let mut line = String::new();
+            for (i, cell) in row.iter().enumerate() {
+                // the numbers are aligned to the right, the name is the last column
+                if i + 1 == row.len() {
+                    let _ = write!(line, "  {cell}");
+                } else {
+                    let pad = widths[i].saturating_sub(cell.chars().count());
+                    let _ = write!(line, "  {}{cell}", " ".repeat(pad));
+                }
+            }
+            let _ = writeln!(table, "{line}");
+        }
+        table
+    }
+}
+
+/// The `p`-th percentile of the non-empty `sorted` durations, by the nearest rank.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Shows the live table until the session is `done`, or until the user stops it.
+fn show(stats: &Mutex<Stats>, done: &AtomicBool) -> eyre::Result<()> {
+    // the signals that stop the session are for the thread that waits for the tracee
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGALRM);
+    signals.thread_block()?;
+
+    let terminal = ratatui::init();
+    let result = show_until_done(terminal, stats, done);
+    ratatui::restore();
+    result
+}
+
+fn show_until_done(
+    mut terminal: DefaultTerminal,
+    stats: &Mutex<Stats>,
+    done: &AtomicBool,
+) -> eyre::Result<()> {
+    while !done.load(Ordering::Relaxed) {
+        {
+            let stats = stats.lock().expect("not poisoned");
+            terminal.draw(|frame| frame.render_widget(table(&stats), frame.area()))?;
+        }
+
+        if event::poll(REFRESH_INTERVAL)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c {
+                batch::request_stop()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn table(stats: &Stats) -> Table<'static> {
+    let rows: Vec<Row> = stats
+        .rows()
+        .iter()
+        .map(|row| Row::new(row.cells()))
+        .collect();
+    let header = Row::new(FunctionRow::HEADER).style(Style::default().add_modifier(Modifier::BOLD));
+    let widths = [
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Length(9),
+        Constraint::Min(0),
+    ];
+
+    Table::new(rows, widths)
+        .header(header)
+        .block(
+            Block::bordered()
+                .title(format!("Stats · {}", stats.summary()))
+                .title_alignment(Alignment::Center)
+                .title_bottom("[q] stop")
+                .border_type(BorderType::Rounded),
+        )
+        .fg(Color::White)
+        .bg(Color::Black)
+}
+
+/// A call that didn't return yet.
+struct Frame {
+    function: FunctionId,
+    /// The stack pointer after the return
+    sp: u64,
+    start: Instant,
+    /// [`Calls::entered`] after the entry of this call
+    entered: u64,
+    /// The durations of the callees that are measured
+    callees: Duration,
+}
+
+/// The calls that are running, which are measured into the [`FunctionStats`] as they return.
+struct Calls {
+    /// The innermost call last
+    stack: Vec<Frame>,
+    /// The number of calls that are entered since the start
+    entered: u64,
+    overhead: Overhead,
+}
+
+impl Calls {
+    fn new(overhead: Overhead) -> Self {
+        Calls {
+            stack: Vec::new(),
+            entered: 0,
+            overhead,
+        }
+    }
+
+    /// A call of `function` is entered at `time`, it returns with the stack pointer `sp`.
+    fn enter(
+        &mut self,
+        functions: &mut HashMap<FunctionId, FunctionStats>,
+        function: FunctionId,
+        sp: u64,
+        time: Instant,
+    ) {
+        self.entered += 1;
+        self.stack.push(Frame {
+            function,
+            sp,
+            start: time,
+            entered: self.entered,
+            callees: Duration::ZERO,
+        });
+        if let Some(function) = functions.get_mut(&function) {
+            function.active += 1;
+        }
+    }
+
+    /// The call that returns with the stack pointer `sp` returns at `time`. The overhead of
+    /// its stops and of the ones of its callees is subtracted from its duration, which is zero
+    /// if they take longer.
+    fn ret(&mut self, functions: &mut HashMap<FunctionId, FunctionStats>, sp: u64, time: Instant) {
+        let Some(depth) = self.stack.iter().rposition(|frame| frame.sp == sp) else {
+            return;
+        };
+        // the calls above the one that returns are unwound, e.g. by a trap
+        for frame in self.stack.drain(depth + 1..) {
+            if let Some(function) = functions.get_mut(&frame.function) {
+                function.active -= 1;
+            }
+        }
+        let frame = self.stack.pop().expect("found");
+        let callees = (self.entered - frame.entered) as u32;
+        let duration = time
+            .saturating_duration_since(frame.start)
+            .saturating_sub(self.overhead.call + self.overhead.callee * callees);
+        if let Some(caller) = self.stack.last_mut() {
+            caller.callees += duration;
+        }
+
+        if let Some(function) = functions.get_mut(&frame.function) {
+            function.active -= 1;
+            function.durations.push(duration);
+            function.own += duration.saturating_sub(frame.callees);
+            if function.active == 0 {
+                function.total += duration;
+            }
+        }
+    }
+
+    /// All the calls are unwound without returning.
+    fn unwind(&mut self, functions: &mut HashMap<FunctionId, FunctionStats>) {
+        for frame in self.stack.drain(..) {
+            if let Some(function) = functions.get_mut(&frame.function) {
+                function.active -= 1;
+            }
+        }
+    }
+}
+
+struct Meter {
+    ctx: DebuggerCtx,
+    stats: Arc<Mutex<Stats>>,
+    entries: HashMap<BreakpointId, FunctionId>,
+    calls: Calls,
+}
+
+impl Meter {
+    /// Resumes the tracee and measures its calls, until it exits, the session is stopped or
+    /// `done` holds. Returns why it's over.
+    fn measure(&mut self, done: impl Fn(&Stats) -> bool) -> eyre::Result<String> {
+        loop {
+            if batch::stop_requested() {
+                return Ok("stopped by the user".into());
+            }
+            if done(&self.stats.lock().expect("not poisoned")) {
+                return Ok("done".into());
+            }
+            self.ctx.resume()?;
+
+            let state = batch::wait(&mut self.ctx)?;
+            let stopped = Instant::now();
+            let reason = match state {
+                None => return Ok("stopped by the user".into()),
+                Some(TraceeState::Exited(reason)) => {
+                    return Ok(format!("the tracee exited ({reason})"));
+                }
+                Some(TraceeState::Stopped(reason)) => reason,
+                Some(TraceeState::NotStarted | TraceeState::Running) => continue,
+            };
+            self.on_stop(reason, stopped)?;
+        }
+    }
+
+    fn on_stop(&mut self, reason: StopReason, stopped: Instant) -> eyre::Result<()> {
+        if batch::handle_interrupt(&mut self.ctx, &reason) {
+            return Ok(());
+        }
+
+        let functions = &mut self.stats.lock().expect("not poisoned").functions;
+        match reason {
+            StopReason::Breakpoint(id) => {
+                let Some(&function) = self.entries.get(&id) else {
+                    return Ok(());
+                };
+                let sp = self.ctx.watch_return()?;
+                self.calls.enter(functions, function, sp, stopped);
+            }
+            StopReason::Returned { sp } => self.calls.ret(functions, sp, stopped),
+            // the runtime turns these into traps and unwinds the wasm frames
+            StopReason::Signal(
+                Signal::SIGILL | Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGFPE,
+            ) => self.calls.unwind(functions),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: FunctionId = FunctionId(0);
+    const LEAF: FunctionId = FunctionId(1);
+
+    fn functions() -> HashMap<FunctionId, FunctionStats> {
+        HashMap::from([
+            (MAIN, FunctionStats::new("main".into())),
+            (LEAF, FunctionStats::new("leaf".into())),
+        ])
+    }
+
+    fn millis(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Plays the entries and the returns at their times in milliseconds since the start. The
+    /// stack pointer that a call returns with is its depth.
+    fn measure(overhead: Overhead, events: &[(u64, Option<FunctionId>)]) -> Stats {
+        let start = Instant::now();
+        let mut functions = functions();
+        let mut calls = Calls::new(overhead);
+        for &(ms, event) in events {
+            let time = start + millis(ms);
+            match event {
+                Some(function) => {
+                    let sp = calls.stack.len() as u64;
+                    calls.enter(&mut functions, function, sp, time);
+                }
+                None => {
+                    let sp = calls.stack.len() as u64 - 1;
+                    calls.ret(&mut functions, sp, time);
+                }
+            }
+        }
+        assert!(calls.stack.is_empty());
+
+        Stats {
+            functions,
+            overhead,
+            compensated: true,
+            start,
+            end: Some(start),
+        }
+    }
+
+    #[test]
+    fn splits_the_self_and_the_total_time() {
+        let stats = measure(
+            Overhead::default(),
+            &[
+                (0, Some(MAIN)),
+                (10, Some(LEAF)),
+                (30, None),
+                (40, Some(LEAF)),
+                (45, None),
+                (100, None),
+            ],
+        );
+
+        let main = &stats.functions[&MAIN];
+        assert_eq!(main.durations, [millis(100)]);
+        assert_eq!((main.total, main.own), (millis(100), millis(75)));
+        let leaf = &stats.functions[&LEAF];
+        assert_eq!(leaf.durations, [millis(20), millis(5)]);
+        assert_eq!((leaf.total, leaf.own), (millis(25), millis(25)));
+    }
+
+    #[test]
+    fn counts_the_recursive_calls_once_in_the_total() {
+        let stats = measure(
+            Overhead::default(),
+            &[(0, Some(MAIN)), (10, Some(MAIN)), (20, None), (50, None)],
+        );
+
+        let main = &stats.functions[&MAIN];
+        assert_eq!(main.durations, [millis(10), millis(50)]);
+        assert_eq!((main.total, main.own), (millis(50), millis(50)));
+        assert_eq!(main.active, 0);
+    }
+
+    #[test]
+    fn subtracts_the_overhead_of_the_call_and_of_its_callees() {
+        let overhead = Overhead {
+            call: millis(5),
+            callee: millis(3),
+        };
+        let stats = measure(
+            overhead,
+            &[(0, Some(MAIN)), (10, Some(LEAF)), (30, None), (100, None)],
+        );
+
+        let leaf = &stats.functions[&LEAF];
+        assert_eq!(leaf.durations, [millis(15)]);
+        let main = &stats.functions[&MAIN];
+        // 100 - 5 - 3
+        assert_eq!(main.durations, [millis(92)]);
+        assert_eq!((main.total, main.own), (millis(92), millis(77)));
+    }
+
+    #[test]
+    fn the_overhead_saturates_at_zero() {
+        let overhead = Overhead {
+            call: millis(50),
+            callee: millis(40),
+        };
+        let stats = measure(
+            overhead,
+            &[(0, Some(MAIN)), (10, Some(LEAF)), (70, None), (80, None)],
+        );
+
+        let leaf = &stats.functions[&LEAF];
+        assert_eq!(leaf.durations, [millis(10)]);
+        let main = &stats.functions[&MAIN];
+        assert_eq!(main.durations, [Duration::ZERO]);
+        assert_eq!((main.total, main.own), (Duration::ZERO, Duration::ZERO));
+    }
+
+    #[test]
+    fn unwinds_the_calls_that_dont_return() {
+        let start = Instant::now();
+        let mut functions = functions();
+        let mut calls = Calls::new(Overhead::default());
+        calls.enter(&mut functions, MAIN, 0, start);
+        calls.enter(&mut functions, LEAF, 1, start);
+        // the return of `leaf` is missed
+        calls.ret(&mut functions, 0, start + millis(10));
+        assert!(calls.stack.is_empty());
+        assert!(functions[&LEAF].durations.is_empty());
+        assert_eq!(functions[&LEAF].active, 0);
+        assert_eq!(functions[&MAIN].durations, [millis(10)]);
+
+        calls.enter(&mut functions, MAIN, 0, start);
+        calls.enter(&mut functions, LEAF, 1, start);
+        // a trap
+        calls.unwind(&mut functions);
+        assert!(calls.stack.is_empty());
+        assert_eq!((functions[&MAIN].active, functions[&LEAF].active), (0, 0));
+        // an unknown return is ignored
+        calls.ret(&mut functions, 0, start + millis(20));
+        assert_eq!(functions[&MAIN].durations, [millis(10)]);
+    }
+
+    #[test]
+    fn percentiles_of_few_samples_are_the_nearest_rank() {
+        let durations = |n: u64| (1..=n).map(millis).collect::<Vec<_>>();
+
+        assert_eq!(percentile(&durations(1), 0.5), millis(1));
+        assert_eq!(percentile(&durations(1), 0.99), millis(1));
+        assert_eq!(percentile(&durations(2), 0.5), millis(1));
+        assert_eq!(percentile(&durations(2), 0.99), millis(2));
+        assert_eq!(percentile(&durations(3), 0.5), millis(2));
+        assert_eq!(percentile(&durations(3), 0.99), millis(3));
+        assert_eq!(percentile(&durations(10), 0.99), millis(10));
+        assert_eq!(percentile(&durations(100), 0.5), millis(50));
+        assert_eq!(percentile(&durations(100), 0.99), millis(99));
+        assert_eq!(percentile(&durations(3), 0.0), millis(1));
+    }
+
+    #[test]
+    fn the_rows_are_sorted_by_self_time() {
+        let stats = measure(
+            Overhead::default(),
+            &[
+                (0, Some(MAIN)),
+                (10, Some(LEAF)),
+                (40, None),
+                (40, Some(LEAF)),
+                (50, None),
+                (60, None),
+            ],
+        );
+
+        let rows: Vec<_> = stats
+            .rows()
+            .iter()
+            .map(|row| {
+                (
+                    row.name, row.calls, row.own, row.min, row.p50, row.p99, row.max,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                (
+                    "leaf",
+                    2,
+                    millis(40),
+                    millis(10),
+                    millis(10),
+                    millis(30),
+                    millis(30)
+                ),
+                (
+                    "main",
+                    1,
+                    millis(20),
+                    millis(60),
+                    millis(60),
+                    millis(60),
+                    millis(60)
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::{fs, ops::Range, path::Path};
+
+use color_eyre::eyre::{self, WrapErr};
+
+use crate::function_mapping::FunctionMapping;
+
+const ELF_HEADER_SIZE: u16 = 64;
+const PROGRAM_HEADER_SIZE: u16 = 56;
+const SECTION_HEADER_SIZE: u16 = 64;
+const SYMBOL_SIZE: u64 = 24;
+
+const ET_DYN: u16 = 3;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_R: u32 = 4;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const STB_GLOBAL: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// Index of `.text` in the section headers.
+const TEXT_SECTION: u16 = 1;
+const STRTAB_SECTION: u32 = 3;
+const SHSTRTAB_SECTION: u16 = 4;
+
+/// The addresses that the JIT-compiled functions span, empty when there is none.
+pub fn text_range(mapping: &FunctionMapping) -> Range<u64> {
+    let start = mapping.iter_by_addr().map(|meta| meta.addr).min();
+    let end = mapping.iter_by_addr().map(|meta| meta.range().end).max();
+
+    match (start, end) {
+        (Some(start), Some(end)) => start..end,
+        _ => 0..0,
+    }
+}
+
+/// Writes the symbol file of the JIT-compiled functions to `path`, see [`generate`].
+pub fn write(mapping: &FunctionMapping, path: &Path) -> eyre::Result<()> {
+    fs::write(path, generate(mapping))
+        .wrap_err_with(|| format!("failed to write the symbol file `{}`", path.display()))
+}
+
+/// Synthesizes an ELF file that only contains a symbol for every JIT-compiled function, so
+/// that gdb and lldb can symbolize the JIT code, e.g. with `add-symbol-file`.
+///
+/// The code is not in the file, `.text` is `NOBITS` at the addresses of the functions, so the
+/// debuggers read the code from the tracee. The symbols have their absolute addresses, hence
+/// the file needs no relocation.
+pub fn generate(mapping: &FunctionMapping) -> Vec<u8> {
+    let text = text_range(mapping);
+
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; SYMBOL_SIZE as usize];
+    for meta in mapping.iter_by_addr() {
+        let name = strtab.len() as u32;
+        strtab.extend_from_slice(meta.symbol.to_string().as_bytes());
+        strtab.push(0);
+
+        symtab.extend_from_slice(&name.to_le_bytes());
+        symtab.push((STB_GLOBAL << 4) | STT_FUNC);
+        symtab.push(0);
+        symtab.extend_from_slice(&TEXT_SECTION.to_le_bytes());
+        symtab.extend_from_slice(&meta.addr.to_le_bytes());
+        symtab.extend_from_slice(&meta.size.to_le_bytes());
+    }
+
+    let mut shstrtab = vec![0];
+    let mut section_name = |name: &str| {
+        let offset = shstrtab.len() as u32;
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+        offset
+    };
+    let names = [
+        section_name(".text"),
+        section_name(".symtab"),
+        section_name(".strtab"),
+        section_name(".shstrtab"),
+    ];
+
+    // layout: ELF header, program header, .symtab, .strtab, .shstrtab, section headers
+    let symtab_offset = (ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE) as u64;
+    let strtab_offset = symtab_offset + symtab.len() as u64;
+    let shstrtab_offset = strtab_offset + strtab.len() as u64;
+    let section_headers_offset = (shstrtab_offset + shstrtab.len() as u64).next_multiple_of(8);
+
+    let mut elf = Vec::new();
+
+    // ELF header
+    elf.extend_from_slice(b"\x7fELF");
+    // 64-bit, little endian, version 1, System V ABI
+    elf.extend_from_slice(&[2, 1, 1, 0]);
+    elf.resize(16, 0);
+    elf.extend_from_slice(&ET_DYN.to_le_bytes());
+    elf.extend_from_slice(&EM_X86_64.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    // entry
+    elf.extend_from_slice(&0u64.to_le_bytes());
+    elf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes());
+    elf.extend_from_slice(&section_headers_offset.to_le_bytes());
+    // flags
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes());
+    elf.extend_from_slice(&PROGRAM_HEADER_SIZE.to_le_bytes());
+    elf.extend_from_slice(&1u16.to_le_bytes());
+    elf.extend_from_slice(&SECTION_HEADER_SIZE.to_le_bytes());
+    elf.extend_from_slice(&(names.len() as u16 + 1).to_le_bytes());
+    elf.extend_from_slice(&SHSTRTAB_SECTION.to_le_bytes());
+
+    // the segment lets the debuggers place the file like a library
+    elf.extend_from_slice(&PT_LOAD.to_le_bytes());
+    elf.extend_from_slice(&(PF_R | PF_X).to_le_bytes());
+    // offset, vaddr, paddr, filesz, memsz, align
+    for value in [0, text.start, text.start, 0, text.end - text.start, 1] {
+        elf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    elf.extend_from_slice(&symtab);
+    elf.extend_from_slice(&strtab);
+    elf.extend_from_slice(&shstrtab);
+    elf.resize(section_headers_offset as usize, 0);
+
+    let sections = [
+        SectionHeader::default(),
+        SectionHeader {
+            name: names[0],
+            kind: SHT_NOBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            addr: text.start,
+            size: text.end - text.start,
+            align: 1,
+            ..Default::default()
+        },
+        SectionHeader {
+            name: names[1],
+            kind: SHT_SYMTAB,
+            offset: symtab_offset,
+            size: symtab.len() as u64,
+            link: STRTAB_SECTION,
+            // index of the first global symbol
+            info: 1,
+            align: 8,
+            entry_size: SYMBOL_SIZE,
+            ..Default::default()
+        },
+        SectionHeader {
+            name: names[2],
+            kind: SHT_STRTAB,
+            offset: strtab_offset,
+            size: strtab.len() as u64,
+            align: 1,
+            ..Default::default()
+        },
+        SectionHeader {
+            name: names[3],
+            kind: SHT_STRTAB,
+            offset: shstrtab_offset,
+            size: shstrtab.len() as u64,
+            align: 1,
+            ..Default::default()
+        },
+    ];
+    for section in sections {
+        section.write(&mut elf);
+    }
+
+    elf
+}
+
+#[derive(Debug, Default)]
+struct SectionHeader {
+    name: u32,
+    kind: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+impl SectionHeader {
+    fn write(&self, elf: &mut Vec<u8>) {
+        elf.extend_from_slice(&self.name.to_le_bytes());
+        elf.extend_from_slice(&self.kind.to_le_bytes());
+        elf.extend_from_slice(&self.flags.to_le_bytes());
+        elf.extend_from_slice(&self.addr.to_le_bytes());
+        elf.extend_from_slice(&self.offset.to_le_bytes());
+        elf.extend_from_slice(&self.size.to_le_bytes());
+        elf.extend_from_slice(&self.link.to_le_bytes());
+        elf.extend_from_slice(&self.info.to_le_bytes());
+        elf.extend_from_slice(&self.align.to_le_bytes());
+        elf.extend_from_slice(&self.entry_size.to_le_bytes());
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    io::{self, BufRead},
+    path::PathBuf,
+};
+
+use color_eyre::eyre::{self, eyre};
+
+use crate::{
+    batch::next_value,
+    function_mapping::{FunctionMapping, LayoutIssue},
+};
+
+const USAGE: &str = "\
+usage: poc-tui symbolize [--bin NAME] (--pid PID | --perfmap PATH) [ADDR...]
+
+Prints every ADDR as `func+0xoff`. Addresses are read from the stdin when none is given.
+
+options:
+    --bin NAME        only consider the functions of the module NAME and the trampolines
+    --pid PID         resolve the perf map of the process PID
+    --perfmap PATH    use the perf map at PATH";
+
+/// Entry point of the `symbolize` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut bin_name = String::new();
+    let mut pid = None;
+    let mut perfmap_path = None;
+    let mut addrs = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bin" => bin_name = next_value(&mut args, "--bin", USAGE)?,
+            "--pid" => pid = Some(next_value(&mut args, "--pid", USAGE)?.parse::<u32>()?),
+            "--perfmap" => {
+                perfmap_path = Some(PathBuf::from(next_value(&mut args, "--perfmap", USAGE)?))
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => addrs.push(parse_addr(&arg)?),
+        }
+    }
+
+    let mapping = match (pid, perfmap_path) {
+        (_, Some(path)) => FunctionMapping::generate_from_perfmap_file(&bin_name, &path)?,
+        (Some(pid), None) => {
+            FunctionMapping::generate_from_perfmap_file_with_pid(&bin_name, pid, None)?
+        }
+        (None, None) => return Err(eyre!("either --pid or --perfmap is required\n\n{USAGE}")),
+    };
+
+    for issue in mapping.layout_issues() {
+        match issue {
+            LayoutIssue::Overlap { first, second } => eprintln!(
+                "warning: `{}` ({:#x}..{:#x}) overlaps with `{}` ({:#x}..{:#x})",
+                first.name,
+                first.range().start,
+                first.range().end,
+                second.name,
+                second.range().start,
+                second.range().end,
+            ),
+            // Gaps are expected since the functions are aligned, only the big ones are suspicious.
+            LayoutIssue::Gap { range, before, .. } if range.end - range.start >= 0x100 => {
+                eprintln!(
+                    "warning: {:#x} bytes after `{}` ({:#x}..{:#x}) are not mapped to any function",
+                    range.end - range.start,
+                    before.name,
+                    range.start,
+                    range.end,
+                )
+            }
+            LayoutIssue::Gap { .. } => {}
+        }
+    }
+
+    if addrs.is_empty() {
+        for line in io::stdin().lock().lines() {
+            for word in line?.split_whitespace() {
+                print_symbolized(&mapping, parse_addr(word)?);
+            }
+        }
+    } else {
+        for addr in addrs {
+            print_symbolized(&mapping, addr);
+        }
+    }
+
+    Ok(())
+}
+
+fn print_symbolized(mapping: &FunctionMapping, addr: u64) {
+    match mapping.symbolize(addr) {
+        Some(sym) => println!("{addr:#x}: {sym}"),
+        None => println!("{addr:#x}: ??"),
+    }
+}
+
+/// Parses a hexadecimal address, with or without the `0x` prefix.
+pub fn parse_addr(s: &str) -> eyre::Result<u64> {
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+
+    u64::from_str_radix(hex, 16).map_err(|e| eyre!("invalid address `{s}`: {e}"))
+}
@@ -0,0 +1,746 @@
+//! The trace file that `poc-tui record` writes and `poc-tui view` reads.
+//!
+//! All the integers are unsigned LEB128, except the signed ones which are zigzag encoded
+//! first. The strings and the byte arrays are prefixed by their length.
+//!
+//! ```text
+//! header:   "TWTRACE\0" | version | start (unix ns) | pid | command
+//! records:  tag | fields
+//!   FUNCTION   1 | index | addr | size | name
+//!   ENTER      2 | dt | tid | function | argc | args ...
+//!   EXIT       3 | dt | tid | function | ret
+//!   SYSCALL    4 | dt | tid | nr | 6 args
+//!   SYSRET     5 | dt | tid | nr | ret (signed)
+//!   SNAPSHOT   6 | dt | tid | addr | bytes
+//!   END        7 | dt | reason
+//!   TRAP       8 | dt | tid | signal | pc
+//! ```
+//!
+//! `dt` is the time since the previous record with a time in nanoseconds, which keeps the
+//! timestamps at 1-3 bytes for a busy tracee. `function` is the index of a function that is
+//! declared by a `FUNCTION` record before. The `FUNCTION` records are numbered from 0 in the
+//! order they are written.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::Duration,
+};
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use nix::sys::signal::Signal;
+
+const MAGIC: &[u8; 8] = b"TWTRACE\0";
+const VERSION: u64 = 1;
+
+const FUNCTION: u8 = 1;
+const ENTER: u8 = 2;
+const EXIT: u8 = 3;
+const SYSCALL: u8 = 4;
+const SYSRET: u8 = 5;
+const SNAPSHOT: u8 = 6;
+const END: u8 = 7;
+const TRAP: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceHeader {
+    /// When the recording started, in nanoseconds since the unix epoch
+    pub start: u64,
+    pub pid: u32,
+    /// What is traced, as the user gave it
+    pub command: String,
+}
+
+/// A function of the traced module, the events refer to it by its `index`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFunction {
+    pub index: u32,
+    pub addr: u64,
+    pub size: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// Since the start of the recording
+    pub time: Duration,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// A function is called, with its first wasm arguments
+    Enter {
+        tid: u32,
+        function: u32,
+        args: Vec<u64>,
+    },
+    /// A function returned `ret`, i.e. the value of `rax`
+    Exit {
+        tid: u32,
+        function: u32,
+        ret: u64,
+    },
+    Syscall {
+        tid: u32,
+        nr: u64,
+        args: [u64; 6],
+    },
+    SyscallReturn {
+        tid: u32,
+        nr: u64,
+        ret: i64,
+    },
+    /// The linear memory at `addr`
+    Snapshot {
+        tid: u32,
+        addr: u64,
+        bytes: Vec<u8>,
+    },
+    /// The recording is over, e.g. the tracee exited
+    End {
+        reason: String,
+    },
+    /// The tracee got a fault signal at `pc`, e.g. the `SIGILL` of a wasm trap
+    Trap {
+        tid: u32,
+        signal: i32,
+        pc: u64,
+    },
+}
+
+impl EventKind {
+    pub fn tid(&self) -> Option<u32> {
+        match self {
+            EventKind::Enter { tid, .. }
+            | EventKind::Exit { tid, .. }
+            | EventKind::Syscall { tid, .. }
+            | EventKind::SyscallReturn { tid, .. }
+            | EventKind::Snapshot { tid, .. }
+            | EventKind::Trap { tid, .. } => Some(*tid),
+            EventKind::End { .. } => None,
+        }
+    }
+}
+
+/// Writes a trace, the records go through a buffer.
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    /// The time of the last event
+    last: Duration,
+}
+
+impl TraceWriter<BufWriter<File>> {
+    pub fn create(path: &Path, header: &TraceHeader) -> eyre::Result<Self> {
+        let file = File::create(path)
+            .wrap_err_with(|| format!("failed to create `{}`", path.display()))?;
+        Ok(Self::new(BufWriter::new(file), header)?)
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut writer: W, header: &TraceHeader) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        write_uint(&mut writer, VERSION)?;
+        write_uint(&mut writer, header.start)?;
+        write_uint(&mut writer, header.pid.into())?;
+        write_bytes(&mut writer, header.command.as_bytes())?;
+
+        Ok(TraceWriter {
+            writer,
+            last: Duration::ZERO,
+        })
+    }
+
+    pub fn function(&mut self, function: &TraceFunction) -> io::Result<()> {
+        let w = &mut self.writer;
+        w.write_all(&[FUNCTION])?;
+        write_uint(w, function.index.into())?;
+        write_uint(w, function.addr)?;
+        write_uint(w, function.size)?;
+        write_bytes(w, function.name.as_bytes())
+    }
+
+    /// Writes an event, which can't be earlier than the previous one.
+    pub fn event(&mut self, event: &TraceEvent) -> io::Result<()> {
+        let tag = match &event.kind {
+            EventKind::Enter { .. } => ENTER,
+            EventKind::Exit { .. } => EXIT,
+            EventKind::Syscall { .. } => SYSCALL,
+            EventKind::SyscallReturn { .. } => SYSRET,
+            EventKind::Snapshot { .. } => SNAPSHOT,
+            EventKind::End { .. } => END,
+            EventKind::Trap { .. } => TRAP,
+        };
+        let dt = event.time.saturating_sub(self.last);
+        self.last = self.last.max(event.time);
+
+        let w = &mut self.writer;
+        w.write_all(&[tag])?;
+        write_uint(w, dt.as_nanos() as u64)?;
+        if let Some(tid) = event.kind.tid() {
+            write_uint(w, tid.into())?;
+        }
+
+        match &event.kind {
+            EventKind::Enter { function, args, .. } => {
+                write_uint(w, (*function).into())?;
+                write_uint(w, args.len() as u64)?;
+                for arg in args {
+                    write_uint(w, *arg)?;
+                }
+            }
+            EventKind::Exit { function, ret, .. } => {
+                write_uint(w, (*function).into())?;
+                write_uint(w, *ret)?;
+            }
+            EventKind::Syscall { nr, args, .. } => {
+                write_uint(w, *nr)?;
+                for arg in args {
+                    write_uint(w, *arg)?;
+                }
+            }
+            EventKind::SyscallReturn { nr, ret, .. } => {
+                write_uint(w, *nr)?;
+                write_int(w, *ret)?;
+            }
+            EventKind::Snapshot { addr, bytes, .. } => {
+                write_uint(w, *addr)?;
+                write_bytes(w, bytes)?;
+            }
+            EventKind::End { reason } => write_bytes(w, reason.as_bytes())?,
+            EventKind::Trap { signal, pc, .. } => {
+                write_uint(w, *signal as u64)?;
+                write_uint(w, *pc)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A trace that is read back.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub header: TraceHeader,
+    /// Indexed by [`TraceFunction::index`]
+    pub functions: Vec<TraceFunction>,
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Reads the trace at `path`. A trace that is cut short, e.g. because the recorder is
+    /// killed, is read up to the last complete record.
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let file =
+            File::open(path).wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+        Self::read(BufReader::new(file))
+            .wrap_err_with(|| format!("failed to read the trace `{}`", path.display()))
+    }
+
+    pub fn read(mut reader: impl Read) -> eyre::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(eyre!("not a trace file"));
+        }
+        let version = read_uint(&mut reader)?;
+        if version != VERSION {
+            return Err(eyre!("unsupported version {version}, expected {VERSION}"));
+        }
+        let header = TraceHeader {
+            start: read_uint(&mut reader)?,
+            pid: read_uint(&mut reader)? as u32,
+            command: read_string(&mut reader)?,
+        };
+
+        let mut trace = Trace {
+            header,
+            functions: Vec::new(),
+            events: Vec::new(),
+        };
+        let mut time = Duration::ZERO;
+        loop {
+            let mut tag = [0];
+            if reader.read(&mut tag)? == 0 {
+                break;
+            }
+            match trace.read_record(&mut reader, tag[0], &mut time) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(trace)
+    }
+
+    fn read_record(&mut self, r: &mut impl Read, tag: u8, time: &mut Duration) -> io::Result<()> {
+        if tag == FUNCTION {
+            let index = read_uint(r)?;
+            if index != self.functions.len() as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the function {index} is declared after {} functions",
+                        self.functions.len()
+                    ),
+                ));
+            }
+            self.functions.push(TraceFunction {
+                index: index as u32,
+                addr: read_uint(r)?,
+                size: read_uint(r)?,
+                name: read_string(r)?,
+            });
+            return Ok(());
+        }
+
+        *time += Duration::from_nanos(read_uint(r)?);
+        let tid = match tag {
+            END => 0,
+            _ => read_uint(r)? as u32,
+        };
+        let kind = match tag {
+            ENTER => {
+                let function = read_uint(r)? as u32;
+                let argc = read_uint(r)?;
+                let args = (0..argc).map(|_| read_uint(r)).collect::<io::Result<_>>()?;
+                EventKind::Enter {
+                    tid,
+                    function,
+                    args,
+                }
+            }
+            EXIT => EventKind::Exit {
+                tid,
+                function: read_uint(r)? as u32,
+                ret: read_uint(r)?,
+            },
+            SYSCALL => {
+                let nr = read_uint(r)?;
+                let mut args = [0; 6];
+                for arg in &mut args {
+                    *arg = read_uint(r)?;
+                }
+                EventKind::Syscall { tid, nr, args }
+            }
+            SYSRET => EventKind::SyscallReturn {
+                tid,
+                nr: read_uint(r)?,
+                ret: read_int(r)?,
+            },
+            SNAPSHOT => EventKind::Snapshot {
+                tid,
+                addr: read_uint(r)?,
+                bytes: read_bytes(r)?,
+            },
+            END => EventKind::End {
+                reason: read_string(r)?,
+            },
+            TRAP => EventKind::Trap {
+                tid,
+                signal: read_uint(r)? as i32,
+                pc: read_uint(r)?,
+            },
+            tag => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown record {tag}"),
+                ));
+            }
+        };
+        self.events.push(TraceEvent { time: *time, kind });
+
+        Ok(())
+    }
+
+    pub fn function_name(&self, index: u32) -> &str {
+        self.functions
+            .get(index as usize)
+            .map_or("<unknown>", |function| function.name.as_str())
+    }
+
+    /// The function whose code contains `addr`.
+    pub fn function_at(&self, addr: u64) -> Option<&TraceFunction> {
+        self.functions
+            .iter()
+            .find(|function| (function.addr..function.addr + function.size).contains(&addr))
+    }
+
+    /// The calls in the order they are entered, see [`Call`].
+    pub fn calls(&self) -> Vec<Call> {
+        let mut calls: Vec<Call> = Vec::new();
+        // the open calls of every thread, the innermost last
+        let mut stacks: Vec<(u32, Vec<usize>)> = Vec::new();
+
+        for (event_index, event) in self.events.iter().enumerate() {
+            let Some(tid) = event.kind.tid() else {
+                continue;
+            };
+            let stack = match stacks.iter().position(|(t, _)| *t == tid) {
+                Some(i) => &mut stacks[i].1,
+                None => {
+                    stacks.push((tid, Vec::new()));
+                    &mut stacks.last_mut().expect("just pushed").1
+                }
+            };
+
+            match &event.kind {
+                EventKind::Enter { function, args, .. } => {
+                    let index = calls.len();
+                    let parent = stack.last().copied();
+                    calls.push(Call {
+                        function: *function,
+                        tid,
+                        start: event.time,
+                        end: None,
+                        args: args.clone(),
+                        ret: None,
+                        depth: stack.len(),
+                        parent,
+                        children: Vec::new(),
+                        enter_event: event_index,
+                        exit_event: None,
+                    });
+                    if let Some(parent) = parent {
+                        calls[parent].children.push(index);
+                    }
+                    stack.push(index);
+                }
+                EventKind::Exit { function, ret, .. } => {
+                    // the calls above the one that returns are unwound, e.g. by a trap
+                    let Some(depth) = stack.iter().rposition(|&i| calls[i].function == *function)
+                    else {
+                        continue;
+                    };
+                    let returned = stack[depth];
+                    for i in stack.drain(depth..) {
+                        calls[i].end = Some(event.time);
+                    }
+                    calls[returned].ret = Some(*ret);
+                    calls[returned].exit_event = Some(event_index);
+                }
+                EventKind::Trap { .. } => {
+                    // the runtime unwinds all the wasm frames of the thread
+                    for i in stack.drain(..) {
+                        calls[i].end = Some(event.time);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        calls
+    }
+}
+
+/// A function call, as recorded by its enter and exit events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub function: u32,
+    pub tid: u32,
+    pub start: Duration,
+    /// `None` if it didn't return before the recording ended
+    pub end: Option<Duration>,
+    pub args: Vec<u64>,
+    /// `None` if it didn't return, or it's unwound, e.g. by a trap
+    pub ret: Option<u64>,
+    /// The number of the calls it's nested in
+    pub depth: usize,
+    /// The index of the caller in [`Trace::calls`]
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// The indices of the events in [`Trace::events`]
+    pub enter_event: usize,
+    pub exit_event: Option<usize>,
+}
+
+impl Call {
+    pub fn duration(&self) -> Option<Duration> {
+        Some(self.end? - self.start)
+    }
+}
+
+/// `SIGILL` for 4, or the number itself if it's not a signal.
+pub fn signal_name(signal: i32) -> String {
+    Signal::try_from(signal).map_or_else(|_| signal.to_string(), |signal| signal.as_str().into())
+}
+
+fn write_uint(w: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn write_int(w: &mut impl Write, value: i64) -> io::Result<()> {
+    write_uint(w, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+fn write_bytes(w: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_uint(w, bytes.len() as u64)?;
+    w.write_all(bytes)
+}
+
+fn read_uint(r: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        r.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "an integer is longer than 64 bits",
+    ))
+}
+
+fn read_int(r: &mut impl Read) -> io::Result<i64> {
+    let value = read_uint(r)?;
+    Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+}
+
+fn read_bytes(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_uint(r)?;
+    let mut bytes = Vec::new();
+    r.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(bytes)
+}
+
+fn read_string(r: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(read_bytes(r)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> TraceHeader {
+        TraceHeader {
+            start: 1_700_000_000_000_000_000,
+            pid: 4242,
+            command: "wasmtime run -g app.wasm".into(),
+        }
+    }
+
+    fn functions() -> Vec<TraceFunction> {
+        vec![
+            TraceFunction {
+                index: 0,
+                addr: 0x7f00_0000_1146,
+                size: 5,
+                name: "leaf".into(),
+            },
+            TraceFunction {
+                index: 1,
+                addr: 0x7f00_0000_114b,
+                size: 0xe,
+                name: "mid".into(),
+            },
+        ]
+    }
+
+    fn events() -> Vec<TraceEvent> {
+        let at = Duration::from_nanos;
+        vec![
+            TraceEvent {
+                time: at(10),
+                kind: EventKind::Enter {
+                    tid: 4242,
+                    function: 1,
+                    args: vec![1, u64::MAX],
+                },
+            },
+            TraceEvent {
+                time: at(10),
+                kind: EventKind::Syscall {
+                    tid: 4242,
+                    nr: 1,
+                    args: [1, 0x7ffd_0000_0000, 12, 0, 0, 0],
+                },
+            },
+            TraceEvent {
+                time: at(2_000),
+                kind: EventKind::SyscallReturn {
+                    tid: 4242,
+                    nr: 1,
+                    ret: -9,
+                },
+            },
+            TraceEvent {
+                time: at(3_000),
+                kind: EventKind::Snapshot {
+                    tid: 4243,
+                    addr: 0x1000,
+                    bytes: vec![0xde, 0xad, 0xbe, 0xef],
+                },
+            },
+            TraceEvent {
+                time: at(5_000_000_000),
+                kind: EventKind::Exit {
+                    tid: 4242,
+                    function: 1,
+                    ret: 42,
+                },
+            },
+            TraceEvent {
+                time: at(5_000_000_100),
+                kind: EventKind::Trap {
+                    tid: 4243,
+                    signal: 4,
+                    pc: 0x7f00_0000_1149,
+                },
+            },
+            TraceEvent {
+                time: at(5_000_000_100),
+                kind: EventKind::End {
+                    reason: "exited with 0".into(),
+                },
+            },
+        ]
+    }
+
+    fn encode() -> Vec<u8> {
+        let mut writer = TraceWriter::new(Vec::new(), &header()).unwrap();
+        for function in &functions() {
+            writer.function(function).unwrap();
+        }
+        for event in &events() {
+            writer.event(event).unwrap();
+        }
+        writer.writer
+    }
+
+    fn header_len() -> usize {
+        let mut buf = Vec::new();
+        TraceWriter::new(&mut buf, &header()).unwrap();
+        buf.len()
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        format!("{:#}", Trace::read(bytes).unwrap_err())
+    }
+
+    #[test]
+    fn round_trips() {
+        let trace = Trace::read(&encode()[..]).unwrap();
+        assert_eq!(trace.header, header());
+        assert_eq!(trace.functions, functions());
+        assert_eq!(trace.events, events());
+        assert_eq!(trace.function_name(1), "mid");
+        assert_eq!(trace.function_name(7), "<unknown>");
+        assert_eq!(trace.function_at(0x7f00_0000_1149).unwrap().name, "leaf");
+    }
+
+    #[test]
+    fn round_trips_the_integers() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, u64::MAX / 3, u64::MAX] {
+            let mut buf = Vec::new();
+            write_uint(&mut buf, value).unwrap();
+            assert_eq!(read_uint(&mut &buf[..]).unwrap(), value);
+        }
+        for value in [0, 1, -1, 63, -64, i64::MAX, i64::MIN] {
+            let mut buf = Vec::new();
+            write_int(&mut buf, value).unwrap();
+            assert_eq!(read_int(&mut &buf[..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn reads_truncated_traces_up_to_the_last_complete_record() {
+        let bytes = encode();
+        let full = Trace::read(&bytes[..]).unwrap();
+
+        for len in header_len()..bytes.len() {
+            let trace = Trace::read(&bytes[..len]).unwrap();
+            assert!(full.functions.starts_with(&trace.functions), "{len}");
+            assert!(full.events.starts_with(&trace.events), "{len}");
+            if !trace.events.is_empty() {
+                assert_eq!(trace.functions, full.functions, "{len}");
+            }
+        }
+
+        // the header itself is not optional
+        for len in 0..header_len() {
+            assert!(Trace::read(&bytes[..len]).is_err(), "{len}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(error(b"\x7fELF\x02\x01\x01\0\0\0"), "not a trace file");
+
+        let mut bytes = MAGIC.to_vec();
+        write_uint(&mut bytes, VERSION + 1).unwrap();
+        assert_eq!(error(&bytes), "unsupported version 2, expected 1");
+    }
+
+    #[test]
+    fn rejects_corrupt_records() {
+        let mut bytes = Vec::new();
+        TraceWriter::new(&mut bytes, &header()).unwrap();
+
+        let mut unknown = bytes.clone();
+        unknown.extend_from_slice(&[42, 0, 0]);
+        assert_eq!(error(&unknown), "unknown record 42");
+
+        let mut overlong = bytes.clone();
+        overlong.push(END);
+        overlong.extend_from_slice(&[0xff; 10]);
+        overlong.push(0);
+        assert_eq!(error(&overlong), "an integer is longer than 64 bits");
+
+        let mut not_utf8 = bytes.clone();
+        not_utf8.extend_from_slice(&[END, 0, 2, 0xc3, 0x28]);
+        assert!(error(&not_utf8).contains("invalid utf-8"));
+    }
+
+    #[test]
+    fn requires_the_functions_in_order() {
+        let mut bytes = Vec::new();
+        let mut writer = TraceWriter::new(&mut bytes, &header()).unwrap();
+        writer.function(&functions()[1]).unwrap();
+        assert_eq!(
+            error(&bytes),
+            "the function 1 is declared after 0 functions"
+        );
+
+        // a huge index doesn't make us allocate for it
+        let mut bytes = Vec::new();
+        TraceWriter::new(&mut bytes, &header()).unwrap();
+        bytes.push(FUNCTION);
+        write_uint(&mut bytes, u64::MAX).unwrap();
+        assert_eq!(
+            error(&bytes),
+            format!("the function {} is declared after 0 functions", u64::MAX)
+        );
+
+        // nor redeclare one
+        let mut bytes = Vec::new();
+        let mut writer = TraceWriter::new(&mut bytes, &header()).unwrap();
+        writer.function(&functions()[0]).unwrap();
+        writer.function(&functions()[0]).unwrap();
+        assert_eq!(
+            error(&bytes),
+            "the function 0 is declared after 1 functions"
+        );
+    }
+}
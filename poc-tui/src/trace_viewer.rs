@@ -0,0 +1,595 @@
+use std::{cell::Cell, collections::HashSet, fmt::Write as _, path::PathBuf, time::Duration};
+
+use color_eyre::eyre::{self, eyre};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    DefaultTerminal,
+    buffer::Buffer,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, Paragraph, Widget},
+};
+
+use crate::trace::{Call, EventKind, Trace, TraceEvent, signal_name};
+
+const USAGE: &str = "\
+usage: poc-tui view FILE
+
+Browses a trace that is written by `poc-tui record`, without a tracee.
+
+keys:
+    Tab            switch between the timeline and the call tree
+    Up/Down, j/k   move, PgUp/PgDn by a page, g/G to the start/end
+    %              jump between the enter and the exit of a call in the timeline
+    Enter          expand/collapse a call in the call tree, show the call of an event in it
+    Left/Right     collapse/expand, Left again goes to the caller
+    t              show the selected call in the timeline
+    /, n, N        search for a function, next/previous match
+    q, Esc         quit";
+
+/// The height of the details pane, including the borders.
+const DETAILS_HEIGHT: u16 = 10;
+
+/// The bytes per line in the hex dumps of the snapshots.
+const DUMP_WIDTH: usize = 16;
+
+/// Entry point of the `view` subcommand. `args` doesn't contain the subcommand itself.
+pub fn run(args: impl IntoIterator<Item = String>) -> eyre::Result<()> {
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            other => return Err(eyre!("unknown argument `{other}`\n\n{USAGE}")),
+        }
+    }
+    let path = path.ok_or_else(|| eyre!("FILE is required\n\n{USAGE}"))?;
+    let trace = Trace::load(&path)?;
+
+    let terminal = ratatui::init();
+    let result = TraceViewer::new(trace, path).run(terminal);
+    ratatui::restore();
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Timeline,
+    CallTree,
+}
+
+/// Browses a [`Trace`]: its events in the order they are recorded, and the calls nested in
+/// their callers.
+struct TraceViewer {
+    path: PathBuf,
+    trace: Trace,
+    calls: Vec<Call>,
+    /// The nesting of every event, to indent the timeline
+    event_depths: Vec<usize>,
+    /// The call of every enter and exit event
+    event_calls: Vec<Option<usize>>,
+    tab: Tab,
+    /// The selected event
+    event: usize,
+    /// The calls that show their callees
+    expanded: HashSet<usize>,
+    /// The visible calls in the call tree, in the order they are shown
+    rows: Vec<usize>,
+    /// The selected row in the call tree
+    row: usize,
+    /// The search being typed
+    input: Option<String>,
+    search: String,
+    /// The height of the list in the last frame, for paging
+    page: Cell<usize>,
+    running: bool,
+}
+
+impl TraceViewer {
+    fn new(trace: Trace, path: PathBuf) -> Self {
+        let calls = trace.calls();
+
+        let mut event_calls = vec![None; trace.events.len()];
+        for (i, call) in calls.iter().enumerate() {
+            event_calls[call.enter_event] = Some(i);
+            if let Some(exit) = call.exit_event {
+                event_calls[exit] = Some(i);
+            }
+        }
+        let mut event_depths = vec![0; trace.events.len()];
+        let mut depth = 0;
+        for (i, event) in trace.events.iter().enumerate() {
+            event_depths[i] = match (&event.kind, event_calls[i]) {
+                (EventKind::Enter { .. } | EventKind::Exit { .. }, Some(call)) => calls[call].depth,
+                _ => depth,
+            };
+            // the events after an enter are in its call, the ones after an exit are in the
+            // caller
+            depth = match (&event.kind, event_calls[i]) {
+                (EventKind::Enter { .. }, Some(call)) => calls[call].depth + 1,
+                (EventKind::Exit { .. }, Some(call)) => calls[call].depth,
+                _ => depth,
+            };
+        }
+
+        let mut viewer = TraceViewer {
+            path,
+            trace,
+            calls,
+            event_depths,
+            event_calls,
+            tab: Tab::Timeline,
+            event: 0,
+            expanded: HashSet::new(),
+            rows: Vec::new(),
+            row: 0,
+            input: None,
+            search: String::new(),
+            page: Cell::new(0),
+            running: true,
+        };
+        viewer.update_rows();
+        viewer
+    }
+
+    fn run(mut self, mut terminal: DefaultTerminal) -> eyre::Result<()> {
+        while self.running {
+            terminal.draw(|frame| frame.render_widget(&self, frame.area()))?;
+            if let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.handle_key_event(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) {
+        if let Some(input) = &mut self.input {
+            match key.code {
+                KeyCode::Enter => {
+                    self.search = self.input.take().unwrap_or_default();
+                    self.find(true);
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return;
+        }
+
+        let page = self.page.get().max(1);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.running = false,
+            KeyCode::Tab => {
+                self.tab = match self.tab {
+                    Tab::Timeline => Tab::CallTree,
+                    Tab::CallTree => Tab::Timeline,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-(page as isize)),
+            KeyCode::PageDown => self.move_by(page as isize),
+            KeyCode::Home | KeyCode::Char('g') => self.move_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => self.move_by(isize::MAX),
+            KeyCode::Char('/') => self.input = Some(String::new()),
+            KeyCode::Char('n') => self.find(true),
+            KeyCode::Char('N') => self.find(false),
+            KeyCode::Char('%') if self.tab == Tab::Timeline => {
+                if let Some(call) = self.event_calls.get(self.event).copied().flatten() {
+                    let call = &self.calls[call];
+                    self.event = match call.exit_event {
+                        Some(exit) if exit != self.event => exit,
+                        _ => call.enter_event,
+                    };
+                }
+            }
+            KeyCode::Enter if self.tab == Tab::Timeline => {
+                if let Some(call) = self.event_calls.get(self.event).copied().flatten() {
+                    self.reveal(call);
+                    self.tab = Tab::CallTree;
+                }
+            }
+            KeyCode::Enter | KeyCode::Char(' ') if self.tab == Tab::CallTree => {
+                if let Some(&call) = self.rows.get(self.row)
+                    && !self.expanded.remove(&call)
+                {
+                    self.expanded.insert(call);
+                }
+                self.update_rows();
+            }
+            KeyCode::Right | KeyCode::Char('l') if self.tab == Tab::CallTree => {
+                if let Some(&call) = self.rows.get(self.row) {
+                    self.expanded.insert(call);
+                    self.update_rows();
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') if self.tab == Tab::CallTree => {
+                let Some(&call) = self.rows.get(self.row) else {
+                    return;
+                };
+                if !self.expanded.remove(&call)
+                    && let Some(parent) = self.calls[call].parent
+                {
+                    self.reveal(parent);
+                }
+                self.update_rows();
+            }
+            KeyCode::Char('t') if self.tab == Tab::CallTree => {
+                if let Some(&call) = self.rows.get(self.row) {
+                    self.event = self.calls[call].enter_event;
+                    self.tab = Tab::Timeline;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let (selected, len) = match self.tab {
+            Tab::Timeline => (&mut self.event, self.trace.events.len()),
+            Tab::CallTree => (&mut self.row, self.rows.len()),
+        };
+        *selected = selected
+            .saturating_add_signed(delta)
+            .min(len.saturating_sub(1));
+    }
+
+    /// Selects the next or the previous call of a function whose name contains the search.
+    fn find(&mut self, forward: bool) {
+        if self.search.is_empty() {
+            return;
+        }
+        let matches = |call: usize| {
+            self.trace
+                .function_name(self.calls[call].function)
+                .contains(&self.search)
+        };
+
+        match self.tab {
+            Tab::Timeline => {
+                let is_match = |i: &usize| {
+                    matches!(self.trace.events[*i].kind, EventKind::Enter { .. })
+                        && self.event_calls[*i].is_some_and(matches)
+                };
+                let found = if forward {
+                    (self.event + 1..self.trace.events.len()).find(is_match)
+                } else {
+                    (0..self.event).rev().find(is_match)
+                };
+                if let Some(event) = found {
+                    self.event = event;
+                }
+            }
+            Tab::CallTree => {
+                // the calls are in the order they are entered, hence the hidden ones are found
+                // too
+                let current = self.rows.get(self.row).copied().unwrap_or(0);
+                let found = if forward {
+                    (current + 1..self.calls.len()).find(|&i| matches(i))
+                } else {
+                    (0..current).rev().find(|&i| matches(i))
+                };
+                if let Some(call) = found {
+                    self.reveal(call);
+                }
+            }
+        }
+    }
+
+    /// Expands the callers of `call` and selects it in the call tree.
+    fn reveal(&mut self, call: usize) {
+        let mut parent = self.calls[call].parent;
+        while let Some(p) = parent {
+            self.expanded.insert(p);
+            parent = self.calls[p].parent;
+        }
+        self.update_rows();
+        if let Some(row) = self.rows.iter().position(|&c| c == call) {
+            self.row = row;
+        }
+    }
+
+    /// Recomputes the visible rows of the call tree, keeping the selected call.
+    fn update_rows(&mut self) {
+        let selected = self.rows.get(self.row).copied();
+
+        self.rows.clear();
+        let mut pending: Vec<usize> = self
+            .calls
+            .iter()
+            .enumerate()
+            .filter(|(_, call)| call.parent.is_none())
+            .map(|(i, _)| i)
+            .rev()
+            .collect();
+        while let Some(call) = pending.pop() {
+            self.rows.push(call);
+            if self.expanded.contains(&call) {
+                pending.extend(self.calls[call].children.iter().rev());
+            }
+        }
+
+        self.row = selected
+            .and_then(|call| self.rows.iter().position(|&c| c == call))
+            .unwrap_or(self.row.min(self.rows.len().saturating_sub(1)));
+    }
+
+    fn call_label(&self, call: &Call) -> String {
+        let mut label = format!("{}(", self.trace.function_name(call.function));
+        for (i, arg) in call.args.iter().enumerate() {
+            if i > 0 {
+                label.push_str(", ");
+            }
+            let _ = write!(label, "{arg:#x}");
+        }
+        label.push(')');
+        label
+    }
+
+    fn event_line(&self, index: usize, event: &TraceEvent) -> Line<'_> {
+        let indent = "  ".repeat(self.event_depths[index]);
+        let call = self.event_calls[index].map(|call| &self.calls[call]);
+        let time = Span::styled(
+            format!("{:>14}  ", format_time(event.time)),
+            Color::DarkGray,
+        );
+
+        let text = match (&event.kind, call) {
+            (EventKind::Enter { .. }, Some(call)) => {
+                Span::raw(format!("{indent}→ {}", self.call_label(call)))
+            }
+            (EventKind::Exit { ret, .. }, Some(call)) => Span::styled(
+                format!(
+                    "{indent}← {} = {ret:#x}{}",
+                    self.trace.function_name(call.function),
+                    call.duration()
+                        .map(|d| format!("  ({})", format_duration(d)))
+                        .unwrap_or_default()
+                ),
+                Color::Gray,
+            ),
+            (EventKind::Enter { function, .. } | EventKind::Exit { function, .. }, None) => {
+                Span::raw(format!("{indent}? {}", self.trace.function_name(*function)))
+            }
+            (EventKind::Syscall { nr, args, .. }, _) => Span::styled(
+                format!(
+                    "{indent}syscall {nr}({:#x}, {:#x}, {:#x}, ...)",
+                    args[0], args[1], args[2]
+                ),
+                Color::Yellow,
+            ),
+            (EventKind::SyscallReturn { nr, ret, .. }, _) => {
+                Span::styled(format!("{indent}syscall {nr} = {ret}"), Color::Yellow)
+            }
+            (EventKind::Snapshot { addr, bytes, .. }, _) => Span::styled(
+                format!("{indent}snapshot of {} bytes at {addr:#x}", bytes.len()),
+                Color::Magenta,
+            ),
+            (EventKind::End { reason }, _) => Span::styled(format!("end: {reason}"), Color::Red),
+            (EventKind::Trap { signal, pc, .. }, _) => Span::styled(
+                format!(
+                    "{indent}trap: {} at {pc:#x}{}",
+                    signal_name(*signal),
+                    self.trace
+                        .function_at(*pc)
+                        .map(|function| format!(" in {}", function.name))
+                        .unwrap_or_default()
+                ),
+                Color::Red,
+            ),
+        };
+
+        Line::from(vec![time, text])
+    }
+
+    fn call_line(&self, call: usize) -> Line<'_> {
+        let c = &self.calls[call];
+        let marker = match (c.children.is_empty(), self.expanded.contains(&call)) {
+            (true, _) => " ",
+            (false, true) => "▾",
+            (false, false) => "▸",
+        };
+        let duration = c
+            .duration()
+            .map_or_else(|| "unfinished".into(), format_duration);
+
+        Line::from(vec![
+            Span::raw(format!(
+                "{}{marker} {}",
+                "  ".repeat(c.depth),
+                self.call_label(c)
+            )),
+            Span::styled(format!("  {duration}"), Color::DarkGray),
+            Span::styled(
+                match c.children.len() {
+                    0 => String::new(),
+                    n => format!("  {n} calls"),
+                },
+                Color::DarkGray,
+            ),
+        ])
+    }
+
+    fn details(&self) -> Vec<Line<'_>> {
+        let call = match self.tab {
+            Tab::Timeline => {
+                let Some(event) = self.trace.events.get(self.event) else {
+                    return Vec::new();
+                };
+                match &event.kind {
+                    EventKind::Snapshot { addr, bytes, .. } => return hex_dump(*addr, bytes),
+                    EventKind::Syscall { nr, args, .. } => {
+                        return vec![Line::raw(format!("syscall {nr} {args:#x?}"))];
+                    }
+                    _ => self.event_calls[self.event],
+                }
+            }
+            Tab::CallTree => self.rows.get(self.row).copied(),
+        };
+        let Some(call) = call.map(|call| &self.calls[call]) else {
+            return Vec::new();
+        };
+
+        let mut lines = vec![Line::from(vec![
+            Span::styled(self.trace.function_name(call.function), Modifier::BOLD),
+            Span::raw(format!("  tid {}", call.tid)),
+        ])];
+        lines.push(Line::raw(format!(
+            "called at {}, returned {}",
+            format_time(call.start),
+            match call.ret {
+                Some(ret) => format!("{ret:#x} ({}) after {}", ret as i64, {
+                    call.duration().map(format_duration).unwrap_or_default()
+                }),
+                None if call.end.is_some() => "never, it's unwound".into(),
+                None => "never, the recording ended".into(),
+            }
+        )));
+        if let Some(total) = call.duration() {
+            let children: Duration = call
+                .children
+                .iter()
+                .filter_map(|&child| self.calls[child].duration())
+                .sum();
+            lines.push(Line::raw(format!(
+                "self time {}, {} callees",
+                format_duration(total.saturating_sub(children)),
+                call.children.len()
+            )));
+        }
+        for (i, arg) in call.args.iter().enumerate() {
+            lines.push(Line::raw(format!("arg{i} = {arg:#x} ({})", *arg as i64)));
+        }
+
+        lines
+    }
+}
+
+impl Widget for &TraceViewer {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(DETAILS_HEIGHT),
+                Constraint::Length(1),
+            ])
+            .split(area);
+
+        let (title, selected, len) = match self.tab {
+            Tab::Timeline => ("Timeline", self.event, self.trace.events.len()),
+            Tab::CallTree => ("Call tree", self.row, self.rows.len()),
+        };
+        let block = Block::bordered()
+            .title(format!("{title} ({}/{len})", (selected + 1).min(len)))
+            .title_alignment(Alignment::Center)
+            .title_bottom("[Tab] switch [Enter] expand/show [%] enter/exit [t] timeline [/] search")
+            .border_type(BorderType::Rounded);
+        let height = block.inner(rows[0]).height as usize;
+        self.page.set(height);
+
+        // keep the selection in the middle, the traces are too long to render all the rows
+        let start = selected
+            .saturating_sub(height / 2)
+            .min(len.saturating_sub(height));
+        let lines: Vec<Line> = (start..len.min(start + height))
+            .map(|i| {
+                let line = match self.tab {
+                    Tab::Timeline => self.event_line(i, &self.trace.events[i]),
+                    Tab::CallTree => self.call_line(self.rows[i]),
+                };
+                if i == selected {
+                    line.style(
+                        Style::default()
+                            .fg(Color::Black)
+                            .bg(Color::Cyan)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    line
+                }
+            })
+            .collect();
+        Paragraph::new(lines)
+            .block(block)
+            .fg(Color::White)
+            .bg(Color::Black)
+            .render(rows[0], buf);
+
+        Paragraph::new(self.details())
+            .block(
+                Block::bordered()
+                    .title("Details")
+                    .title_alignment(Alignment::Center)
+                    .border_type(BorderType::Rounded),
+            )
+            .fg(Color::White)
+            .bg(Color::Black)
+            .render(rows[1], buf);
+
+        let status = match &self.input {
+            Some(input) => Line::from(vec![Span::styled("/", Color::Cyan), Span::raw(input)]),
+            None => Line::from(vec![
+                Span::styled(format!(" {} ", self.path.display()), Color::Cyan),
+                Span::raw(format!(
+                    " pid {} · {} · {} functions · {} calls",
+                    self.trace.header.pid,
+                    self.trace.header.command,
+                    self.trace.functions.len(),
+                    self.calls.len()
+                )),
+            ]),
+        };
+        Paragraph::new(status).bg(Color::Black).render(rows[2], buf);
+    }
+}
+
+/// A timestamp since the start of the recording, e.g. `1.234567s`.
+fn format_time(time: Duration) -> String {
+    format!("{}.{:06}s", time.as_secs(), time.subsec_micros())
+}
+
+/// A duration in the most readable unit, e.g. `12.3µs`.
+pub fn format_duration(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    match nanos {
+        0..1_000 => format!("{nanos}ns"),
+        1_000..1_000_000 => format!("{:.1}µs", nanos as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1}ms", nanos as f64 / 1e6),
+        _ => format!("{:.2}s", nanos as f64 / 1e9),
+    }
+}
+
+/// `xxd`-like lines of the bytes that are read from `addr`.
+fn hex_dump(addr: u64, bytes: &[u8]) -> Vec<Line<'static>> {
+    bytes
+        .chunks(DUMP_WIDTH)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut line = format!("{:#010x}:", addr + (i * DUMP_WIDTH) as u64);
+            for byte in chunk {
+                let _ = write!(line, " {byte:02x}");
+            }
+            line.push_str(&"   ".repeat(DUMP_WIDTH - chunk.len()));
+            line.push_str("  ");
+            line.extend(chunk.iter().map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            }));
+            Line::raw(line)
+        })
+        .collect()
+}
@@ -2,11 +2,21 @@ use ratatui::{
     buffer::Buffer,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
-    text::Text,
+    text::{Line, Span},
     widgets::{Block, BorderType, Clear, List, ListItem, Paragraph, StatefulWidget, Widget},
 };
 
-use crate::app::{App, Mode};
+use crate::{
+    app::{App, Mode},
+    console::ConsoleLineKind,
+    debugger_ctx::TraceeState,
+    launch::OutputStream,
+    notification::Severity,
+    registers, wasm_abi,
+};
+
+/// Fits the `xmm` registers in hexadecimal with their roles.
+const REGISTERS_WIDTH: u16 = 52;
 
 impl Widget for &App {
     /// Renders the user interface widgets.
@@ -16,6 +26,18 @@ impl Widget for &App {
     // - https://docs.rs/ratatui/latest/ratatui/widgets/index.html
     // - https://github.com/ratatui/ratatui/tree/master/examples
     fn render(self, area: Rect, buf: &mut Buffer) {
+        // 0) Reserve the bottom line for the status bar
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(1)])
+            .split(area);
+
+        if self.mode == Mode::Console {
+            self.render_prompt(rows[1], buf);
+        } else {
+            self.render_status_bar(rows[1], buf);
+        }
+
         // 1) Split the screen into Left + Right
         let cols = Layout::default()
             .direction(Direction::Horizontal)
@@ -23,10 +45,35 @@ impl Widget for &App {
                 Constraint::Percentage(30), // left column
                 Constraint::Percentage(70), // right big pane
             ])
-            .split(area);
+            .split(rows[0]);
 
         let left = cols[0];
-        let right = cols[1];
+
+        // Split the right column into the code + the console + the program output and the
+        // message log side by side
+        let right_rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Percentage(50),
+                Constraint::Percentage(30),
+                Constraint::Percentage(20),
+            ])
+            .split(cols[1]);
+        // the code and the registers side by side
+        let top = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Min(0), Constraint::Length(REGISTERS_WIDTH)])
+            .split(right_rows[0]);
+        let right = top[0];
+        self.render_registers(top[1], buf);
+        let bottom = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(right_rows[2]);
+
+        self.render_console(right_rows[1], buf);
+        self.render_program_output(bottom[0], buf);
+        self.render_message_log(bottom[1], buf);
 
         // 2) Split the left column into Top + Bottom
         let left_rows = Layout::default()
@@ -39,7 +86,6 @@ impl Widget for &App {
 
         // --- Left / Top pane ---
         let left_top_block = Block::bordered()
-            .title("Left / Top")
             .title_alignment(Alignment::Center)
             .border_type(BorderType::Rounded);
 
@@ -69,11 +115,39 @@ impl Widget for &App {
             // optional marker shown beside selected item
             .highlight_symbol("➤ ");
 
-        if let Some(mapping) = &self.debugger_ctx.function_mapping {
-            let functions: Vec<ListItem> = mapping
-                .into_iter()
-                .map(|(k, v)| ListItem::new(format!("{k}: {}", v.symbol)))
+        if let Some(mapping) = &self.function_mapping {
+            let functions: Vec<ListItem> = self
+                .function_view
+                .iter()
+                .filter_map(|id| mapping.function(*id))
+                .map(|meta| {
+                    // the hits of the breakpoints in the function
+                    let hits = self
+                        .breakpoints
+                        .iter()
+                        .filter(|bp| bp.addr.is_some_and(|addr| meta.range().contains(&addr)))
+                        .map(|bp| bp.hits)
+                        .reduce(|a, b| a + b);
+                    match hits {
+                        Some(hits) => {
+                            ListItem::new(format!("{:x} {} ● {hits}", meta.addr, meta.symbol))
+                        }
+                        None => ListItem::new(format!("{:x} {}", meta.addr, meta.symbol)),
+                    }
+                })
                 .collect();
+
+            let mut left_top_block = left_top_block
+                .title(format!(
+                    "Functions ({}/{})",
+                    self.function_view.len(),
+                    mapping.len()
+                ))
+                .title_bottom(format!("[s]ort: {} [g]o to", self.function_sort.title()));
+            if self.mode == Mode::FunctionSearch || !self.function_query.is_empty() {
+                left_top_block = left_top_block.title_bottom(format!("/{}", self.function_query));
+            }
+
             let function_list = List::new(functions)
                 .block(left_top_block)
                 // style for unselected items
@@ -94,29 +168,18 @@ impl Widget for &App {
                 &mut self.mapping_list_state.clone(),
             );
         } else {
-            let left_top = Paragraph::new(format!("Counter",))
-                .block(left_top_block)
+            let left_top = Paragraph::new("Counter")
+                .block(left_top_block.title("Left / Top"))
                 .fg(Color::Yellow)
                 .bg(Color::Black)
                 .centered();
             left_top.render(left_rows[0], buf);
         }
 
-        // --- Right / Big pane ---
-        let right_block = Block::bordered()
-            .title("Right / Big")
-            .title_alignment(Alignment::Center)
-            .border_type(BorderType::Rounded);
-
-        let right_pane = Paragraph::new(Text::styled(&self.disas_str, Style::default()))
-            .block(right_block)
-            .fg(Color::Cyan)
-            .bg(Color::Black);
-
         StatefulWidget::render(list, left_rows[1], buf, &mut self.list_state.clone());
 
         // list.render(left_rows[1], buf);
-        right_pane.render(right, buf);
+        self.render_disassembly(right, buf);
 
         // Popup overlay
         if self.mode == Mode::StartProcessPopup {
@@ -131,7 +194,7 @@ impl Widget for &App {
                 .border_type(BorderType::Rounded);
 
             let text = format!(
-                "Enter PID (or target):\n\n{}\n\n[Enter]=confirm  [Esc]=cancel",
+                "Command line: [cd DIR &&] [VAR=value ...] program [args ...]\n\n{}\n\n[Enter]=confirm  [Esc]=cancel",
                 self.attach_input
             );
 
@@ -147,6 +210,370 @@ impl Widget for &App {
     }
 }
 
+impl App {
+    /// pid, tracee state, the last stop reason and the last notification in a single line.
+    fn render_status_bar(&self, area: Rect, buf: &mut Buffer) {
+        let pid = self
+            .tracee_pid
+            .map(|pid| pid.to_string())
+            .unwrap_or_else(|| "-".into());
+
+        let state_color = match self.tracee_state {
+            TraceeState::NotStarted => Color::Gray,
+            TraceeState::Running => Color::Green,
+            TraceeState::Stopped(_) => Color::Yellow,
+            TraceeState::Exited(_) => Color::Red,
+        };
+
+        let mut spans = vec![
+            Span::raw(format!(" pid: {pid} ")),
+            Span::raw("| "),
+            Span::styled(
+                match &self.tracee_state {
+                    // the reason is shown separately
+                    TraceeState::Stopped(_) => "stopped".to_string(),
+                    state => state.to_string(),
+                },
+                Style::default()
+                    .fg(state_color)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        ];
+
+        if let Some(reason) = &self.last_stop {
+            spans.push(Span::raw(format!(" | last stop: {reason}")));
+        }
+
+        if let Some(notification) = self.notifications.last() {
+            spans.push(Span::raw(" | "));
+            spans.push(Span::styled(
+                notification
+                    .message
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                severity_style(notification.severity),
+            ));
+        }
+
+        Paragraph::new(Line::from(spans))
+            .style(Style::default().fg(Color::White).bg(Color::DarkGray))
+            .render(area, buf);
+    }
+
+    /// The `:` prompt, in place of the status bar.
+    fn render_prompt(&self, area: Rect, buf: &mut Buffer) {
+        Paragraph::new(Line::from(vec![
+            Span::raw(format!(":{}", self.console_input)),
+            // the cursor
+            Span::styled(" ", Style::default().add_modifier(Modifier::REVERSED)),
+        ]))
+        .style(Style::default().fg(Color::White).bg(Color::Black))
+        .render(area, buf);
+    }
+
+    /// The instructions of the selected function, with the breakpoints and the instruction
+    /// that the tracee is stopped at in the gutter. That instruction is kept in view.
+    fn render_disassembly(&self, area: Rect, buf: &mut Buffer) {
+        let meta = self
+            .selected_function()
+            .and_then(|id| self.function_mapping.as_ref()?.function(id));
+        let block = Block::bordered()
+            .title(meta.map_or("Disassembly".into(), |meta| {
+                format!("Disassembly · {}", meta.symbol)
+            }))
+            .title_alignment(Alignment::Center)
+            .border_type(BorderType::Rounded);
+
+        let start = self.disassembly.first().map_or(0, |insn| insn.addr);
+        let lines: Vec<Line> = self
+            .disassembly
+            .iter()
+            .map(|insn| {
+                let enabled = self
+                    .breakpoints
+                    .iter()
+                    .filter(|bp| bp.addr == Some(insn.addr))
+                    .map(|bp| bp.enabled)
+                    .reduce(|a, b| a || b);
+                let current = self.pc == Some(insn.addr);
+                let mnemonic_style = if insn.is_branch() {
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(Color::Cyan)
+                };
+
+                let mut spans = vec![
+                    match enabled {
+                        Some(true) => Span::styled("●", Color::Red),
+                        Some(false) => Span::styled("○", Color::DarkGray),
+                        None => Span::raw(" "),
+                    },
+                    if current {
+                        Span::styled("➤ ", Color::Yellow)
+                    } else {
+                        Span::raw("  ")
+                    },
+                    Span::styled(
+                        format!(
+                            "{:#x} {:<8} ",
+                            insn.addr,
+                            format!("<+{}>", insn.addr - start)
+                        ),
+                        Color::DarkGray,
+                    ),
+                    Span::styled(format!("{:<7} ", insn.mnemonic), mnemonic_style),
+                ];
+                spans.extend(operand_spans(&insn.operands));
+                if let Some(comment) = &insn.comment {
+                    spans.push(Span::styled(format!("  ; {comment}"), Color::DarkGray));
+                }
+
+                let line = Line::from(spans);
+                if current { line.bg(Color::Blue) } else { line }
+            })
+            .collect();
+
+        let height = block.inner(area).height as usize;
+        let scroll = self
+            .pc
+            .and_then(|pc| self.disassembly.iter().position(|insn| insn.addr == pc))
+            .filter(|&i| i >= height)
+            .map_or(0, |i| i - height / 2);
+
+        Paragraph::new(lines)
+            .block(block)
+            .scroll((scroll as u16, 0))
+            .fg(Color::Cyan)
+            .bg(Color::Black)
+            .render(area, buf);
+    }
+
+    /// The registers at the current stop with what they hold for wasm, highlighting the ones
+    /// that changed since the previous stop. The selected one is kept in view.
+    fn render_registers(&self, area: Rect, buf: &mut Buffer) {
+        let mut block = Block::bordered()
+            .title("Registers")
+            .title_alignment(Alignment::Center)
+            .border_type(BorderType::Rounded);
+        let selecting = matches!(self.mode, Mode::Registers | Mode::RegisterEdit);
+        block = match self.mode {
+            Mode::Registers => block.title_bottom("[Enter] edit [Esc] back"),
+            Mode::RegisterEdit => block.title_bottom("[Enter] write [Esc] cancel"),
+            _ if self.registers.is_some() => block.title_bottom("[r] select"),
+            _ => block,
+        };
+        let Some(regs) = &self.registers else {
+            block.bg(Color::Black).render(area, buf);
+            return;
+        };
+
+        let value_style = |changed: bool| {
+            if changed {
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::White)
+            }
+        };
+        let general = regs.general.iter().map(|&(name, value)| {
+            let changed = self
+                .previous_registers
+                .as_ref()
+                .and_then(|previous| previous.get(name))
+                .is_some_and(|previous| previous != value);
+            let mut note = wasm_abi::register_role(name).unwrap_or_default();
+            if name == "eflags" {
+                note = registers::format_flags(value);
+            }
+            (name.to_string(), format!("{value:#018x}"), changed, note)
+        });
+        let xmm = regs.xmm.iter().enumerate().map(|(i, &value)| {
+            let name = format!("xmm{i}");
+            let changed = self
+                .previous_registers
+                .as_ref()
+                .and_then(|previous| previous.xmm.get(i))
+                .is_some_and(|&previous| previous != value);
+            let note = wasm_abi::register_role(&name).unwrap_or_default();
+            (name, format!("{value:#034x}"), changed, note)
+        });
+
+        let lines: Vec<Line> = general
+            .chain(xmm)
+            .enumerate()
+            .map(|(i, (name, value, changed, note))| {
+                let selected = selecting && i == self.register_index;
+                let value = if selected && self.mode == Mode::RegisterEdit {
+                    Span::styled(
+                        format!("{} ", self.register_input),
+                        Style::default().add_modifier(Modifier::UNDERLINED),
+                    )
+                } else {
+                    Span::styled(value, value_style(changed))
+                };
+                let line = Line::from(vec![
+                    Span::styled(format!("{name:<8}"), Color::Green),
+                    value,
+                    Span::styled(format!(" {note}"), Color::DarkGray),
+                ]);
+                if selected {
+                    line.add_modifier(Modifier::REVERSED)
+                } else {
+                    line
+                }
+            })
+            .collect();
+
+        let height = block.inner(area).height as usize;
+        let scroll = if selecting {
+            (self.register_index + 1).saturating_sub(height)
+        } else {
+            0
+        };
+
+        Paragraph::new(lines)
+            .block(block)
+            .scroll((scroll as u16, 0))
+            .bg(Color::Black)
+            .render(area, buf);
+    }
+
+    fn render_console(&self, area: Rect, buf: &mut Buffer) {
+        let mut block = Block::bordered()
+            .title("Console")
+            .title_alignment(Alignment::Center)
+            .border_type(BorderType::Rounded);
+        if self.console.scroll() > 0 {
+            block =
+                block.title_bottom(format!("scrolled up {} [PgUp/PgDn]", self.console.scroll()));
+        } else if self.mode != Mode::Console {
+            block = block.title_bottom("[:] command");
+        }
+
+        let height = block.inner(area).height as usize;
+        let lines: Vec<Line> = self
+            .console
+            .visible(height)
+            .map(|line| match line.kind {
+                ConsoleLineKind::Input => Line::styled(line.text.as_str(), Color::Cyan),
+                ConsoleLineKind::Output => Line::raw(line.text.as_str()),
+                ConsoleLineKind::Error => Line::styled(line.text.as_str(), Color::Red),
+            })
+            .collect();
+
+        Paragraph::new(lines)
+            .block(block)
+            .fg(Color::White)
+            .bg(Color::Black)
+            .render(area, buf);
+    }
+
+    fn render_program_output(&self, area: Rect, buf: &mut Buffer) {
+        let mut block = Block::bordered()
+            .title("Program output")
+            .title_alignment(Alignment::Center)
+            .border_type(BorderType::Rounded);
+        if self.program_output.scroll() > 0 {
+            block = block.title_bottom(format!(
+                "scrolled up {} [Shift+PgUp/PgDn]",
+                self.program_output.scroll()
+            ));
+        }
+
+        let height = block.inner(area).height as usize;
+        let lines: Vec<Line> = self
+            .program_output
+            .visible(height)
+            .map(|line| match line.stream {
+                OutputStream::Stdout => Line::raw(line.text.as_str()),
+                OutputStream::Stderr => Line::styled(line.text.as_str(), Color::Red),
+            })
+            .collect();
+
+        Paragraph::new(lines)
+            .block(block)
+            .fg(Color::White)
+            .bg(Color::Black)
+            .render(area, buf);
+    }
+
+    fn render_message_log(&self, area: Rect, buf: &mut Buffer) {
+        let mut block = Block::bordered()
+            .title(format!("Messages ({})", self.notifications.len()))
+            .title_alignment(Alignment::Center)
+            .border_type(BorderType::Rounded);
+        if self.notifications.scroll() > 0 {
+            block = block.title_bottom(format!(
+                "scrolled up {} [PgUp/PgDn]",
+                self.notifications.scroll()
+            ));
+        }
+
+        let height = block.inner(area).height as usize;
+        let lines: Vec<Line> = self
+            .notifications
+            .visible(height)
+            .map(|notification| {
+                Line::from(vec![
+                    Span::styled(
+                        format!("{} ", notification.timestamp()),
+                        Style::default().fg(Color::DarkGray),
+                    ),
+                    Span::styled(
+                        format!("{:<5} ", notification.severity),
+                        severity_style(notification.severity),
+                    ),
+                    Span::raw(notification.message.replace('\n', " ")),
+                ])
+            })
+            .collect();
+
+        Paragraph::new(lines)
+            .block(block)
+            .fg(Color::White)
+            .bg(Color::Black)
+            .render(area, buf);
+    }
+}
+
+fn severity_style(severity: Severity) -> Style {
+    match severity {
+        Severity::Info => Style::default().fg(Color::Cyan),
+        Severity::Warning => Style::default().fg(Color::Yellow),
+        Severity::Error => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+    }
+}
+
+/// Colors the registers and the immediates in the operands of an instruction in the Intel
+/// syntax, e.g. `qword ptr [rdi + 8], 0x10`.
+fn operand_spans(operands: &str) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut rest = operands;
+    while let Some(first) = rest.chars().next() {
+        let word = first.is_ascii_alphanumeric();
+        let len = rest
+            .find(|c: char| c.is_ascii_alphanumeric() != word)
+            .unwrap_or(rest.len());
+        let (token, tail) = rest.split_at(len);
+        let color = match token {
+            _ if !word => Color::White,
+            _ if first.is_ascii_digit() => Color::LightMagenta,
+            "byte" | "word" | "dword" | "qword" | "tbyte" | "xmmword" | "ymmword" | "zmmword"
+            | "ptr" => Color::White,
+            _ => Color::Green,
+        };
+        spans.push(Span::styled(token.to_string(), color));
+        rest = tail;
+    }
+
+    spans
+}
+
 // Helper: centered rectangle by percentage
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
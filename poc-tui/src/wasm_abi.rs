@@ -0,0 +1,171 @@
+use std::fs;
+
+use color_eyre::eyre::{self, WrapErr, eyre};
+use libc::user_regs_struct;
+use nix::unistd::Pid;
+
+use crate::{debugger_ctx::WASM_MEMORY_IMAGE_IDENT, registers};
+
+/// The register that holds the `VMContext` of the callee.
+///
+/// wasmtime passes two contexts before the wasm parameters, the callee's and the caller's:
+/// https://github.com/bytecodealliance/wasmtime/blob/v41.0.3/crates/cranelift/src/func_environ.rs
+pub const VMCTX: &str = "rdi";
+
+/// The register that holds the `VMContext` of the caller.
+pub const CALLER_VMCTX: &str = "rsi";
+
+/// The registers of the integer wasm parameters in the System V calling convention, after the
+/// two contexts. The rest of them are on the stack.
+pub const ARGUMENT_REGISTERS: &[&str] = &["rdx", "rcx", "r8", "r9"];
+
+/// The registers of the floating point wasm parameters, in the order of those parameters.
+pub const FLOAT_ARGUMENT_REGISTERS: &[&str] = &[
+    "xmm0", "xmm1", "xmm2", "xmm3", "xmm4", "xmm5", "xmm6", "xmm7",
+];
+
+/// What the register holds at the entry of a wasm function, e.g. `vmctx` for `rdi` or `arg0`
+/// for `rdx`. The floating point parameters are counted separately, as `farg0` and so on.
+pub fn register_role(name: &str) -> Option<String> {
+    match name {
+        VMCTX => Some("vmctx".into()),
+        CALLER_VMCTX => Some("caller vmctx".into()),
+        _ => ARGUMENT_REGISTERS
+            .iter()
+            .position(|&register| register == name)
+            .map(|index| format!("arg{index}"))
+            .or_else(|| {
+                FLOAT_ARGUMENT_REGISTERS
+                    .iter()
+                    .position(|&register| register == name)
+                    .map(|index| format!("farg{index}"))
+            }),
+    }
+}
+
+/// The wasm argument `index`, i.e. `arg0` is the first parameter of the wasm function.
+///
+/// Cranelift is free to move the arguments around once the function starts, hence this is
+/// only reliable at the entry of the function. The floating point arguments are not
+/// supported.
+pub fn argument(
+    regs: &user_regs_struct,
+    index: usize,
+    read_word: impl Fn(u64) -> eyre::Result<u64>,
+) -> eyre::Result<u64> {
+    if let Some(name) = ARGUMENT_REGISTERS.get(index) {
+        return Ok(registers::read(regs, name).expect("a general purpose register"));
+    }
+
+    // above the return address, every argument takes a slot
+    let slot = index - ARGUMENT_REGISTERS.len();
+    read_word(regs.rsp + 8 + 8 * slot as u64)
+        .wrap_err_with(|| format!("failed to read the argument {index} from the stack"))
+}
+
+/// Finds the base address of the linear memory in the address space of `pid`.
+///
+/// wasmtime reserves the linear memory as a whole and maps the memory image, i.e. the
+/// `memfd` that holds the data segments, into it. The image starts at the page of the first
+/// data segment, and the pages before it are the accessible anonymous memory:
+///
+/// ```text
+///   guard | anonymous | memory image (memfd) | anonymous | PROT_NONE
+///         ^ base
+/// ```
+///
+/// https://github.com/bytecodealliance/wasmtime/blob/v41.0.3/crates/wasmtime/src/runtime/vm/cow.rs
+///
+/// Only the first linear memory that has an image is found.
+pub fn locate_linear_memory(pid: Pid) -> eyre::Result<u64> {
+    let path = format!("/proc/{pid}/maps");
+    let maps = fs::read_to_string(&path).wrap_err_with(|| format!("failed to read `{path}`"))?;
+    let mappings: Vec<Mapping> = maps.lines().filter_map(Mapping::parse).collect();
+
+    let image = mappings
+        .iter()
+        .position(|mapping| {
+            mapping
+                .path
+                .strip_prefix("/memfd:")
+                .is_some_and(|name| name.starts_with(WASM_MEMORY_IMAGE_IDENT))
+        })
+        .ok_or_else(|| eyre!("the memory image of the module is not mapped"))?;
+
+    let mut base = mappings[image].start;
+    for mapping in mappings[..image].iter().rev() {
+        if mapping.end != base || !mapping.path.is_empty() || !mapping.perms.starts_with("rw") {
+            break;
+        }
+        base = mapping.start;
+    }
+
+    Ok(base)
+}
+
+/// Finds the start of the compiled image that contains the code at `addr` in the address
+/// space of `pid`, i.e. the ELF file that wasmtime compiles the module to.
+///
+/// wasmtime maps the image as a whole, then makes its `.text` executable. Hence the image
+/// starts at one of the mappings right before the code, where the ELF header is.
+///
+/// https://github.com/bytecodealliance/wasmtime/blob/v41.0.3/crates/wasmtime/src/runtime/code_memory.rs
+pub fn locate_code_image(
+    pid: Pid,
+    addr: u64,
+    read_word: impl Fn(u64) -> eyre::Result<u64>,
+) -> eyre::Result<u64> {
+    let path = format!("/proc/{pid}/maps");
+    let maps = fs::read_to_string(&path).wrap_err_with(|| format!("failed to read `{path}`"))?;
+    let mappings: Vec<Mapping> = maps.lines().filter_map(Mapping::parse).collect();
+
+    let code = mappings
+        .iter()
+        .position(|mapping| (mapping.start..mapping.end).contains(&addr))
+        .ok_or_else(|| eyre!("{addr:#x} is not mapped"))?;
+
+    let mut end = mappings[code].end;
+    for mapping in mappings[..=code].iter().rev() {
+        if mapping.end != end || mapping.path != mappings[code].path {
+            break;
+        }
+        let magic = read_word(mapping.start)? as u32;
+        if magic.to_le_bytes() == *b"\x7fELF" {
+            return Ok(mapping.start);
+        }
+        end = mapping.start;
+    }
+
+    Err(eyre!("the code at {addr:#x} is not in a compiled image"))
+}
+
+/// A line of `/proc/PID/maps`.
+struct Mapping<'a> {
+    start: u64,
+    end: u64,
+    perms: &'a str,
+    /// Empty for the anonymous mappings
+    path: &'a str,
+}
+
+impl<'a> Mapping<'a> {
+    /// Example: "7f3a1c400000-7f3a1c500000 rw-p 00000000 00:01 1234   /memfd:wasm-memory-image (deleted)"
+    fn parse(line: &'a str) -> Option<Self> {
+        // address, perms, offset, device and inode, then the path which can contain spaces
+        let mut fields = [""; 5];
+        let mut rest = line;
+        for field in &mut fields {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            (*field, rest) = rest.split_at(end);
+        }
+        let (start, end) = fields[0].split_once('-')?;
+
+        Some(Mapping {
+            start: u64::from_str_radix(start, 16).ok()?,
+            end: u64::from_str_radix(end, 16).ok()?,
+            perms: fields[1],
+            path: rest.trim(),
+        })
+    }
+}
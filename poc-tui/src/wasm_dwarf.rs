@@ -0,0 +1,530 @@
+//! The functions of a `.wasm` file, with their names from the `name` section, and their source
+//! locations and the lines of their instructions from the DWARF in the `.debug_*` custom
+//! sections, when the guest toolchain emits them.
+
+use std::{collections::HashMap, fmt, fs, ops::Range, path::Path};
+
+use color_eyre::eyre::{self, WrapErr};
+use gimli::{
+    AttributeValue, EndianSlice, FileEntry, LineProgramHeader, LittleEndian, SectionId, UnitRef,
+};
+use wasmparser::{KnownCustom, Name, Parser, Payload, TypeRef};
+
+use crate::wasm_symbol::WasmSymbol;
+
+type Reader<'a> = EndianSlice<'a, LittleEndian>;
+/// The rows of the line tables by their offset in the code section, `None` after the end of a
+/// sequence
+type Lines = Vec<(u64, Option<SourceLocation>)>;
+
+/// Where a function or an instruction is in the source of the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u64,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// A function defined in the module, the imported ones are left out.
+#[derive(Debug, Clone)]
+pub struct WasmFunction {
+    /// The index in the function index space, which counts the imported functions too
+    pub index: u32,
+    /// From the `name` section
+    pub name: Option<String>,
+    /// From the DWARF subprogram that starts at the body of the function
+    pub source: Option<SourceLocation>,
+}
+
+#[derive(Debug, Default)]
+pub struct WasmModule {
+    /// In the order of the code section
+    pub functions: Vec<WasmFunction>,
+    /// Whether there are DWARF sections, even if none of them described a function
+    pub has_dwarf: bool,
+    lines: Lines,
+    /// The offset of the code section in the file
+    code_start: u64,
+    /// The demangled names of the functions, see [`WasmSymbol::demangled`]
+    by_name: HashMap<String, usize>,
+}
+
+impl WasmModule {
+    pub fn load(path: &Path) -> eyre::Result<Self> {
+        let bytes =
+            fs::read(path).wrap_err_with(|| format!("failed to read `{}`", path.display()))?;
+        Self::parse(&bytes).wrap_err_with(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    pub fn parse(bytes: &[u8]) -> eyre::Result<Self> {
+        let mut imported = 0;
+        let mut code_start = 0;
+        let mut bodies = Vec::new();
+        let mut names = HashMap::new();
+        let mut dwarf_sections = HashMap::new();
+
+        for payload in Parser::new(0).parse_all(bytes) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader.into_imports() {
+                        if let TypeRef::Func(_) | TypeRef::FuncExact(_) = import?.ty {
+                            imported += 1;
+                        }
+                    }
+                }
+                Payload::CodeSectionStart { range, .. } => code_start = range.start,
+                Payload::CodeSectionEntry(body) => bodies.push(body.range()),
+                Payload::CustomSection(reader) => match reader.as_known() {
+                    KnownCustom::Name(section) => {
+                        for name in section {
+                            if let Name::Function(map) = name? {
+                                for naming in map {
+                                    let naming = naming?;
+                                    names.insert(naming.index, naming.name.to_string());
+                                }
+                            }
+                        }
+                    }
+                    _ if reader.name().starts_with(".debug_") => {
+                        dwarf_sections.insert(reader.name(), reader.data());
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+
+        // the addresses in the DWARF are offsets in the code section
+        let bodies: Vec<Range<u64>> = bodies
+            .into_iter()
+            .map(|body| (body.start - code_start) as u64..(body.end - code_start) as u64)
+            .collect();
+        let (sources, lines) = if dwarf_sections.is_empty() {
+            (HashMap::new(), Vec::new())
+        } else {
+            read_dwarf(&dwarf_sections, &bodies).wrap_err("failed to read the DWARF")?
+        };
+
+        let mut module = WasmModule {
+            has_dwarf: !dwarf_sections.is_empty(),
+            lines,
+            code_start: code_start as u64,
+            ..WasmModule::default()
+        };
+        for i in 0..bodies.len() {
+            let index = imported + i as u32;
+            let name = names.remove(&index);
+            if let Some(name) = &name {
+                module.by_name.insert(WasmSymbol::parse(name).demangled, i);
+            }
+            module.functions.push(WasmFunction {
+                index,
+                name,
+                source: sources.get(&i).cloned(),
+            });
+        }
+
+        Ok(module)
+    }
+
+    /// The function that a symbol of the perf map refers to, either by its index or by its name.
+    pub fn function_of(&self, symbol: &WasmSymbol) -> Option<&WasmFunction> {
+        match symbol.func_index {
+            Some(index) => self.functions.iter().find(|f| f.index == index),
+            None => self
+                .by_name
+                .get(&symbol.demangled)
+                .map(|&i| &self.functions[i]),
+        }
+    }
+
+    /// The source line of the instruction at `offset` in the file, from the line tables.
+    pub fn line_of(&self, offset: u32) -> Option<&SourceLocation> {
+        let offset = (offset as u64).checked_sub(self.code_start)?;
+        let i = self.lines.partition_point(|&(addr, _)| addr <= offset);
+        self.lines[i.checked_sub(1)?].1.as_ref()
+    }
+}
+
+/// The source locations of the subprograms that start at a function body, by the position of
+/// the body in the code section, and the rows of the line tables.
+fn read_dwarf(
+    sections: &HashMap<&str, &[u8]>,
+    bodies: &[Range<u64>],
+) -> eyre::Result<(HashMap<usize, SourceLocation>, Lines)> {
+    let dwarf = gimli::Dwarf::load(|id: SectionId| -> gimli::Result<Reader<'_>> {
+        let data = sections.get(id.name()).copied().unwrap_or_default();
+        Ok(EndianSlice::new(data, LittleEndian))
+    })?;
+
+    let mut sources = HashMap::new();
+    let mut lines = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let unit = unit.unit_ref(&dwarf);
+
+        if let Some(program) = unit.line_program.clone() {
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let source = match (row.end_sequence(), row.file(header), row.line()) {
+                    (false, Some(file), Some(line)) => Some(SourceLocation {
+                        file: file_path(unit, header, file)?,
+                        line: line.get(),
+                    }),
+                    _ => None,
+                };
+                lines.push((row.address(), source));
+            }
+        }
+
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() != gimli::DW_TAG_subprogram {
+                continue;
+            }
+            let Some(low_pc) = entry.attr_value(gimli::DW_AT_low_pc)? else {
+                continue;
+            };
+            let Some(low_pc) = unit.attr_address(low_pc)? else {
+                continue;
+            };
+            // the functions that the linker drops are left at 0 or at -1
+            let Some(body) = bodies.iter().position(|body| body.contains(&low_pc)) else {
+                continue;
+            };
+            if let Some(source) = source_location(unit, entry)? {
+                sources.entry(body).or_insert(source);
+            }
+        }
+    }
+    // the end of a sequence goes before the start of the next one at the same address
+    lines.sort_by_key(|(addr, source)| (*addr, source.is_some()));
+
+    Ok((sources, lines))
+}
+
+/// The declaration of a subprogram, which can be on the abstract subprogram that it's a
+/// concrete instance of.
+fn source_location(
+    unit: UnitRef<'_, Reader<'_>>,
+    entry: &gimli::DebuggingInformationEntry<'_, '_, Reader<'_>>,
+) -> eyre::Result<Option<SourceLocation>> {
+    let file = entry.attr_value(gimli::DW_AT_decl_file)?;
+    let line = entry.attr_value(gimli::DW_AT_decl_line)?;
+    let (file, line) = match (file, line) {
+        (Some(AttributeValue::FileIndex(file)), Some(line)) => (file, line.udata_value()),
+        _ => {
+            for origin in [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+                if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(origin)? {
+                    return source_location(unit, &unit.entry(offset)?);
+                }
+            }
+            return Ok(None);
+        }
+    };
+    let (Some(line), Some(program)) = (line, &unit.line_program) else {
+        return Ok(None);
+    };
+    let header = program.header();
+    let Some(file) = header.file(file) else {
+        return Ok(None);
+    };
+
+    Ok(Some(SourceLocation {
+        file: file_path(unit, header, file)?,
+        line,
+    }))
+}
+
+/// The path of a file of the line table, from the compilation directory.
+fn file_path<'a>(
+    unit: UnitRef<'_, Reader<'a>>,
+    header: &LineProgramHeader<Reader<'a>>,
+    file: &FileEntry<Reader<'a>>,
+) -> eyre::Result<String> {
+    let mut path = String::new();
+    if let Some(dir) = &unit.comp_dir {
+        path.push_str(&dir.to_string_lossy());
+    }
+    if let Some(dir) = file.directory(header) {
+        join(&mut path, &unit.attr_string(dir)?.to_string_lossy());
+    }
+    join(
+        &mut path,
+        &unit.attr_string(file.path_name())?.to_string_lossy(),
+    );
+
+    Ok(path)
+}
+
+/// Appends `component` to `path`, or replaces it when `component` is absolute.
+fn join(path: &mut String, component: &str) {
+    if component.starts_with('/') || path.is_empty() {
+        *path = component.into();
+    } else if !component.is_empty() {
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(component);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn uleb(bytes: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    /// A string or a name of wasm, prefixed with its length.
+    fn name(bytes: &mut Vec<u8>, name: &str) {
+        uleb(bytes, name.len() as u64);
+        bytes.extend_from_slice(name.as_bytes());
+    }
+
+    fn section(module: &mut Vec<u8>, id: u8, content: &[u8]) {
+        module.push(id);
+        uleb(module, content.len() as u64);
+        module.extend_from_slice(content);
+    }
+
+    fn custom_section(module: &mut Vec<u8>, section_name: &str, content: &[u8]) {
+        let mut custom = Vec::new();
+        name(&mut custom, section_name);
+        custom.extend_from_slice(content);
+        section(module, 0, &custom);
+    }
+
+    /// Prefixes a DWARF unit with its 32-bit length.
+    fn unit(content: Vec<u8>) -> Vec<u8> {
+        let mut unit = (content.len() as u32).to_le_bytes().to_vec();
+        unit.extend(content);
+        unit
+    }
+
+    /// A module that imports a function and defines two:
+    ///
+    /// - 1, `wasm_binary::main`, declared at `/work/src/main.rs:3`, whose instructions are on
+    ///   the lines 3 to 5
+    /// - 2, `leaf`, declared at `/rustc/lib/leaf.rs:10` by its abstract instance, whose
+    ///   instructions are on the lines 11 and 12
+    ///
+    /// The bodies are at 2..7 and at 8..11 in the code section, whose offset in the module is
+    /// returned too. The DWARF describes a third subprogram, which the linker dropped.
+    pub(crate) fn module_with_dwarf() -> (Vec<u8>, u32) {
+        let mut module = b"\0asm\x01\0\0\0".to_vec();
+        // `() -> ()`
+        section(&mut module, 1, &[1, 0x60, 0, 0]);
+        let mut imports = vec![1];
+        name(&mut imports, "env");
+        name(&mut imports, "log");
+        imports.extend_from_slice(&[0, 0]);
+        section(&mut module, 2, &imports);
+        section(&mut module, 3, &[2, 0, 0]);
+        // no locals, `nop`s and `end`
+        let code = [2, 5, 0, 1, 1, 1, 0x0b, 3, 0, 1, 0x0b];
+        module.push(10);
+        uleb(&mut module, code.len() as u64);
+        let code_start = module.len() as u32;
+        module.extend_from_slice(&code);
+
+        let mut names = vec![3];
+        for (index, function) in ["log", "_ZN11wasm_binary4main17h0123456789abcdefE", "leaf"]
+            .into_iter()
+            .enumerate()
+        {
+            uleb(&mut names, index as u64);
+            name(&mut names, function);
+        }
+        let mut section_names = vec![1];
+        uleb(&mut section_names, names.len() as u64);
+        section_names.extend(names);
+        custom_section(&mut module, "name", &section_names);
+
+        let abbrev = [
+            // the compile unit: name, comp_dir, stmt_list
+            1, 0x11, 1, 0x03, 0x08, 0x1b, 0x08, 0x10, 0x17, 0, 0, //
+            // a subprogram: low_pc, high_pc, decl_file, decl_line
+            2, 0x2e, 0, 0x11, 0x01, 0x12, 0x06, 0x3a, 0x0b, 0x3b, 0x0b, 0, 0, //
+            // an abstract subprogram: decl_file, decl_line
+            3, 0x2e, 0, 0x3a, 0x0b, 0x3b, 0x0b, 0, 0, //
+            // a concrete subprogram: low_pc, high_pc, abstract_origin
+            4, 0x2e, 0, 0x11, 0x01, 0x12, 0x06, 0x31, 0x13, 0, 0, //
+            0,
+        ];
+        custom_section(&mut module, ".debug_abbrev", &abbrev);
+
+        // version, abbrev offset, address size
+        let mut info = vec![4, 0, 0, 0, 0, 0, 4];
+        info.push(1);
+        info.extend_from_slice(b"lib.rs\0/work\0");
+        info.extend_from_slice(&0u32.to_le_bytes());
+        info.push(2);
+        info.extend_from_slice(&2u32.to_le_bytes());
+        info.extend_from_slice(&5u32.to_le_bytes());
+        info.extend_from_slice(&[1, 3]);
+        // from the start of the unit, which has its length first
+        let abstract_leaf = info.len() as u32 + 4;
+        info.extend_from_slice(&[3, 2, 10]);
+        info.push(4);
+        info.extend_from_slice(&8u32.to_le_bytes());
+        info.extend_from_slice(&3u32.to_le_bytes());
+        info.extend_from_slice(&abstract_leaf.to_le_bytes());
+        info.push(2);
+        info.extend_from_slice(&u32::MAX.to_le_bytes());
+        info.extend_from_slice(&0u32.to_le_bytes());
+        info.extend_from_slice(&[1, 99]);
+        info.push(0);
+        custom_section(&mut module, ".debug_info", &unit(info));
+
+        // min_inst_length, max_ops_per_inst, default_is_stmt, line_base, line_range,
+        // opcode_base and the lengths of the standard opcodes
+        let mut header = vec![
+            1, 1, 1, -5i8 as u8, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1,
+        ];
+        header.extend_from_slice(b"src\0/rustc/lib\0\0");
+        header.extend_from_slice(b"main.rs\0\x01\0\0leaf.rs\0\x02\0\0\0");
+        let set_address = |program: &mut Vec<u8>, addr: u32| {
+            program.extend_from_slice(&[0, 5, 2]);
+            program.extend_from_slice(&addr.to_le_bytes());
+        };
+        // advance_pc, advance_line, copy, set_file and end_sequence
+        let mut program = Vec::new();
+        set_address(&mut program, 2);
+        program.extend_from_slice(&[3, 2, 1]);
+        program.extend_from_slice(&[2, 1, 3, 1, 1]);
+        program.extend_from_slice(&[2, 2, 3, 1, 1]);
+        program.extend_from_slice(&[2, 2, 0, 1, 1]);
+        set_address(&mut program, 8);
+        program.extend_from_slice(&[4, 2, 3, 10, 1]);
+        program.extend_from_slice(&[2, 1, 3, 1, 1]);
+        program.extend_from_slice(&[2, 2, 0, 1, 1]);
+        let mut line = vec![4, 0];
+        line.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        line.extend(header);
+        line.extend(program);
+        custom_section(&mut module, ".debug_line", &unit(line));
+
+        (module, code_start)
+    }
+
+    fn location(file: &str, line: u64) -> Option<SourceLocation> {
+        Some(SourceLocation {
+            file: file.into(),
+            line,
+        })
+    }
+
+    #[test]
+    fn finds_the_declarations_of_the_functions() {
+        let module = WasmModule::parse(&module_with_dwarf().0).unwrap();
+
+        assert!(module.has_dwarf);
+        let functions: Vec<_> = module
+            .functions
+            .iter()
+            .map(|f| (f.index, f.name.as_deref(), f.source.clone()))
+            .collect();
+        assert_eq!(
+            functions,
+            [
+                (
+                    1,
+                    Some("_ZN11wasm_binary4main17h0123456789abcdefE"),
+                    location("/work/src/main.rs", 3)
+                ),
+                (2, Some("leaf"), location("/rustc/lib/leaf.rs", 10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_the_functions_of_the_symbols() {
+        let module = WasmModule::parse(&module_with_dwarf().0).unwrap();
+        let index_of = |symbol: &str| {
+            module
+                .function_of(&WasmSymbol::parse(symbol))
+                .map(|f| f.index)
+        };
+
+        assert_eq!(index_of("wasm[0]::function[2]"), Some(2));
+        assert_eq!(index_of("wasm[0]::function[1]::whatever"), Some(1));
+        assert_eq!(index_of("wasm_binary::main"), Some(1));
+        assert_eq!(index_of("leaf"), Some(2));
+        // the imported one
+        assert_eq!(index_of("wasm[0]::function[0]"), None);
+        assert_eq!(index_of("log"), None);
+    }
+
+    #[test]
+    fn maps_the_instructions_to_their_lines() {
+        let (bytes, code_start) = module_with_dwarf();
+        let module = WasmModule::parse(&bytes).unwrap();
+        let lines: Vec<_> = (0..12)
+            .map(|offset| module.line_of(code_start + offset).cloned())
+            .collect();
+
+        let main = |line| location("/work/src/main.rs", line);
+        let leaf = |line| location("/rustc/lib/leaf.rs", line);
+        assert_eq!(
+            lines,
+            [
+                None,
+                None,
+                main(3),
+                main(4),
+                main(4),
+                main(5),
+                main(5),
+                None,
+                leaf(11),
+                leaf(12),
+                leaf(12),
+                None,
+            ]
+        );
+        assert_eq!(module.line_of(code_start - 1), None);
+    }
+
+    #[test]
+    fn a_module_without_dwarf_has_no_sources() {
+        let (bytes, _) = module_with_dwarf();
+        let mut module = Vec::new();
+        // drops the custom sections but `name`, which come after the code section
+        let mut payloads = Parser::new(0).parse_all(&bytes);
+        let mut end = 0;
+        while let Some(Ok(payload)) = payloads.next() {
+            if let Payload::CustomSection(reader) = &payload
+                && reader.name().starts_with(".debug_")
+            {
+                break;
+            }
+            if let Some((_, range)) = payload.as_section() {
+                end = range.end;
+            }
+        }
+        module.extend_from_slice(&bytes[..end]);
+
+        let module = WasmModule::parse(&module).unwrap();
+        assert!(!module.has_dwarf);
+        assert_eq!(module.functions.len(), 2);
+        assert!(module.functions.iter().all(|f| f.source.is_none()));
+        assert_eq!(module.functions[1].name.as_deref(), Some("leaf"));
+        assert_eq!(module.line_of(20), None);
+    }
+}
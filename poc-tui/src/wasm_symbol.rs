@@ -0,0 +1,388 @@
+use std::{borrow::Cow, fmt};
+
+/// What kind of code a symbol in the perf map belongs to.
+///
+/// The names are generated by wasmtime:
+/// https://github.com/bytecodealliance/wasmtime/blob/v41.0.3/crates/wasmtime/src/compile.rs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A function defined in the wasm module.
+    WasmFunction,
+    /// `wasm[M]::array_to_wasm_trampoline[F]`, the host-to-wasm entry of an exported function.
+    ArrayToWasmTrampoline,
+    /// `signatures[S]::wasm_to_array_trampoline`, used when wasm calls a host function.
+    WasmToArrayTrampoline { signature: u32 },
+    /// `wasmtime_builtin_NAME`, wasm calling into a libcall of the runtime.
+    WasmToBuiltin { builtin: String },
+    /// `wasmtime_patchable_builtin_NAME`
+    PatchableWasmToBuiltin { builtin: String },
+    /// `component-trampolines[I]-ABI-NAME`
+    ComponentTrampoline,
+    /// `unsafe-intrinsics-ABI-NAME`
+    UnsafeIntrinsic,
+}
+
+impl SymbolKind {
+    pub fn is_trampoline(&self) -> bool {
+        !matches!(self, SymbolKind::WasmFunction | SymbolKind::UnsafeIntrinsic)
+    }
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolKind::WasmFunction => write!(f, "wasm function"),
+            SymbolKind::ArrayToWasmTrampoline => write!(f, "array-to-wasm trampoline"),
+            SymbolKind::WasmToArrayTrampoline { .. } => write!(f, "wasm-to-array trampoline"),
+            SymbolKind::WasmToBuiltin { .. } => write!(f, "wasm-to-builtin trampoline"),
+            SymbolKind::PatchableWasmToBuiltin { .. } => {
+                write!(f, "patchable wasm-to-builtin trampoline")
+            }
+            SymbolKind::ComponentTrampoline => write!(f, "component trampoline"),
+            SymbolKind::UnsafeIntrinsic => write!(f, "unsafe intrinsic"),
+        }
+    }
+}
+
+/// A symbol from the perf map that wasmtime writes, parsed into its parts.
+///
+/// wasmtime writes the name from the `name` section for the wasm functions when there is one
+/// (e.g. `wasm_binary::trim_ascii_whitespace`), and `wasm[M]::function[F]` otherwise. Hence, the
+/// module and the function indices are only known for the latter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmSymbol {
+    /// The symbol as it's written in the perf map
+    pub raw: String,
+    pub kind: SymbolKind,
+    /// Index of the module within the compiled image
+    pub module_index: Option<u32>,
+    /// Index of the function within the wasm module
+    pub func_index: Option<u32>,
+    /// The name of the module, which is the crate name for the Rust guests
+    pub module_name: Option<String>,
+    /// The demangled name, without the Rust hash suffix
+    pub demangled: String,
+}
+
+impl WasmSymbol {
+    pub fn parse(raw: &str) -> Self {
+        let mut symbol = WasmSymbol {
+            raw: raw.into(),
+            kind: SymbolKind::WasmFunction,
+            module_index: None,
+            func_index: None,
+            module_name: None,
+            demangled: raw.into(),
+        };
+
+        if let Some(rest) = raw.strip_prefix("wasm[") {
+            let Some((module_index, rest)) = parse_index(rest) else {
+                return symbol;
+            };
+            symbol.module_index = Some(module_index);
+
+            if let Some(rest) = rest.strip_prefix("::function[") {
+                let Some((func_index, rest)) = parse_index(rest) else {
+                    return symbol;
+                };
+                symbol.func_index = Some(func_index);
+                if let Some(name) = rest.strip_prefix("::") {
+                    symbol.set_demangled(name);
+                }
+            } else if let Some(rest) = rest.strip_prefix("::array_to_wasm_trampoline[") {
+                symbol.kind = SymbolKind::ArrayToWasmTrampoline;
+                symbol.func_index = parse_index(rest).map(|(idx, _)| idx);
+            }
+        } else if let Some(rest) = raw.strip_prefix("signatures[") {
+            if let Some((signature, "::wasm_to_array_trampoline")) = parse_index(rest) {
+                symbol.kind = SymbolKind::WasmToArrayTrampoline { signature };
+            }
+        } else if let Some(builtin) = raw.strip_prefix("wasmtime_patchable_builtin_") {
+            symbol.kind = SymbolKind::PatchableWasmToBuiltin {
+                builtin: builtin.into(),
+            };
+        } else if let Some(builtin) = raw.strip_prefix("wasmtime_builtin_") {
+            symbol.kind = SymbolKind::WasmToBuiltin {
+                builtin: builtin.into(),
+            };
+        } else if raw.starts_with("component-trampolines[") {
+            symbol.kind = SymbolKind::ComponentTrampoline;
+        } else if raw.starts_with("unsafe-intrinsics-") {
+            symbol.kind = SymbolKind::UnsafeIntrinsic;
+        } else {
+            symbol.set_demangled(raw);
+        }
+
+        symbol
+    }
+
+    /// The last segment of the demangled path, e.g. `trim_ascii_whitespace` for
+    /// `wasm_binary::trim_ascii_whitespace`.
+    pub fn short_name(&self) -> &str {
+        match path_segments(&self.demangled).last() {
+            Some(name) if self.kind == SymbolKind::WasmFunction => name,
+            _ => &self.demangled,
+        }
+    }
+
+    /// Whether the symbol is a function of the module `module`, or code that wasmtime
+    /// generated (i.e. a `wasm[M]::function[F]` or a trampoline). An empty `module` matches
+    /// every symbol.
+    pub fn belongs_to(&self, module: &str) -> bool {
+        module.is_empty()
+            || self.module_index.is_some()
+            || self.kind != SymbolKind::WasmFunction
+            || self.module_name.as_deref() == Some(module)
+    }
+
+    fn set_demangled(&mut self, name: &str) {
+        let name = demangle(name);
+        let segments = path_segments(&name);
+
+        let segments = match segments.split_last() {
+            Some((last, rest)) if is_rust_hash(last) && !rest.is_empty() => rest,
+            _ => &segments[..],
+        };
+
+        // `<T as Trait>::method` doesn't tell which crate the function is defined in.
+        if segments.len() > 1 && !segments[0].starts_with('<') {
+            self.module_name = Some(segments[0].into());
+        }
+        self.demangled = segments.join("::");
+    }
+}
+
+impl fmt::Display for WasmSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.demangled)
+    }
+}
+
+/// Demangles Rust (legacy and v0) and C++ symbols, returns `name` as is if it's not mangled.
+///
+/// wasmtime already demangles the names when it's built with the `demangle` feature, but the
+/// guest toolchain might leave them mangled in the `name` section.
+pub fn demangle(name: &str) -> Cow<'_, str> {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // the alternate form omits the hash
+        return Cow::Owned(format!("{demangled:#}"));
+    }
+
+    // `cpp_demangle` happily "demangles" some plain identifiers as types, hence the prefix check.
+    if name.starts_with("_Z")
+        && let Ok(symbol) = cpp_demangle::Symbol::new(name)
+        && let Ok(demangled) = symbol.demangle(&cpp_demangle::DemangleOptions::default())
+    {
+        return Cow::Owned(demangled);
+    }
+
+    Cow::Borrowed(name)
+}
+
+/// Parses `N]` and returns `N` and the rest of the string.
+fn parse_index(s: &str) -> Option<(u32, &str)> {
+    let (idx, rest) = s.split_once(']')?;
+    Some((idx.parse().ok()?, rest))
+}
+
+/// Splits a Rust path on `::`, ignoring the ones within the generic arguments
+/// (e.g. `<alloc::string::String as core::fmt::Write>::write_str`).
+fn path_segments(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let bytes = path.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'<' => depth += 1,
+            b'>' => depth -= 1,
+            b':' if depth == 0 && bytes.get(i + 1) == Some(&b':') => {
+                segments.push(&path[start..i]);
+                i += 2;
+                start = i;
+                continue;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    segments.push(&path[start..]);
+    segments
+}
+
+/// Rust symbols end with `::h` followed by 16 hex digits of hash.
+fn is_rust_hash(segment: &str) -> bool {
+    segment.len() == 17
+        && segment.starts_with('h')
+        && segment[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_name_section_names() {
+        let symbol = WasmSymbol::parse("wasm_binary::trim_ascii_whitespace");
+        assert_eq!(symbol.kind, SymbolKind::WasmFunction);
+        assert_eq!(symbol.module_index, None);
+        assert_eq!(symbol.func_index, None);
+        assert_eq!(symbol.module_name.as_deref(), Some("wasm_binary"));
+        assert_eq!(symbol.demangled, "wasm_binary::trim_ascii_whitespace");
+        assert_eq!(symbol.short_name(), "trim_ascii_whitespace");
+    }
+
+    #[test]
+    fn strips_the_rust_hash() {
+        let symbol = WasmSymbol::parse("wasm_binary::main::h0123456789abcdef");
+        assert_eq!(symbol.demangled, "wasm_binary::main");
+        assert_eq!(symbol.short_name(), "main");
+        assert_eq!(symbol.raw, "wasm_binary::main::h0123456789abcdef");
+    }
+
+    #[test]
+    fn trait_impls_have_no_module() {
+        let symbol = WasmSymbol::parse("<alloc::string::String as core::fmt::Write>::write_str");
+        assert_eq!(symbol.module_name, None);
+        assert_eq!(symbol.short_name(), "write_str");
+    }
+
+    #[test]
+    fn parses_unnamed_functions() {
+        let symbol = WasmSymbol::parse("wasm[1]::function[42]");
+        assert_eq!(symbol.kind, SymbolKind::WasmFunction);
+        assert_eq!(symbol.module_index, Some(1));
+        assert_eq!(symbol.func_index, Some(42));
+        assert_eq!(symbol.module_name, None);
+        assert_eq!(symbol.short_name(), "function[42]");
+    }
+
+    #[test]
+    fn parses_indexed_functions_with_a_name() {
+        let symbol = WasmSymbol::parse("wasm[0]::function[3]::wasm_binary::leaf");
+        assert_eq!(symbol.kind, SymbolKind::WasmFunction);
+        assert_eq!(symbol.module_index, Some(0));
+        assert_eq!(symbol.func_index, Some(3));
+        assert_eq!(symbol.module_name.as_deref(), Some("wasm_binary"));
+        assert_eq!(symbol.demangled, "wasm_binary::leaf");
+        assert_eq!(symbol.short_name(), "leaf");
+    }
+
+    #[test]
+    fn parses_array_to_wasm_trampolines() {
+        let symbol = WasmSymbol::parse("wasm[0]::array_to_wasm_trampoline[7]");
+        assert_eq!(symbol.kind, SymbolKind::ArrayToWasmTrampoline);
+        assert_eq!(symbol.module_index, Some(0));
+        assert_eq!(symbol.func_index, Some(7));
+        assert!(symbol.kind.is_trampoline());
+    }
+
+    #[test]
+    fn parses_wasm_to_array_trampolines() {
+        let symbol = WasmSymbol::parse("signatures[5]::wasm_to_array_trampoline");
+        assert_eq!(
+            symbol.kind,
+            SymbolKind::WasmToArrayTrampoline { signature: 5 }
+        );
+        assert_eq!(symbol.module_index, None);
+        assert_eq!(
+            symbol.short_name(),
+            "signatures[5]::wasm_to_array_trampoline"
+        );
+    }
+
+    #[test]
+    fn parses_builtins() {
+        assert_eq!(
+            WasmSymbol::parse("wasmtime_builtin_memory_grow").kind,
+            SymbolKind::WasmToBuiltin {
+                builtin: "memory_grow".into()
+            }
+        );
+        assert_eq!(
+            WasmSymbol::parse("wasmtime_patchable_builtin_raise").kind,
+            SymbolKind::PatchableWasmToBuiltin {
+                builtin: "raise".into()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_component_trampolines_and_intrinsics() {
+        let symbol = WasmSymbol::parse("component-trampolines[0]-wasm-call-resource_drop");
+        assert_eq!(symbol.kind, SymbolKind::ComponentTrampoline);
+        assert!(symbol.kind.is_trampoline());
+
+        let symbol = WasmSymbol::parse("unsafe-intrinsics-wasm-call-store_data_address");
+        assert_eq!(symbol.kind, SymbolKind::UnsafeIntrinsic);
+        assert!(!symbol.kind.is_trampoline());
+    }
+
+    #[test]
+    fn malformed_indices_are_kept_raw() {
+        let symbol = WasmSymbol::parse("wasm[x]::function[1]");
+        assert_eq!(symbol.kind, SymbolKind::WasmFunction);
+        assert_eq!(symbol.module_index, None);
+        assert_eq!(symbol.demangled, "wasm[x]::function[1]");
+    }
+
+    #[test]
+    fn demangles_rust_legacy_symbols() {
+        let raw = "_ZN11wasm_binary4main17h0123456789abcdefE";
+        assert_eq!(demangle(raw), "wasm_binary::main");
+
+        let symbol = WasmSymbol::parse(raw);
+        assert_eq!(symbol.raw, raw);
+        assert_eq!(symbol.demangled, "wasm_binary::main");
+        assert_eq!(symbol.module_name.as_deref(), Some("wasm_binary"));
+        assert_eq!(symbol.short_name(), "main");
+    }
+
+    #[test]
+    fn demangles_rust_v0_symbols() {
+        let raw = "_RNvCs15kBYyAo9fc_11wasm_binary4leaf";
+        assert_eq!(demangle(raw), "wasm_binary::leaf");
+
+        let symbol = WasmSymbol::parse(raw);
+        assert_eq!(symbol.module_name.as_deref(), Some("wasm_binary"));
+        assert_eq!(symbol.short_name(), "leaf");
+    }
+
+    #[test]
+    fn demangles_itanium_symbols() {
+        let raw = "_ZN11wasm_binary4leafEi";
+        assert_eq!(demangle(raw), "wasm_binary::leaf(int)");
+
+        let symbol = WasmSymbol::parse(raw);
+        assert_eq!(symbol.module_name.as_deref(), Some("wasm_binary"));
+        assert_eq!(symbol.short_name(), "leaf(int)");
+    }
+
+    #[test]
+    fn demangles_within_indexed_functions() {
+        let symbol =
+            WasmSymbol::parse("wasm[0]::function[2]::_ZN11wasm_binary3mid17h0123456789abcdefE");
+        assert_eq!(symbol.func_index, Some(2));
+        assert_eq!(symbol.demangled, "wasm_binary::mid");
+    }
+
+    #[test]
+    fn leaves_plain_names_alone() {
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle("_Zfoo"), "_Zfoo");
+        assert_eq!(demangle("wasm_binary::leaf"), "wasm_binary::leaf");
+    }
+
+    #[test]
+    fn belongs_to_the_module_or_wasmtime() {
+        assert!(WasmSymbol::parse("wasm_binary::leaf").belongs_to("wasm_binary"));
+        assert!(!WasmSymbol::parse("other::leaf").belongs_to("wasm_binary"));
+        assert!(WasmSymbol::parse("other::leaf").belongs_to(""));
+        assert!(WasmSymbol::parse("wasm[0]::function[1]").belongs_to("wasm_binary"));
+        assert!(
+            WasmSymbol::parse("signatures[0]::wasm_to_array_trampoline").belongs_to("wasm_binary")
+        );
+        assert!(WasmSymbol::parse("wasmtime_builtin_memory_grow").belongs_to("wasm_binary"));
+    }
+}